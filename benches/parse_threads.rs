@@ -0,0 +1,119 @@
+//! Throughput of `--parse-threads` on a synthetic commonsense-style JSONL input.
+//!
+//! Converts the same seeded input with the `ethics-pipeline` binary, once with one
+//! parser thread and once with N, checks the two shards are byte-identical, and prints
+//! lines/s and MB/s for each.
+//!
+//!     cargo bench --bench parse_threads
+//!     PARSE_THREADS_BENCH_LINES=5000000 PARSE_THREADS_BENCH_THREADS=8 cargo bench --bench parse_threads
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Vocabulary of the synthetic sentences.
+const WORDS: &str = "I my friend neighbour told took gave borrowed returned broke the a car money \
+    cookie phone secret promise her his back without asking after work because lied helped \
+    shopping door";
+
+/// Runs of each configuration; the fastest is reported.
+const REPEAT: u32 = 3;
+
+/// `name` from the environment, or `default`.
+fn env_or(name: &str, default: usize) -> usize {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name}={value} is not a count")),
+        Err(_) => default,
+    }
+}
+
+/// SplitMix64, so the fixture is the same on every run without a rand dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % n as u64) as usize
+    }
+}
+
+/// `lines` commonsense-style rows: a sentence, a 0/1 label and an `is_short` flag.
+fn synthetic(lines: usize) -> Vec<u8> {
+    let words: Vec<&str> = WORDS.split_whitespace().collect();
+    let mut rng = Rng(0);
+    let mut out = Vec::with_capacity(lines * 120);
+    for _ in 0..lines {
+        let len = 6 + rng.below(34);
+        let sentence: Vec<&str> = (0..len).map(|_| words[rng.below(words.len())]).collect();
+        let text = sentence.join(" ") + ".";
+        let row =
+            serde_json::json!({"label": rng.below(2), "scenario": text, "is_short": len < 20});
+        serde_json::to_writer(&mut out, &row).expect("writes to a Vec");
+        out.push(b'\n');
+    }
+    out
+}
+
+/// Fastest of [`REPEAT`] conversions of `input` with `parse_threads` parsers, and the shard.
+fn convert(input: &Path, parse_threads: usize) -> (Duration, Vec<u8>) {
+    let out = input.with_extension(format!("{parse_threads}.pb"));
+    let mut best = Duration::MAX;
+    for _ in 0..REPEAT {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_ethics-pipeline"))
+            .arg("--input")
+            .arg(input)
+            .args(["--subset", "commonsense", "--split", "train", "--out"])
+            .arg(&out)
+            .args(["--no-compress", "--overwrite", "--quiet", "--parse-threads"])
+            .arg(parse_threads.to_string())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .expect("runs ethics-pipeline");
+        best = best.min(start.elapsed());
+        assert!(status.success(), "ethics-pipeline failed: {status}");
+    }
+    (best, fs::read(&out).expect("reads the shard"))
+}
+
+fn main() {
+    let lines = env_or("PARSE_THREADS_BENCH_LINES", 1_000_000);
+    let threads = env_or(
+        "PARSE_THREADS_BENCH_THREADS",
+        std::thread::available_parallelism().map_or(4, |n| n.get().max(2)),
+    );
+    let dir = tempfile::tempdir().expect("creates a temporary directory");
+    let input = dir.path().join("commonsense-train.jsonl");
+    let fixture = synthetic(lines);
+    fs::write(&input, &fixture).expect("writes the fixture");
+    let mb = fixture.len() as f64 / 1e6;
+    println!("{lines} lines, {mb:.1} MB (best of {REPEAT}):");
+
+    let (single, expected) = convert(&input, 1);
+    let (parallel, shard) = convert(&input, threads);
+    assert!(
+        shard == expected,
+        "--parse-threads {threads} wrote a different shard"
+    );
+    for (label, took) in [
+        ("1 thread".to_string(), single),
+        (format!("{threads} threads"), parallel),
+    ] {
+        let secs = took.as_secs_f64().max(1e-9);
+        println!(
+            "  {label:<12} {took:>10.3?}  {:>10.0} lines/s  {:>7.1} MB/s",
+            lines as f64 / secs,
+            mb / secs
+        );
+    }
+    println!(
+        "  speedup      {:.2}x",
+        single.as_secs_f64() / parallel.as_secs_f64().max(1e-9)
+    );
+}
@@ -1,3 +1,45 @@
+use std::path::PathBuf;
+use std::process::Command;
+
 fn main() {
-    prost_build::compile_protos(&["proto/ethics.proto"], &["proto"]).unwrap();
+    // BTreeMap keeps `Example.meta` entries in key order on the wire, so identical
+    // input always encodes to identical bytes. The descriptor set is embedded for
+    // `ethics-pipeline schema`.
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    prost_build::Config::new()
+        .btree_map(["."])
+        .file_descriptor_set_path(out_dir.join("ethics.v1.desc"))
+        .compile_protos(&["proto/ethics.proto"], &["proto"])
+        .unwrap();
+
+    // The `server` binary's service, generated apart so the messages above (and the
+    // descriptor set's hash) are the same with or without the feature. The client
+    // is for `tests/grpc.rs`.
+    #[cfg(feature = "grpc")]
+    {
+        let grpc_dir = out_dir.join("grpc");
+        std::fs::create_dir_all(&grpc_dir).unwrap();
+        tonic_prost_build::configure()
+            .out_dir(&grpc_dir)
+            .extern_path(".ethics.v1.Example", "::protobuf_ethics::ethics::Example")
+            .extern_path(
+                ".ethics.v1.ShardHeader",
+                "::protobuf_ethics::ethics::ShardHeader",
+            )
+            .compile_protos(&["proto/ethics_service.proto"], &["proto"])
+            .unwrap();
+        println!("cargo:rerun-if-changed=proto/ethics_service.proto");
+    }
+
+    // Stamped into shard headers; empty outside a git checkout.
+    let git = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=ETHICS_PIPELINE_GIT={git}");
+    println!("cargo:rerun-if-changed=proto/ethics.proto");
+    println!("cargo:rerun-if-changed=.git/HEAD");
 }
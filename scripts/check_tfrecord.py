@@ -0,0 +1,70 @@
+"""Check a TFRecord from `ethics-pipeline export --format tfrecord` with TensorFlow.
+
+TensorFlow reads the file (verifying its CRC32C framing) and parses every record as
+a tf.train.Example; each one is compared with the same example decoded from the
+shard by `pb_to_jsonl`:
+
+    cargo run --bin pb_to_jsonl -- data/processed/justice/test-00000.pb.zst > /tmp/test.jsonl
+    cargo run --bin ethics-pipeline -- export --format tfrecord --out-dir /tmp data/processed/justice/test-00000.pb.zst
+    uv run python scripts/check_tfrecord.py /tmp/test-00000.tfrecord /tmp/test.jsonl
+
+Files ending in `.gz` are read with compression_type="GZIP".
+"""
+
+import argparse
+import json
+import sys
+
+import numpy as np
+import tensorflow as tf
+
+
+def from_tf(raw):
+    """The feature dict of a serialized tf.train.Example, as pb_to_jsonl would print it."""
+    example = tf.train.Example.FromString(raw)
+    out = {"meta": {}}
+    for name, feature in example.features.feature.items():
+        kind = feature.WhichOneof("kind")
+        values = list(getattr(feature, kind).value)
+        if len(values) != 1:
+            raise ValueError(f"feature {name} has {len(values)} values")
+        value = values[0].decode("utf-8") if kind == "bytes_list" else values[0]
+        if name.startswith("meta/"):
+            out["meta"][name[len("meta/"):]] = value
+        elif name.startswith("labels_by_name/"):
+            out.setdefault("labels_by_name", {})[name[len("labels_by_name/"):]] = value
+        else:
+            out[name] = value
+    return out
+
+
+def main():
+    ap = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    ap.add_argument("tfrecord")
+    ap.add_argument("jsonl", help="the same shard decoded by pb_to_jsonl")
+    args = ap.parse_args()
+
+    compression = "GZIP" if args.tfrecord.endswith(".gz") else ""
+    records = tf.data.TFRecordDataset(args.tfrecord, compression_type=compression)
+    with open(args.jsonl, encoding="utf-8") as f:
+        expected = [json.loads(line) for line in f if line.strip()]
+
+    count = 0
+    for i, raw in enumerate(records.as_numpy_iterator()):
+        if i >= len(expected):
+            sys.exit(f"{args.tfrecord} has more than the {len(expected)} example(s) of {args.jsonl}")
+        got, want = from_tf(raw), dict(expected[i])
+        # TFRecord floats are f32.
+        if "score" in want:
+            want["score"] = float(np.float32(want["score"]))
+            got["score"] = float(got["score"])
+        if got != want:
+            sys.exit(f"example {i} differs:\n  tfrecord {got}\n  jsonl    {want}")
+        count += 1
+    if count != len(expected):
+        sys.exit(f"{args.tfrecord} has {count} example(s), {args.jsonl} has {len(expected)}")
+    print(f"{args.tfrecord}: {count} example(s) match {args.jsonl}")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,73 @@
+"""Write JSONL inputs with the line-ending and line-length cases the readers handle.
+
+    uv run python scripts/make_edge_case_fixtures.py --out data/fixtures
+
+writes
+
+  commonsense-bom_crlf.jsonl   a UTF-8 BOM, CRLF endings and a blank CRLF line;
+                               converts to the same examples as the plain file
+  commonsense-huge_line.jsonl  a 100 MB record between two ordinary ones; with the
+                               default --max-line-bytes (16 MiB) it is rejected as
+                               line_too_long at line 2 and the other two convert
+
+The huge line is streamed out in chunks, so the script itself stays small in memory.
+"""
+
+import argparse
+import json
+import os
+
+ROWS = [
+    {"text": "I helped my neighbour carry her shopping.", "label": 0},
+    {"text": "I took the last cookie and blamed my brother.", "label": 1},
+    {"text": "Ich habe das Fundbüro angerufen. 🙂", "label": 0},
+]
+
+CHUNK = 1 << 20
+
+
+def write_bom_crlf(path):
+    with open(path, "wb") as f:
+        f.write(b"\xef\xbb\xbf")
+        for i, row in enumerate(ROWS):
+            if i == 1:
+                f.write(b"\r\n")
+            f.write(json.dumps(row, ensure_ascii=False).encode("utf-8") + b"\r\n")
+
+
+def write_huge_line(path, size):
+    first, last = ROWS[0], ROWS[1]
+    with open(path, "wb") as f:
+        f.write(json.dumps(first).encode("utf-8") + b"\n")
+        f.write(b'{"label": 0, "text": "')
+        remaining = size
+        while remaining > 0:
+            n = min(CHUNK, remaining)
+            f.write(b"a" * n)
+            remaining -= n
+        f.write(b'"}\n')
+        f.write(json.dumps(last).encode("utf-8") + b"\n")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--out", default="data/fixtures", help="directory to write into")
+    parser.add_argument(
+        "--huge-bytes",
+        type=int,
+        default=100 * 1000 * 1000,
+        help="length of the text in the huge line (default 100 MB)",
+    )
+    args = parser.parse_args()
+
+    os.makedirs(args.out, exist_ok=True)
+    bom_crlf = os.path.join(args.out, "commonsense-bom_crlf.jsonl")
+    huge = os.path.join(args.out, "commonsense-huge_line.jsonl")
+    write_bom_crlf(bom_crlf)
+    write_huge_line(huge, args.huge_bytes)
+    for path in (bom_crlf, huge):
+        print(f"wrote {path} ({os.path.getsize(path)} bytes)")
+
+
+if __name__ == "__main__":
+    main()
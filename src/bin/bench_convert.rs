@@ -0,0 +1,215 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use protobuf_ethics::convert::{
+    convert_stream, parse_record, ConvertOptions, Parsed, RecordOptions, RunState,
+};
+use protobuf_ethics::input::{records, InputFormat};
+use protobuf_ethics::logging::LogArgs;
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "bench-convert",
+    about = "Time reading, parsing and converting a synthetic commonsense-style JSONL file."
+)]
+struct Args {
+    /// Lines in the synthetic input.
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    lines: usize,
+
+    /// Seed for the synthetic input, so runs compare like with like.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Runs of each stage; the fastest is reported.
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// Parser threads for the convert stage, as `--parse-threads`.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    parse_threads: usize,
+
+    /// Also write the synthetic input here, e.g. to profile the converter on it.
+    #[arg(long, value_name = "JSONL")]
+    write_fixture: Option<PathBuf>,
+
+    /// Save the timings as JSON, to pass as `--baseline` later.
+    #[arg(long, value_name = "JSON")]
+    save: Option<PathBuf>,
+
+    /// Fail if a stage is slower than in this saved run by more than `--tolerance`.
+    #[arg(long, value_name = "JSON")]
+    baseline: Option<PathBuf>,
+
+    /// Slowdown allowed against `--baseline`, as a fraction.
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.10)]
+    tolerance: f64,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// Vocabulary of the synthetic sentences.
+const WORDS: &str = "I my friend neighbour told took gave borrowed returned broke the a car money \
+    cookie phone secret promise her his back without asking after work because lied helped \
+    shopping door";
+
+/// A commonsense-style row: a sentence, a 0/1 label and an `is_short` flag.
+fn synthetic(lines: usize, seed: u64) -> Vec<u8> {
+    let words: Vec<&str> = WORDS.split_whitespace().collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut out = Vec::with_capacity(lines * 120);
+    for _ in 0..lines {
+        let len = rng.random_range(6..40);
+        let sentence: Vec<&str> = (0..len).map(|_| *words.choose(&mut rng).unwrap()).collect();
+        let text = sentence.join(" ") + ".";
+        let row = json!({"label": rng.random_range(0..2), "input": text, "is_short": len < 20});
+        serde_json::to_writer(&mut out, &row).expect("writes to a Vec");
+        out.push(b'\n');
+    }
+    out
+}
+
+/// Fastest of `repeat` runs of `f`.
+fn best_of(repeat: u32, mut f: impl FnMut() -> Result<()>) -> Result<Duration> {
+    let mut best = Duration::MAX;
+    for _ in 0..repeat {
+        let start = Instant::now();
+        f()?;
+        best = best.min(start.elapsed());
+    }
+    Ok(best)
+}
+
+fn reader(fixture: &Arc<[u8]>) -> Box<dyn std::io::BufRead + Send> {
+    Box::new(Cursor::new(Arc::clone(fixture)))
+}
+
+/// Fails when a stage in `timings` is slower than in the saved `baseline` by more than `tolerance`.
+fn check_baseline(path: &Path, timings: &serde_json::Value, tolerance: f64) -> Result<()> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let baseline: serde_json::Value =
+        serde_json::from_str(&text).with_context(|| format!("{} is not JSON", path.display()))?;
+    if baseline["lines"] != timings["lines"] {
+        bail!(
+            "{} timed {} lines, this run {}; pass the same --lines",
+            path.display(),
+            baseline["lines"],
+            timings["lines"]
+        );
+    }
+    let mut slower = Vec::new();
+    for stage in ["read", "parse", "convert"] {
+        let (Some(then), Some(now)) = (baseline[stage].as_f64(), timings[stage].as_f64()) else {
+            continue;
+        };
+        if now > then * (1.0 + tolerance) {
+            slower.push(format!("{stage} {then:.3}s -> {now:.3}s"));
+        }
+    }
+    if !slower.is_empty() {
+        bail!(
+            "slower than {} by more than {:.0}%: {}",
+            path.display(),
+            tolerance * 100.0,
+            slower.join(", ")
+        );
+    }
+    println!("within {:.0}% of {}", tolerance * 100.0, path.display());
+    Ok(())
+}
+
+fn run(args: Args) -> Result<()> {
+    let fixture: Arc<[u8]> = synthetic(args.lines, args.seed).into();
+    if let Some(path) = &args.write_fixture {
+        fs::write(path, &fixture).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    let opts = ConvertOptions {
+        parse_threads: args.parse_threads,
+        record: RecordOptions {
+            text_fields: vec!["input".to_string()],
+            ..RecordOptions::default()
+        },
+        ..ConvertOptions::default()
+    };
+    let name = Path::new("commonsense-train.jsonl");
+
+    let read = best_of(args.repeat, || {
+        let n =
+            records(reader(&fixture), InputFormat::Jsonl)?.try_fold(0, |n, r| r.map(|_| n + 1))?;
+        std::hint::black_box(n);
+        Ok(())
+    })?;
+    let parse = best_of(args.repeat, || {
+        for record in records(reader(&fixture), InputFormat::Jsonl)? {
+            let (_, line) = record?;
+            let parsed = parse_record(&line, false, "commonsense", "train", &opts.record);
+            if !matches!(parsed, Parsed::Encoded(_)) {
+                bail!("a synthetic row did not convert");
+            }
+            std::hint::black_box(parsed);
+        }
+        Ok(())
+    })?;
+    let convert = best_of(args.repeat, || {
+        let state = RunState::new(None, None);
+        let info = convert_stream(
+            Cursor::new(Arc::clone(&fixture)),
+            name,
+            ("commonsense", "train"),
+            &opts,
+            &state,
+            &mut std::io::sink(),
+        )?;
+        std::hint::black_box(info);
+        Ok(())
+    })?;
+
+    let mb = fixture.len() as f64 / 1e6;
+    println!(
+        "{} lines, {mb:.1} MB (best of {}):",
+        args.lines, args.repeat
+    );
+    for (stage, took) in [("read", read), ("parse", parse), ("convert", convert)] {
+        let secs = took.as_secs_f64().max(1e-9);
+        println!(
+            "  {stage:<8} {took:>10.3?}  {:>10.0} lines/s  {:>7.1} MB/s",
+            args.lines as f64 / secs,
+            mb / secs
+        );
+    }
+
+    let timings = json!({
+        "lines": args.lines,
+        "seed": args.seed,
+        "parse_threads": args.parse_threads,
+        "read": read.as_secs_f64(),
+        "parse": parse.as_secs_f64(),
+        "convert": convert.as_secs_f64(),
+    });
+    if let Some(path) = &args.save {
+        fs::write(path, serde_json::to_string_pretty(&timings)? + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    if let Some(path) = &args.baseline {
+        check_baseline(path, &timings, args.tolerance)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "bench-random-access",
+    about = "Time fetching one example of an indexed shard sequentially and through its `.idx` sidecar."
+)]
+struct Args {
+    /// Shard with a `<PB_ZST>.idx` sidecar (`--frame-every` or `index_shard`).
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Index of the example to fetch; the default is the millionth.
+    #[arg(long, value_name = "N", default_value_t = 999_999)]
+    example: u64,
+
+    /// Runs of each method; the fastest is reported.
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// zstd dictionary the shard was compressed with.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// Fastest of `repeat` runs of `f`.
+fn best_of(repeat: u32, mut f: impl FnMut() -> Result<()>) -> Result<Duration> {
+    let mut best = Duration::MAX;
+    for _ in 0..repeat {
+        let start = Instant::now();
+        f()?;
+        best = best.min(start.elapsed());
+    }
+    Ok(best)
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let n = args.example;
+
+    let sequential = best_of(args.repeat, || {
+        let mut reader = ExampleReader::open_with_dict(&args.input, dict.as_ref())?;
+        let ex = reader
+            .nth(n as usize)
+            .with_context(|| format!("{} has no example {n}", args.input.display()))??;
+        std::hint::black_box(ex);
+        Ok(())
+    })?;
+
+    // Opening checks the sidecar against the shard's SHA-256, which reads the whole
+    // file once; that cost is paid per reader, not per lookup, so it is timed apart.
+    let start = Instant::now();
+    let reader = ExampleReader::open_indexed(&args.input, dict.as_ref())?;
+    let open = start.elapsed();
+    let indexed = best_of(args.repeat, || {
+        let ex = reader
+            .get(n)?
+            .with_context(|| format!("{} has no example {n}", args.input.display()))?;
+        std::hint::black_box(ex);
+        Ok(())
+    })?;
+
+    // Both paths must agree on what example `n` is.
+    let a = ExampleReader::open_with_dict(&args.input, dict.as_ref())?.nth(n as usize);
+    let b = reader.get(n)?;
+    ensure!(
+        a.transpose()?.as_ref() == b.as_ref(),
+        "indexed and sequential reads of example {n} differ"
+    );
+
+    println!(
+        "example {n} of {} (best of {}):",
+        args.input.display(),
+        args.repeat
+    );
+    println!("  sequential  {sequential:>12.3?}");
+    println!(
+        "  indexed     {indexed:>12.3?}  ({:.0}x faster)",
+        sequential.as_secs_f64() / indexed.as_secs_f64().max(1e-9)
+    );
+    println!("  open_indexed (index check, once per reader)  {open:.3?}");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -1,98 +1,40 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::Read;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clap::Parser;
 use glob::glob;
-use serde::Serialize;
+use protobuf_ethics::convert::{infer_subset_split, LabelMap, RawLabel};
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::input::{
+    decompressed_name, is_pairs_shard, line_too_long, lines, open_maybe_compressed,
+    DEFAULT_MAX_LINE_BYTES, GZIP_MAGIC,
+};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::protojson;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::sample::Reservoir;
+use protobuf_ethics::shard::{SHARD_MAGIC, ZSTD_MAGIC};
+use protobuf_ethics::stats::{
+    compare_reports, histogram, label_correlation, parse_histogram_edges, parse_percentiles,
+    percentile_key, preview, summarize_per_file, DuplicateSummary, DuplicateText, ExtremesBuilder,
+    FieldProfiler, FileTexts, HistogramEdges, HistogramSpec, LengthUnit, LineCounts, Quantiles,
+    Report, RunTexts, RunningStats, SampleCounts, SampleInfo, Section, SharedTexts, Stats, TextLen,
+    TokenizerInfo, UnitStats, DEFAULT_PERCENTILES, MISSING_LABEL,
+};
+#[cfg(feature = "tokenizer")]
+use protobuf_ethics::tokens::TokenCounter;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
 use serde_json::Value;
 use tracing::{info, warn};
 
-/// Newtype for text length in bytes.
-#[derive(Debug, Clone, Copy)]
-struct TextLen(usize);
-
-/// Per-file / overall statistics.
-#[derive(Debug, Clone, Serialize)]
-struct Stats {
-    count: usize,
-    min: Option<f64>,
-    max: Option<f64>,
-    mean: Option<f64>,
-    std: Option<f64>,
-    p25: Option<f64>,
-    p50: Option<f64>,
-    p75: Option<f64>,
-}
-
-/// Top-level TOML structure.
-#[derive(Debug, Serialize)]
-struct Report {
-    overall: Stats,
-    files: BTreeMap<String, Stats>,
-}
-
-/// Streaming aggregator for overall stats (mean/std/min/max).
-#[derive(Debug, Default)]
-struct RunningStats {
-    count: usize,
-    mean: f64,
-    m2: f64, // sum of squared deviations
-    min: Option<usize>,
-    max: Option<usize>,
-}
-
-impl RunningStats {
-    fn push(&mut self, len: TextLen) {
-        let x = len.0;
-        // update count, min, max
-        self.count += 1;
-        self.min = Some(self.min.map_or(x, |m| m.min(x)));
-        self.max = Some(self.max.map_or(x, |m| m.max(x)));
-
-        // Welford's online algorithm for mean/std
-        let xf = x as f64;
-        let delta = xf - self.mean;
-        self.mean += delta / self.count as f64;
-        let delta2 = xf - self.mean;
-        self.m2 += delta * delta2;
-    }
-
-    fn finalize(self, p25: Option<f64>, p50: Option<f64>, p75: Option<f64>) -> Stats {
-        if self.count == 0 {
-            return Stats {
-                count: 0,
-                min: None,
-                max: None,
-                mean: None,
-                std: None,
-                p25,
-                p50,
-                p75,
-            };
-        }
-
-        let var = if self.count > 1 {
-            self.m2 / (self.count as f64 - 1.0)
-        } else {
-            0.0
-        };
-
-        Stats {
-            count: self.count,
-            min: self.min.map(|v| v as f64),
-            max: self.max.map(|v| v as f64),
-            mean: Some(self.mean),
-            std: Some(var.sqrt()),
-            p25,
-            p50,
-            p75,
-        }
-    }
-}
-
 /// CLI arguments.
 #[derive(Parser, Debug)]
 #[command(
@@ -100,6 +42,8 @@ impl RunningStats {
     about = "Compute per-file and overall text-length statistics from JSONL files."
 )]
 struct Args {
+    /// Files to summarize: JSONL, plain, .gz or .zst, and `.pb` / `.pb.zst` shards,
+    /// mixed freely; each file's `format` says how it was read.
     #[arg(
         long,
         default_value = "data/raw/commonsense-*.jsonl",
@@ -107,115 +51,970 @@ struct Args {
     )]
     glob: String,
 
+    /// zstd dictionary the shards were compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Report path; without an extension, the one for `--format` is added.
     #[arg(
         long,
-        default_value = "data/stats/commonsense_length_stats.toml",
+        default_value = "data/stats/commonsense_length_stats",
         value_name = "OUT"
     )]
     out: String,
+
+    /// Report format; by default taken from the `--out` extension, TOML otherwise.
+    #[arg(long, value_enum)]
+    format: Option<ReportFormat>,
+
+    /// Field to summarize: string lengths (e.g. `context` for deontology scenarios), or
+    /// values for numeric fields such as `score`. A list is tried in order, as
+    /// `--text-fields` in the converter: `scenario,question,observation,text` takes
+    /// each record's first field holding a non-blank string or a number.
+    #[arg(
+        long,
+        value_name = "FIELD,...",
+        value_delimiter = ',',
+        default_value = "text"
+    )]
+    field: Vec<String>,
+
+    /// Also report stats per value of this field; dotted paths reach into objects,
+    /// e.g. `meta.trait` for virtue shards decoded with `pb_to_jsonl`.
+    #[arg(long, value_name = "FIELD")]
+    group_by: Option<String>,
+
+    /// Group whole files instead, by the first capture group of this regex in the file
+    /// name (the whole match without one): `([a-z]+)-` puts `justice-test.jsonl` in
+    /// `justice`. Files it doesn't match go to `ungrouped`.
+    #[arg(long, value_name = "REGEX", conflicts_with_all = ["group_by", "group_by_subset"])]
+    group_by_regex: Option<Regex>,
+
+    /// Group whole files by the subset in their name, as the converter infers it
+    /// (`cm_train.jsonl` is `commonsense`); other files go to `ungrouped`.
+    #[arg(long, conflicts_with = "group_by")]
+    group_by_subset: bool,
+
+    /// Longest line read, in bytes; longer lines are skipped with a warning.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_LINE_BYTES)]
+    max_line_bytes: usize,
+
+    /// An earlier report (TOML or JSON) to compare with: a `deltas` table gets the change
+    /// in count, min, max, mean, std and each percentile, overall and per file (matched
+    /// by name), and lists the files only in one of the two.
+    #[arg(long, value_name = "REPORT")]
+    compare: Option<PathBuf>,
+
+    /// Exit non-zero, after writing the report, when more than this fraction of the
+    /// records of any file, or of all of them, have no usable `--field`, e.g. `0.05`.
+    #[arg(long, value_name = "RATE")]
+    fail_on_missing_rate: Option<f64>,
+
+    /// Quantiles to report, in (0, 1) and increasing; each is written as `p25`, `p90`, ...
+    /// in every section. Default: 0.25,0.5,0.75.
+    #[arg(long, value_name = "Q,...", value_parser = parse_percentiles)]
+    percentiles: Option<Quantiles>,
+
+    /// Unit text lengths are counted in. Several, e.g. `bytes,chars,words`, give each
+    /// section one table per unit (`[overall.bytes]`, `[overall.chars]`, ...).
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "bytes")]
+    unit: Vec<LengthUnit>,
+
+    /// Also count tokens under this Hugging Face `tokenizer.json` (the `tokenizer`
+    /// feature), as a `tokens` unit next to `--unit`; `--unit tokens` reports tokens only.
+    #[arg(long, value_name = "TOKENIZER_JSON")]
+    tokenizer: Option<PathBuf>,
+
+    /// Also count lengths into buckets with these lower edges, e.g. `0,100,250,500,1000`
+    /// (the last bucket is open-ended), as a `histogram` table in every section.
+    #[arg(long, value_name = "EDGE,...", value_parser = parse_histogram_edges, conflicts_with = "histogram_auto")]
+    histogram_buckets: Option<HistogramEdges>,
+
+    /// Like `--histogram-buckets`, with N equal-width buckets from each section's
+    /// shortest length to its longest.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    histogram_auto: Option<u32>,
+
+    /// Print the overall histogram as a bar chart.
+    #[arg(long)]
+    histogram_chart: bool,
+
+    /// Keep the N longest and N shortest records of every file, by the first `--unit`,
+    /// as an `extremes` table with each one's length, line and a text preview.
+    #[arg(long, value_name = "N")]
+    show_extremes: Option<usize>,
+
+    /// Also print `--show-extremes` records.
+    #[arg(long, requires = "show_extremes")]
+    print_extremes: bool,
+
+    /// Count texts that repeat an earlier one, per file and across all files, as
+    /// `ethics-pipeline --dedup` compares them; also lists the most repeated texts and
+    /// the files that share texts. Only 128-bit hashes are kept in memory.
+    #[arg(long)]
+    detect_duplicates: bool,
+
+    /// Most repeated texts listed with `--detect-duplicates`.
+    #[arg(long, value_name = "K", default_value_t = 10)]
+    duplicates_top: usize,
+
+    /// Warn when `--detect-duplicates` holds more distinct texts than this (about 64
+    /// bytes each).
+    #[arg(long, value_name = "N", default_value_t = 20_000_000)]
+    duplicates_max_entries: usize,
+
+    /// Count every top-level key of the records, per file and overall, as a `fields`
+    /// array: how many records have it, its most common JSON type and, for strings,
+    /// its mean length in bytes.
+    #[arg(long)]
+    profile_fields: bool,
+
+    /// Most keys in each `--profile-fields` array, by coverage.
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    profile_fields_max: usize,
+
+    /// Files read concurrently (default: number of cores). The report is the same for
+    /// any number: per-file stats are exact either way, and the overall and group stats
+    /// are merged in file order.
+    #[arg(long, short = 'j', value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Measure only a sample: each line independently with probability F, e.g. 0.05.
+    /// Every figure then describes the sample, and the report's `[sample]` table says so.
+    #[arg(long, value_name = "F", conflicts_with = "sample_max")]
+    sample_rate: Option<f64>,
+
+    /// Measure only a uniform sample of at most N lines per file (reservoir sampling);
+    /// the sampled lines are held in memory until the file is read.
+    #[arg(long, value_name = "N")]
+    sample_max: Option<NonZeroUsize>,
+
+    /// The same seed draws the same sample from the same inputs, whatever `--jobs`.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
-fn lengths_from_jsonl(path: &Path) -> Result<Vec<TextLen>> {
-    let file = File::open(path)
-        .with_context(|| format!("failed to open JSONL file {}", path.display()))?;
-    let reader = BufReader::new(file);
+/// How the report is written.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    /// The full report.
+    Toml,
+    /// The full report, for dashboards.
+    Json,
+    /// One row per file and unit, then `__overall__`: count, min, max, mean, std and
+    /// each percentile. Labels, histograms, extremes, duplicates, field profiles, deltas
+    /// and groups are left out.
+    Csv,
+}
 
-    let mut out = Vec::new();
+impl ReportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Toml => "toml",
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+        }
+    }
 
-    for line_result in reader.lines() {
-        let line = line_result
-            .with_context(|| format!("error reading line from {}", path.display()))?;
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(ReportFormat::Toml),
+            "json" => Some(ReportFormat::Json),
+            "csv" => Some(ReportFormat::Csv),
+            _ => None,
         }
+    }
+}
 
-        let obj: Value = match serde_json::from_str(trimmed) {
-            Ok(v) => v,
-            Err(_) => {
-                continue;
-            }
-        };
+/// Row key of the overall stats in CSV reports.
+const OVERALL_ROW: &str = "__overall__";
 
-        if let Some(text) = obj.get("text").and_then(|v| v.as_str()) {
-            // Use byte length for efficiency; suitable proxy for token count here.
-            let len = text.len();
-            out.push(TextLen(len));
+/// `report` as CSV: `file,unit,count,min,max,mean,std,p..` with a column per
+/// quantile, files in name order and `__overall__` last. Missing values are empty.
+fn report_csv(report: &Report<Section<UnitStats>>, quantiles: &[f64]) -> Result<Vec<u8>> {
+    let mut w = csv::Writer::from_writer(Vec::new());
+    let mut header: Vec<String> = ["file", "unit", "count", "min", "max", "mean", "std"]
+        .map(String::from)
+        .to_vec();
+    header.extend(quantiles.iter().map(|&q| percentile_key(q)));
+    w.write_record(&header)?;
+    let cell = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let rows = report
+        .files
+        .iter()
+        .map(|(name, section)| (name.as_str(), section))
+        .chain([(OVERALL_ROW, &report.overall)]);
+    for (name, section) in rows {
+        for (unit, stats) in &section.stats.0 {
+            let mut row = vec![
+                name.to_string(),
+                unit.name().to_string(),
+                stats.count.to_string(),
+            ];
+            row.extend([stats.min, stats.max, stats.mean, stats.std].map(cell));
+            row.extend(stats.percentiles.iter().map(|&(_, v)| cell(v)));
+            w.write_record(&row)?;
         }
     }
+    Ok(w.into_inner().map_err(|e| e.into_error())?)
+}
 
-    Ok(out)
+/// An earlier report for `--compare`, TOML or (by extension) JSON, as a JSON value.
+fn read_report(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read report {}", path.display()))?;
+    let parsed = match ReportFormat::from_extension(path) {
+        Some(ReportFormat::Json) => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        Some(ReportFormat::Csv) => bail!(
+            "--compare needs a TOML or JSON report, not {}",
+            path.display()
+        ),
+        Some(ReportFormat::Toml) | None => toml::from_str(&text).map_err(anyhow::Error::from),
+    };
+    parsed.with_context(|| format!("{} is not a stats report", path.display()))
 }
 
-fn percentile(sorted_vals: &[TextLen], q: f64) -> Option<f64> {
-    if sorted_vals.is_empty() {
+/// Stands in for the tokenizer when built without the `tokenizer` feature; it can't
+/// be loaded, so it is never constructed.
+#[cfg(not(feature = "tokenizer"))]
+enum TokenCounter {}
+
+#[cfg(not(feature = "tokenizer"))]
+impl TokenCounter {
+    fn from_file(path: &Path) -> Result<Self> {
+        bail!(
+            "--tokenizer {} needs the `tokenizer` feature (cargo run --features tokenizer ...)",
+            path.display()
+        )
+    }
+
+    fn path(&self) -> &str {
+        match *self {}
+    }
+
+    fn sha256(&self) -> &str {
+        match *self {}
+    }
+
+    fn count(&self, _texts: &[&str]) -> Vec<Option<usize>> {
+        match *self {}
+    }
+}
+
+/// Records per label, `missing` for those without one.
+type Labels = BTreeMap<String, usize>;
+
+/// Texts are tokenized this many at a time, spread over the tokenizer's threads.
+const TOKENIZE_BATCH: usize = 1024;
+
+/// Records without the `--group-by` field are grouped under this key.
+const UNGROUPED: &str = "(none)";
+
+/// Files that `--group-by-regex` / `--group-by-subset` don't place are grouped under this key.
+const UNGROUPED_FILE: &str = "ungrouped";
+
+/// The group of every record in `path` under `--group-by-regex` / `--group-by-subset`,
+/// `None` when grouping by record field or not at all.
+fn file_group(path: &Path, args: &Args) -> Option<String> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let group = if let Some(regex) = &args.group_by_regex {
+        regex.captures(&name).map(|c| {
+            c.get(1)
+                .or_else(|| c.get(0))
+                .map_or("", |m| m.as_str())
+                .to_string()
+        })
+    } else if args.group_by_subset {
+        infer_subset_split(path).map(|(subset, _)| subset)
+    } else {
         return None;
+    };
+    Some(group.unwrap_or_else(|| UNGROUPED_FILE.to_string()))
+}
+
+/// Looks up a dotted `path` in `obj`, rendering non-string values as JSON.
+fn group_key(obj: &Value, path: &str) -> String {
+    let value = path.split('.').try_fold(obj, |v, key| v.get(key));
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => UNGROUPED.to_string(),
+        Some(other) => other.to_string(),
     }
-    let n = sorted_vals.len();
-    if n == 1 {
-        return Some(sorted_vals[0].0 as f64);
+}
+
+/// The `label_counts` key of a record: its label as the converter reads it (`"1"` for
+/// `1`, `1.0`, `true` or `"1"`), the value itself when it isn't one, or `missing`.
+fn label_key(obj: &Value) -> String {
+    let value = match obj.get("label") {
+        None | Some(Value::Null) => return MISSING_LABEL.to_string(),
+        Some(value) => value,
+    };
+    let resolved = RawLabel::deserialize(value)
+        .ok()
+        .and_then(|raw| raw.resolve(&LabelMap::default()));
+    match (resolved, value) {
+        (Some(label), _) => label.to_string(),
+        (None, Value::String(s)) => s.clone(),
+        (None, other) => other.to_string(),
     }
+}
 
-    let idx = q * (n as f64 - 1.0);
-    let lo = idx.floor() as usize;
-    let hi = (lo + 1).min(n - 1);
-    let frac = idx - lo as f64;
+/// What a record is counted under besides its lengths.
+struct Keys {
+    /// `--group-by` value, empty without one.
+    group: String,
+    label: String,
+}
 
-    let lo_val = sorted_vals[lo].0 as f64;
-    let hi_val = sorted_vals[hi].0 as f64;
+/// Lengths measured in one file: one column per `--unit`, in order, and each record's
+/// keys.
+struct FileLengths {
+    by_unit: Vec<Vec<TextLen>>,
+    keys: Vec<Keys>,
+    /// Records the tokenizer failed on, left out of every column.
+    encode_errors: usize,
+    /// Records per `--field` name their value was taken from.
+    by_field: BTreeMap<String, usize>,
+    /// What the lines were; `no_text` counts records with none of the `--field` names
+    /// usable.
+    lines: LineCounts,
+    /// `--show-extremes`, by the first unit.
+    extremes: Option<ExtremesBuilder>,
+    /// `--detect-duplicates`, over the texts (not numbers) measured.
+    texts: Option<FileTexts>,
+    /// `--profile-fields`, over every record.
+    profile: Option<FieldProfiler>,
+    /// Lines read, sampled or not; `lines` counts the sampled ones.
+    lines_read: usize,
+}
 
-    Some(lo_val * (1.0 - frac) + hi_val * frac)
+impl FileLengths {
+    /// Adds the text on `line` in every unit; `tokens` is its count for the `tokens` unit.
+    fn push_text(
+        &mut self,
+        units: &[LengthUnit],
+        line: usize,
+        text: &str,
+        tokens: Option<usize>,
+        keys: Keys,
+    ) {
+        for (col, unit) in self.by_unit.iter_mut().zip(units) {
+            let len = unit
+                .measure(text)
+                .unwrap_or_else(|| TextLen(tokens.unwrap_or_default() as f64));
+            col.push(len);
+        }
+        if let (Some(extremes), Some(&len)) = (
+            &mut self.extremes,
+            self.by_unit.first().and_then(|col| col.last()),
+        ) {
+            extremes.push(len, line, text);
+        }
+        self.keys.push(keys);
+    }
+
+    /// Tokenizes `batch` and adds its texts; failures are only counted.
+    fn push_batch(
+        &mut self,
+        units: &[LengthUnit],
+        counter: &TokenCounter,
+        batch: &mut Vec<(usize, String, Keys)>,
+    ) {
+        if batch.is_empty() {
+            return;
+        }
+        let counts = counter.count(
+            &batch
+                .iter()
+                .map(|(_, text, _)| text.as_str())
+                .collect::<Vec<_>>(),
+        );
+        for ((line, text, keys), count) in batch.drain(..).zip(counts) {
+            match count {
+                Some(n) => self.push_text(units, line, &text, Some(n), keys),
+                None => self.encode_errors += 1,
+            }
+        }
+    }
 }
 
-fn summarize_per_file(vals: &[TextLen]) -> Stats {
-    if vals.is_empty() {
-        return Stats {
-            count: 0,
-            min: None,
-            max: None,
-            mean: None,
-            std: None,
-            p25: None,
-            p50: None,
-            p75: None,
-        };
+/// How a file is read, detected from its first bytes and its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileFormat {
+    Jsonl,
+    JsonlGz,
+    JsonlZst,
+    /// A shard of `Example`s, as written by the converter.
+    Pb,
+    PbZst,
+}
+
+impl FileFormat {
+    /// gzip and zstd are told apart by their magic bytes, whatever the extension. A
+    /// shard is a file starting with the shard magic, or named `*.pb` (once `.zst` is
+    /// dropped); anything else is JSONL.
+    fn detect(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut head = Vec::with_capacity(SHARD_MAGIC.len());
+        file.take(SHARD_MAGIC.len() as u64)
+            .read_to_end(&mut head)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let named_shard = decompressed_name(path).ends_with(".pb");
+        Ok(if head.starts_with(&GZIP_MAGIC) {
+            FileFormat::JsonlGz
+        } else if head.starts_with(&ZSTD_MAGIC) {
+            if named_shard {
+                FileFormat::PbZst
+            } else {
+                FileFormat::JsonlZst
+            }
+        } else if head == SHARD_MAGIC || named_shard {
+            FileFormat::Pb
+        } else {
+            FileFormat::Jsonl
+        })
     }
 
-    let mut s = vals.to_vec();
-    s.sort_unstable_by_key(|x| x.0);
+    fn name(self) -> &'static str {
+        match self {
+            FileFormat::Jsonl => "jsonl",
+            FileFormat::JsonlGz => "jsonl.gz",
+            FileFormat::JsonlZst => "jsonl.zst",
+            FileFormat::Pb => "pb",
+            FileFormat::PbZst => "pb.zst",
+        }
+    }
 
-    let n = s.len();
-    let sum: f64 = s.iter().map(|&x| x.0 as f64).sum();
-    let mean = sum / n as f64;
+    fn is_shard(self) -> bool {
+        matches!(self, FileFormat::Pb | FileFormat::PbZst)
+    }
+}
+
+/// A line of input: a record, with its line number, or what it was instead.
+enum Line {
+    Record(usize, Value),
+    /// A JSONL line, parsed only if it is sampled.
+    Unparsed(usize, String),
+    Empty,
+    TooLong,
+}
 
-    let var = if n > 1 {
-        let mut acc = 0.0;
-        for &x in &s {
-            let dx = x.0 as f64 - mean;
-            acc += dx * dx;
+/// The lines of a JSONL file (plain, .gz or .zst). A BOM and CRLF endings are
+/// dropped; overlong lines are reported.
+fn jsonl_records(path: &Path, max_line_bytes: usize) -> Result<impl Iterator<Item = Result<Line>>> {
+    let reader = open_maybe_compressed(path)
+        .with_context(|| format!("failed to open JSONL file {}", path.display()))?;
+    let path = path.to_path_buf();
+    Ok(lines(reader, max_line_bytes).map(move |line_result| {
+        let (n, line) = match line_result {
+            Ok(line) => line,
+            Err(e) => match line_too_long(&e) {
+                Some(long) => {
+                    warn!("{}: {long}; skipped", path.display());
+                    return Ok(Line::TooLong);
+                }
+                None => {
+                    return Err(e)
+                        .with_context(|| format!("error reading line from {}", path.display()))
+                }
+            },
+        };
+        if line.trim().is_empty() {
+            return Ok(Line::Empty);
         }
-        acc / (n as f64 - 1.0)
-    } else {
-        0.0
-    };
+        Ok(Line::Unparsed(n, line))
+    }))
+}
+
+/// The `Example`s of a shard as records, with the same keys as JSONL input
+/// (`text`, `label`, `meta`, `context`, `score`, ...), numbered from 1.
+fn shard_records(
+    path: &Path,
+    dict: Option<&Dictionary>,
+) -> Result<impl Iterator<Item = Result<Line>>> {
+    let reader = ExampleReader::open_with_dict(path, dict)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let path = path.to_path_buf();
+    Ok(reader.enumerate().map(move |(i, ex)| {
+        let ex = ex.with_context(|| format!("failed to read {}", path.display()))?;
+        // Defaults included, so a label of 0 is counted as `0`, not `missing`.
+        Ok(Line::Record(i + 1, protojson::example_to_json(&ex, true)))
+    }))
+}
+
+/// What one file adds to the report.
+struct FileReport {
+    name: String,
+    section: Section<UnitStats>,
+    /// The file's lengths and labels, for the overall stats.
+    aggregate: Aggregate,
+    /// Its lengths and labels per group.
+    groups: BTreeMap<String, Aggregate>,
+    encode_errors: usize,
+    texts: Option<FileTexts>,
+    profile: Option<FieldProfiler>,
+}
+
+/// Lengths and labels of the records of one or more files: one aggregator per unit,
+/// for all of them and per label ([`MISSING_LABEL`] left out of `by_label`).
+struct Aggregate {
+    running: Vec<RunningStats>,
+    by_label: BTreeMap<String, Vec<RunningStats>>,
+    labels: Labels,
+}
+
+impl Aggregate {
+    /// Adds record `i` of `lens`.
+    fn push(&mut self, lens: &FileLengths, i: usize) {
+        let label = &lens.keys[i].label;
+        *self.labels.entry(label.clone()).or_insert(0) += 1;
+        for (r, col) in self.running.iter_mut().zip(&lens.by_unit) {
+            r.push(col[i]);
+        }
+        if label != MISSING_LABEL {
+            let running = self.by_label.entry(label.clone()).or_insert_with(|| {
+                lens.by_unit
+                    .iter()
+                    .map(|_| RunningStats::default())
+                    .collect()
+            });
+            for (r, col) in running.iter_mut().zip(&lens.by_unit) {
+                r.push(col[i]);
+            }
+        }
+    }
+
+    /// Adds everything in `other`, unit by unit.
+    fn merge(&mut self, other: Aggregate) {
+        merge_running(&mut self.running, other.running);
+        for (label, running) in other.by_label {
+            match self.by_label.get_mut(&label) {
+                Some(into) => merge_running(into, running),
+                None => {
+                    self.by_label.insert(label, running);
+                }
+            }
+        }
+        for (label, n) in other.labels {
+            *self.labels.entry(label).or_insert(0) += n;
+        }
+    }
+}
 
+/// `stats` with its per-label stats and their correlation with length.
+fn with_labels(stats: Stats, by_label: BTreeMap<String, Stats>) -> Stats {
     Stats {
-        count: n,
-        min: Some(s[0].0 as f64),
-        max: Some(s[n - 1].0 as f64),
-        mean: Some(mean),
-        std: Some(var.sqrt()),
-        p25: percentile(&s, 0.25),
-        p50: percentile(&s, 0.50),
-        p75: percentile(&s, 0.75),
+        label_correlation: label_correlation(&by_label),
+        by_label,
+        ..stats
     }
 }
 
+/// How every file is summarized.
+struct Summary<'a> {
+    args: &'a Args,
+    units: &'a [LengthUnit],
+    tokens: Option<&'a TokenCounter>,
+    dict: Option<&'a Dictionary>,
+    spec: Option<&'a HistogramSpec>,
+    quantiles: &'a [f64],
+    sampling: Option<Sampling>,
+}
+
+/// `--sample-rate` / `--sample-max`: which lines of each file are measured.
+#[derive(Debug, Clone, Copy)]
+enum Sampling {
+    /// Each line with this probability.
+    Rate(f64),
+    /// Up to this many lines per file, drawn uniformly.
+    Max(usize),
+}
+
+impl Summary<'_> {
+    /// One empty aggregator per unit; labels get theirs as they show up.
+    fn new_aggregate(&self) -> Aggregate {
+        Aggregate {
+            running: self
+                .units
+                .iter()
+                .map(|_| RunningStats::with_histogram(self.spec))
+                .collect(),
+            by_label: BTreeMap::new(),
+            labels: Labels::new(),
+        }
+    }
+
+    fn finalize(&self, aggregate: Aggregate) -> Section<UnitStats> {
+        let mut by_label: Vec<BTreeMap<String, Stats>> = vec![BTreeMap::new(); self.units.len()];
+        for (label, running) in aggregate.by_label {
+            for (unit, r) in by_label.iter_mut().zip(running) {
+                unit.insert(label.clone(), r.finalize(self.quantiles));
+            }
+        }
+        let stats = aggregate.running.into_iter().zip(by_label);
+        let stats = stats.map(|(r, by_label)| with_labels(r.finalize(self.quantiles), by_label));
+        Section::new(
+            UnitStats(self.units.iter().copied().zip(stats).collect()),
+            aggregate.labels,
+        )
+    }
+
+    /// The lines of `path`; shard records have the same keys as JSONL ones, so `text`
+    /// is `Example.text`.
+    fn records(
+        &self,
+        path: &Path,
+        format: FileFormat,
+    ) -> Result<Box<dyn Iterator<Item = Result<Line>>>> {
+        Ok(if format.is_shard() {
+            Box::new(shard_records(path, self.dict)?)
+        } else {
+            Box::new(jsonl_records(path, self.args.max_line_bytes)?)
+        })
+    }
+
+    /// Lengths of the first usable `--field` in each record of `path`, in each unit,
+    /// over the lines `--sample-rate` / `--sample-max` pick when given.
+    fn lengths(&self, path: &Path, format: FileFormat) -> Result<FileLengths> {
+        let args = self.args;
+        let records = self.records(path, format)?;
+
+        let mut out = FileLengths {
+            by_unit: vec![Vec::new(); self.units.len()],
+            keys: Vec::new(),
+            encode_errors: 0,
+            by_field: BTreeMap::new(),
+            lines: LineCounts::default(),
+            lines_read: 0,
+            extremes: args.show_extremes.map(ExtremesBuilder::new),
+            texts: args.detect_duplicates.then(FileTexts::default),
+            profile: args.profile_fields.then(FieldProfiler::default),
+        };
+        // Texts waiting to be tokenized, with their lines and keys.
+        let mut batch: Vec<(usize, String, Keys)> = Vec::new();
+
+        // Seeded by file name too, so a file's sample doesn't depend on the others.
+        let name_hash = blake3::hash(file_name(path).as_bytes());
+        let file_seed = u64::from_le_bytes(
+            name_hash.as_bytes()[..8]
+                .try_into()
+                .expect("a blake3 hash has 32 bytes"),
+        );
+        let mut rng = StdRng::seed_from_u64(args.seed ^ file_seed);
+        let mut reservoir = match self.sampling {
+            Some(Sampling::Max(n)) => Some(Reservoir::new(n)),
+            _ => None,
+        };
+        for record in records {
+            let line = record?;
+            out.lines_read += 1;
+            match (self.sampling, &mut reservoir) {
+                (_, Some(reservoir)) => reservoir.push(&mut rng, line),
+                (Some(Sampling::Rate(p)), None) if !rng.random_bool(p) => {}
+                _ => self.push_line(&mut out, &mut batch, line),
+            }
+        }
+        // Lines left out of the sample were never parsed; the kept ones are counted
+        // in file order.
+        for (_, line) in reservoir.map(Reservoir::into_sorted).unwrap_or_default() {
+            self.push_line(&mut out, &mut batch, line);
+        }
+        if let Some(counter) = self.tokens {
+            out.push_batch(self.units, counter, &mut batch);
+        }
+        if out.encode_errors > 0 {
+            warn!(
+                "{}: the tokenizer failed on {} record(s); they are left out of the stats",
+                path.display(),
+                out.encode_errors
+            );
+        }
+
+        Ok(out)
+    }
+
+    /// Counts `line` into `out`; texts to tokenize wait in `batch`.
+    fn push_line(&self, out: &mut FileLengths, batch: &mut Vec<(usize, String, Keys)>, line: Line) {
+        let args = self.args;
+        out.lines.total += 1;
+        let (line, mut obj) = match line {
+            Line::Record(line, obj) => (line, obj),
+            Line::Unparsed(line, text) => match serde_json::from_str(&text) {
+                Ok(obj) => (line, obj),
+                Err(_) => {
+                    out.lines.parse_errors += 1;
+                    return;
+                }
+            },
+            Line::Empty => {
+                out.lines.empty += 1;
+                return;
+            }
+            Line::TooLong => {
+                out.lines.too_long += 1;
+                return;
+            }
+        };
+        if let Some(profile) = &mut out.profile {
+            profile.push(&obj);
+        }
+        let keys = Keys {
+            group: args
+                .group_by
+                .as_deref()
+                .map(|g| group_key(&obj, g))
+                .unwrap_or_default(),
+            label: label_key(&obj),
+        };
+        let Some(field) = usable_field(&obj, &args.field) else {
+            if args
+                .field
+                .iter()
+                .any(|field| obj.get(field.as_str()).is_some_and(Value::is_string))
+            {
+                out.lines.empty_text += 1;
+            } else {
+                out.lines.missing_text += 1;
+            }
+            return;
+        };
+        *out.by_field.entry(field.clone()).or_insert(0) += 1;
+        match obj.get_mut(field.as_str()).map(Value::take) {
+            Some(Value::String(text)) => {
+                if let Some(texts) = &mut out.texts {
+                    texts.push(&text, line);
+                }
+                match self.tokens {
+                    Some(counter) => {
+                        batch.push((line, text, keys));
+                        if batch.len() >= TOKENIZE_BATCH {
+                            out.push_batch(self.units, counter, batch);
+                        }
+                    }
+                    None => out.push_text(self.units, line, &text, None, keys),
+                }
+            }
+            // Numeric fields (`score`) are summarized by value, whatever the unit.
+            Some(Value::Number(n)) => {
+                if let Some(x) = n.as_f64() {
+                    out.by_unit.iter_mut().for_each(|col| col.push(TextLen(x)));
+                    if let Some(extremes) = &mut out.extremes {
+                        extremes.push(TextLen(x), line, &n.to_string());
+                    }
+                    out.keys.push(keys);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads `path` and summarizes it: its own section, exact, plus aggregators for the
+    /// overall and group stats.
+    fn file(&self, path: &Path) -> Result<FileReport> {
+        let args = self.args;
+        let format = FileFormat::detect(path)?;
+        info!("Processing {} ({})", path.display(), format.name());
+        let lens = self.lengths(path, format)?;
+        // Each length is pushed once more, into the file's aggregators; they are merged
+        // into the overall stats, in file order. Every file falls in one group, so the
+        // groups add up to the overall stats.
+        let mut aggregate = self.new_aggregate();
+        let mut groups: BTreeMap<String, Aggregate> = BTreeMap::new();
+        let file_group = file_group(path, args);
+        let mut label_rows: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (i, keys) in lens.keys.iter().enumerate() {
+            aggregate.push(&lens, i);
+            if args.group_by.is_some() || file_group.is_some() {
+                let group = file_group.as_ref().unwrap_or(&keys.group);
+                groups
+                    .entry(group.clone())
+                    .or_insert_with(|| self.new_aggregate())
+                    .push(&lens, i);
+            }
+            if keys.label != MISSING_LABEL {
+                label_rows.entry(keys.label.as_str()).or_default().push(i);
+            }
+        }
+        let summarize = |col: &Vec<TextLen>| {
+            let by_label = label_rows
+                .iter()
+                .map(|(&label, rows)| {
+                    let vals: Vec<TextLen> = rows.iter().map(|&i| col[i]).collect();
+                    (label.to_string(), summarize_per_file(&vals, self.quantiles))
+                })
+                .collect();
+            let stats = Stats {
+                histogram: self.spec.and_then(|spec| histogram(col, spec)),
+                ..summarize_per_file(col, self.quantiles)
+            };
+            with_labels(stats, by_label)
+        };
+        let stats = UnitStats(
+            self.units
+                .iter()
+                .copied()
+                .zip(lens.by_unit.iter().map(summarize))
+                .collect(),
+        );
+        let no_text = lens.lines.no_text();
+        if no_text > 0 {
+            warn!(
+                "{}: {} record(s) have no usable --field ({}): {} missing, {} empty",
+                path.display(),
+                no_text,
+                args.field.join(","),
+                lens.lines.missing_text,
+                lens.lines.empty_text
+            );
+        }
+        let section = Section {
+            format: Some(format.name()),
+            no_text: Some(no_text),
+            line_counts: Some(lens.lines),
+            field_counts: lens.by_field,
+            extremes: lens.extremes.map(ExtremesBuilder::finish),
+            duplicates: lens.texts.as_ref().map(FileTexts::counts),
+            fields: lens
+                .profile
+                .as_ref()
+                .map(|profile| profile.profiles(args.profile_fields_max)),
+            sample: self.sampling.map(|_| SampleCounts {
+                lines_read: lens.lines_read,
+                lines_kept: lens.lines.total,
+            }),
+            ..Section::new(stats, aggregate.labels.clone())
+        };
+
+        Ok(FileReport {
+            name: file_name(path),
+            section,
+            aggregate,
+            groups,
+            encode_errors: lens.encode_errors,
+            texts: lens.texts,
+            profile: lens.profile,
+        })
+    }
+
+    /// The `--field` texts on `lines` of `path`, read again; reading stops after the
+    /// last of them.
+    fn texts_at(&self, path: &Path, lines: &BTreeSet<usize>) -> Result<Vec<(usize, String)>> {
+        let last = lines.last().copied().unwrap_or_default();
+        let mut out = Vec::new();
+        for record in self.records(path, FileFormat::detect(path)?)? {
+            let (line, obj) = match record? {
+                Line::Record(line, obj) => (line, obj),
+                Line::Unparsed(line, text) if lines.contains(&line) => {
+                    match serde_json::from_str(&text) {
+                        Ok(obj) => (line, obj),
+                        Err(_) => continue,
+                    }
+                }
+                Line::Unparsed(line, _) if line > last => break,
+                _ => continue,
+            };
+            if line > last {
+                break;
+            }
+            if !lines.contains(&line) {
+                continue;
+            }
+            if let Some(Value::String(text)) =
+                usable_field(&obj, &self.args.field).and_then(|field| obj.get(field.as_str()))
+            {
+                out.push((line, text.clone()));
+            }
+        }
+        Ok(out)
+    }
+
+    /// The `[duplicates]` table. Only hashes were kept, so the previews of the top
+    /// texts are read back from the files they first occur in.
+    fn duplicates(&self, files: &[PathBuf], run: &RunTexts) -> Result<DuplicateSummary> {
+        let top = run.top(self.args.duplicates_top);
+        let mut wanted: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for text in &top {
+            wanted.entry(text.files[0]).or_default().insert(text.line);
+        }
+        let mut previews: HashMap<(usize, usize), String> = HashMap::new();
+        for (file, lines) in wanted {
+            for (line, text) in self.texts_at(&files[file], &lines)? {
+                previews.insert((file, line), preview(&text));
+            }
+        }
+        let name = |file: usize| file_name(&files[file]);
+        Ok(DuplicateSummary {
+            top: top
+                .into_iter()
+                .map(|text| DuplicateText {
+                    hash: format!("{:032x}", text.hash),
+                    count: text.count,
+                    preview: previews
+                        .remove(&(text.files[0], text.line))
+                        .unwrap_or_default(),
+                    files: text.files.into_iter().map(name).collect(),
+                })
+                .collect(),
+            shared: run
+                .shared()
+                .into_iter()
+                .map(|((a, b), texts)| SharedTexts {
+                    files: [name(a), name(b)],
+                    texts,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Name of `path` in the report.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The first of `fields` in `obj` holding a non-blank string or a number: as
+/// `pick_text`, which only takes strings.
+fn usable_field<'f>(obj: &Value, fields: &'f [String]) -> Option<&'f String> {
+    fields.iter().find(|field| match obj.get(field.as_str()) {
+        Some(Value::String(text)) => !text.trim().is_empty(),
+        Some(Value::Number(n)) => n.as_f64().is_some(),
+        _ => false,
+    })
+}
+
+/// Adds the aggregators of `other` to `into`, unit by unit.
+fn merge_running(into: &mut [RunningStats], other: Vec<RunningStats>) {
+    for (r, other) in into.iter_mut().zip(other) {
+        r.merge(other);
+    }
+}
+
+/// Summarizes `files` on a rayon pool of `workers` threads. Results come back in file
+/// order whatever the number of workers; after a failure no more files are started.
+fn summarize_all(files: &[PathBuf], workers: usize, summary: &Summary) -> Result<Vec<FileReport>> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .context("failed to start the worker threads")?
+        .install(|| files.par_iter().map(|path| summary.file(path)).collect())
+}
+
 fn run(args: Args) -> Result<()> {
     // Find input files by glob.
     let mut files: Vec<PathBuf> = Vec::new();
     for entry in glob(&args.glob).with_context(|| format!("invalid glob: {}", args.glob))? {
         match entry {
+            // `PairExample` shards have no single text to measure.
+            Ok(path) if is_pairs_shard(&path) => {
+                warn!("Skipping PairExample shard {}", path.display())
+            }
             Ok(path) => files.push(path),
             Err(e) => warn!("glob match error: {e}"),
         }
@@ -226,55 +1025,251 @@ fn run(args: Args) -> Result<()> {
     } else {
         info!("Found {} file(s) for pattern {}", files.len(), args.glob);
     }
+    if let Some(rate) = args.fail_on_missing_rate {
+        ensure!(
+            (0.0..=1.0).contains(&rate),
+            "--fail-on-missing-rate must be in [0, 1], got {rate}"
+        );
+    }
+    let sampling = match (args.sample_rate, args.sample_max) {
+        (Some(p), _) => {
+            ensure!(
+                (0.0..=1.0).contains(&p),
+                "--sample-rate must be in [0, 1], got {p}"
+            );
+            Some(Sampling::Rate(p))
+        }
+        (None, Some(n)) => Some(Sampling::Max(n.get())),
+        (None, None) => None,
+    };
+    let csv_out = matches!(
+        args.format
+            .or_else(|| ReportFormat::from_extension(Path::new(&args.out))),
+        Some(ReportFormat::Csv)
+    );
+    if sampling.is_some() && csv_out {
+        bail!("a CSV report can't say it was sampled; write TOML or JSON with --sample-rate / --sample-max");
+    }
+    let quantiles = args
+        .percentiles
+        .as_ref()
+        .map_or(&DEFAULT_PERCENTILES[..], |q| &q.0);
+    let mut units = args.unit.clone();
+    if let Some(i) = (1..units.len()).find(|&i| units[..i].contains(&units[i])) {
+        bail!("--unit lists {} more than once", units[i].name());
+    }
+    let tokens = match &args.tokenizer {
+        Some(path) => {
+            let counter = TokenCounter::from_file(path)?;
+            info!(
+                "Counting tokens with {} (sha256 {})",
+                counter.path(),
+                counter.sha256()
+            );
+            if !units.contains(&LengthUnit::Tokens) {
+                units.push(LengthUnit::Tokens);
+            }
+            Some(counter)
+        }
+        None if units.contains(&LengthUnit::Tokens) => bail!("--unit tokens needs --tokenizer"),
+        None => None,
+    };
+    let spec = match (&args.histogram_buckets, args.histogram_auto) {
+        (Some(edges), _) => Some(HistogramSpec::Edges(edges.0.clone())),
+        (None, Some(buckets)) => Some(HistogramSpec::Auto(buckets as usize)),
+        (None, None) if args.histogram_chart => {
+            bail!("--histogram-chart needs --histogram-buckets or --histogram-auto")
+        }
+        (None, None) => None,
+    };
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let summary = Summary {
+        args: &args,
+        units: &units,
+        tokens: tokens.as_ref(),
+        dict: dict.as_ref(),
+        spec: spec.as_ref(),
+        quantiles,
+        sampling,
+    };
 
-    let mut file_stats: BTreeMap<String, Stats> = BTreeMap::new();
-    let mut overall_lengths: Vec<TextLen> = Vec::new();
-    let mut overall_running = RunningStats::default();
-
-    for path in &files {
-        info!("Processing {}", path.display());
-        let lens = lengths_from_jsonl(path)?;
-        let stats = summarize_per_file(&lens);
-
-        // Add per-file stats.
-        let fname = path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        file_stats.insert(fname, stats);
+    let workers = args.jobs.map_or_else(
+        || std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        NonZeroUsize::get,
+    );
+    let mut file_stats: BTreeMap<String, Section<UnitStats>> = BTreeMap::new();
+    let mut overall = summary.new_aggregate();
+    let mut overall_fields: BTreeMap<String, usize> = BTreeMap::new();
+    let mut overall_lines = LineCounts::default();
+    let mut grouped: BTreeMap<String, Aggregate> = BTreeMap::new();
+    let mut encode_errors = 0;
+    let mut run_texts = args.detect_duplicates.then(RunTexts::default);
+    let mut warned_entries = false;
+    let mut overall_profile = args.profile_fields.then(FieldProfiler::default);
+    let mut overall_sample = sampling.map(|_| SampleCounts::default());
 
-        // Feed lengths into overall running stats + global vector for percentiles.
-        for len in &lens {
-            overall_running.push(*len);
+    // Merged in file order, so the report doesn't depend on `--jobs`.
+    for file in summarize_all(&files, workers, &summary)? {
+        encode_errors += file.encode_errors;
+        if let (Some(run), Some(texts)) = (&mut run_texts, file.texts) {
+            run.add_file(texts);
+            if run.len() > args.duplicates_max_entries && !warned_entries {
+                warn!(
+                    "--detect-duplicates holds over {} distinct texts (--duplicates-max-entries), about 64 bytes each",
+                    args.duplicates_max_entries
+                );
+                warned_entries = true;
+            }
+        }
+        for (group, contribution) in file.groups {
+            grouped
+                .entry(group)
+                .or_insert_with(|| summary.new_aggregate())
+                .merge(contribution);
+        }
+        for (field, n) in &file.section.field_counts {
+            *overall_fields.entry(field.clone()).or_insert(0) += n;
+        }
+        if let Some(lines) = &file.section.line_counts {
+            overall_lines.add(lines);
+        }
+        if let (Some(all), Some(profile)) = (&mut overall_profile, file.profile) {
+            all.merge(profile);
+        }
+        if let (Some(all), Some(sample)) = (&mut overall_sample, &file.section.sample) {
+            all.add(sample);
         }
-        overall_lengths.extend(lens);
+        overall.merge(file.aggregate);
+        file_stats.insert(file.name, file.section);
     }
 
-    // Compute overall percentiles once, from sorted global lengths.
-    overall_lengths.sort_unstable_by_key(|x| x.0);
-    let p25 = percentile(&overall_lengths, 0.25);
-    let p50 = percentile(&overall_lengths, 0.50);
-    let p75 = percentile(&overall_lengths, 0.75);
-
-    let overall = overall_running.finalize(p25, p50, p75);
+    // Overall and group percentiles are estimated from sketches, see `SKETCH_RELATIVE_ACCURACY`.
+    let overall = Section {
+        no_text: Some(overall_lines.no_text()),
+        line_counts: Some(overall_lines),
+        field_counts: overall_fields,
+        duplicates: run_texts.as_ref().map(RunTexts::counts),
+        fields: overall_profile.map(|profile| profile.profiles(args.profile_fields_max)),
+        sample: overall_sample,
+        ..summary.finalize(overall)
+    };
+    let duplicates = run_texts
+        .map(|run| summary.duplicates(&files, &run))
+        .transpose()?;
 
     // Build and write report.
-    let report = Report {
+    let mut report = Report {
         overall,
         files: file_stats,
+        groups: grouped
+            .into_iter()
+            .map(|(group, section)| (group, summary.finalize(section)))
+            .collect(),
+        label_counts: BTreeMap::new(),
+        meta_coverage: BTreeMap::new(),
+        tokenizer: tokens.as_ref().map(|counter| TokenizerInfo {
+            path: counter.path().to_string(),
+            sha256: counter.sha256().to_string(),
+            encode_errors,
+        }),
+        duplicates,
+        deltas: None,
+        sample: sampling
+            .zip(overall_sample)
+            .map(|(sampling, counts)| SampleInfo {
+                method: match sampling {
+                    Sampling::Rate(_) => "bernoulli",
+                    Sampling::Max(_) => "reservoir",
+                },
+                rate: args.sample_rate,
+                max_per_file: args.sample_max.map(NonZeroUsize::get),
+                seed: args.seed,
+                lines_read: counts.lines_read,
+                lines_kept: counts.lines_kept,
+                exact: false,
+            }),
     };
+    if let Some(sample) = &report.sample {
+        warn!(
+            "Stats are from a sample of {} of {} line(s) ({}, seed {}); percentiles, mean and std are estimates",
+            sample.lines_kept, sample.lines_read, sample.method, sample.seed
+        );
+    }
+    if let Some(path) = &args.compare {
+        let before = read_report(path)?;
+        let after =
+            serde_json::to_value(&report).context("failed to serialize statistics report")?;
+        let deltas = compare_reports(&path.display().to_string(), &before, &after);
+        if !deltas.skipped.is_empty() {
+            warn!(
+                "Not compared with {}, as only one report has them: {}",
+                path.display(),
+                deltas.skipped.join(", ")
+            );
+        }
+        info!(
+            "Compared with {}: {} file(s) in both, {} added, {} removed",
+            path.display(),
+            deltas.files.len(),
+            deltas.added.len(),
+            deltas.removed.len()
+        );
+        report.deltas = Some(deltas);
+    }
 
-    let out_path = PathBuf::from(&args.out);
+    let mut out_path = PathBuf::from(&args.out);
+    let format = args
+        .format
+        .or_else(|| ReportFormat::from_extension(&out_path))
+        .unwrap_or(ReportFormat::Toml);
+    if out_path.extension().is_none() {
+        out_path.set_extension(format.extension());
+    }
     if let Some(parent) = out_path.parent() {
         std::fs::create_dir_all(parent)
             .with_context(|| format!("failed to create parent dir {}", parent.display()))?;
     }
 
-    let toml_str = toml::to_string_pretty(&report)
-        .context("failed to serialize statistics report to TOML")?;
-    std::fs::write(&out_path, toml_str)
-        .with_context(|| format!("failed to write TOML report to {}", out_path.display()))?;
+    if args.print_extremes {
+        let unit = units[0].name();
+        for (name, section) in &report.files {
+            let Some(extremes) = &section.extremes else {
+                continue;
+            };
+            for (end, records) in [
+                ("longest", &extremes.longest),
+                ("shortest", &extremes.shortest),
+            ] {
+                println!("{name}, {end} ({unit}):");
+                for r in records {
+                    println!("{:>10}  line {:<8} {}", r.length, r.line, r.preview);
+                }
+            }
+        }
+    }
+
+    if args.histogram_chart {
+        for (unit, stats) in &report.overall.stats.0 {
+            if let Some(histogram) = &stats.histogram {
+                println!("overall, {}:", unit.name());
+                print!("{}", histogram.chart(50));
+            }
+        }
+    }
+
+    let bytes = match format {
+        ReportFormat::Toml => toml::to_string_pretty(&report)
+            .context("failed to serialize statistics report to TOML")?
+            .into_bytes(),
+        // Non-finite floats (the open `inf` edge of a histogram) come out as `null`.
+        ReportFormat::Json => serde_json::to_vec_pretty(&report)
+            .context("failed to serialize statistics report to JSON")?,
+        ReportFormat::Csv => {
+            report_csv(&report, quantiles).context("failed to write statistics report as CSV")?
+        }
+    };
+    std::fs::write(&out_path, bytes)
+        .with_context(|| format!("failed to write report to {}", out_path.display()))?;
 
     info!(
         "Wrote {} with stats for {} file(s).",
@@ -282,11 +1277,31 @@ fn run(args: Args) -> Result<()> {
         report.files.len()
     );
 
+    if let Some(max) = args.fail_on_missing_rate {
+        let sections = report
+            .files
+            .iter()
+            .map(|(name, section)| (name.as_str(), section))
+            .chain([("overall", &report.overall)]);
+        let over: Vec<String> = sections
+            .filter_map(|(name, section)| {
+                let rate = section.line_counts?.missing_rate()?;
+                (rate > max).then(|| format!("{name} ({:.2}%)", rate * 100.0))
+            })
+            .collect();
+        if !over.is_empty() {
+            bail!(
+                "records without text exceed --fail-on-missing-rate {max} in: {}",
+                over.join(", ")
+            );
+        }
+    }
+
     Ok(())
 }
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
     let args = Args::parse();
+    args.log.init()?;
     run(args)
 }
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use glob::glob;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::input::is_pairs_shard;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::stats::{
+    summarize_per_file, Report, RunningStats, Stats, TextLen, DEFAULT_PERCENTILES,
+};
+use tracing::{info, warn};
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "calculate-shard-stats",
+    about = "Compute text-length statistics, label counts and meta-key coverage from .pb.zst shards."
+)]
+struct Args {
+    #[arg(
+        long,
+        default_value = "data/processed/**/*.pb.zst",
+        value_name = "GLOB"
+    )]
+    glob: String,
+
+    #[arg(
+        long,
+        default_value = "data/stats/shard_stats.toml",
+        value_name = "OUT"
+    )]
+    out: String,
+
+    /// zstd dictionary the shards were compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+
+    // Find shards by glob. `PairExample` shards have no single text to measure.
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in glob(&args.glob).with_context(|| format!("invalid glob: {}", args.glob))? {
+        match entry {
+            Ok(path) if is_pairs_shard(&path) => {
+                warn!("Skipping PairExample shard {}", path.display())
+            }
+            Ok(path) => files.push(path),
+            Err(e) => warn!("glob match error: {e}"),
+        }
+    }
+
+    if files.is_empty() {
+        warn!("No shards matched pattern: {}", args.glob);
+    } else {
+        info!("Found {} shard(s) for pattern {}", files.len(), args.glob);
+    }
+
+    let mut file_stats: BTreeMap<String, Stats> = BTreeMap::new();
+    let mut overall_running = RunningStats::default();
+    let mut grouped: BTreeMap<String, RunningStats> = BTreeMap::new();
+    let mut label_counts: BTreeMap<i32, usize> = BTreeMap::new();
+    let mut meta_keys: BTreeMap<String, usize> = BTreeMap::new();
+
+    for path in &files {
+        info!("Processing {}", path.display());
+        let reader = ExampleReader::open_with_dict(path, dict.as_ref())
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut lens = Vec::new();
+        for ex in reader {
+            let ex = ex.with_context(|| format!("failed to read {}", path.display()))?;
+            // Byte length, as for JSONL.
+            let len = TextLen(ex.text.len() as f64);
+            lens.push(len);
+            grouped
+                .entry(format!("{}/{}", ex.subset, ex.split))
+                .or_default()
+                .push(len);
+            *label_counts.entry(ex.label).or_insert(0) += 1;
+            for key in ex.meta.keys() {
+                *meta_keys.entry(key.clone()).or_insert(0) += 1;
+            }
+        }
+
+        // Shards of different subsets often share a file name; key them by path.
+        file_stats.insert(
+            path.display().to_string(),
+            summarize_per_file(&lens, &DEFAULT_PERCENTILES),
+        );
+
+        for len in &lens {
+            overall_running.push(*len);
+        }
+    }
+
+    // Overall and group percentiles are estimated from sketches, see `SKETCH_RELATIVE_ACCURACY`.
+    let total = overall_running.count() as f64;
+    let report = Report {
+        overall: overall_running.finalize(&DEFAULT_PERCENTILES),
+        files: file_stats,
+        groups: grouped
+            .into_iter()
+            .map(|(group, running)| (group, running.finalize(&DEFAULT_PERCENTILES)))
+            .collect(),
+        // TOML keys are strings.
+        label_counts: label_counts
+            .into_iter()
+            .map(|(label, n)| (label.to_string(), n))
+            .collect(),
+        meta_coverage: meta_keys
+            .into_iter()
+            .map(|(key, n)| (key, 100.0 * n as f64 / total))
+            .collect(),
+        tokenizer: None,
+        duplicates: None,
+        deltas: None,
+        sample: None,
+    };
+
+    let out_path = PathBuf::from(&args.out);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create parent dir {}", parent.display()))?;
+    }
+
+    let toml_str =
+        toml::to_string_pretty(&report).context("failed to serialize statistics report to TOML")?;
+    std::fs::write(&out_path, toml_str)
+        .with_context(|| format!("failed to write TOML report to {}", out_path.display()))?;
+
+    info!(
+        "Wrote {} with stats for {} shard(s).",
+        out_path.display(),
+        report.files.len()
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::input::is_stdio;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "diff-shards",
+    about = "Compare two shards of ethics.v1.Example messages record by record."
+)]
+struct Args {
+    /// The shard before the change.
+    #[arg(value_name = "OLD")]
+    old: PathBuf,
+
+    /// The shard after the change.
+    #[arg(value_name = "NEW")]
+    new: PathBuf,
+
+    /// How records are paired up; `auto` uses `id` when the first record of each shard
+    /// has one (and `index` for stdin, which can't be read twice).
+    #[arg(long, value_enum, default_value_t = Align::Auto)]
+    align: Align,
+
+    /// Print at most N differences; the summary still counts all of them.
+    #[arg(long, value_name = "N")]
+    max_diffs: Option<usize>,
+
+    /// Print one JSON object per difference, then a summary object.
+    #[arg(long)]
+    json: bool,
+
+    /// zstd dictionary the shards were compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Auto,
+    /// The Nth record of one shard against the Nth of the other.
+    Index,
+    /// Records with the same `id`, wherever they are; the old shard is held in memory.
+    Id,
+}
+
+/// One field that differs between two paired examples.
+#[derive(Serialize, Debug)]
+struct FieldChange {
+    field: String,
+    old: Value,
+    new: Value,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// An example only in one shard, or in both with different contents.
+#[derive(Serialize, Debug)]
+struct Diff {
+    kind: Kind,
+    /// Position in the old shard; `None` for added examples.
+    old_index: Option<u64>,
+    /// Position in the new shard; `None` for removed examples.
+    new_index: Option<u64>,
+    id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changes: Vec<FieldChange>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Summary {
+    unchanged: u64,
+    added: u64,
+    removed: u64,
+    changed: u64,
+}
+
+/// Field-level differences between `old` and `new`, in proto field order.
+/// Meta keys are reported one by one, as `meta.<key>`.
+fn compare(old: &Example, new: &Example) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut field = |name: &str, old: Value, new: Value| {
+        if old != new {
+            changes.push(FieldChange {
+                field: name.to_string(),
+                old,
+                new,
+            });
+        }
+    };
+    field("id", json!(old.id), json!(new.id));
+    field("subset", json!(old.subset), json!(new.subset));
+    field("split", json!(old.split), json!(new.split));
+    field("text", json!(old.text), json!(new.text));
+    field("label", json!(old.label), json!(new.label));
+    field("context", json!(old.context), json!(new.context));
+    field("score", json!(old.score), json!(new.score));
+    field(
+        "labels_by_name",
+        json!(old.labels_by_name),
+        json!(new.labels_by_name),
+    );
+    let keys: BTreeSet<&String> = old.meta.keys().chain(new.meta.keys()).collect();
+    for key in keys {
+        field(
+            &format!("meta.{key}"),
+            json!(old.meta.get(key)),
+            json!(new.meta.get(key)),
+        );
+    }
+    changes
+}
+
+/// One-line description of a change for the readable output.
+fn describe(change: &FieldChange) -> String {
+    match (&change.old, &change.new) {
+        (Value::Null, _) => format!("{} added", change.field),
+        (_, Value::Null) => format!("{} removed", change.field),
+        (Value::String(old), Value::String(new)) if change.field == "text" => {
+            format!("text changed ({} -> {} bytes)", old.len(), new.len())
+        }
+        (old, new) => format!("{} {old} -> {new}", change.field),
+    }
+}
+
+/// Prints the differences as they are found, up to `--max-diffs`, and counts them all.
+struct Report<'a, W: Write> {
+    out: W,
+    args: &'a Args,
+    summary: Summary,
+    printed: usize,
+}
+
+impl<W: Write> Report<'_, W> {
+    fn same(&mut self) {
+        self.summary.unchanged += 1;
+    }
+
+    fn diff(&mut self, diff: Diff) -> Result<()> {
+        match diff.kind {
+            Kind::Added => self.summary.added += 1,
+            Kind::Removed => self.summary.removed += 1,
+            Kind::Changed => self.summary.changed += 1,
+        }
+        if self.args.max_diffs.is_some_and(|n| self.printed >= n) {
+            return Ok(());
+        }
+        self.printed += 1;
+        if self.args.json {
+            serde_json::to_writer(&mut self.out, &diff)?;
+            writeln!(self.out)?;
+            return Ok(());
+        }
+        let index = |i: Option<u64>| i.map_or("-".to_string(), |i| i.to_string());
+        let (sign, what) = match diff.kind {
+            Kind::Added => ('+', "added".to_string()),
+            Kind::Removed => ('-', "removed".to_string()),
+            Kind::Changed => (
+                '~',
+                diff.changes
+                    .iter()
+                    .map(describe)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+        };
+        writeln!(
+            self.out,
+            "{sign} old #{} new #{} id {}: {what}",
+            index(diff.old_index),
+            index(diff.new_index),
+            if diff.id.is_empty() {
+                "-"
+            } else {
+                diff.id.as_str()
+            },
+        )?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Summary> {
+        let s = &self.summary;
+        if self.args.json {
+            writeln!(self.out, "{}", json!({ "summary": s }))?;
+        } else {
+            writeln!(
+                self.out,
+                "{} unchanged, {} added, {} removed, {} changed",
+                s.unchanged, s.added, s.removed, s.changed
+            )?;
+        }
+        self.out.flush()?;
+        Ok(self.summary)
+    }
+}
+
+fn open(path: &Path, dict: Option<&Dictionary>) -> Result<ExampleReader> {
+    ExampleReader::open_with_dict(path, dict)
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Pairs the Nth example of each shard; the longer shard's tail is added or removed.
+fn diff_by_index(
+    old: ExampleReader,
+    mut new: ExampleReader,
+    report: &mut Report<impl Write>,
+) -> Result<()> {
+    let mut index = 0u64;
+    for o in old {
+        let o = o?;
+        let Some(n) = new.next().transpose()? else {
+            report.diff(Diff {
+                kind: Kind::Removed,
+                old_index: Some(index),
+                new_index: None,
+                id: o.id,
+                changes: Vec::new(),
+            })?;
+            index += 1;
+            continue;
+        };
+        let changes = compare(&o, &n);
+        if changes.is_empty() {
+            report.same();
+        } else {
+            report.diff(Diff {
+                kind: Kind::Changed,
+                old_index: Some(index),
+                new_index: Some(index),
+                id: n.id,
+                changes,
+            })?;
+        }
+        index += 1;
+    }
+    for n in new {
+        let n = n?;
+        report.diff(Diff {
+            kind: Kind::Added,
+            old_index: None,
+            new_index: Some(index),
+            id: n.id,
+            changes: Vec::new(),
+        })?;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Pairs examples by `id`, so reordered shards compare equal. Ids must be
+/// present and unique in both shards.
+fn diff_by_id(
+    old: ExampleReader,
+    new: ExampleReader,
+    (old_path, new_path): (&Path, &Path),
+    report: &mut Report<impl Write>,
+) -> Result<()> {
+    let mut by_id: HashMap<String, (u64, Example)> = HashMap::new();
+    for (i, ex) in (0u64..).zip(old) {
+        let ex = ex?;
+        if ex.id.is_empty() {
+            bail!(
+                "{}: example {i} has no id; use --align index",
+                old_path.display()
+            );
+        }
+        if let Some((first, _)) = by_id.get(&ex.id) {
+            bail!(
+                "{}: examples {first} and {i} share id {}; use --align index",
+                old_path.display(),
+                ex.id
+            );
+        }
+        by_id.insert(ex.id.clone(), (i, ex));
+    }
+    let mut seen = BTreeSet::new();
+    for (i, ex) in (0u64..).zip(new) {
+        let n = ex?;
+        if n.id.is_empty() {
+            bail!(
+                "{}: example {i} has no id; use --align index",
+                new_path.display()
+            );
+        }
+        if !seen.insert(n.id.clone()) {
+            bail!(
+                "{}: id {} occurs more than once; use --align index",
+                new_path.display(),
+                n.id
+            );
+        }
+        match by_id.remove(&n.id) {
+            None => report.diff(Diff {
+                kind: Kind::Added,
+                old_index: None,
+                new_index: Some(i),
+                id: n.id,
+                changes: Vec::new(),
+            })?,
+            Some((j, o)) => {
+                let changes = compare(&o, &n);
+                if changes.is_empty() {
+                    report.same();
+                } else {
+                    report.diff(Diff {
+                        kind: Kind::Changed,
+                        old_index: Some(j),
+                        new_index: Some(i),
+                        id: n.id,
+                        changes,
+                    })?;
+                }
+            }
+        }
+    }
+    let mut removed: Vec<(u64, Example)> = by_id.into_values().collect();
+    removed.sort_by_key(|(i, _)| *i);
+    for (i, o) in removed {
+        report.diff(Diff {
+            kind: Kind::Removed,
+            old_index: Some(i),
+            new_index: None,
+            id: o.id,
+            changes: Vec::new(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Whether the first example of the shard at `path` has an id (false when it is empty).
+fn first_has_id(path: &Path, dict: Option<&Dictionary>) -> Result<bool> {
+    Ok(open(path, dict)?
+        .next()
+        .transpose()?
+        .is_some_and(|ex| !ex.id.is_empty()))
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let dict = dict.as_ref();
+    let align = match args.align {
+        Align::Auto if is_stdio(&args.old) || is_stdio(&args.new) => Align::Index,
+        Align::Auto if first_has_id(&args.old, dict)? && first_has_id(&args.new, dict)? => {
+            Align::Id
+        }
+        Align::Auto => Align::Index,
+        other => other,
+    };
+    let (old, new) = (open(&args.old, dict)?, open(&args.new, dict)?);
+    let mut report = Report {
+        out: BufWriter::new(io::stdout().lock()),
+        args: &args,
+        summary: Summary::default(),
+        printed: 0,
+    };
+    match align {
+        Align::Id => diff_by_id(old, new, (&args.old, &args.new), &mut report)?,
+        _ => diff_by_index(old, new, &mut report)?,
+    }
+    let summary = report.finish()?;
+    // Like diff: 1 when the shards differ.
+    let differ = summary.added + summary.removed + summary.changed > 0;
+    Ok(if differ {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
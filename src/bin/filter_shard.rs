@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::input::is_stdio;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::shard;
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use regex::Regex;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "filter-shard",
+    about = "Keep the ethics.v1.Example records of a shard that match a set of predicates, streaming."
+)]
+struct Args {
+    /// Shard to filter (`-` for stdin).
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Filtered shard (`-` for stdout; the counts then go to stderr).
+    #[arg(long, value_name = "OUT")]
+    out: PathBuf,
+
+    /// Examples with this label.
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    label: Option<i32>,
+
+    /// Examples of this subset.
+    #[arg(long, value_name = "SUBSET")]
+    subset: Option<String>,
+
+    /// Examples of this split.
+    #[arg(long, value_name = "SPLIT")]
+    split: Option<String>,
+
+    /// Examples whose meta has KEY, with any value; repeat for several keys.
+    #[arg(long, value_name = "KEY")]
+    has_meta: Vec<String>,
+
+    /// Examples whose meta has KEY set to VALUE; repeat for several pairs.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_meta_filter)]
+    meta: Vec<(String, String)>,
+
+    /// Examples whose text matches this regex (`regex` crate syntax).
+    #[arg(long, value_name = "PATTERN")]
+    text_regex: Option<Regex>,
+
+    /// Examples whose text has at least this many characters.
+    #[arg(long, value_name = "CHARS")]
+    min_len: Option<usize>,
+
+    /// Examples whose text has at most this many characters.
+    #[arg(long, value_name = "CHARS")]
+    max_len: Option<usize>,
+
+    /// Keep examples matching any of the predicates, instead of all of them.
+    #[arg(long)]
+    any: bool,
+
+    /// zstd compression level of the output (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the output as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the input was compressed with; the output is compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums (kept anyway when the input has them).
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the input and list the output without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn parse_meta_filter(s: &str) -> Result<(String, String)> {
+    let (k, v) = s
+        .split_once('=')
+        .with_context(|| format!("expected KEY=VALUE, got {s:?}"))?;
+    Ok((k.to_string(), v.to_string()))
+}
+
+/// One condition from the command line, with how many examples met it.
+struct Predicate<'a> {
+    name: String,
+    test: Box<dyn Fn(&Example) -> bool + 'a>,
+    matched: u64,
+}
+
+impl<'a> Predicate<'a> {
+    fn new(name: String, test: impl Fn(&Example) -> bool + 'a) -> Self {
+        Predicate {
+            name,
+            test: Box::new(test),
+            matched: 0,
+        }
+    }
+}
+
+fn predicates(args: &Args) -> Vec<Predicate<'_>> {
+    let mut all = Vec::new();
+    if let Some(label) = args.label {
+        all.push(Predicate::new(format!("label {label}"), move |ex| {
+            ex.label == label
+        }));
+    }
+    if let Some(subset) = &args.subset {
+        all.push(Predicate::new(format!("subset {subset}"), move |ex| {
+            ex.subset == *subset
+        }));
+    }
+    if let Some(split) = &args.split {
+        all.push(Predicate::new(format!("split {split}"), move |ex| {
+            ex.split == *split
+        }));
+    }
+    for key in &args.has_meta {
+        all.push(Predicate::new(format!("has meta.{key}"), move |ex| {
+            ex.meta.contains_key(key)
+        }));
+    }
+    for (key, value) in &args.meta {
+        all.push(Predicate::new(format!("meta.{key}={value}"), move |ex| {
+            ex.meta.get(key).is_some_and(|v| v == value)
+        }));
+    }
+    if let Some(regex) = &args.text_regex {
+        all.push(Predicate::new(format!("text =~ /{regex}/"), move |ex| {
+            regex.is_match(&ex.text)
+        }));
+    }
+    if let Some(min) = args.min_len {
+        all.push(Predicate::new(format!("at least {min} chars"), move |ex| {
+            ex.text.chars().count() >= min
+        }));
+    }
+    if let Some(max) = args.max_len {
+        all.push(Predicate::new(format!("at most {max} chars"), move |ex| {
+            ex.text.chars().count() <= max
+        }));
+    }
+    all
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut predicates = predicates(&args);
+    ensure!(
+        !predicates.is_empty(),
+        "give at least one predicate (--label, --subset, --split, --has-meta, --meta, --text-regex, --min-len, --max-len)"
+    );
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        plan.read_shard(&args.input, dict.as_ref());
+        plan.write(&args.out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    let reader = ExampleReader::open_with_dict(&args.input, dict.as_ref())
+        .with_context(|| format!("failed to read {}", args.input.display()))?;
+    let (subset, split) = reader
+        .header()
+        .map(|h| (h.subset.clone(), h.split.clone()))
+        .unwrap_or_default();
+    let checksums = args.checksums || reader.header().is_some_and(|h| h.checksums);
+
+    let mut writer = ExampleWriter::create(&args.out)
+        .with_header(shard::header("ethics.v1.Example", &subset, &split))
+        .checksums(checksums)
+        .overwrite(args.overwrite);
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+
+    let (mut kept, mut dropped) = (0u64, 0u64);
+    for ex in reader {
+        let ex = ex.with_context(|| format!("failed to read {}", args.input.display()))?;
+        // Every predicate is evaluated, not short-circuited, so the per-predicate counts are complete.
+        let mut hits = 0;
+        for p in &mut predicates {
+            if (p.test)(&ex) {
+                p.matched += 1;
+                hits += 1;
+            }
+        }
+        let keep = if args.any {
+            hits > 0
+        } else {
+            hits == predicates.len()
+        };
+        if keep {
+            enc.write(&ex)?;
+            kept += 1;
+        } else {
+            dropped += 1;
+        }
+    }
+    let (bytes, _) = enc.finish()?;
+
+    let mut report = format!(
+        "kept {kept}, dropped {dropped} of {} example(s) -> {} ({bytes} bytes)",
+        kept + dropped,
+        args.out.display()
+    );
+    for p in &predicates {
+        report.push_str(&format!("\n  {}: {} matched", p.name, p.matched));
+    }
+    if is_stdio(&args.out) {
+        eprintln!("{report}");
+    } else {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,209 @@
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use regex::{Regex, RegexBuilder};
+use tracing::info;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "grep-shards",
+    about = "Search the text of ethics.v1.Example shards with a regex, streaming each shard."
+)]
+struct Args {
+    /// Regex matched against each example's text (`regex` crate syntax).
+    #[arg(value_name = "PATTERN")]
+    pattern: String,
+
+    /// Shards to search, or `-` for stdin.
+    #[arg(value_name = "PB_ZST", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Only examples with this label.
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    label: Option<i32>,
+
+    /// Only examples of this subset.
+    #[arg(long, value_name = "SUBSET")]
+    subset: Option<String>,
+
+    /// Only examples whose meta has KEY set to VALUE; repeat to require several.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_meta_filter)]
+    meta: Vec<(String, String)>,
+
+    /// Print the examples whose text does not match instead. The other filters still apply.
+    #[arg(long, short = 'v')]
+    invert_match: bool,
+
+    /// Match case-insensitively.
+    #[arg(long, short = 'i')]
+    ignore_case: bool,
+
+    /// Only print the number of matching examples per shard.
+    #[arg(long, short = 'c')]
+    count_only: bool,
+
+    /// Characters of text shown on each side of the match.
+    #[arg(long, value_name = "CHARS", default_value_t = 40)]
+    context: usize,
+
+    /// zstd dictionary the shards were compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn parse_meta_filter(s: &str) -> Result<(String, String)> {
+    let (k, v) = s
+        .split_once('=')
+        .with_context(|| format!("expected KEY=VALUE, got {s:?}"))?;
+    Ok((k.to_string(), v.to_string()))
+}
+
+/// What an example is checked against, besides the pattern.
+struct Filters<'a> {
+    label: Option<i32>,
+    subset: Option<&'a str>,
+    meta: &'a [(String, String)],
+}
+
+impl Filters<'_> {
+    fn admit(&self, ex: &Example) -> bool {
+        self.label.is_none_or(|l| ex.label == l)
+            && self.subset.is_none_or(|s| ex.subset == s)
+            && self
+                .meta
+                .iter()
+                .all(|(k, v)| ex.meta.get(k).is_some_and(|m| m == v))
+    }
+}
+
+/// Up to `context` characters either side of `text[start..end]`, on one line,
+/// with the match itself highlighted when `color` is set.
+fn snippet(text: &str, (start, end): (usize, usize), context: usize, color: bool) -> String {
+    let from = text[..start]
+        .char_indices()
+        .rev()
+        .take(context)
+        .last()
+        .map_or(start, |(i, _)| i);
+    let to = text[end..]
+        .char_indices()
+        .nth(context)
+        .map_or(text.len(), |(i, _)| end + i);
+    let one_line = |s: &str| s.replace(['\n', '\r', '\t'], " ");
+    let (open, close) = if color {
+        ("\x1b[1;31m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+    format!(
+        "{}{}{open}{}{close}{}{}",
+        if from > 0 { "…" } else { "" },
+        one_line(&text[from..start]),
+        one_line(&text[start..end]),
+        one_line(&text[end..to]),
+        if to < text.len() { "…" } else { "" },
+    )
+}
+
+/// Streams one shard, printing its matches; returns (matches, examples read).
+fn grep_shard(
+    path: &Path,
+    regex: &Regex,
+    filters: &Filters,
+    args: &Args,
+    dict: Option<&Dictionary>,
+    color: bool,
+    out: &mut impl Write,
+) -> Result<(u64, u64)> {
+    let reader = ExampleReader::open_with_dict(path, dict)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let (mut matches, mut read) = (0, 0);
+    for (index, ex) in (0u64..).zip(reader) {
+        let ex = ex.with_context(|| format!("failed to read {}", path.display()))?;
+        read += 1;
+        if !filters.admit(&ex) {
+            continue;
+        }
+        let found = regex.find(&ex.text).map(|m| (m.start(), m.end()));
+        if found.is_some() == args.invert_match {
+            continue;
+        }
+        matches += 1;
+        if args.count_only {
+            continue;
+        }
+        // Inverted matches have nothing to highlight; show the start of the text.
+        let snippet = snippet(&ex.text, found.unwrap_or((0, 0)), args.context, color);
+        writeln!(
+            out,
+            "{}:{index}: label {}: {snippet}",
+            path.display(),
+            ex.label
+        )?;
+    }
+    Ok((matches, read))
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    let regex = RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .with_context(|| format!("invalid pattern {:?}", args.pattern))?;
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let filters = Filters {
+        label: args.label,
+        subset: args.subset.as_deref(),
+        meta: &args.meta,
+    };
+    let color = io::stdout().is_terminal();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut total = 0;
+    for path in &args.inputs {
+        let (matches, read) = grep_shard(
+            path,
+            &regex,
+            &filters,
+            &args,
+            dict.as_ref(),
+            color,
+            &mut out,
+        )?;
+        total += matches;
+        if args.count_only {
+            writeln!(out, "{}:{matches}", path.display())?;
+        } else {
+            info!(
+                "{}: {matches} match(es) in {read} example(s)",
+                path.display()
+            );
+        }
+    }
+    out.flush()?;
+    if args.inputs.len() > 1 {
+        info!("{total} match(es) in {} shard(s)", args.inputs.len());
+    }
+    // Like grep: 1 when nothing matched, so scripts can branch on it.
+    Ok(if total > 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    })
+}
+
+fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
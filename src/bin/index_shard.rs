@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::index::{FrameEntry, ShardIndex, INDEX_VERSION};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::shard::{ShardReader, ZSTD_MAGIC};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use zstd::zstd_safe;
+
+/// Largest zstd frame buffered while looking for its end.
+const MAX_FRAME_BYTES: usize = 256 << 20;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "index-shard",
+    about = "Write the `.idx` frame index of a shard written with --frame-every, for random access by example index."
+)]
+struct Args {
+    /// Shard to index.
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Where to write the index; defaults to `<PB_ZST>.idx`, where readers look for it.
+    #[arg(long, value_name = "IDX")]
+    out: Option<PathBuf>,
+
+    /// zstd dictionary the shard was compressed with.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Replace an existing index, e.g. one left stale by rewriting the shard.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the shard and show where the index would go without writing it.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn run(args: Args) -> Result<()> {
+    let out = args
+        .out
+        .clone()
+        .unwrap_or_else(|| ShardIndex::path_for(&args.input));
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        plan.read_shard(&args.input, dict.as_ref());
+        plan.write(&out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    ensure!(
+        args.overwrite || !out.exists(),
+        "{} already exists (pass --overwrite to replace it)",
+        out.display()
+    );
+    let mut file = File::open(&args.input)
+        .with_context(|| format!("failed to open shard {}", args.input.display()))?;
+
+    // Walk the file one zstd frame at a time, counting the records each one holds.
+    // Frames always end on a record boundary, so each decodes on its own.
+    let mut hasher = Sha256::new();
+    let mut buf = Vec::new();
+    let mut chunk = vec![0; 1 << 20];
+    let mut eof = false;
+    let (mut offset, mut first) = (0u64, 0u64);
+    let mut header = None;
+    let mut frames = Vec::new();
+    loop {
+        ensure!(
+            buf.len() < ZSTD_MAGIC.len() || buf.starts_with(&ZSTD_MAGIC),
+            "{} is not zstd-compressed at byte {offset}; only --frame-every shards can be indexed",
+            args.input.display()
+        );
+        let len = match zstd_safe::find_frame_compressed_size(&buf) {
+            Ok(len) if len > 0 => len,
+            _ if eof && buf.is_empty() => break,
+            _ if eof => bail!(
+                "{} ends in a truncated or corrupt zstd frame at byte {offset}",
+                args.input.display()
+            ),
+            _ => {
+                ensure!(
+                    buf.len() <= MAX_FRAME_BYTES,
+                    "{} has a zstd frame over {MAX_FRAME_BYTES} bytes at byte {offset}; was it written with --frame-every?",
+                    args.input.display()
+                );
+                let n = file.read(&mut chunk)?;
+                hasher.update(&chunk[..n]);
+                buf.extend_from_slice(&chunk[..n]);
+                eof = n == 0;
+                continue;
+            }
+        };
+        let frame = Cursor::new(buf.drain(..len).collect::<Vec<u8>>());
+        let mut reader = if offset == 0 {
+            let reader = ShardReader::from_reader(frame, dict.as_ref(), &args.input)?;
+            header = reader.header.clone();
+            reader
+        } else {
+            ShardReader::from_frame(frame, dict.as_ref(), &args.input, header.clone())?
+        };
+        let mut count = 0;
+        while reader
+            .skip_record()
+            .with_context(|| format!("in the zstd frame at byte {offset}"))?
+        {
+            count += 1;
+        }
+        if count > 0 {
+            frames.push(FrameEntry {
+                offset,
+                first,
+                count,
+            });
+        }
+        offset += len as u64;
+        first += count;
+    }
+
+    if frames.len() == 1 {
+        warn!(
+            "{} is a single frame, so the index can't skip any of it; write it with --frame-every",
+            args.input.display()
+        );
+    }
+    let index = ShardIndex {
+        version: INDEX_VERSION,
+        shard_bytes: offset,
+        shard_sha256: format!("{:x}", hasher.finalize()),
+        frames,
+    };
+    index.write(&out)?;
+    println!(
+        "{}: {} example(s) in {} frame(s) -> {}",
+        args.input.display(),
+        index.examples(),
+        index.frames.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,170 @@
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::sample::Reservoir;
+use protobuf_ethics::text::truncate;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::json;
+use tracing::info;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "inspect-shard",
+    about = "Count a shard of ethics.v1.Example messages, or print its first or a random sample of its examples."
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// zstd dictionary the shard was compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT", global = true)]
+    dict: Option<PathBuf>,
+
+    /// Print one JSON object per line (full text and meta) instead of the readable layout.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Characters of text shown per example in the readable layout; 0 shows it all.
+    #[arg(long, value_name = "CHARS", default_value_t = 100, global = true)]
+    width: usize,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Count the examples, reading only their length prefixes.
+    Count {
+        /// Input shard, or `-` for stdin.
+        #[arg(value_name = "PB_ZST")]
+        input: PathBuf,
+    },
+    /// Print the first N examples.
+    Head {
+        #[arg(value_name = "PB_ZST")]
+        input: PathBuf,
+        #[arg(long, short, value_name = "N", default_value_t = 10)]
+        n: usize,
+    },
+    /// Print N examples drawn uniformly in one pass (reservoir sampling), in shard order.
+    Sample {
+        #[arg(value_name = "PB_ZST")]
+        input: PathBuf,
+        #[arg(long, short, value_name = "N", default_value_t = 10)]
+        n: usize,
+        /// The same seed draws the same examples from the same shard.
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+    },
+}
+
+/// Draws `n` examples in one pass, returned in shard order with their positions.
+fn reservoir_sample(reader: ExampleReader, n: usize, seed: u64) -> Result<Vec<(u64, Example)>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut reservoir = Reservoir::new(n);
+    for ex in reader {
+        reservoir.push(&mut rng, ex?);
+    }
+    Ok(reservoir.into_sorted())
+}
+
+/// The readable layout: a line of labels, the text on one line, then the meta keys.
+fn print_example(w: &mut impl Write, index: u64, ex: &Example, width: usize) -> io::Result<()> {
+    writeln!(
+        w,
+        "#{index}  label {}  {}/{}  id {}",
+        ex.label, ex.subset, ex.split, ex.id
+    )?;
+    let text = ex.text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let text = match width {
+        0 => text,
+        width => truncate(&text, Some(width), None, "…").unwrap_or(text),
+    };
+    writeln!(w, "  text: {text}")?;
+    if let Some(score) = ex.score {
+        writeln!(w, "  score: {score}")?;
+    }
+    if !ex.meta.is_empty() {
+        let keys: Vec<&str> = ex.meta.keys().map(String::as_str).collect();
+        writeln!(w, "  meta: {}", keys.join(", "))?;
+    }
+    writeln!(w)
+}
+
+fn example_to_json(index: u64, ex: &Example) -> serde_json::Value {
+    let mut value = json!({
+        "index": index,
+        "id": ex.id,
+        "subset": ex.subset,
+        "split": ex.split,
+        "label": ex.label,
+        "text": ex.text,
+        "meta": &ex.meta,
+    });
+    if let Some(context) = &ex.context {
+        value["context"] = json!(context);
+    }
+    if let Some(score) = ex.score {
+        value["score"] = json!(score);
+    }
+    value
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let open = |input: &PathBuf| {
+        ExampleReader::open_with_dict(input, dict.as_ref())
+            .with_context(|| format!("failed to read {}", input.display()))
+    };
+    let (input, examples) = match &args.command {
+        Command::Count { input } => {
+            let n = open(input)?.count()?;
+            if args.json {
+                println!("{}", json!({ "shard": input, "examples": n }));
+            } else {
+                println!("{n}");
+            }
+            return Ok(());
+        }
+        Command::Head { input, n } => {
+            let head = (0u64..)
+                .zip(open(input)?.take(*n))
+                .map(|(i, ex)| Ok((i, ex?)))
+                .collect::<Result<Vec<_>>>()?;
+            (input, head)
+        }
+        Command::Sample { input, n, seed } => (input, reservoir_sample(open(input)?, *n, *seed)?),
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (index, ex) in &examples {
+        if args.json {
+            serde_json::to_writer(&mut out, &example_to_json(*index, ex))?;
+            writeln!(out)?;
+        } else {
+            print_example(&mut out, *index, ex, args.width)?;
+        }
+    }
+    out.flush()?;
+    info!(
+        "printed {} example(s) from {}",
+        examples.len(),
+        input.display()
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
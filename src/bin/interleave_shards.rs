@@ -0,0 +1,267 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Parser, ValueEnum};
+use protobuf_ethics::convert::Mode;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::input::{is_pairs_shard, is_stdio};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::shard::{self, ShardReader};
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "interleave-shards",
+    about = "Mix several shards into one stream, drawing records from each in proportion to its weight."
+)]
+struct Args {
+    /// A shard to draw from, as `PATH` or `PATH:WEIGHT` (weight 1 by default); repeat per source.
+    #[arg(long = "input", value_name = "PB_ZST[:WEIGHT]", value_parser = parse_source, required = true)]
+    inputs: Vec<Source>,
+
+    /// Mixed shard.
+    #[arg(long, value_name = "OUT")]
+    out: PathBuf,
+
+    /// The same seed draws the same mixture from the same inputs.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// What happens when a source runs out.
+    #[arg(long, value_enum, default_value_t = Exhausted::Drain)]
+    on_exhausted: Exhausted,
+
+    /// zstd compression level of the output (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the output as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the inputs were compressed with; the output is compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums (kept anyway when any input has them).
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the inputs and list the output without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Exhausted {
+    /// Keep drawing from the remaining sources, reweighted, until all are exhausted.
+    Drain,
+    /// End the output as soon as any source is exhausted, keeping the mixture exact.
+    Stop,
+    /// Start an exhausted source over, until every source has been read through once.
+    Cycle,
+}
+
+/// One `--input`.
+#[derive(Clone, Debug)]
+struct Source {
+    path: PathBuf,
+    weight: f64,
+}
+
+fn parse_source(s: &str) -> Result<Source, String> {
+    // Only a number after the last `:` is a weight, so paths with colons still work.
+    if let Some((path, weight)) = s.rsplit_once(':') {
+        if let Ok(weight) = weight.parse::<f64>() {
+            if !(weight.is_finite() && weight > 0.0) {
+                return Err(format!("weight must be a positive number, got {weight}"));
+            }
+            return Ok(Source {
+                path: path.into(),
+                weight,
+            });
+        }
+    }
+    Ok(Source {
+        path: s.into(),
+        weight: 1.0,
+    })
+}
+
+/// A source being drawn from.
+struct Stream<'a> {
+    source: &'a Source,
+    reader: Option<ShardReader>,
+    written: u64,
+    /// Times the source has been read to its end.
+    passes: u64,
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        for source in &args.inputs {
+            plan.read_shard(&source.path, dict.as_ref());
+        }
+        plan.write(&args.out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+
+    let mut mode = None;
+    let mut checksums = args.checksums;
+    let mut subset_split: Option<(String, String)> = None;
+    let mut streams = Vec::new();
+    for source in &args.inputs {
+        let path = &source.path;
+        ensure!(
+            args.on_exhausted != Exhausted::Cycle || !is_stdio(path),
+            "--on-exhausted cycle reopens its inputs, so they can't be stdin"
+        );
+        let reader = ShardReader::open(path, dict.as_ref())?;
+        shard::check_schema_version(reader.header.as_ref(), None)
+            .with_context(|| format!("cannot interleave {}", path.display()))?;
+        let m = match &reader.header {
+            Some(h) if h.message == Mode::Pair.message() => Mode::Pair,
+            Some(h) if h.message == Mode::Example.message() => Mode::Example,
+            Some(h) => bail!("{} holds unknown {} messages", path.display(), h.message),
+            None if is_pairs_shard(path) => Mode::Pair,
+            None => Mode::Example,
+        };
+        ensure!(
+            mode.is_none_or(|first| first == m),
+            "{} holds {} records, unlike {}",
+            path.display(),
+            m.message(),
+            args.inputs[0].path.display()
+        );
+        mode = Some(m);
+        checksums |= reader.header.as_ref().is_some_and(|h| h.checksums);
+        // Each example keeps its own `subset`; the header names one only when all inputs agree.
+        let this = reader
+            .header
+            .as_ref()
+            .map(|h| (h.subset.clone(), h.split.clone()))
+            .unwrap_or_default();
+        subset_split = match subset_split {
+            None => Some(this),
+            Some(common) if common == this => Some(common),
+            Some((_, split)) if split == this.1 => Some((String::new(), split)),
+            _ => Some(Default::default()),
+        };
+        streams.push(Stream {
+            source,
+            reader: Some(reader),
+            written: 0,
+            passes: 0,
+        });
+    }
+    let mode = mode.expect("at least one input");
+    let (subset, split) = subset_split.unwrap_or_default();
+
+    let mut writer = ExampleWriter::create(&args.out)
+        .with_header(shard::header(mode.message(), &subset, &split))
+        .checksums(checksums)
+        .overwrite(args.overwrite);
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    loop {
+        // Pick a source with probability proportional to its weight among those still open.
+        let total: f64 = streams
+            .iter()
+            .filter(|s| s.reader.is_some())
+            .map(|s| s.source.weight)
+            .sum();
+        if total == 0.0 {
+            break;
+        }
+        let mut x = rng.random::<f64>() * total;
+        let i = streams
+            .iter()
+            .position(|s| {
+                if s.reader.is_none() {
+                    return false;
+                }
+                x -= s.source.weight;
+                x < 0.0
+            })
+            // Rounding can leave `x` a hair above zero after the last open source.
+            .unwrap_or_else(|| streams.iter().rposition(|s| s.reader.is_some()).unwrap());
+        let stream = &mut streams[i];
+        let reader = stream.reader.as_mut().unwrap();
+        if let Some(record) = reader.next_record()? {
+            enc.write_record(record)?;
+            stream.written += 1;
+            continue;
+        }
+
+        stream.passes += 1;
+        stream.reader = None;
+        ensure!(
+            stream.written > 0,
+            "{} holds no records",
+            stream.source.path.display()
+        );
+        match args.on_exhausted {
+            Exhausted::Drain => {}
+            Exhausted::Stop => break,
+            Exhausted::Cycle => {
+                if streams.iter().all(|s| s.passes > 0) {
+                    break;
+                }
+                let stream = &mut streams[i];
+                stream.reader = Some(ShardReader::open(&stream.source.path, dict.as_ref())?);
+            }
+        }
+    }
+
+    let examples = enc.examples();
+    let (bytes, _) = enc.finish()?;
+    println!(
+        "interleaved {} shard(s) -> {} ({examples} example(s), {bytes} bytes)",
+        streams.len(),
+        args.out.display()
+    );
+    let weights: f64 = args.inputs.iter().map(|s| s.weight).sum();
+    for stream in &streams {
+        let passes = match stream.passes {
+            0 => "partly read".to_string(),
+            1 => "read once".to_string(),
+            n => format!("read {n} times"),
+        };
+        println!(
+            "  {}: {} example(s), {:.1}% (weight {:.1}%), {passes}",
+            stream.source.path.display(),
+            stream.written,
+            100.0 * stream.written as f64 / examples.max(1) as f64,
+            100.0 * stream.source.weight / weights
+        );
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::convert::{DedupConfig, Encoded, Mode, SeenTexts};
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::input::{is_pairs_shard, is_stdio};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::shard::{self, ShardReader};
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use serde::Serialize;
+use tracing::info;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "merge-shards",
+    about = "Concatenate shards of the same message type into one, optionally dropping duplicate texts."
+)]
+struct Args {
+    /// Shards to merge, in order.
+    #[arg(value_name = "PB_ZST", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Merged shard; `<OUT>.manifest.json` records what each input contributed.
+    #[arg(long, value_name = "OUT")]
+    out: PathBuf,
+
+    /// Drop examples whose whitespace-collapsed text was already written, from any input.
+    #[arg(long)]
+    dedup: bool,
+
+    /// Also lowercase text before comparing for `--dedup`.
+    #[arg(long, requires = "dedup")]
+    dedup_case_insensitive: bool,
+
+    /// Remember 128-bit hashes instead of full texts for `--dedup`.
+    #[arg(long, requires = "dedup")]
+    dedup_hash_only: bool,
+
+    /// zstd compression level of the output (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the output as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the inputs were compressed with; the output is compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums (kept anyway when any input has them).
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the inputs and list the output without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// What one input contributed, for the manifest.
+#[derive(Serialize, Debug)]
+struct Source {
+    path: PathBuf,
+    examples_read: u64,
+    examples_written: u64,
+    /// Dropped by `--dedup` as already written from this or an earlier input.
+    #[serde(skip_serializing_if = "is_zero")]
+    duplicates: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// `<OUT>.manifest.json`.
+#[derive(Serialize, Debug)]
+struct Manifest<'a> {
+    message: &'static str,
+    path: &'a Path,
+    examples: u64,
+    bytes: u64,
+    sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<DedupConfig>,
+    sources: Vec<Source>,
+}
+
+/// The message type of `reader`'s records, by header or, without one, by name.
+fn mode_of(reader: &ShardReader, path: &Path) -> Result<Mode> {
+    Ok(match &reader.header {
+        Some(h) if h.message == Mode::Pair.message() => Mode::Pair,
+        Some(h) if h.message == Mode::Example.message() => Mode::Example,
+        Some(h) => bail!("{} holds unknown {} messages", path.display(), h.message),
+        None if is_pairs_shard(path) => Mode::Pair,
+        None => Mode::Example,
+    })
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        for path in &args.inputs {
+            plan.read_shard(path, dict.as_ref());
+        }
+        plan.write(&args.out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    let dedup = args.dedup.then_some(DedupConfig {
+        case_insensitive: args.dedup_case_insensitive,
+        hash_only: args.dedup_hash_only,
+        rejects: false,
+    });
+
+    // Check every input up front, so a mismatch doesn't leave half a merge behind.
+    let mut mode = None;
+    let mut checksums = args.checksums;
+    let mut subset_split: Option<(String, String)> = None;
+    for path in &args.inputs {
+        // Inputs are opened twice: here and when copying.
+        ensure!(!is_stdio(path), "merge_shards reads shard files, not stdin");
+        let reader = ShardReader::open(path, dict.as_ref())?;
+        shard::check_schema_version(reader.header.as_ref(), None)
+            .with_context(|| format!("cannot merge {}", path.display()))?;
+        let m = mode_of(&reader, path)?;
+        ensure!(
+            mode.is_none_or(|first| first == m),
+            "{} holds {} records, unlike {}",
+            path.display(),
+            m.message(),
+            args.inputs[0].display()
+        );
+        mode = Some(m);
+        checksums |= reader.header.as_ref().is_some_and(|h| h.checksums);
+        // The output header names a subset/split only when every input agrees on one.
+        let this = reader
+            .header
+            .as_ref()
+            .map(|h| (h.subset.clone(), h.split.clone()))
+            .unwrap_or_default();
+        subset_split = match subset_split {
+            None => Some(this),
+            Some(common) if common == this => Some(common),
+            _ => Some(Default::default()),
+        };
+    }
+    let mode = mode.expect("at least one input");
+    let (subset, split) = subset_split.unwrap_or_default();
+
+    let mut writer = ExampleWriter::create(&args.out)
+        .with_header(shard::header(mode.message(), &subset, &split))
+        .checksums(checksums)
+        .overwrite(args.overwrite);
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+
+    let mut seen = SeenTexts::new(dedup);
+    let mut sources = Vec::new();
+    for path in &args.inputs {
+        let mut reader = ShardReader::open(path, dict.as_ref())?;
+        let mut source = Source {
+            path: path.clone(),
+            examples_read: 0,
+            examples_written: 0,
+            duplicates: 0,
+        };
+        while let Some(record) = reader.next_record()? {
+            source.examples_read += 1;
+            // Records are copied through as they are; only `--dedup` needs to look inside.
+            if dedup.is_some() {
+                let decoded = Encoded::decode(mode, record).with_context(|| {
+                    format!(
+                        "{} holds a record that is not a {}",
+                        path.display(),
+                        mode.message()
+                    )
+                })?;
+                if !seen.insert(&decoded.text) {
+                    source.duplicates += 1;
+                    continue;
+                }
+            }
+            enc.write_record(record)?;
+            source.examples_written += 1;
+        }
+        if dedup.is_some() {
+            info!(
+                "{}: {} duplicate(s) dropped",
+                path.display(),
+                source.duplicates
+            );
+        }
+        sources.push(source);
+    }
+
+    let examples = enc.examples();
+    let (bytes, sha256) = enc.finish()?;
+    let mut manifest_path = args.out.as_os_str().to_owned();
+    manifest_path.push(".manifest.json");
+    let manifest_path = PathBuf::from(manifest_path);
+    let manifest = Manifest {
+        message: mode.message(),
+        path: &args.out,
+        examples,
+        bytes,
+        sha256,
+        dedup,
+        sources,
+    };
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+    println!(
+        "merged {} shard(s) -> {} ({examples} example(s), {bytes} bytes, sha256 {})",
+        args.inputs.len(),
+        args.out.display(),
+        manifest.sha256
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,349 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use prost::Message;
+use protobuf_ethics::dict::{check_frame, Dictionary};
+use protobuf_ethics::ethics::{Example, PairExample, Preference, ShardHeader};
+use protobuf_ethics::index::ShardIndex;
+use protobuf_ethics::input::{is_pairs_shard, is_stdio};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::protojson;
+use protobuf_ethics::shard::{self, CHECKSUM_LEN, ZSTD_MAGIC};
+use serde_json::json;
+use tracing::{info, warn};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "pb-to-jsonl",
+    about = "Decode a .pb.zst (or plain .pb) shard of length-delimited ethics.v1.Example messages back to JSONL."
+)]
+struct Args {
+    /// Input shard, or `-` for stdin.
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Output JSONL file (defaults to stdout).
+    #[arg(long, value_name = "OUT")]
+    out: Option<PathBuf>,
+
+    /// Stop after this many examples.
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Index of the first example to output. With a `.idx` sidecar (from `--frame-every`)
+    /// the reader seeks to the right frame instead of decoding everything before it.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    start: u64,
+
+    /// Frame index for `--start`; defaults to `<PB_ZST>.idx` when that file exists.
+    #[arg(long, value_name = "IDX")]
+    index: Option<PathBuf>,
+
+    /// zstd dictionary the shard was compressed with (`ethics-pipeline --dict`).
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Fail if the shard's schema version is older than N (headerless shards count as 0).
+    #[arg(long, value_name = "N")]
+    require_schema_version: Option<u32>,
+
+    /// Print the shard header as JSON (`null` for shards without one) instead of the records.
+    #[arg(long, conflicts_with_all = ["out", "limit", "start"])]
+    header: bool,
+
+    /// Message type in the shard; `auto` goes by the shard header, or for shards
+    /// without one reads `*.pairs.pb[.zst]` as `PairExample`.
+    #[arg(long, value_enum, default_value_t = MessageType::Auto)]
+    message: MessageType,
+
+    /// Output JSON: `jsonl`, this tool's own layout, or `proto-json`, protobuf's canonical
+    /// JSON mapping (lowerCamelCase names, default values omitted).
+    #[arg(long, value_enum, default_value_t = OutputFormat::Jsonl)]
+    format: OutputFormat,
+
+    /// With `--format proto-json`, write fields at their default value (`"label": 0`) too.
+    #[arg(long)]
+    emit_defaults: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Jsonl,
+    ProtoJson,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageType {
+    Auto,
+    Example,
+    Pair,
+}
+
+/// Reads one varint length prefix. Returns `Ok(None)` on a clean end of stream.
+fn read_len<R: Read>(reader: &mut R, offset: u64) -> Result<Option<(usize, u64)>> {
+    let mut value: u64 = 0;
+    let mut consumed: u64 = 0;
+    let mut byte = [0u8; 1];
+
+    loop {
+        match reader.read_exact(&mut byte) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && consumed == 0 => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                bail!("truncated length prefix at byte offset {offset}")
+            }
+            Err(e) => return Err(e).context(format!("read error at byte offset {offset}")),
+        }
+
+        if consumed == 10 {
+            bail!("invalid varint length prefix at byte offset {offset}");
+        }
+        value |= u64::from(byte[0] & 0x7f) << (7 * consumed);
+        consumed += 1;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some((value as usize, consumed)));
+        }
+    }
+}
+
+fn example_to_json(ex: &Example) -> serde_json::Value {
+    // `meta` is a BTreeMap, so keys come out in a stable order.
+    let meta = &ex.meta;
+    let mut value = json!({
+        "id": ex.id,
+        "subset": ex.subset,
+        "split": ex.split,
+        "text": ex.text,
+        "label": ex.label,
+        "meta": meta,
+    });
+    // Only shards written with these optional fields have them.
+    if let Some(context) = &ex.context {
+        value["context"] = json!(context);
+    }
+    if let Some(score) = ex.score {
+        value["score"] = json!(score);
+    }
+    if !ex.labels_by_name.is_empty() {
+        value["labels_by_name"] = json!(&ex.labels_by_name);
+    }
+    value
+}
+
+fn pair_to_json(pair: &PairExample) -> serde_json::Value {
+    let preferred = match pair.preferred() {
+        Preference::A => "a",
+        Preference::B => "b",
+        Preference::Unspecified => "unspecified",
+    };
+    json!({
+        "id": pair.id,
+        "subset": pair.subset,
+        "split": pair.split,
+        "text_a": pair.text_a,
+        "text_b": pair.text_b,
+        "preferred": preferred,
+        "meta": &pair.meta,
+    })
+}
+
+fn header_to_json(header: &ShardHeader) -> serde_json::Value {
+    let mut value = json!({
+        "format_version": header.format_version,
+        "message": header.message,
+        "subset": header.subset,
+        "split": header.split,
+        "created_unix": header.created_unix,
+        "converter_version": header.converter_version,
+        "converter_git": header.converter_git,
+        "checksums": header.checksums,
+        "schema_version": header.schema_version,
+    });
+    if let Some(sha256) = &header.dict_sha256 {
+        value["dict_sha256"] = json!(sha256);
+    }
+    if !header.descriptor_sha256.is_empty() {
+        value["descriptor_sha256"] = json!(header.descriptor_sha256);
+    }
+    value
+}
+
+/// Wraps `file` in a zstd decoder if it starts with the zstd magic, so uncompressed
+/// `.pb` shards decode too.
+fn decompress(
+    file: Box<dyn Read>,
+    dict: Option<&Dictionary>,
+    input: &Path,
+) -> Result<Box<dyn Read>> {
+    let mut file = BufReader::new(file);
+    let head = file.fill_buf()?;
+    if !head.starts_with(&ZSTD_MAGIC) {
+        return Ok(Box::new(file));
+    }
+    check_frame(head, dict, input)?;
+    let dict_bytes = dict.map_or(&[][..], |d| &d.bytes);
+    let mut decoder = ZstdDecoder::with_dictionary(file, dict_bytes)
+        .context("failed to initialise zstd decoder")?;
+    // Shards written with `--zstd-long` may use windows up to 2^31.
+    decoder.window_log_max(shard::ZSTD_WINDOW_LOG_MAX)?;
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+/// Loads the frame index for `--start`, if one was given or sits next to the shard.
+fn load_index(args: &Args) -> Result<Option<ShardIndex>> {
+    let path = match &args.index {
+        Some(path) => path.clone(),
+        None => {
+            let path = ShardIndex::path_for(&args.input);
+            if !path.exists() {
+                return Ok(None);
+            }
+            path
+        }
+    };
+    Ok(Some(ShardIndex::load_for(&path, &args.input)?))
+}
+
+fn run(args: Args) -> Result<()> {
+    if args.emit_defaults && args.format != OutputFormat::ProtoJson {
+        bail!("--emit-defaults only applies to --format proto-json");
+    }
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    // Examples still to pass over before output starts.
+    let mut skip = args.start;
+    // The shard header sits in the first frame; read it before seeking past it.
+    let mut header = None;
+    let file: Box<dyn Read> = if is_stdio(&args.input) {
+        Box::new(io::stdin().lock())
+    } else {
+        let mut file = File::open(&args.input)
+            .with_context(|| format!("failed to open shard {}", args.input.display()))?;
+        if args.start > 0 {
+            if let Some(index) = load_index(&args)? {
+                match index.frame_for(args.start) {
+                    Some(frame) => {
+                        if frame.offset > 0 {
+                            let start = decompress(
+                                Box::new(File::open(&args.input)?),
+                                dict.as_ref(),
+                                &args.input,
+                            )?;
+                            header = shard::read_header(start)?.header;
+                        }
+                        file.seek(SeekFrom::Start(frame.offset))?;
+                        skip = args.start - frame.first;
+                    }
+                    // Past the last example: nothing to output.
+                    None => {
+                        file.seek(SeekFrom::End(0))?;
+                        skip = 0;
+                    }
+                }
+            }
+        }
+        Box::new(file)
+    };
+    let framed = shard::read_header(decompress(file, dict.as_ref(), &args.input)?)?;
+    let header = header.or(framed.header);
+    if args.header {
+        let value = header
+            .as_ref()
+            .map_or(serde_json::Value::Null, header_to_json);
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+    let warning = shard::check_schema_version(header.as_ref(), args.require_schema_version)
+        .with_context(|| format!("cannot decode {}", args.input.display()))?;
+    if let Some(warning) = warning {
+        warn!("{}: {warning}", args.input.display());
+    }
+    let checksums = header.as_ref().is_some_and(|h| h.checksums);
+    let pairs = match (args.message, &header) {
+        (MessageType::Auto, Some(header)) => header.message == "ethics.v1.PairExample",
+        (MessageType::Auto, None) => is_pairs_shard(&args.input),
+        (MessageType::Example, _) => false,
+        (MessageType::Pair, _) => true,
+    };
+    let mut reader = framed.reader;
+
+    let sink: Box<dyn Write> = match &args.out {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = BufWriter::new(sink);
+
+    // Offsets are positions in the decompressed stream, counted from the frame
+    // `--start` seeked to.
+    let mut offset: u64 = framed.header_len;
+    let mut count: usize = 0;
+    let mut buf = Vec::new();
+
+    while args.limit.is_none_or(|n| count < n) {
+        let Some((len, prefix_len)) = read_len(&mut reader, offset)? else {
+            break;
+        };
+
+        buf.resize(len, 0);
+        reader.read_exact(&mut buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => anyhow::anyhow!(
+                "truncated record at byte offset {offset}: expected {len} byte(s) after the length prefix"
+            ),
+            _ => anyhow::Error::new(e).context(format!("read error at byte offset {offset}")),
+        })?;
+        // Checked even for records `--start` passes over, so corruption isn't skipped silently.
+        let mut record_len = prefix_len + len as u64;
+        if checksums {
+            shard::check_record(&mut reader, &buf, offset)?;
+            record_len += CHECKSUM_LEN;
+        }
+
+        if skip > 0 {
+            skip -= 1;
+            offset += record_len;
+            continue;
+        }
+
+        // A shard holds one message type; a record that doesn't decode as it is an error.
+        let value = if pairs {
+            let pair = PairExample::decode(buf.as_slice())
+                .with_context(|| format!("failed to decode PairExample at byte offset {offset}"))?;
+            match args.format {
+                OutputFormat::Jsonl => pair_to_json(&pair),
+                OutputFormat::ProtoJson => protojson::pair_to_json(&pair, args.emit_defaults),
+            }
+        } else {
+            let ex = Example::decode(buf.as_slice()).with_context(|| {
+                format!("failed to decode Example at byte offset {offset} (pass --message pair for PairExample shards)")
+            })?;
+            match args.format {
+                OutputFormat::Jsonl => example_to_json(&ex),
+                OutputFormat::ProtoJson => protojson::example_to_json(&ex, args.emit_defaults),
+            }
+        };
+        serde_json::to_writer(&mut writer, &value)?;
+        writer.write_all(b"\n")?;
+
+        offset += record_len;
+        count += 1;
+    }
+
+    writer.flush()?;
+    info!("decoded {} example(s) from {}", count, args.input.display());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,473 @@
+//! Runs the whole raw-JSONL-to-shards workflow from one TOML config: length
+//! statistics on the sources, pruning, then conversion, with one progress bar and
+//! one report at the end. Every setting defaults to what the individual tools do
+//! without flags, so an empty config reproduces `calculate_text_length_stats`,
+//! `prune_data_by_length` and `ethics-pipeline --glob "data/filtered/*.jsonl"`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use protobuf_ethics::convert::{
+    infer_subset_split, run_job, ConvertOptions, DedupConfig, Job, RunState,
+};
+use protobuf_ethics::input::{
+    decompressed_name, input_stem, line_too_long, lines, open_maybe_compressed,
+    DEFAULT_MAX_LINE_BYTES,
+};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::manifest::{ratio, write_manifest, ShardInfo};
+use protobuf_ethics::stats::{
+    summarize_per_file, Report, RunningStats, TextLen, DEFAULT_PERCENTILES,
+};
+use protobuf_ethics::writer::DEFAULT_ZSTD_LEVEL;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "pipeline",
+    about = "Run stats, pruning and conversion over the raw JSONL files as configured in a TOML file."
+)]
+struct Args {
+    /// The pipeline config; missing settings take the tools' defaults.
+    #[arg(value_name = "TOML", default_value = "pipeline.toml")]
+    config: PathBuf,
+
+    /// Print the resolved config and every file each stage would read and write,
+    /// then stop without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    sources: Sources,
+    stats: StatsStage,
+    clean: Clean,
+    convert: Convert,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Sources {
+    /// Input JSONL files (plain, `.gz` or `.zst`).
+    globs: Vec<String>,
+    /// Subset and split of files whose names don't say, as `--subset`/`--split`.
+    subset: Option<String>,
+    split: Option<String>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            globs: vec!["data/raw/commonsense-*.jsonl".into()],
+            subset: None,
+            split: None,
+        }
+    }
+}
+
+/// `calculate_text_length_stats` over the sources, before pruning.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct StatsStage {
+    enabled: bool,
+    field: String,
+    out: PathBuf,
+}
+
+impl Default for StatsStage {
+    fn default() -> Self {
+        StatsStage {
+            enabled: true,
+            field: "text".into(),
+            out: "data/stats/commonsense_length_stats.toml".into(),
+        }
+    }
+}
+
+/// `prune_data_by_length`, plus the converter's dedup and normalization.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Clean {
+    /// Drop records whose trimmed `field` is longer than this many characters.
+    max_chars: Option<usize>,
+    field: String,
+    /// Keep records without `field` instead of dropping them.
+    keep_missing: bool,
+    out_dir: PathBuf,
+    /// `--dedup`, `--dedup-case-insensitive` and `--normalize`, applied while converting.
+    dedup: bool,
+    dedup_case_insensitive: bool,
+    normalize: bool,
+}
+
+impl Default for Clean {
+    fn default() -> Self {
+        Clean {
+            max_chars: Some(1000),
+            field: "text".into(),
+            keep_missing: false,
+            out_dir: "data/filtered".into(),
+            dedup: false,
+            dedup_case_insensitive: false,
+            normalize: false,
+        }
+    }
+}
+
+/// `ethics-pipeline --glob` over the pruned files.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Convert {
+    out_dir: PathBuf,
+    compress: bool,
+    zstd_level: i32,
+    checksums: bool,
+    frame_every: Option<u64>,
+    max_examples_per_shard: Option<u64>,
+    text_fields: Vec<String>,
+    meta_keys: Vec<String>,
+    meta_all: bool,
+    legacy_meta: bool,
+    rejects_out: Option<PathBuf>,
+    overwrite: bool,
+}
+
+impl Default for Convert {
+    fn default() -> Self {
+        let record = ConvertOptions::default().record;
+        Convert {
+            out_dir: "data/processed".into(),
+            compress: true,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+            checksums: false,
+            frame_every: None,
+            max_examples_per_shard: None,
+            text_fields: record.text_fields,
+            meta_keys: record.meta_keys,
+            meta_all: false,
+            legacy_meta: false,
+            rejects_out: None,
+            overwrite: false,
+        }
+    }
+}
+
+impl Config {
+    fn convert_options(&self) -> ConvertOptions {
+        let c = &self.convert;
+        let mut opts = ConvertOptions {
+            zstd_level: c.compress.then_some(c.zstd_level),
+            checksums: c.checksums,
+            frame_every: c.frame_every,
+            max_examples_per_shard: c.max_examples_per_shard,
+            overwrite: c.overwrite,
+            // The pipeline draws its own progress bar.
+            quiet: true,
+            dedup: self.clean.dedup.then_some(DedupConfig {
+                case_insensitive: self.clean.dedup_case_insensitive,
+                hash_only: false,
+                rejects: false,
+            }),
+            ..ConvertOptions::default()
+        };
+        opts.record.normalize = self.clean.normalize;
+        opts.record.text_fields = c.text_fields.clone();
+        opts.record.meta_keys = c.meta_keys.clone();
+        opts.record.meta_all = c.meta_all;
+        opts.record.legacy_meta = c.legacy_meta;
+        opts
+    }
+}
+
+/// Every file the run reads and writes, worked out before anything is written.
+struct Plan {
+    sources: Vec<PathBuf>,
+    /// Source -> pruned copy; the sources themselves without pruning.
+    cleaned: Vec<(PathBuf, PathBuf)>,
+    jobs: Vec<Job>,
+}
+
+fn plan(cfg: &Config, opts: &ConvertOptions) -> Result<Plan> {
+    let mut sources = Vec::new();
+    for pattern in &cfg.sources.globs {
+        let matched: Vec<PathBuf> = glob::glob(pattern)
+            .with_context(|| format!("sources: invalid glob {pattern}"))?
+            .flatten()
+            .collect();
+        if matched.is_empty() {
+            bail!("sources: no files match {pattern}");
+        }
+        sources.extend(matched);
+    }
+    sources.sort();
+    sources.dedup();
+    let cleaned: Vec<(PathBuf, PathBuf)> = sources
+        .iter()
+        .map(|path| match cfg.clean.max_chars {
+            Some(_) => (
+                path.clone(),
+                cfg.clean.out_dir.join(decompressed_name(path)),
+            ),
+            None => (path.clone(), path.clone()),
+        })
+        .collect();
+    let mut jobs = Vec::new();
+    for (_, input) in &cleaned {
+        let fallback = || Some((cfg.sources.subset.clone()?, cfg.sources.split.clone()?));
+        let Some((subset, split)) = infer_subset_split(input).or_else(fallback) else {
+            bail!(
+                "convert: cannot tell the subset and split of {} from its name; set sources.subset and sources.split",
+                input.display()
+            );
+        };
+        let out = cfg
+            .convert
+            .out_dir
+            .join(format!("{}.{}", input_stem(input), opts.extension()));
+        if out.exists() && !cfg.convert.overwrite {
+            bail!(
+                "convert: {} already exists (set convert.overwrite = true to replace it)",
+                out.display()
+            );
+        }
+        jobs.push(Job {
+            input: input.clone(),
+            subset,
+            split,
+            out,
+        });
+    }
+    Ok(Plan {
+        sources,
+        cleaned,
+        jobs,
+    })
+}
+
+fn print_plan(cfg: &Config, plan: &Plan) -> Result<()> {
+    println!("# resolved config\n{}", toml::to_string_pretty(cfg)?);
+    println!("# plan");
+    if cfg.stats.enabled {
+        println!(
+            "stats: {} file(s) -> {}",
+            plan.sources.len(),
+            cfg.stats.out.display()
+        );
+    }
+    if cfg.clean.max_chars.is_some() {
+        for (input, output) in &plan.cleaned {
+            println!("clean: {} -> {}", input.display(), output.display());
+        }
+    }
+    for job in &plan.jobs {
+        println!(
+            "convert: {} ({}/{}) -> {}",
+            job.input.display(),
+            job.subset,
+            job.split,
+            job.out.display()
+        );
+    }
+    Ok(())
+}
+
+/// Lengths (or numeric values) of `field` in the JSON lines of `path`.
+fn lengths(path: &Path, field: &str) -> Result<Vec<TextLen>> {
+    let reader = open_maybe_compressed(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut out = Vec::new();
+    for line in lines(reader, DEFAULT_MAX_LINE_BYTES) {
+        let (_, line) = match line {
+            Err(e) if line_too_long(&e).is_some() => continue,
+            line => line.with_context(|| format!("failed to read {}", path.display()))?,
+        };
+        let Ok(obj) = serde_json::from_str::<Value>(line.trim()) else {
+            continue;
+        };
+        match obj.get(field) {
+            Some(Value::String(text)) => out.push(TextLen(text.len() as f64)),
+            Some(Value::Number(n)) => out.extend(n.as_f64().map(TextLen)),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn write_stats(cfg: &StatsStage, sources: &[PathBuf], bar: &ProgressBar) -> Result<usize> {
+    let mut files = BTreeMap::new();
+    let mut running = RunningStats::default();
+    for path in sources {
+        bar.set_message(format!("stats: {}", path.display()));
+        let lens = lengths(path, &cfg.field)
+            .with_context(|| format!("stage `stats` failed on {}", path.display()))?;
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        files.insert(name, summarize_per_file(&lens, &DEFAULT_PERCENTILES));
+        for len in &lens {
+            running.push(*len);
+        }
+        bar.inc(1);
+    }
+    let count = running.count();
+    let overall = running.finalize(&DEFAULT_PERCENTILES);
+    let report = Report {
+        overall,
+        files,
+        groups: BTreeMap::new(),
+        label_counts: BTreeMap::new(),
+        meta_coverage: BTreeMap::new(),
+        tokenizer: None,
+        duplicates: None,
+        deltas: None,
+        sample: None,
+    };
+    if let Some(parent) = cfg.out.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::write(&cfg.out, toml::to_string_pretty(&report)?)
+        .with_context(|| format!("stage `stats` failed to write {}", cfg.out.display()))?;
+    Ok(count)
+}
+
+/// Copies the records of `input` to `output` that `prune_data_by_length` would keep.
+fn prune(cfg: &Clean, max_chars: usize, input: &Path, output: &Path) -> Result<(usize, usize)> {
+    let reader = open_maybe_compressed(input)
+        .with_context(|| format!("failed to open {}", input.display()))?;
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("failed to create {}", output.display()))?,
+    );
+    let (mut kept, mut dropped) = (0, 0);
+    for line in lines(reader, DEFAULT_MAX_LINE_BYTES) {
+        let (_, line) = match line {
+            Err(e) if line_too_long(&e).is_some() => {
+                dropped += 1;
+                continue;
+            }
+            line => line.with_context(|| format!("failed to read {}", input.display()))?,
+        };
+        let trimmed = line.trim();
+        let Ok(record) = serde_json::from_str::<Value>(trimmed) else {
+            continue;
+        };
+        let keep = match record.get(&cfg.field).and_then(Value::as_str) {
+            Some(text) => text.trim().chars().count() <= max_chars,
+            None => cfg.keep_missing,
+        };
+        if keep {
+            writer.write_all(trimmed.as_bytes())?;
+            writer.write_all(b"\n")?;
+            kept += 1;
+        } else {
+            dropped += 1;
+        }
+    }
+    writer.flush()?;
+    Ok((kept, dropped))
+}
+
+fn run(cfg: &Config, plan: Plan) -> Result<()> {
+    let steps = (cfg.stats.enabled as usize * plan.sources.len())
+        + (cfg.clean.max_chars.is_some() as usize * plan.cleaned.len())
+        + plan.jobs.len();
+    let bar = ProgressBar::new(steps as u64);
+    bar.set_style(
+        ProgressStyle::with_template("[{elapsed_precise}] {bar:30} {pos}/{len} {wide_msg}")
+            .unwrap(),
+    );
+    let mut report = Vec::new();
+
+    if cfg.stats.enabled {
+        let values = write_stats(&cfg.stats, &plan.sources, &bar)?;
+        report.push(format!(
+            "stats: {values} value(s) of `{}` in {} file(s) -> {}",
+            cfg.stats.field,
+            plan.sources.len(),
+            cfg.stats.out.display()
+        ));
+    }
+
+    if let Some(max_chars) = cfg.clean.max_chars {
+        fs::create_dir_all(&cfg.clean.out_dir)
+            .with_context(|| format!("failed to create {}", cfg.clean.out_dir.display()))?;
+        let (mut kept, mut dropped) = (0, 0);
+        for (input, output) in &plan.cleaned {
+            bar.set_message(format!("clean: {}", input.display()));
+            let (k, d) = prune(&cfg.clean, max_chars, input, output)
+                .with_context(|| format!("stage `clean` failed on {}", input.display()))?;
+            (kept, dropped) = (kept + k, dropped + d);
+            bar.inc(1);
+        }
+        report.push(format!(
+            "clean: kept {kept}, dropped {dropped} record(s) over {max_chars} characters -> {}",
+            cfg.clean.out_dir.display()
+        ));
+    }
+
+    let opts = cfg.convert_options();
+    let state = RunState::new(cfg.convert.rejects_out.clone(), opts.dedup);
+    let mut shards: Vec<ShardInfo> = Vec::new();
+    let mut bytes_in = 0;
+    for job in &plan.jobs {
+        bar.set_message(format!("convert: {}", job.input.display()));
+        let output = run_job(job, &opts, &state)
+            .with_context(|| format!("stage `convert` failed on {}", job.input.display()))?;
+        bytes_in += output.bytes_in;
+        shards.extend(output.shards);
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+    let examples: usize = shards.iter().map(|s| s.counts.examples).sum();
+    let rejected: usize = shards
+        .iter()
+        .map(|s| s.counts.rejected.values().sum::<usize>())
+        .sum();
+    let bytes_out: u64 = shards.iter().map(|s| s.bytes).sum();
+    report.push(format!("convert: {examples} example(s), {rejected} rejected, in {} shard(s); {bytes_in} bytes in, {bytes_out} bytes out ({:.2}x)", shards.len(), ratio(bytes_in, bytes_out)));
+    if let Some((n, path)) = state.finish_rejects()? {
+        report.push(format!(
+            "convert: {n} rejected line(s) -> {}",
+            path.display()
+        ));
+    }
+    let manifest = write_manifest(&cfg.convert.out_dir, &opts, shards)?;
+    report.push(format!("convert: manifest -> {}", manifest.display()));
+
+    for line in report {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    let cfg: Config = match fs::read_to_string(&args.config) {
+        Ok(text) => toml::from_str(&text)
+            .with_context(|| format!("invalid config {}", args.config.display()))?,
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read {}", args.config.display()))
+        }
+    };
+    let opts = cfg.convert_options();
+    let plan = plan(&cfg, &opts)?;
+    if args.dry_run {
+        return print_plan(&cfg, &plan);
+    }
+    run(&cfg, plan)
+}
@@ -1,10 +1,22 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+use clap::ValueEnum;
 use glob::glob;
-use serde_json::Value;
+use protobuf_ethics::convert::{parse_reject_rate, RejectBudget};
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::error::EthicsError;
+use protobuf_ethics::input::{
+    decompressed_name, line_too_long, lines, open_maybe_compressed, InputFormat,
+    DEFAULT_MAX_LINE_BYTES,
+};
+use protobuf_ethics::logging::{LogArgs, LogFormat, RUN_TARGET};
+use serde_json::{json, Value};
+use tracing::{info, info_span, warn};
 
 const CUTOFF: usize = 1000;
 const COMMONSENSE_GLOB: &str = "data/raw/commonsense-*.jsonl";
@@ -18,17 +30,84 @@ fn keep(record: &Value) -> bool {
     text.trim().chars().count() <= CUTOFF
 }
 
+/// Fails when the `rejects` among `records` lines go beyond `budget`.
+fn check_budget(
+    budget: &RejectBudget,
+    scope: &str,
+    (rejects, records): (&BTreeMap<String, usize>, usize),
+    samples: &BTreeMap<String, Vec<String>>,
+    finished: bool,
+) -> Result<(), EthicsError> {
+    let rejected = rejects.values().sum();
+    if !budget.exceeded(rejected, records, finished) {
+        return Ok(());
+    }
+    Err(budget.error(scope, rejects, records, samples))
+}
+
+/// Removes `flag VALUE` or `flag=VALUE` from `args`, returning the value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let prefix = format!("{flag}=");
+    let Some(i) = args
+        .iter()
+        .position(|a| a == flag || a.starts_with(&prefix))
+    else {
+        return Ok(None);
+    };
+    let arg = args.remove(i);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        return Ok(Some(value.to_string()));
+    }
+    if i < args.len() {
+        Ok(Some(args.remove(i)))
+    } else {
+        Err(format!("{flag} needs a value"))
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    fs::create_dir_all(OUTDIR)?;
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // `--dry-run` checks the inputs and lists the outputs without writing.
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    args.retain(|a| a != "--dry-run");
+    // `--summary-json` prints one JSON object at the end instead of a line per file.
+    let summary_json = args.iter().any(|a| a == "--summary-json");
+    args.retain(|a| a != "--summary-json");
+    let log = LogArgs {
+        log_level: take_value(&mut args, "--log-level")?,
+        log_format: match take_value(&mut args, "--log-format")? {
+            Some(format) => LogFormat::from_str(&format, true)?,
+            None => LogFormat::Text,
+        },
+    };
+    log.init()?;
+    // Lines longer than `--max-line-bytes` (default 16 MiB) are skipped unread.
+    let max_line_bytes = match take_value(&mut args, "--max-line-bytes")? {
+        Some(v) => v.parse::<usize>()?,
+        None => DEFAULT_MAX_LINE_BYTES,
+    };
+    // Lines that aren't JSON or are too long are rejects; `--max-reject-rate` and
+    // `--max-rejects` bound them per file and over the run, as for the converter.
+    let budget = RejectBudget {
+        max_rate: take_value(&mut args, "--max-reject-rate")?
+            .map(|v| parse_reject_rate(&v))
+            .transpose()?,
+        max_rejects: take_value(&mut args, "--max-rejects")?
+            .map(|v| v.parse::<usize>())
+            .transpose()?,
+    };
+    let (mut run_rejects, mut run_records) = (BTreeMap::new(), 0);
+    let mut plan = DryRun::new();
+    let mut files = Vec::new();
 
-    let args: Vec<String> = env::args().skip(1).collect();
+    if !dry_run {
+        fs::create_dir_all(OUTDIR)?;
+    }
 
     let input_paths: Vec<PathBuf> = if args.is_empty() {
         let mut paths = Vec::new();
-        for entry in glob(COMMONSENSE_GLOB)? {
-            if let Ok(path) = entry {
-                paths.push(path);
-            }
+        for path in glob(COMMONSENSE_GLOB)?.flatten() {
+            paths.push(path);
         }
         paths
     } else {
@@ -37,45 +116,82 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     for inpath in input_paths {
         if !inpath.exists() {
-            eprintln!("skip: {} not found", inpath.display());
+            warn!("skip: {} not found", inpath.display());
             continue;
         }
 
-        let file_name = match inpath.file_name() {
-            Some(name) => name.to_os_string(),
-            None => {
-                eprintln!("skip: {} has no file name", inpath.display());
-                continue;
-            }
-        };
+        if inpath.file_name().is_none() {
+            warn!("skip: {} has no file name", inpath.display());
+            continue;
+        }
 
-        let outpath = Path::new(OUTDIR).join(file_name);
+        // Compressed inputs are written back out as plain JSONL.
+        let outpath = Path::new(OUTDIR).join(decompressed_name(&inpath));
 
-        let fin = File::open(&inpath)?;
-        let reader = BufReader::new(fin);
+        if dry_run {
+            plan.read_records(&inpath, InputFormat::Jsonl);
+            plan.write(&outpath, true);
+            continue;
+        }
+
+        let _span = info_span!("file", input = %inpath.display()).entered();
+        info!(target: RUN_TARGET, out = %outpath.display(), "started");
+        let started = Instant::now();
+        let reader = open_maybe_compressed(&inpath)?;
 
         let fout = File::create(&outpath)?;
         let mut writer = BufWriter::new(fout);
 
         let mut kept: usize = 0;
         let mut dropped: usize = 0;
-
-        for line_result in reader.lines() {
-            let line = line_result?;
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
+        let (mut rejects, mut records) = (BTreeMap::new(), 0);
+        let mut samples = BTreeMap::new();
+        let scope = inpath.display().to_string();
+
+        // A BOM and CRLF endings are dropped, so Windows-written files prune the same.
+        for line_result in lines(reader, max_line_bytes) {
+            let (n, line) = match line_result {
+                Ok((n, line)) => (n, Some(line)),
+                Err(e) => match line_too_long(&e) {
+                    Some(long) => {
+                        warn!(reason = "line_too_long", "{scope}: {long}; skipped");
+                        (long.line, None)
+                    }
+                    None => return Err(e.into()),
+                },
+            };
+            let trimmed = line.as_deref().map(str::trim);
+            if trimmed == Some("") {
                 continue;
             }
+            records += 1;
 
-            let record: Value = match serde_json::from_str(trimmed) {
+            let parsed = match trimmed {
+                Some(trimmed) => serde_json::from_str(trimmed).map_err(|_| "parse_error"),
+                None => Err("line_too_long"),
+            };
+            let record: Value = match parsed {
                 Ok(v) => v,
-                Err(_) => {
+                Err(reason) => {
+                    *rejects.entry(reason.to_string()).or_insert(0) += 1;
+                    let sample: &mut Vec<String> = samples.entry(reason.to_string()).or_default();
+                    if sample.len() < RejectBudget::SAMPLES {
+                        sample.push(format!("{scope}:{n}"));
+                    }
+                    if let Err(e) =
+                        check_budget(&budget, &scope, (&rejects, records), &samples, false)
+                    {
+                        // Don't leave a half-pruned file where the next step looks.
+                        drop(writer);
+                        fs::remove_file(&outpath)?;
+                        return Err(e.into());
+                    }
                     continue;
                 }
             };
 
             if keep(&record) {
-                writer.write_all(trimmed.as_bytes())?;
+                writer.write_all(trimmed.unwrap_or_default().as_bytes())?;
                 writer.write_all(b"\n")?;
                 kept += 1;
             } else {
@@ -84,18 +200,55 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
 
         writer.flush()?;
+        if let Err(e) = check_budget(&budget, &scope, (&rejects, records), &samples, true) {
+            drop(writer);
+            fs::remove_file(&outpath)?;
+            return Err(e.into());
+        }
+        let rejected: usize = rejects.values().sum();
+        for (reason, n) in rejects {
+            *run_rejects.entry(reason).or_insert(0) += n;
+        }
+        run_records += records;
+        info!(
+            target: RUN_TARGET,
+            kept,
+            dropped,
+            rejected,
+            secs = started.elapsed().as_secs_f64(),
+            "finished"
+        );
 
+        if summary_json {
+            files.push(json!({"input": inpath, "out": outpath, "kept": kept, "dropped": dropped, "rejected": rejected}));
+            continue;
+        }
         println!(
             "{}: kept={} dropped={} -> {}",
-            inpath
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy(),
+            inpath.file_name().unwrap_or_default().to_string_lossy(),
             kept,
             dropped,
             outpath.display()
         );
     }
 
+    if dry_run {
+        return Ok(plan.finish()?);
+    }
+    if summary_json {
+        let kept: u64 = files.iter().filter_map(|f| f["kept"].as_u64()).sum();
+        let dropped: u64 = files.iter().filter_map(|f| f["dropped"].as_u64()).sum();
+        println!(
+            "{}",
+            json!({"files": files, "kept": kept, "dropped": dropped, "cutoff": CUTOFF})
+        );
+    }
+    check_budget(
+        &budget,
+        "run",
+        (&run_rejects, run_records),
+        &BTreeMap::new(),
+        true,
+    )?;
     Ok(())
 }
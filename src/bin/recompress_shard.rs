@@ -0,0 +1,260 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::index::ShardIndex;
+use protobuf_ethics::input::is_stdio;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::shard::ShardReader;
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "recompress-shard",
+    about = "Rewrite shards with new compression settings, copying records and headers through unchanged."
+)]
+#[command(group(clap::ArgGroup::new("dest").required(true).args(["out", "in_place"])))]
+struct Args {
+    /// Shards to recompress.
+    #[arg(value_name = "PB_ZST", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Recompressed shard, for a single input.
+    #[arg(long, value_name = "OUT")]
+    out: Option<PathBuf>,
+
+    /// Replace each input with its recompressed version.
+    #[arg(long)]
+    in_place: bool,
+
+    /// zstd compression level (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary to compress with; its SHA-256 replaces the one in the header.
+    #[arg(long, value_name = "DICT", conflicts_with = "no_compress")]
+    dict: Option<PathBuf>,
+
+    /// zstd dictionary the inputs were compressed with, when it isn't `--dict`.
+    #[arg(long, value_name = "DICT")]
+    input_dict: Option<PathBuf>,
+
+    /// Start a new zstd frame every N examples and write a `<shard>.idx` sidecar.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "no_compress")]
+    frame_every: Option<u64>,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the inputs and list what would be replaced without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// Before and after, for the summary.
+struct Sizes {
+    records: u64,
+    before: u64,
+    after: u64,
+    /// The decompressed stream, header included.
+    raw: u64,
+}
+
+/// The recompressed shard and its index until they are moved into place; removed
+/// if dropped before that, e.g. when verification fails.
+struct Staged {
+    path: PathBuf,
+    index: PathBuf,
+}
+
+impl Staged {
+    fn beside(dest: &Path) -> Result<Self> {
+        let name = dest
+            .file_name()
+            .with_context(|| format!("{} is not a file path", dest.display()))?;
+        let path = dest.with_file_name(format!(".{}.recompress", name.to_string_lossy()));
+        let index = ShardIndex::path_for(&path);
+        Ok(Staged { path, index })
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(&self.index);
+    }
+}
+
+/// Copies the records of `input` into a staged shard next to `dest`, checks the
+/// staged shard holds as many, and only then moves it over `dest`.
+fn recompress(
+    input: &Path,
+    dest: &Path,
+    args: &Args,
+    dict: Option<&Dictionary>,
+    input_dict: Option<&Dictionary>,
+) -> Result<Sizes> {
+    let before = fs::metadata(input)
+        .with_context(|| format!("failed to open shard {}", input.display()))?
+        .len();
+    let mut reader = ShardReader::open(input, input_dict)?;
+    let staged = Staged::beside(dest)?;
+
+    let mut writer = ExampleWriter::create(&staged.path).overwrite(true);
+    writer = match &reader.header {
+        // The writer only sets `checksums` and `dict_sha256`; everything else is kept.
+        Some(h) => writer.with_header(h.clone()).checksums(h.checksums),
+        None => writer.without_header(),
+    };
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = dict {
+        writer = writer.dict(dict);
+    }
+    if let Some(n) = args.frame_every {
+        writer = writer.frame_every(n);
+    }
+    let mut enc = writer.open()?;
+    while let Some(record) = reader.next_record()? {
+        enc.write_record(record)?;
+    }
+    let raw = reader.offset();
+    let records = enc.examples();
+    let (after, _) = enc.finish()?;
+
+    let mut check = ShardReader::open(&staged.path, dict)?;
+    let mut written = 0;
+    while check.next_record()?.is_some() {
+        written += 1;
+    }
+    ensure!(
+        written == records,
+        "recompressed {} holds {written} record(s), not {records}; left {} as it was",
+        input.display(),
+        dest.display()
+    );
+
+    fs::rename(&staged.path, dest).with_context(|| {
+        format!(
+            "failed to move {} to {}",
+            staged.path.display(),
+            dest.display()
+        )
+    })?;
+    // An index describes one exact file, so the old one goes whether or not there is a new one.
+    let dest_index = ShardIndex::path_for(dest);
+    if args.frame_every.is_some() {
+        fs::rename(&staged.index, &dest_index).with_context(|| {
+            format!(
+                "failed to move {} to {}",
+                staged.index.display(),
+                dest_index.display()
+            )
+        })?;
+    } else if dest_index.exists() {
+        fs::remove_file(&dest_index)
+            .with_context(|| format!("failed to remove stale {}", dest_index.display()))?;
+    }
+    Ok(Sizes {
+        records,
+        before,
+        after,
+        raw,
+    })
+}
+
+fn run(args: Args) -> Result<()> {
+    ensure!(
+        args.out.is_none() || args.inputs.len() == 1,
+        "--out takes a single input; use --in-place for several"
+    );
+    for input in &args.inputs {
+        ensure!(
+            !is_stdio(input),
+            "recompress_shard reads shard files, not stdin"
+        );
+    }
+    if let Some(out) = &args.out {
+        ensure!(
+            !is_stdio(out),
+            "recompress_shard writes a shard file, not stdout"
+        );
+    }
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let input_dict = args
+        .input_dict
+        .as_deref()
+        .map(Dictionary::load)
+        .transpose()?;
+    let input_dict = input_dict.as_ref().or(dict.as_ref());
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        for input in &args.inputs {
+            plan.read_shard(input, input_dict);
+            let dest = args.out.as_deref().unwrap_or(input);
+            // `--in-place` replaces the input by design.
+            plan.write(dest, args.overwrite || args.in_place);
+            let dest_index = ShardIndex::path_for(dest);
+            if args.frame_every.is_some() {
+                plan.write(&dest_index, true);
+            } else if dest_index.exists() {
+                plan.remove(&dest_index);
+            }
+        }
+        return Ok(plan.finish()?);
+    }
+    if let Some(out) = &args.out {
+        ensure!(
+            args.overwrite || !out.exists(),
+            "{} already exists (pass --overwrite to replace it)",
+            out.display()
+        );
+    }
+
+    let (mut before, mut after) = (0, 0);
+    for input in &args.inputs {
+        let dest = args.out.as_deref().unwrap_or(input);
+        let sizes = recompress(input, dest, &args, dict.as_ref(), input_dict)
+            .with_context(|| format!("failed to recompress {}", input.display()))?;
+        println!(
+            "{} -> {}: {} record(s), {} -> {} bytes ({:.2}x smaller), zstd ratio {:.2}x",
+            input.display(),
+            dest.display(),
+            sizes.records,
+            sizes.before,
+            sizes.after,
+            sizes.before as f64 / sizes.after.max(1) as f64,
+            sizes.raw as f64 / sizes.after.max(1) as f64
+        );
+        before += sizes.before;
+        after += sizes.after;
+    }
+    if args.inputs.len() > 1 {
+        println!(
+            "total: {before} -> {after} bytes ({:.2}x smaller)",
+            before as f64 / after.max(1) as f64
+        );
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
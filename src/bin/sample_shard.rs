@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::sample::Reservoir;
+use protobuf_ethics::shard;
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracing::warn;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "sample-shard",
+    about = "Draw a uniform or per-label sample of ethics.v1.Example shards in one pass into a new shard."
+)]
+#[command(group(clap::ArgGroup::new("size").required(true).args(["n", "fraction", "per_label"])))]
+struct Args {
+    /// Shards to sample from, as one stream.
+    #[arg(value_name = "PB_ZST", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Output shard.
+    #[arg(long, value_name = "OUT")]
+    out: PathBuf,
+
+    /// Exactly N examples, drawn uniformly (reservoir sampling).
+    #[arg(long, value_name = "N")]
+    n: Option<usize>,
+
+    /// Each example independently with probability F, e.g. 0.01 for a 1% sample.
+    #[arg(long, value_name = "F")]
+    fraction: Option<f64>,
+
+    /// N examples of every label, drawn uniformly within each label.
+    #[arg(long, value_name = "N")]
+    per_label: Option<usize>,
+
+    /// The same seed draws the same sample from the same inputs.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// zstd compression level of the output (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the output as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the inputs were compressed with; the output is compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums to the output.
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the inputs and list the output without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// How examples are chosen. The reservoirs hold at most the requested sample;
+/// `--fraction` keeps nothing and writes examples as they stream past.
+enum Sampler {
+    Uniform(usize, Reservoir<Example>),
+    Fraction(f64),
+    /// One reservoir per label; each example carries its position in the whole stream.
+    PerLabel(usize, BTreeMap<i32, Reservoir<(u64, Example)>>),
+}
+
+/// Counts per label, for the summary.
+#[derive(Default)]
+struct Tally {
+    seen: BTreeMap<i32, u64>,
+    kept: BTreeMap<i32, u64>,
+}
+
+fn run(args: Args) -> Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        for path in &args.inputs {
+            plan.read_shard(path, dict.as_ref());
+        }
+        plan.write(&args.out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    let mut sampler = match (args.n, args.fraction, args.per_label) {
+        (Some(n), ..) => Sampler::Uniform(n, Reservoir::new(n)),
+        (_, Some(p), _) => {
+            ensure!(
+                (0.0..=1.0).contains(&p),
+                "--fraction must be in [0, 1], got {p}"
+            );
+            Sampler::Fraction(p)
+        }
+        (.., Some(n)) => Sampler::PerLabel(n, BTreeMap::new()),
+        _ => unreachable!("clap requires one of --n, --fraction, --per-label"),
+    };
+    let mut rng = StdRng::seed_from_u64(args.seed);
+
+    let mut readers = Vec::new();
+    let mut checksums = args.checksums;
+    let mut subset_split: Option<(String, String)> = None;
+    for path in &args.inputs {
+        let reader = ExampleReader::open_with_dict(path, dict.as_ref())
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        checksums |= reader.header().is_some_and(|h| h.checksums);
+        // The output header names a subset/split only when every input agrees on one.
+        let this = reader
+            .header()
+            .map(|h| (h.subset.clone(), h.split.clone()))
+            .unwrap_or_default();
+        subset_split = match subset_split {
+            None => Some(this),
+            Some(common) if common == this => Some(common),
+            _ => Some(Default::default()),
+        };
+        readers.push((path, reader));
+    }
+    let (subset, split) = subset_split.unwrap_or_default();
+
+    let mut writer = ExampleWriter::create(&args.out)
+        .with_header(shard::header("ethics.v1.Example", &subset, &split))
+        .checksums(checksums)
+        .overwrite(args.overwrite);
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+
+    let mut tally = Tally::default();
+    let mut index = 0u64;
+    for (path, reader) in readers {
+        for ex in reader {
+            let ex = ex.with_context(|| format!("failed to read {}", path.display()))?;
+            *tally.seen.entry(ex.label).or_insert(0) += 1;
+            match &mut sampler {
+                Sampler::Uniform(_, reservoir) => reservoir.push(&mut rng, ex),
+                Sampler::Fraction(p) => {
+                    if rng.random_bool(*p) {
+                        *tally.kept.entry(ex.label).or_insert(0) += 1;
+                        enc.write(&ex)?;
+                    }
+                }
+                Sampler::PerLabel(n, reservoirs) => reservoirs
+                    .entry(ex.label)
+                    .or_insert_with(|| Reservoir::new(*n))
+                    .push(&mut rng, (index, ex)),
+            }
+            index += 1;
+        }
+    }
+
+    // Reservoir samples are written back in input order.
+    let sample: Vec<Example> = match sampler {
+        Sampler::Uniform(n, reservoir) => {
+            if reservoir.seen() < n as u64 {
+                warn!(
+                    "asked for {n} example(s) but the input has only {}; taking all of them",
+                    reservoir.seen()
+                );
+            }
+            reservoir
+                .into_sorted()
+                .into_iter()
+                .map(|(_, ex)| ex)
+                .collect()
+        }
+        Sampler::Fraction(_) => Vec::new(),
+        Sampler::PerLabel(n, reservoirs) => {
+            let mut all = Vec::new();
+            for (label, reservoir) in reservoirs {
+                if reservoir.seen() < n as u64 {
+                    warn!(
+                        "label {label} has only {} example(s), fewer than {n}; taking all of them",
+                        reservoir.seen()
+                    );
+                }
+                all.extend(reservoir.into_sorted().into_iter().map(|(_, kept)| kept));
+            }
+            all.sort_unstable_by_key(|(index, _)| *index);
+            all.into_iter().map(|(_, ex)| ex).collect()
+        }
+    };
+    for ex in &sample {
+        *tally.kept.entry(ex.label).or_insert(0) += 1;
+        enc.write(ex)?;
+    }
+
+    let examples = enc.examples();
+    let (bytes, _) = enc.finish()?;
+    println!(
+        "sampled {examples} of {index} example(s) -> {} ({bytes} bytes)",
+        args.out.display()
+    );
+    for (label, seen) in &tally.seen {
+        let kept = tally.kept.get(label).copied().unwrap_or(0);
+        println!("  label {label}: {kept} of {seen}");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,253 @@
+//! gRPC `ethics.v1.ExampleService` over the shards under a directory, so remote
+//! trainers can stream examples without copying shard files. Built with
+//! `--features grpc`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::error::EthicsError;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::index::ShardIndex;
+use protobuf_ethics::input::is_pairs_shard;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/grpc/ethics.v1.rs"));
+}
+
+use pb::example_service_server::{ExampleService, ExampleServiceServer};
+use pb::{
+    GetShardInfoRequest, GetShardInfoResponse, ListShardsRequest, ListShardsResponse,
+    StreamExamplesRequest,
+};
+
+/// Examples buffered per stream ahead of a slow client.
+const STREAM_BUFFER: usize = 256;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "server",
+    about = "Serve the Example shards under a directory over gRPC (ethics.v1.ExampleService)."
+)]
+struct Args {
+    /// Directory holding the shards; every `*.pb.zst` and `*.pb` below it is served.
+    #[arg(value_name = "DIR")]
+    root: PathBuf,
+
+    /// Address to listen on; port 0 picks a free one, printed at startup.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:50051")]
+    addr: SocketAddr,
+
+    /// zstd dictionary the shards were compressed with.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+struct Shards {
+    root: PathBuf,
+    dict: Option<Dictionary>,
+}
+
+fn status(e: EthicsError) -> Status {
+    let message = e.to_string();
+    match e.root() {
+        EthicsError::Io { source, .. } if source.kind() == ErrorKind::NotFound => {
+            Status::not_found(message)
+        }
+        EthicsError::InvalidArgument(_) => Status::invalid_argument(message),
+        EthicsError::SchemaMismatch(_) => Status::failed_precondition(message),
+        EthicsError::Corrupt(_) | EthicsError::Decode { .. } => Status::data_loss(message),
+        _ => Status::internal(message),
+    }
+}
+
+impl Shards {
+    /// `Example` shards below the root, as paths relative to it.
+    fn list(&self) -> Result<Vec<String>, Status> {
+        let mut names = BTreeSet::new();
+        for pattern in ["**/*.pb.zst", "**/*.pb"] {
+            let pattern = self.root.join(pattern).to_string_lossy().into_owned();
+            let paths = glob::glob(&pattern).map_err(|e| Status::internal(e.to_string()))?;
+            for path in paths.flatten() {
+                if !path.is_file() || is_pairs_shard(&path) {
+                    continue;
+                }
+                if let Ok(name) = path.strip_prefix(&self.root) {
+                    names.insert(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    /// The path of shard `name`, which must stay inside the root.
+    fn path(&self, name: &str) -> Result<PathBuf, Status> {
+        let relative = Path::new(name);
+        let inside = !name.is_empty()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !inside {
+            return Err(Status::invalid_argument(format!(
+                "shard {name:?} is not a path below the served directory"
+            )));
+        }
+        let path = self.root.join(relative);
+        if !path.is_file() {
+            return Err(Status::not_found(format!("no shard {name}")));
+        }
+        Ok(path)
+    }
+
+    fn info(&self, name: &str) -> Result<GetShardInfoResponse, Status> {
+        let path = self.path(name)?;
+        let size_bytes = fs::metadata(&path)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .len();
+        let reader = ExampleReader::open_with_dict(&path, self.dict.as_ref()).map_err(status)?;
+        let header = reader.header().cloned();
+        // The sidecar has the count; otherwise walk the length prefixes.
+        let index = ShardIndex::load_for(&ShardIndex::path_for(&path), &path).ok();
+        let examples = match &index {
+            Some(index) => index.examples(),
+            None => reader.count().map_err(status)?,
+        };
+        Ok(GetShardInfoResponse {
+            shard: name.to_string(),
+            size_bytes,
+            header,
+            examples,
+            indexed: index.is_some(),
+        })
+    }
+
+    /// Sends the examples `req` asks for to `tx` until they run out, the limit is
+    /// reached or the client goes away.
+    fn stream(&self, req: StreamExamplesRequest, tx: mpsc::Sender<Result<Example, Status>>) {
+        let send_all = || -> Result<(), Status> {
+            let path = self.path(&req.shard)?;
+            let dict = self.dict.as_ref();
+            let examples: Box<dyn Iterator<Item = protobuf_ethics::error::Result<Example>>> =
+                if ShardIndex::path_for(&path).exists() {
+                    let reader = ExampleReader::open_indexed(&path, dict).map_err(status)?;
+                    Box::new(reader.range(req.start_index..u64::MAX).map_err(status)?)
+                } else {
+                    // Without an index, decode up to the start so errors there still surface.
+                    let mut reader = ExampleReader::open_with_dict(&path, dict).map_err(status)?;
+                    for ex in reader.by_ref().take(req.start_index as usize) {
+                        ex.map_err(status)?;
+                    }
+                    Box::new(reader)
+                };
+            let limit = if req.limit == 0 { u64::MAX } else { req.limit };
+            let mut sent = 0;
+            for ex in examples {
+                if sent == limit {
+                    break;
+                }
+                let ex = ex.map_err(status)?;
+                if !req.label_filter.is_empty() && !req.label_filter.contains(&ex.label) {
+                    continue;
+                }
+                if tx.blocking_send(Ok(ex)).is_err() {
+                    return Ok(());
+                }
+                sent += 1;
+            }
+            Ok(())
+        };
+        if let Err(status) = send_all() {
+            let _ = tx.blocking_send(Err(status));
+        }
+    }
+}
+
+struct Service(Arc<Shards>);
+
+#[tonic::async_trait]
+impl ExampleService for Service {
+    async fn list_shards(
+        &self,
+        _: Request<ListShardsRequest>,
+    ) -> Result<Response<ListShardsResponse>, Status> {
+        let shards = self.0.clone();
+        let shards = tokio::task::spawn_blocking(move || shards.list())
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(ListShardsResponse { shards }))
+    }
+
+    async fn get_shard_info(
+        &self,
+        req: Request<GetShardInfoRequest>,
+    ) -> Result<Response<GetShardInfoResponse>, Status> {
+        let shards = self.0.clone();
+        let name = req.into_inner().shard;
+        let info = tokio::task::spawn_blocking(move || shards.info(&name))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(info))
+    }
+
+    type StreamExamplesStream = ReceiverStream<Result<Example, Status>>;
+
+    async fn stream_examples(
+        &self,
+        req: Request<StreamExamplesRequest>,
+    ) -> Result<Response<Self::StreamExamplesStream>, Status> {
+        let req = req.into_inner();
+        // Fail fast on a bad name rather than in the stream.
+        self.0.path(&req.shard)?;
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let shards = self.0.clone();
+        tokio::task::spawn_blocking(move || shards.stream(req, tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    anyhow::ensure!(
+        args.root.is_dir(),
+        "{} is not a directory",
+        args.root.display()
+    );
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let shards = Arc::new(Shards {
+        root: args.root.clone(),
+        dict,
+    });
+    let listener = TcpListener::bind(args.addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.addr))?;
+    let addr = listener.local_addr()?;
+    info!(
+        "serving {} shard(s) from {} on {addr}",
+        shards.list()?.len(),
+        args.root.display()
+    );
+    tonic::transport::Server::builder()
+        .add_service(ExampleServiceServer::new(Service(shards)))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .context("server failed")
+}
@@ -0,0 +1,280 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::convert::Mode;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::input::{is_pairs_shard, is_stdio};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::shard::{self, ShardReader};
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "shuffle-shard",
+    about = "Shuffle the records of a shard larger than memory, reproducibly, via temporary bucket files."
+)]
+struct Args {
+    /// Shard to shuffle (`-` for stdin).
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Shuffled shard.
+    #[arg(long, value_name = "OUT")]
+    out: PathBuf,
+
+    /// The same seed shuffles the same input into the same order.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Temporary bucket files to scatter records into; each must fit in `--buffer-mem`
+    /// when it is shuffled, so raise this for larger inputs.
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..=4096))]
+    buckets: u64,
+
+    /// Memory for write buffers while scattering, split across the buckets, and the
+    /// size a bucket is expected to stay under when it is shuffled in memory.
+    #[arg(long, value_name = "BYTES", default_value_t = 256 << 20, value_parser = clap::value_parser!(u64).range(1..))]
+    buffer_mem: u64,
+
+    /// Directory for the bucket files; defaults to OUT's directory. Removed afterwards,
+    /// also on error.
+    #[arg(long, value_name = "DIR")]
+    tmp_dir: Option<PathBuf>,
+
+    /// Re-read OUT and check it holds the same records as the input, by count and
+    /// an order-independent digest.
+    #[arg(long)]
+    verify: bool,
+
+    /// zstd compression level of the output (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the output as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the input was compressed with; the output is compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums (kept anyway when the input has them).
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace OUT if it exists.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the input and list the output without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// The temporary bucket files, removed with their directory when dropped.
+struct Buckets {
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl Buckets {
+    fn create(parent: &Path, out: &Path, n: u64) -> Result<Self> {
+        let name = out
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "shuffle".into());
+        let dir = parent.join(format!(".{name}.buckets-{}", std::process::id()));
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let paths = (0..n).map(|i| dir.join(format!("{i:04}.pb"))).collect();
+        Ok(Buckets { dir, paths })
+    }
+}
+
+impl Drop for Buckets {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!("failed to remove {}: {e}", self.dir.display());
+        }
+    }
+}
+
+/// Which bucket record `index` goes to, from a seeded hash (SplitMix64) of its position.
+fn bucket_of(seed: u64, index: u64, buckets: u64) -> usize {
+    let mut z = seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) % buckets) as usize
+}
+
+/// Count and order-independent digest of a set of records: the wrapping sum of
+/// each record's truncated SHA-256, so equal multisets give equal digests.
+#[derive(Default, PartialEq, Debug)]
+struct Digest128 {
+    records: u64,
+    sum: u128,
+}
+
+impl Digest128 {
+    fn add(&mut self, record: &[u8]) {
+        let hash = Sha256::digest(record);
+        self.records += 1;
+        self.sum = self
+            .sum
+            .wrapping_add(u128::from_be_bytes(hash[..16].try_into().unwrap()));
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    ensure!(
+        !is_stdio(&args.out),
+        "shuffle_shard writes a shard file, not stdout"
+    );
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        plan.read_shard(&args.input, dict.as_ref());
+        plan.write(&args.out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    let mut reader = ShardReader::open(&args.input, dict.as_ref())?;
+    shard::check_schema_version(reader.header.as_ref(), None)
+        .with_context(|| format!("cannot shuffle {}", args.input.display()))?;
+    let header = match &reader.header {
+        Some(h) => shard::header(&h.message, &h.subset, &h.split),
+        None if is_pairs_shard(&args.input) => shard::header(Mode::Pair.message(), "", ""),
+        None => shard::header(Mode::Example.message(), "", ""),
+    };
+    let checksums = args.checksums || reader.header.as_ref().is_some_and(|h| h.checksums);
+
+    let parent = match &args.tmp_dir {
+        Some(dir) => dir.clone(),
+        None => args
+            .out
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+    };
+    let buckets = Buckets::create(&parent, &args.out, args.buckets)?;
+
+    // First pass: scatter records, still length-delimited, into the bucket files. Each
+    // starts with the header so reading it back can't mistake a record for one.
+    let capacity = (args.buffer_mem / args.buckets).clamp(8 << 10, 64 << 20) as usize;
+    let bucket_header = shard::header(&header.message, "", "");
+    let mut files = buckets
+        .paths
+        .iter()
+        .map(|path| {
+            let mut file = BufWriter::with_capacity(capacity, File::create(path)?);
+            shard::write_header(&mut file, &bucket_header)?;
+            Ok(file)
+        })
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("failed to create bucket files in {}", parent.display()))?;
+    let mut input_digest = Digest128::default();
+    let mut len_buf = Vec::with_capacity(10);
+    while let Some(record) = reader.next_record()? {
+        let file = &mut files[bucket_of(args.seed, input_digest.records, args.buckets)];
+        len_buf.clear();
+        prost::encoding::encode_varint(record.len() as u64, &mut len_buf);
+        file.write_all(&len_buf)?;
+        file.write_all(record)?;
+        input_digest.add(record);
+    }
+    drop(reader);
+    for (file, path) in files.into_iter().zip(&buckets.paths) {
+        file.into_inner()
+            .map(drop)
+            .map_err(|e| e.into_error())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    // Second pass: shuffle each bucket in memory and append it to the output.
+    let mut writer = ExampleWriter::create(&args.out)
+        .with_header(header)
+        .checksums(checksums)
+        .overwrite(args.overwrite);
+    writer = if args.no_compress {
+        writer.uncompressed()
+    } else {
+        writer.zstd_level(args.zstd_level)
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut largest = 0;
+    for path in &buckets.paths {
+        let size = fs::metadata(path)?.len();
+        largest = largest.max(size);
+        if size > args.buffer_mem {
+            warn!(
+                "bucket {} holds {size} bytes, more than --buffer-mem {}; use more --buckets",
+                path.display(),
+                args.buffer_mem
+            );
+        }
+        let mut bucket = ShardReader::open(path, None)?;
+        let mut records = Vec::new();
+        while let Some(record) = bucket.next_record()? {
+            records.push(record.to_vec());
+        }
+        records.shuffle(&mut rng);
+        for record in &records {
+            enc.write_record(record)?;
+        }
+        // Each bucket's file goes as soon as it is written out, to free the disk space.
+        fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    let examples = enc.examples();
+    let (bytes, sha256) = enc.finish()?;
+    drop(buckets);
+
+    if args.verify {
+        let mut reader = ShardReader::open(&args.out, dict.as_ref())?;
+        let mut output_digest = Digest128::default();
+        while let Some(record) = reader.next_record()? {
+            output_digest.add(record);
+        }
+        ensure!(
+            output_digest == input_digest,
+            "{} is not a permutation of {}: {} record(s) vs {}, digests {:032x} vs {:032x}",
+            args.out.display(),
+            args.input.display(),
+            output_digest.records,
+            input_digest.records,
+            output_digest.sum,
+            input_digest.sum
+        );
+        println!(
+            "verified: same {} record(s) as the input",
+            input_digest.records
+        );
+    }
+    println!(
+        "shuffled {examples} record(s) through {} bucket(s) (largest {largest} bytes) -> {} ({bytes} bytes, sha256 {sha256})",
+        args.buckets,
+        args.out.display()
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::input::is_stdio;
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::shard;
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use sha2::{Digest, Sha256};
+
+/// CLI arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "split-shard",
+    about = "Carve a validation set out of a shard of ethics.v1.Example messages, reproducibly."
+)]
+struct Args {
+    /// Shard to split (read twice, so not stdin).
+    #[arg(value_name = "PB_ZST")]
+    input: PathBuf,
+
+    /// Output for the training side.
+    #[arg(long, value_name = "OUT")]
+    train_out: PathBuf,
+
+    /// Output for the validation side.
+    #[arg(long, value_name = "OUT")]
+    val_out: PathBuf,
+
+    /// Fraction of examples kept for training.
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = 0.95,
+        conflicts_with = "val_count"
+    )]
+    ratio: f64,
+
+    /// Exact number of validation examples, instead of `--ratio`.
+    #[arg(long, value_name = "N")]
+    val_count: Option<u64>,
+
+    /// Which examples go to validation depends only on the seed and each example's id
+    /// (its index when it has none), so reruns are identical.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Split each label separately, so both sides keep the input's label ratio.
+    #[arg(long)]
+    stratify_by_label: bool,
+
+    /// `split` written into the training examples and header.
+    #[arg(long, value_name = "NAME", default_value = "train")]
+    train_split: String,
+
+    /// `split` written into the validation examples and header.
+    #[arg(long, value_name = "NAME", default_value = "validation")]
+    val_split: String,
+
+    /// zstd compression level of the outputs (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write the outputs as raw length-delimited protobuf.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// zstd dictionary the shard was compressed with; the outputs are compressed with it too.
+    #[arg(long, value_name = "DICT")]
+    dict: Option<PathBuf>,
+
+    /// Add record checksums (kept anyway when the input has them).
+    #[arg(long)]
+    checksums: bool,
+
+    /// Replace the outputs if they exist.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check the input and list the outputs without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// Where an example falls in the seeded order; the lowest go to validation.
+fn rank(seed: u64, id: &str, index: u64) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_le_bytes());
+    if id.is_empty() {
+        hasher.update(b"\0index");
+        hasher.update(index.to_le_bytes());
+    } else {
+        hasher.update(id.as_bytes());
+    }
+    u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap())
+}
+
+/// Splits `total` over groups of the given sizes in proportion, by largest remainder,
+/// so the shares add up to exactly `total`.
+fn apportion(sizes: &BTreeMap<i32, u64>, total: u64) -> BTreeMap<i32, u64> {
+    let all: u64 = sizes.values().sum();
+    let mut shares: BTreeMap<i32, u64> = BTreeMap::new();
+    let mut remainders = Vec::new();
+    for (&label, &n) in sizes {
+        let exact = n as u128 * total as u128;
+        shares.insert(label, (exact / all as u128) as u64);
+        remainders.push((exact % all as u128, label));
+    }
+    let left = total - shares.values().sum::<u64>();
+    // Largest remainders first; ties go to the smaller label.
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, label) in remainders.into_iter().take(left as usize) {
+        *shares.get_mut(&label).unwrap() += 1;
+    }
+    shares
+}
+
+/// One side of the split: its output and what went into it.
+struct Side<'a> {
+    writer: ExampleWriter<'a>,
+    split: &'a str,
+    path: &'a Path,
+    labels: BTreeMap<i32, u64>,
+}
+
+impl<'a> Side<'a> {
+    fn open(
+        path: &'a Path,
+        split: &'a str,
+        subset: &str,
+        checksums: bool,
+        args: &Args,
+        dict: Option<&'a Dictionary>,
+    ) -> Result<Self> {
+        let mut writer = ExampleWriter::create(path)
+            .with_header(shard::header("ethics.v1.Example", subset, split))
+            .checksums(checksums)
+            .overwrite(args.overwrite);
+        writer = if args.no_compress {
+            writer.uncompressed()
+        } else {
+            writer.zstd_level(args.zstd_level)
+        };
+        if let Some(dict) = dict {
+            writer = writer.dict(dict);
+        }
+        Ok(Side {
+            writer: writer.open()?,
+            split,
+            path,
+            labels: BTreeMap::new(),
+        })
+    }
+
+    fn report(self) -> Result<()> {
+        let n = self.writer.examples();
+        let path = self.path.display().to_string();
+        let (bytes, _) = self.writer.finish()?;
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(label, k)| format!("{label}: {k} ({:.1}%)", 100.0 * *k as f64 / n.max(1) as f64))
+            .collect();
+        println!(
+            "{}: {n} example(s) -> {path} ({bytes} bytes); labels {}",
+            self.split,
+            labels.join(", ")
+        );
+        Ok(())
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    ensure!(
+        !is_stdio(&args.input),
+        "split_shard reads its input twice and can't split stdin"
+    );
+    ensure!(
+        args.ratio > 0.0 && args.ratio <= 1.0,
+        "--ratio must be in (0, 1], got {}",
+        args.ratio
+    );
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    if args.dry_run {
+        let mut plan = DryRun::new();
+        plan.read_shard(&args.input, dict.as_ref());
+        plan.write(&args.train_out, args.overwrite);
+        plan.write(&args.val_out, args.overwrite);
+        return Ok(plan.finish()?);
+    }
+    let open = || {
+        ExampleReader::open_with_dict(&args.input, dict.as_ref())
+            .with_context(|| format!("failed to read {}", args.input.display()))
+    };
+
+    // First pass: each example's rank and label, then the validation set.
+    let mut ranked: Vec<(u64, u64, i32)> = Vec::new();
+    let reader = open()?;
+    // Headerless shards only say what subset they hold in their records.
+    let mut subset = reader.header().map(|h| h.subset.clone());
+    for (index, ex) in (0u64..).zip(reader) {
+        let ex = ex?;
+        subset.get_or_insert_with(|| ex.subset.clone());
+        ranked.push((rank(args.seed, &ex.id, index), index, ex.label));
+    }
+    let subset = subset.unwrap_or_default();
+    let total = ranked.len() as u64;
+    let val_total = match args.val_count {
+        Some(n) => {
+            ensure!(
+                n <= total,
+                "--val-count {n} is more than the {total} example(s) in {}",
+                args.input.display()
+            );
+            n
+        }
+        None => (total as f64 * (1.0 - args.ratio)).round() as u64,
+    };
+    ranked.sort_unstable();
+    let mut is_val = vec![false; ranked.len()];
+    if args.stratify_by_label {
+        let mut sizes: BTreeMap<i32, u64> = BTreeMap::new();
+        for &(_, _, label) in &ranked {
+            *sizes.entry(label).or_insert(0) += 1;
+        }
+        let mut quota = apportion(&sizes, val_total);
+        for &(_, index, label) in &ranked {
+            let left = quota.get_mut(&label).unwrap();
+            if *left > 0 {
+                *left -= 1;
+                is_val[index as usize] = true;
+            }
+        }
+    } else {
+        for &(_, index, _) in ranked.iter().take(val_total as usize) {
+            is_val[index as usize] = true;
+        }
+    }
+    drop(ranked);
+
+    // Second pass: rewrite `split` and send each example to its side.
+    let reader = open()?;
+    let checksums = args.checksums || reader.header().is_some_and(|h| h.checksums);
+    let dict = dict.as_ref();
+    let mut train = Side::open(
+        &args.train_out,
+        &args.train_split,
+        &subset,
+        checksums,
+        &args,
+        dict,
+    )?;
+    let mut val = Side::open(
+        &args.val_out,
+        &args.val_split,
+        &subset,
+        checksums,
+        &args,
+        dict,
+    )?;
+    for (index, ex) in (0u64..).zip(reader) {
+        let mut ex = ex?;
+        let side = if is_val[index as usize] {
+            &mut val
+        } else {
+            &mut train
+        };
+        ex.split = side.split.to_string();
+        *side.labels.entry(ex.label).or_insert(0) += 1;
+        side.writer.write(&ex)?;
+    }
+    train.report()?;
+    val.report()?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+    run(args)
+}
@@ -0,0 +1,379 @@
+//! Shards written straight to object storage, for `--out s3://bucket/key` and
+//! `--out gs://bucket/key`. Built with the `cloud` feature.
+//!
+//! The shard is streamed through a multipart upload in [`PART_SIZE`] parts, so
+//! nothing is staged on local disk; at most a few parts are held in memory. Failed
+//! parts are retried with backoff, and an upload that fails or is dropped before
+//! [`Upload::complete`] is aborted so no incomplete parts are left to be billed.
+//!
+//! Both schemes go through the AWS SDK: `s3://` against S3 (or `AWS_ENDPOINT_URL`),
+//! `gs://` against Cloud Storage's S3-compatible XML API at
+//! `storage.googleapis.com`. Credentials come from the SDK's default chain
+//! (environment, profile, web identity, instance metadata); for `gs://` that means
+//! an HMAC key in `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY` or a profile.
+//!
+//! The SDK is async; the upload runs on its own thread and runtime, so the
+//! [`Write`] side can be used from anywhere, async code included.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::error::{bail, EthicsError, Result};
+
+/// Bytes per uploaded part. S3 allows 10,000 parts, so shards up to ~160 GB.
+pub const PART_SIZE: usize = 16 << 20;
+
+/// Attempts after the first for a failed part.
+const PART_RETRIES: u32 = 5;
+
+/// Wait before the first retry of a part; doubled for each one after.
+const PART_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Parts queued for the upload thread before `write` blocks.
+const QUEUED_PARTS: usize = 2;
+
+/// Cloud Storage's S3-compatible endpoint.
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// An `s3://` or `gs://` URL split into its parts.
+#[derive(Debug, Clone)]
+struct ObjectUrl {
+    url: String,
+    gcs: bool,
+    bucket: String,
+    key: String,
+}
+
+impl ObjectUrl {
+    fn parse(path: &Path) -> Result<Self> {
+        let url = path.to_string_lossy().into_owned();
+        let (gcs, rest) = if let Some(rest) = url.strip_prefix("s3://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("gs://") {
+            (true, rest)
+        } else {
+            bail!(InvalidArgument, "{url} is not an s3:// or gs:// URL");
+        };
+        let Some((bucket, key)) = rest
+            .split_once('/')
+            .filter(|(b, k)| !b.is_empty() && !k.is_empty())
+        else {
+            bail!(
+                InvalidArgument,
+                "{url} does not name an object (s3://<bucket>/<key>)"
+            );
+        };
+        Ok(ObjectUrl {
+            gcs,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            url,
+        })
+    }
+}
+
+fn upload_error(url: &str, what: impl std::fmt::Display) -> EthicsError {
+    io::Error::other(format!("{url}: {what}")).into()
+}
+
+enum Msg {
+    Part(Bytes),
+    Complete,
+}
+
+/// A multipart upload being written. Bytes are gathered into parts here and
+/// uploaded in order by a background thread.
+pub struct Upload {
+    url: String,
+    buf: Vec<u8>,
+    parts: usize,
+    tx: Option<SyncSender<Msg>>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+impl Upload {
+    /// Starts a multipart upload to `path`, refusing to replace an existing
+    /// object unless `overwrite`. Fails here, not on first write, when the
+    /// credentials or bucket are wrong.
+    pub fn start(path: &Path, overwrite: bool) -> Result<Self> {
+        let object = ObjectUrl::parse(path)?;
+        let url = object.url.clone();
+        let (tx, rx) = mpsc::sync_channel(QUEUED_PARTS);
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("upload".into())
+            .spawn(move || run(object, overwrite, rx, ready_tx))?;
+        let mut upload = Upload {
+            url,
+            buf: Vec::with_capacity(PART_SIZE),
+            parts: 0,
+            tx: Some(tx),
+            worker: Some(worker),
+        };
+        if ready_rx.recv().is_err() {
+            // The worker only hangs up early when it failed to start.
+            return Err(upload.join());
+        }
+        Ok(upload)
+    }
+
+    /// Hands a part to the upload thread, or returns the error it stopped on.
+    fn send(&mut self, msg: Msg) -> Result<()> {
+        let sent = self.tx.as_ref().is_some_and(|tx| tx.send(msg).is_ok());
+        if sent {
+            Ok(())
+        } else {
+            Err(self.join())
+        }
+    }
+
+    /// Waits for the upload thread, returning why it stopped early.
+    fn join(&mut self) -> EthicsError {
+        self.tx = None;
+        match self.worker.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e,
+            Some(Err(_)) => upload_error(&self.url, "upload thread panicked"),
+            _ => upload_error(&self.url, "upload already finished"),
+        }
+    }
+
+    fn flush_part(&mut self) -> Result<()> {
+        let part = std::mem::replace(&mut self.buf, Vec::with_capacity(PART_SIZE));
+        self.parts += 1;
+        self.send(Msg::Part(Bytes::from(part)))
+    }
+
+    /// Uploads what is buffered and completes the upload, making the object
+    /// visible.
+    pub fn complete(&mut self) -> Result<()> {
+        // An empty object is still one (empty) part.
+        if !self.buf.is_empty() || self.parts == 0 {
+            self.flush_part()?;
+        }
+        self.send(Msg::Complete)?;
+        self.tx = None;
+        match self.worker.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(upload_error(&self.url, "upload thread panicked")),
+            None => Err(upload_error(&self.url, "upload already finished")),
+        }
+    }
+}
+
+impl Write for Upload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(PART_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == PART_SIZE {
+            self.flush_part().map_err(io::Error::other)?;
+        }
+        Ok(n)
+    }
+
+    /// Parts only go out once full; S3 rejects small ones.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Hanging up without `Complete` makes the worker abort the upload; wait
+        // for that so the process does not exit first.
+        self.tx = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The upload thread: creates the upload, signals `ready`, uploads parts until
+/// told to complete, and aborts on any failure or hang-up.
+fn run(
+    object: ObjectUrl,
+    overwrite: bool,
+    rx: Receiver<Msg>,
+    ready: mpsc::Sender<()>,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let client = client(&object).await;
+        let url = &object.url;
+        if !overwrite {
+            let head = client
+                .head_object()
+                .bucket(&object.bucket)
+                .key(&object.key)
+                .send()
+                .await;
+            match head {
+                Ok(_) => bail!(
+                    InvalidArgument,
+                    "{url} already exists (pass --overwrite to replace it)"
+                ),
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {}
+                Err(e) => return Err(upload_error(url, DisplayErrorContext(e))),
+            }
+        }
+        let created = client
+            .create_multipart_upload()
+            .bucket(&object.bucket)
+            .key(&object.key)
+            .send()
+            .await
+            .map_err(|e| upload_error(url, DisplayErrorContext(e)))?;
+        let Some(upload_id) = created.upload_id else {
+            bail!(Corrupt, "{url}: the server returned no upload id");
+        };
+        let upload = MultipartUpload {
+            client,
+            object: &object,
+            upload_id,
+        };
+        let _ = ready.send(());
+        match upload.parts(&rx).await {
+            Ok(Some(parts)) => match upload.complete(parts).await {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    upload.abort().await;
+                    Err(e)
+                }
+            },
+            Ok(None) => {
+                upload.abort().await;
+                Ok(())
+            }
+            Err(e) => {
+                upload.abort().await;
+                Err(e)
+            }
+        }
+    })
+}
+
+async fn client(object: &ObjectUrl) -> Client {
+    let config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+    let mut builder = aws_sdk_s3::config::Builder::from(&config);
+    if object.gcs {
+        builder = builder
+            .endpoint_url(GCS_ENDPOINT)
+            .region(aws_sdk_s3::config::Region::new("auto"));
+    }
+    Client::from_conf(builder.build())
+}
+
+struct MultipartUpload<'a> {
+    client: Client,
+    object: &'a ObjectUrl,
+    upload_id: String,
+}
+
+impl MultipartUpload<'_> {
+    /// Uploads parts as they arrive; `None` if the writer hung up without
+    /// asking to complete.
+    async fn parts(&self, rx: &Receiver<Msg>) -> Result<Option<Vec<CompletedPart>>> {
+        let mut parts = Vec::new();
+        loop {
+            // Blocking is fine: nothing else runs on this thread's runtime.
+            match rx.recv() {
+                Ok(Msg::Part(body)) => {
+                    let number = parts.len() as i32 + 1;
+                    let e_tag = self.part(number, body).await?;
+                    parts.push(
+                        CompletedPart::builder()
+                            .part_number(number)
+                            .e_tag(e_tag)
+                            .build(),
+                    );
+                }
+                Ok(Msg::Complete) => return Ok(Some(parts)),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// Uploads part `number`, retrying with backoff; returns its ETag.
+    async fn part(&self, number: i32, body: Bytes) -> Result<String> {
+        let url = &self.object.url;
+        let mut attempt = 0;
+        loop {
+            let sent = self
+                .client
+                .upload_part()
+                .bucket(&self.object.bucket)
+                .key(&self.object.key)
+                .upload_id(&self.upload_id)
+                .part_number(number)
+                .body(ByteStream::from(body.clone()))
+                .send()
+                .await;
+            match sent {
+                Ok(out) => return Ok(out.e_tag.unwrap_or_default()),
+                Err(e) if attempt < PART_RETRIES => {
+                    warn!(
+                        "{url}: part {number} failed ({}), retrying",
+                        DisplayErrorContext(e)
+                    );
+                    thread::sleep(PART_BACKOFF * 2u32.pow(attempt));
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(upload_error(
+                        url,
+                        format_args!("part {number} failed: {}", DisplayErrorContext(e)),
+                    ))
+                }
+            }
+        }
+    }
+
+    async fn complete(&self, parts: Vec<CompletedPart>) -> Result<()> {
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.object.bucket)
+            .key(&self.object.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| upload_error(&self.object.url, DisplayErrorContext(e)))?;
+        Ok(())
+    }
+
+    /// Deletes the parts uploaded so far; failing that, says what to clean up.
+    async fn abort(&self) {
+        let aborted = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.object.bucket)
+            .key(&self.object.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await;
+        if let Err(e) = aborted {
+            warn!(
+                "{}: failed to abort multipart upload {} ({}); remove it with a lifecycle rule or `aws s3api abort-multipart-upload`",
+                self.object.url,
+                self.upload_id,
+                DisplayErrorContext(e)
+            );
+        }
+    }
+}
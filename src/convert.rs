@@ -0,0 +1,3128 @@
+//! JSONL -> protobuf conversion: the options a run takes, the row -> `Example`
+//! mapping, and the shard writer. The `ethics-pipeline` binary is a CLI around
+//! `run_job`; `convert_stream` converts between in-memory readers and writers.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use prost::Message;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, File},
+    io::{
+        self as stdio, BufRead, BufReader, BufWriter, ErrorKind, IsTerminal, Read, Seek, SeekFrom,
+        Write,
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{error, info, info_span, warn};
+
+use crate::dict::Dictionary;
+use crate::error::{bail, ensure, Context, EthicsError, Result};
+use crate::ethics::{Example, PairExample, Preference, ShardHeader};
+use crate::index::FrameEntry;
+use crate::input::{
+    decompress_reader, input_stem, is_compressed, is_object_url, is_stdio, line_too_long, lines,
+    records_with_limit, resolve_format, InputFormat, Position, RecordIter, DEFAULT_MAX_LINE_BYTES,
+    ZSTD_MAGIC,
+};
+use crate::logging::RUN_TARGET;
+use crate::manifest::{ShardCounts, ShardInfo};
+use crate::mmap::map_jsonl;
+use crate::shard::{self, ShardReader};
+use crate::text::{normalize_in_place, truncate};
+use crate::writer::{tmp_path, ExampleWriter, ExampleWriterBuilder, DEFAULT_ZSTD_LEVEL};
+
+/// Flags controlling how a JSON row becomes an `Example`; shared by conversion and `verify`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct RecordOptions {
+    /// Input layout; `auto` picks CSV/TSV by extension and treats files starting with `[` as a JSON array.
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    pub format: InputFormat,
+
+    /// Longest JSONL line read, in bytes (default 16 MiB). Longer lines are skipped
+    /// without being held in memory and rejected as `line_too_long`.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_LINE_BYTES)]
+    pub max_line_bytes: usize,
+
+    /// Message written per row: `example` (text + label) or `pair` (two ranked texts,
+    /// as in utilitarianism) into `.pairs.pb.zst` shards.
+    #[arg(long, value_enum, default_value_t = Mode::Example)]
+    pub mode: Mode,
+
+    /// Fields holding the preferred and the other text in `--mode pair`.
+    #[arg(
+        long,
+        value_name = "A,B",
+        value_delimiter = ',',
+        num_args = 1,
+        default_value = "baseline,less_pleasant"
+    )]
+    pub pair_fields: Vec<String>,
+
+    /// Extra string label mappings, e.g. `acceptable=0,unacceptable=1`.
+    /// Integers, integral floats, numeric strings and booleans are always accepted.
+    #[arg(long, value_name = "MAP", value_parser = parse_label_map, default_value = "")]
+    pub label_map: LabelMap,
+
+    /// Reject rows without a label (reported as `missing_label`) instead of defaulting to 0.
+    #[arg(long)]
+    pub require_label: bool,
+
+    /// Fields to take `Example.text` from, in priority order; the first non-blank string wins.
+    /// Rows where none match are skipped and reported as `empty_text`.
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        default_value = "scenario,question,observation"
+    )]
+    pub text_fields: Vec<String>,
+
+    /// Fields holding extra annotations, e.g. `harm,fairness,legality`, stored in
+    /// `Example.labels_by_name`. For rows without a `label` key the first one present fills `label`.
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    pub label_fields: Vec<String>,
+
+    /// Store this field, a number or numeric string, in `Example.score`; rows where it is
+    /// not a finite number are rejected as `bad_score`.
+    #[arg(long, value_name = "FIELD")]
+    pub score_field: Option<String>,
+
+    /// Store this field in `Example.context` and take `text` from the others. Deontology defaults
+    /// to `scenario`, with `text` taken from `excuse`.
+    #[arg(long, value_name = "FIELD")]
+    pub context_field: Option<String>,
+
+    /// Keep deontology's `scenario` as `text` instead of moving it to `context`.
+    #[arg(long, conflicts_with = "context_field")]
+    pub no_context: bool,
+
+    /// Build `Example.text` from several fields instead, e.g. `"{scenario} [SEP] {excuse}"`.
+    /// Referenced fields are also copied into `meta`. Overrides `--text-fields`.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_text_template)]
+    pub text_template: Option<TextTemplate>,
+
+    /// Keep rows whose text is empty or whitespace-only instead of rejecting them as `empty_text`.
+    #[arg(long)]
+    pub allow_empty_text: bool,
+
+    /// Clean up text before encoding: NFC-normalize, strip control characters other than
+    /// newlines and tabs, and collapse whitespace runs. Modified records are counted.
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Apply `--normalize` to meta values too.
+    #[arg(long, requires = "normalize")]
+    pub normalize_meta: bool,
+
+    /// Cut `Example.text` to at most N characters, on a grapheme boundary; the original
+    /// length in characters goes to `meta["orig_len"]`.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub truncate_chars: Option<u64>,
+
+    /// Cut `Example.text` to at most N bytes of UTF-8, likewise.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub truncate_bytes: Option<u64>,
+
+    /// Append this marker to cut texts (counted against the limit; default `…`).
+    #[arg(long, value_name = "MARKER", num_args = 0..=1, default_missing_value = "…")]
+    pub truncate_marker: Option<String>,
+
+    /// Split the text at this marker (virtue's `scenario [SEP] trait` encoding): the part before
+    /// stays `text`, the part after goes to `meta["trait"]`, both trimmed.
+    #[arg(long, value_name = "SEP", num_args = 0..=1, default_missing_value = "[SEP]")]
+    pub virtue_split_sep: Option<String>,
+
+    /// Reject rows without the `--virtue-split-sep` marker (`missing_sep`) instead of keeping the text whole.
+    #[arg(long, requires = "virtue_split_sep")]
+    pub require_sep: bool,
+
+    /// Abort when a `--text-template` field is missing instead of substituting "".
+    #[arg(long, requires = "text_template")]
+    pub strict_template: bool,
+
+    /// Store every meta value as serialized JSON (strings keep their quotes), the original format.
+    #[arg(long)]
+    pub legacy_meta: bool,
+
+    /// Top-level fields copied into `Example.meta`.
+    #[arg(
+        long,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        default_value = "rationale,action,answer,input,output"
+    )]
+    pub meta_keys: Vec<String>,
+
+    /// Copy every top-level field except `label` and the `--text-fields` into meta.
+    #[arg(long, conflicts_with = "meta_keys")]
+    pub meta_all: bool,
+
+    /// Fields to leave out in `--meta-all` mode.
+    #[arg(
+        long,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        requires = "meta_all"
+    )]
+    pub meta_exclude: Vec<String>,
+
+    /// Expand nested objects in selected meta fields into `parent.child` entries.
+    #[arg(long)]
+    pub flatten_meta: bool,
+
+    /// Nesting levels to expand; anything deeper is stored as compact JSON.
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "flatten_meta")]
+    pub max_depth: usize,
+
+    /// Separator between path segments in flattened keys.
+    #[arg(
+        long,
+        value_name = "SEP",
+        default_value = ".",
+        requires = "flatten_meta"
+    )]
+    pub meta_separator: String,
+
+    /// Expand arrays by index (`tags.0`, `tags.1`) instead of storing them as JSON.
+    #[arg(long, requires = "flatten_meta")]
+    pub flatten_arrays: bool,
+
+    /// Take `Example.id` from this column when present instead of hashing the content.
+    #[arg(long, value_name = "FIELD")]
+    pub id_field: Option<String>,
+}
+
+/// Protobuf message a conversion writes.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Example,
+    Pair,
+}
+
+impl Mode {
+    /// Fully qualified message name, as recorded in the manifest.
+    pub fn message(self) -> &'static str {
+        match self {
+            Mode::Example => "ethics.v1.Example",
+            Mode::Pair => "ethics.v1.PairExample",
+        }
+    }
+}
+
+impl Default for RecordOptions {
+    /// The command-line defaults.
+    fn default() -> Self {
+        let cmd = <Self as clap::Args>::augment_args(clap::Command::new("ethics-pipeline"));
+        let matches = cmd.get_matches_from(["ethics-pipeline"]);
+        <Self as clap::FromArgMatches>::from_arg_matches(&matches).expect("flag defaults parse")
+    }
+}
+
+impl RecordOptions {
+    /// Whether top-level field `key` belongs in `Example.meta`.
+    fn keeps_meta(&self, key: &str) -> bool {
+        let is = |list: &[String]| list.iter().any(|k| k == key);
+        if self.meta_all {
+            let text = if self.mode == Mode::Pair {
+                &self.pair_fields
+            } else {
+                &self.text_fields
+            };
+            !is(text) && !is(&self.meta_exclude)
+        } else {
+            is(&self.meta_keys)
+        }
+    }
+
+    fn truncates(&self) -> bool {
+        self.truncate_chars.is_some() || self.truncate_bytes.is_some()
+    }
+}
+
+/// A parsed `--text-template`: literal text interleaved with `{field}` references.
+#[derive(Debug, Clone)]
+pub struct TextTemplate(Vec<TemplatePart>);
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Field(String),
+}
+
+pub fn parse_text_template(s: &str) -> Result<TextTemplate> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(TemplatePart::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}').ok_or_else(|| {
+            EthicsError::InvalidArgument(format!("unclosed '{{' in template {s:?}"))
+        })? + open;
+        let field = rest[open + 1..close].trim();
+        ensure!(
+            !field.is_empty(),
+            InvalidArgument,
+            "empty field reference in template {s:?}"
+        );
+        parts.push(TemplatePart::Field(field.to_string()));
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    Ok(TextTemplate(parts))
+}
+
+impl TextTemplate {
+    fn fields(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|p| match p {
+            TemplatePart::Field(f) => Some(f.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Renders against `row`; strings are inserted verbatim, other JSON values as compact JSON.
+    fn render(&self, row: &Row, strict: bool) -> Result<String, Reject> {
+        let mut out = String::new();
+        for part in &self.0 {
+            match part {
+                TemplatePart::Literal(lit) => out.push_str(lit),
+                TemplatePart::Field(f) => match row.fields.get(f) {
+                    Some(serde_json::Value::String(v)) => out.push_str(v),
+                    Some(v) => out.push_str(&v.to_string()),
+                    None if strict => return Err(Reject::MissingTemplateField(f.clone())),
+                    None => {}
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// String -> label lookup parsed from `--label-map`.
+#[derive(Debug, Clone, Default)]
+pub struct LabelMap(HashMap<String, i32>);
+
+pub fn parse_label_map(s: &str) -> Result<LabelMap> {
+    let mut map = HashMap::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (k, v) = pair.split_once('=').ok_or_else(|| {
+            EthicsError::LabelInvalid(format!("expected KEY=LABEL, got {pair:?}"))
+        })?;
+        let v = v.trim().parse().map_err(|e| {
+            EthicsError::LabelInvalid(format!("label for {k:?} is not an integer: {v:?} ({e})"))
+        })?;
+        map.insert(k.trim().to_string(), v);
+    }
+    Ok(LabelMap(map))
+}
+
+/// Required keys for `--expect-schema`; each entry is satisfied by any one of its alternatives.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub name: String,
+    pub keys: Vec<Vec<String>>,
+    /// Valid labels for presets with a fixed label set.
+    pub labels: Option<Vec<i32>>,
+}
+
+pub fn parse_schema(s: &str) -> Result<Schema> {
+    let (name, spec) = match canonical_subset(s) {
+        // The exporter writes commonsense text as `text`; raw ETHICS CSVs call it `input`.
+        Some(name @ "commonsense") => (name, "input|text,label"),
+        Some(name @ "deontology") => (name, "scenario,excuse,label"),
+        Some(name @ ("justice" | "virtue")) => (name, "scenario,label"),
+        Some(name @ "utilitarianism") => (name, "baseline,less_pleasant"),
+        _ => ("custom", s),
+    };
+    let keys: Vec<Vec<String>> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(|group| group.split('|').map(|k| k.trim().to_string()).collect())
+        .collect();
+    ensure!(
+        !keys.is_empty(),
+        InvalidArgument,
+        "--expect-schema needs a subset name or a list of keys"
+    );
+    let labels =
+        matches!(name, "commonsense" | "deontology" | "justice" | "virtue").then(|| vec![0, 1]);
+    Ok(Schema {
+        name: name.to_string(),
+        keys,
+        labels,
+    })
+}
+
+/// `--shard-template` default.
+pub const DEFAULT_SHARD_TEMPLATE: &str = "{subset}-{split}-{index:05}.{ext}";
+
+/// Settings shared by every file converted in a run. `Default` matches the
+/// command line without flags.
+pub struct ConvertOptions {
+    /// `None` writes uncompressed `.pb` output.
+    pub zstd_level: Option<i32>,
+    /// zstd worker threads per shard; 0 is single-threaded.
+    pub zstd_workers: u32,
+    /// Long-distance matching window log.
+    pub zstd_long: Option<u32>,
+    pub dict: Option<Dictionary>,
+    /// Examples per zstd frame, when writing seekable shards.
+    pub frame_every: Option<u64>,
+    /// Write a CRC32 after every record.
+    pub checksums: bool,
+    /// Run-wide part of the `ShardHeader` (its creation time); `None` with `--no-header`.
+    pub header: Option<ShardHeader>,
+    /// `--header-timestamp`: `header` was stamped with the wall-clock time.
+    pub header_timestamp: bool,
+    pub max_examples_per_shard: Option<u64>,
+    pub max_shard_bytes: Option<u64>,
+    pub shard_template: String,
+    pub max_errors: Option<usize>,
+    /// `--max-reject-rate` / `--max-rejects`, checked per input and for the run.
+    pub reject_budget: RejectBudget,
+    pub strict: bool,
+    pub overwrite: bool,
+    /// Checkpoint to `<out>.progress` and continue from an existing one.
+    pub resume: bool,
+    pub quiet: bool,
+    pub dedup: Option<DedupConfig>,
+    /// Workers parsing each input; 1 parses on the writing thread.
+    pub parse_threads: usize,
+    /// Read plain JSONL inputs through a memory map; see [`crate::mmap`].
+    pub mmap: bool,
+    /// Non-empty lines to pass over at the start of each input.
+    pub skip: usize,
+    /// Examples per input after which conversion stops.
+    pub limit: Option<u64>,
+    /// `--expect-schema` and how many records it checks.
+    pub expect_schema: Option<Schema>,
+    pub schema_sample: usize,
+    /// `--allowed-labels`, or the `--expect-schema` preset's.
+    pub allowed_labels: Option<BTreeSet<i32>>,
+    pub reject_bad_labels: bool,
+    pub record: RecordOptions,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            zstd_level: Some(DEFAULT_ZSTD_LEVEL),
+            zstd_workers: 0,
+            zstd_long: None,
+            dict: None,
+            frame_every: None,
+            checksums: false,
+            header: Some(shard::header(Mode::Example.message(), "", "")),
+            header_timestamp: false,
+            max_examples_per_shard: None,
+            max_shard_bytes: None,
+            shard_template: DEFAULT_SHARD_TEMPLATE.to_string(),
+            max_errors: None,
+            reject_budget: RejectBudget::default(),
+            strict: false,
+            overwrite: false,
+            resume: false,
+            quiet: false,
+            dedup: None,
+            parse_threads: 1,
+            mmap: false,
+            skip: 0,
+            limit: None,
+            expect_schema: None,
+            schema_sample: 100,
+            allowed_labels: None,
+            reject_bad_labels: false,
+            record: RecordOptions::default(),
+        }
+    }
+}
+
+impl ConvertOptions {
+    pub fn extension(&self) -> &'static str {
+        match (self.record.mode, self.zstd_level.is_some()) {
+            (Mode::Example, true) => "pb.zst",
+            (Mode::Example, false) => "pb",
+            (Mode::Pair, true) => "pairs.pb.zst",
+            (Mode::Pair, false) => "pairs.pb",
+        }
+    }
+
+    /// The header for `job`'s shards.
+    fn header_for(&self, job: &Job) -> Option<ShardHeader> {
+        let header = self.header.as_ref()?;
+        Some(ShardHeader {
+            message: self.record.mode.message().to_string(),
+            subset: job.subset.clone(),
+            split: job.split.clone(),
+            ..header.clone()
+        })
+    }
+
+    /// `builder` with this run's compression, framing and checksum settings.
+    fn configure<'a>(
+        &'a self,
+        builder: ExampleWriterBuilder<'a>,
+        header: Option<ShardHeader>,
+    ) -> ExampleWriterBuilder<'a> {
+        let mut builder = match self.zstd_level {
+            Some(level) => builder.zstd_level(level),
+            None => builder.uncompressed(),
+        };
+        builder = builder
+            .zstd_workers(self.zstd_workers)
+            .checksums(self.checksums)
+            .overwrite(self.overwrite)
+            .keep_unfinished(self.resume);
+        if let Some(log) = self.zstd_long {
+            builder = builder.zstd_long(log);
+        }
+        if let Some(dict) = &self.dict {
+            builder = builder.dict(dict);
+        }
+        if let Some(n) = self.frame_every {
+            builder = builder.frame_every(n);
+        }
+        match header {
+            Some(header) => builder.with_header(header),
+            None => builder.without_header(),
+        }
+    }
+
+    pub fn rotates(&self) -> bool {
+        self.max_examples_per_shard.is_some() || self.max_shard_bytes.is_some()
+    }
+
+    /// Path of shard `index` for `job`: the job's own output path unless rotation is on.
+    pub fn shard_path(&self, job: &Job, index: usize) -> PathBuf {
+        if !self.rotates() {
+            return job.out.clone();
+        }
+        let stem = input_stem(&job.input);
+        let name = render_template(
+            &self.shard_template,
+            &job.subset,
+            &job.split,
+            &stem,
+            self.extension(),
+            index,
+        );
+        job.out.parent().unwrap_or(Path::new("")).join(name)
+    }
+}
+
+/// Expands the `--shard-template` placeholders.
+fn render_template(
+    template: &str,
+    subset: &str,
+    split: &str,
+    stem: &str,
+    ext: &str,
+    index: usize,
+) -> String {
+    let mut out = template
+        .replace("{subset}", subset)
+        .replace("{split}", split)
+        .replace("{stem}", stem)
+        .replace("{ext}", ext)
+        .replace("{index}", &index.to_string());
+    // `{index:0N}` zero-pads to width N.
+    while let Some(start) = out.find("{index:0") {
+        let rest = &out[start + "{index:0".len()..];
+        let Some(end) = rest.find('}') else { break };
+        let width: usize = rest[..end].parse().unwrap_or(0);
+        let padded = format!("{index:0width$}");
+        out.replace_range(start..start + "{index:0".len() + end + 1, &padded);
+    }
+    out
+}
+
+/// `--dedup` settings, recorded in the manifest.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct DedupConfig {
+    pub case_insensitive: bool,
+    pub hash_only: bool,
+    /// `--dedup-rejects`: dropped duplicates also go to `--rejects-out`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub rejects: bool,
+}
+
+/// How many rejected records a run tolerates before it fails. Duplicates dropped by
+/// `--dedup` are deliberate and don't count.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct RejectBudget {
+    /// Largest share of non-blank records that may be rejected, in [0, 1].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rejects: Option<usize>,
+}
+
+impl RejectBudget {
+    /// Records an input must have produced before its rate is checked mid-file, so
+    /// a few early rejects can't fail a file that is fine overall. Finished inputs
+    /// are always checked.
+    pub const MIN_RECORDS: usize = 1000;
+
+    /// Reject locations kept per reason for the error message.
+    pub const SAMPLES: usize = 5;
+
+    pub fn is_set(&self) -> bool {
+        self.max_rate.is_some() || self.max_rejects.is_some()
+    }
+
+    pub fn exceeded(&self, rejected: usize, records: usize, finished: bool) -> bool {
+        self.max_rejects.is_some_and(|max| rejected > max)
+            || self.max_rate.is_some_and(|max| {
+                (finished || records >= Self::MIN_RECORDS)
+                    && records > 0
+                    && rejected as f64 / records as f64 > max
+            })
+    }
+
+    /// The error for `scope` (an input, or the run) going over budget, with the
+    /// rejects per reason and, where known, where the first few were.
+    pub fn error(
+        &self,
+        scope: &str,
+        rejected: &BTreeMap<String, usize>,
+        records: usize,
+        samples: &BTreeMap<String, Vec<String>>,
+    ) -> EthicsError {
+        let total: usize = rejected
+            .iter()
+            .filter(|(reason, _)| counts_against_budget(reason))
+            .map(|(_, n)| n)
+            .sum();
+        let limit = match (self.max_rate, self.max_rejects) {
+            (_, Some(max)) if total > max => format!("--max-rejects {max}"),
+            (Some(max), _) => format!("--max-reject-rate {max}"),
+            _ => "the reject budget".to_string(),
+        };
+        let mut message = format!(
+            "{scope}: {total} of {records} record(s) rejected ({:.2}%), over {limit}",
+            reject_rate(total, records) * 100.0
+        );
+        for (reason, n) in rejected
+            .iter()
+            .filter(|(reason, _)| counts_against_budget(reason))
+        {
+            message.push_str(&format!("\n  {reason}: {n}"));
+            if let Some(at) = samples.get(reason).filter(|at| !at.is_empty()) {
+                message.push_str(&format!(" (e.g. {})", at.join(", ")));
+            }
+        }
+        EthicsError::InvalidRecord(message)
+    }
+}
+
+/// Parses `--max-reject-rate`: a fraction in [0, 1], e.g. `0.01` for 1%.
+pub fn parse_reject_rate(s: &str) -> Result<f64> {
+    let rate: f64 = s.trim().parse().map_err(|_| {
+        EthicsError::InvalidArgument(format!("expected a fraction such as 0.01, got {s:?}"))
+    })?;
+    ensure!(
+        (0.0..=1.0).contains(&rate),
+        InvalidArgument,
+        "reject rate must be in [0, 1], got {rate}"
+    );
+    Ok(rate)
+}
+
+/// Whether rejects for `reason` count against `--max-reject-rate` / `--max-rejects`.
+pub fn counts_against_budget(reason: &str) -> bool {
+    reason != "duplicate"
+}
+
+/// Non-blank records read, those `--skip` passed over excluded.
+pub fn records_read(counts: &ShardCounts) -> usize {
+    counts.lines_read - counts.lines_skipped - counts.lines_offset
+}
+
+pub fn reject_rate(rejected: usize, records: usize) -> f64 {
+    if records == 0 {
+        0.0
+    } else {
+        rejected as f64 / records as f64
+    }
+}
+
+fn sample_reject(samples: &mut BTreeMap<String, Vec<String>>, reason: &str, loc: &str) {
+    let at = samples.entry(reason.to_string()).or_default();
+    if at.len() < RejectBudget::SAMPLES {
+        at.push(loc.to_string());
+    }
+}
+
+/// Checks the rejects of one input so far against `opts.reject_budget`.
+fn check_reject_budget(
+    opts: &ConvertOptions,
+    input: &Path,
+    shards: &[ShardInfo],
+    counts: &ShardCounts,
+    samples: &BTreeMap<String, Vec<String>>,
+    finished: bool,
+) -> Result<()> {
+    let budget = opts.reject_budget;
+    if !budget.is_set() {
+        return Ok(());
+    }
+    let all = shards.iter().map(|s| &s.counts).chain([counts]);
+    let mut rejected: BTreeMap<String, usize> = BTreeMap::new();
+    let mut records = 0;
+    for c in all {
+        records += records_read(c);
+        for (reason, n) in &c.rejected {
+            *rejected.entry(reason.clone()).or_insert(0) += n;
+        }
+    }
+    let total = rejected
+        .iter()
+        .filter(|(reason, _)| counts_against_budget(reason))
+        .map(|(_, n)| n)
+        .sum();
+    if budget.exceeded(total, records, finished) {
+        return Err(budget.error(&input.display().to_string(), &rejected, records, samples));
+    }
+    Ok(())
+}
+
+/// Contents of the `<out>.progress` sidecar that `--resume` checkpoints to.
+#[derive(Serialize, Deserialize, Debug)]
+struct ResumeState {
+    version: u32,
+    input: PathBuf,
+    /// Input size at the time; a changed input can't be resumed.
+    input_bytes: u64,
+    /// Input records consumed, blank lines included.
+    records: usize,
+    /// The latest point at or before `records` that a resumed run can seek to. Only
+    /// plain JSONL inputs have one; others are read again from the start.
+    seek: Option<SeekPoint>,
+    /// Examples written for this input, across all its shards.
+    examples: usize,
+    /// Bytes of the open shard's temp file that are safely on disk.
+    tmp_bytes: u64,
+    /// Shards already finished and moved into place.
+    finished: Vec<ShardInfo>,
+    /// Totals of the open shard.
+    counts: ShardCounts,
+    /// Frame table of the open shard, with `--frame-every`.
+    frames: Vec<FrameEntry>,
+}
+
+/// A place in the input: `offset` is the byte offset just past its first `records` records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct SeekPoint {
+    records: usize,
+    offset: u64,
+}
+
+impl ResumeState {
+    const VERSION: u32 = 2;
+    /// Input records between checkpoints.
+    const EVERY: usize = 4096;
+
+    fn path_for(out: &Path) -> PathBuf {
+        let mut name = out.as_os_str().to_owned();
+        name.push(".progress");
+        PathBuf::from(name)
+    }
+
+    /// Loads the sidecar an interrupted run left for `job`, if any.
+    fn load(job: &Job) -> Result<Option<Self>> {
+        let path = Self::path_for(&job.out);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let state: ResumeState = serde_json::from_str(&text)
+            .map_err(|e| EthicsError::Corrupt(format!("{} is corrupt: {e}", path.display())))?;
+        ensure!(
+            state.version == Self::VERSION,
+            SchemaMismatch,
+            "{}: unsupported version {}",
+            path.display(),
+            state.version
+        );
+        ensure!(
+            state.input == job.input,
+            InvalidArgument,
+            "{} was written for {}, not {}",
+            path.display(),
+            state.input.display(),
+            job.input.display()
+        );
+        ensure!(
+            state.input_bytes == fs::metadata(&job.input)?.len(),
+            InvalidArgument,
+            "{} changed size since {} was written; delete it to start over",
+            job.input.display(),
+            path.display()
+        );
+        Ok(Some(state))
+    }
+
+    /// Replaces the sidecar atomically, fsyncing it first.
+    fn save(&self, out: &Path) -> Result<()> {
+        let path = Self::path_for(out);
+        let tmp = tmp_path(&path)?;
+        let mut f =
+            File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+        serde_json::to_writer(&mut f, self)?;
+        f.sync_all()?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("failed to move {} to {}", tmp.display(), path.display()))
+    }
+}
+
+/// One input file and where its shard goes.
+pub struct Job {
+    pub input: PathBuf,
+    pub subset: String,
+    pub split: String,
+    pub out: PathBuf,
+}
+
+/// One input record as read from JSON.
+pub struct Row {
+    label: Option<RawLabel>,
+    fields: serde_json::Map<String, serde_json::Value>, // everything else, text fields included
+}
+
+// By hand rather than `#[serde(flatten)]`, which buffers every field as generic
+// content before building the map and was the slowest part of parsing a row.
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = serde_json::Map::deserialize(deserializer)?;
+        let label = match fields.remove("label") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(v) => Some(RawLabel::deserialize(v).map_err(serde::de::Error::custom)?),
+        };
+        Ok(Row { label, fields })
+    }
+}
+
+/// A label as it appears in the JSON, before mapping to `Example.label`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum RawLabel {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl RawLabel {
+    /// Maps to an `i32` label; `--label-map` entries win over numeric parsing for strings.
+    pub fn resolve(&self, map: &LabelMap) -> Option<i32> {
+        match self {
+            RawLabel::Int(n) => i32::try_from(*n).ok(),
+            RawLabel::Float(f)
+                if f.fract() == 0.0 && *f >= i32::MIN as f64 && *f <= i32::MAX as f64 =>
+            {
+                Some(*f as i32)
+            }
+            RawLabel::Float(_) => None,
+            RawLabel::Bool(b) => Some(i32::from(*b)),
+            RawLabel::Str(s) => map
+                .0
+                .get(s)
+                .copied()
+                .or_else(|| s.trim().parse().ok())
+                .or_else(|| match s.trim() {
+                    "true" => Some(1),
+                    "false" => Some(0),
+                    _ => None,
+                }),
+        }
+    }
+}
+
+/// Why a row was left out of the shard.
+#[derive(Debug)]
+pub enum Reject {
+    UnmappableLabel(RawLabel),
+    /// `--require-label` and the row has none.
+    MissingLabel,
+    /// The text came out empty or whitespace-only.
+    EmptyText,
+    /// `--require-sep` and the text has no `--virtue-split-sep` marker.
+    MissingSeparator,
+    /// The `--score-field` value isn't a finite number.
+    BadScore(String),
+    /// `--strict-template` and a referenced field is absent; aborts the conversion.
+    MissingTemplateField(String),
+}
+
+impl Reject {
+    /// Short reason key used in summaries and the manifest.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Reject::UnmappableLabel(_) => "bad_label",
+            Reject::MissingLabel => "missing_label",
+            Reject::EmptyText => "empty_text",
+            Reject::MissingSeparator => "missing_sep",
+            Reject::BadScore(_) => "bad_score",
+            Reject::MissingTemplateField(_) => "missing_field",
+        }
+    }
+}
+
+impl std::fmt::Display for Reject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reject::UnmappableLabel(raw) => write!(f, "unmappable label {raw:?}"),
+            Reject::MissingLabel => write!(f, "no label"),
+            Reject::EmptyText => write!(f, "empty or whitespace-only text"),
+            Reject::MissingSeparator => write!(f, "text has no trait separator"),
+            Reject::BadScore(raw) => write!(f, "score {raw} is not a finite number"),
+            Reject::MissingTemplateField(field) => write!(f, "template field {field:?} is missing"),
+        }
+    }
+}
+
+/// First non-blank string among `--text-fields`, in priority order.
+pub fn pick_text(r: &Row, text_fields: &[String]) -> Option<String> {
+    text_fields
+        .iter()
+        .filter_map(|f| r.fields.get(f)?.as_str())
+        .find(|s| !s.trim().is_empty())
+        .map(str::to_string)
+}
+
+/// Maps short subset names used by some dumps onto the canonical ETHICS names.
+pub(crate) fn canonical_subset(s: &str) -> Option<&'static str> {
+    match s.to_ascii_lowercase().as_str() {
+        "cm" | "commonsense" => Some("commonsense"),
+        "deontology" | "deont" => Some("deontology"),
+        "justice" => Some("justice"),
+        "util" | "utilitarianism" => Some("utilitarianism"),
+        "virtue" => Some("virtue"),
+        _ => None,
+    }
+}
+
+pub(crate) fn canonical_split(s: &str) -> Option<&'static str> {
+    match s.to_ascii_lowercase().replace('-', "_").as_str() {
+        "train" => Some("train"),
+        "test" => Some("test"),
+        "test_hard" | "testhard" | "hard" => Some("test_hard"),
+        "validation" | "val" | "dev" => Some("validation"),
+        _ => None,
+    }
+}
+
+/// Infers `(subset, split)` from a filename like `cm_train.jsonl` or `justice-test_hard.jsonl`.
+pub fn infer_subset_split(path: &Path) -> Option<(String, String)> {
+    let stem = input_stem(path);
+    let (subset, split) = stem.split_once(['-', '_'])?;
+    Some((
+        canonical_subset(subset)?.to_string(),
+        canonical_split(split)?.to_string(),
+    ))
+}
+
+/// Strings are stored verbatim, scalars in their JSON text form, objects/arrays as compact JSON.
+fn meta_value(v: &serde_json::Value, legacy: bool) -> String {
+    match v {
+        serde_json::Value::String(s) if !legacy => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Inserts `v` under `key`, recursing into objects (and arrays, if asked) when `--flatten-meta` is on.
+pub fn insert_meta(
+    meta: &mut BTreeMap<String, String>,
+    key: String,
+    v: &serde_json::Value,
+    opts: &RecordOptions,
+    depth: usize,
+) {
+    let children: Vec<(String, &serde_json::Value)> = match v {
+        _ if !opts.flatten_meta || depth >= opts.max_depth => Vec::new(),
+        serde_json::Value::Object(obj) => obj.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        serde_json::Value::Array(arr) if opts.flatten_arrays => arr
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if children.is_empty() {
+        meta.insert(key, meta_value(v, opts.legacy_meta));
+        return;
+    }
+    for (child, v) in children {
+        insert_meta(
+            meta,
+            format!("{key}{}{child}", opts.meta_separator),
+            v,
+            opts,
+            depth + 1,
+        );
+    }
+}
+
+/// Hex SHA-256 over subset, split and the whitespace-normalized text.
+fn content_id(subset: &str, split: &str, text: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [subset, split] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    for (i, word) in text.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Texts already written in this run, for `--dedup` (and `merge_shards --dedup`).
+#[derive(Default)]
+pub enum SeenTexts {
+    #[default]
+    Off,
+    Full(HashSet<String>, DedupConfig),
+    /// First 128 bits of the SHA-256 of the normalized text.
+    Hashed(HashSet<u128>, DedupConfig),
+}
+
+impl SeenTexts {
+    pub fn new(config: Option<DedupConfig>) -> Self {
+        match config {
+            None => SeenTexts::Off,
+            Some(c) if c.hash_only => SeenTexts::Hashed(HashSet::new(), c),
+            Some(c) => SeenTexts::Full(HashSet::new(), c),
+        }
+    }
+
+    /// Records `text`, returning `false` if an equivalent one was seen before.
+    pub fn insert(&mut self, text: &str) -> bool {
+        match self {
+            SeenTexts::Off => true,
+            SeenTexts::Full(set, c) => set.insert(dedup_key(text, c.case_insensitive)),
+            SeenTexts::Hashed(set, c) => {
+                set.insert(dedup_hash(&dedup_key(text, c.case_insensitive)))
+            }
+        }
+    }
+}
+
+/// `text` as `--dedup` compares it: whitespace runs collapsed to one space, leading and
+/// trailing ones dropped, and lowercased if `case_insensitive`.
+pub fn dedup_key(text: &str, case_insensitive: bool) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if case_insensitive {
+        collapsed.to_lowercase()
+    } else {
+        collapsed
+    }
+}
+
+/// First 128 bits of the SHA-256 of `key`, as `--dedup --hash-only` keeps it.
+pub fn dedup_hash(key: &str) -> u128 {
+    let digest = Sha256::digest(key.as_bytes());
+    u128::from_be_bytes(digest[..16].try_into().unwrap())
+}
+
+/// Builds the `Example` for `row`; a missing label defaults to 0 as before unless `--require-label`.
+/// The flag says whether `--normalize` changed anything.
+pub fn row_to_example(
+    row: &Row,
+    subset: &str,
+    split: &str,
+    opts: &RecordOptions,
+) -> Result<(Example, bool), Reject> {
+    let mut labels_by_name = BTreeMap::new();
+    for field in &opts.label_fields {
+        let Some(v) = row.fields.get(field).filter(|v| !v.is_null()) else {
+            continue;
+        };
+        let raw = serde_json::from_value::<RawLabel>(v.clone())
+            .map_err(|_| Reject::UnmappableLabel(RawLabel::Str(v.to_string())))?;
+        let label = raw
+            .resolve(&opts.label_map)
+            .ok_or(Reject::UnmappableLabel(raw))?;
+        labels_by_name.insert(field.clone(), label);
+    }
+    let primary = opts
+        .label_fields
+        .iter()
+        .find_map(|f| labels_by_name.get(f))
+        .copied();
+    let label = match (&row.label, primary) {
+        (Some(raw), _) => raw
+            .resolve(&opts.label_map)
+            .ok_or_else(|| Reject::UnmappableLabel(raw.clone()))?,
+        (None, Some(label)) => label,
+        (None, None) if opts.require_label => return Err(Reject::MissingLabel),
+        (None, None) => 0,
+    };
+    // Deontology labels judge the excuse given the scenario, so both are kept apart by default.
+    let deontology = subset == "deontology"
+        && opts.context_field.is_none()
+        && !opts.no_context
+        && opts.text_template.is_none();
+    let context_field = if deontology {
+        Some("scenario")
+    } else {
+        opts.context_field.as_deref()
+    };
+    let mut context = context_field.and_then(|f| pick_text(row, &[f.to_string()]));
+    let text = match &opts.text_template {
+        Some(t) => Some(t.render(row, opts.strict_template)?),
+        None if deontology => pick_text(row, &["excuse".to_string()]),
+        None => {
+            let fields: Vec<String> = opts
+                .text_fields
+                .iter()
+                .filter(|f| Some(f.as_str()) != context_field)
+                .cloned()
+                .collect();
+            pick_text(row, &fields)
+        }
+    };
+    let mut text = text.unwrap_or_default();
+    let score = match opts.score_field.as_ref().and_then(|f| row.fields.get(f)) {
+        None | Some(serde_json::Value::Null) => None,
+        Some(v) => Some(parse_score(v)?),
+    };
+    let mut trait_ = None;
+    if let Some(sep) = &opts.virtue_split_sep {
+        match text.split_once(sep.as_str()) {
+            Some((scenario, t)) => {
+                (text, trait_) = (scenario.trim().to_string(), Some(t.trim().to_string()))
+            }
+            None if opts.require_sep => return Err(Reject::MissingSeparator),
+            None => {}
+        }
+    }
+    let mut normalized = false;
+    if opts.normalize {
+        normalized |= normalize_in_place(&mut text);
+        if let Some(context) = &mut context {
+            normalized |= normalize_in_place(context);
+        }
+    }
+    let mut orig_len = None;
+    if opts.truncates() {
+        let limit = |n: Option<u64>| n.map(|n| usize::try_from(n).unwrap_or(usize::MAX));
+        let marker = opts.truncate_marker.as_deref().unwrap_or("");
+        if let Some(cut) = truncate(
+            &text,
+            limit(opts.truncate_chars),
+            limit(opts.truncate_bytes),
+            marker,
+        ) {
+            // Cut down to nothing: rejected even with `--allow-empty-text`, the row had text.
+            if cut.is_empty() {
+                return Err(Reject::EmptyText);
+            }
+            orig_len = Some(text.chars().count());
+            text = cut;
+        }
+    }
+    // After field selection, templating and splitting, so blank text is caught whichever produced it.
+    if text.trim().is_empty() && !opts.allow_empty_text {
+        return Err(Reject::EmptyText);
+    }
+    let mut ex = Example {
+        subset: subset.to_string(),
+        split: split.to_string(),
+        text,
+        label,
+        meta: Default::default(),
+        id: String::new(),
+        context,
+        score,
+        labels_by_name,
+    };
+    let source_id = opts
+        .id_field
+        .as_ref()
+        .and_then(|f| row.fields.get(f))
+        .map(|v| meta_value(v, false));
+    ex.id = source_id.unwrap_or_else(|| content_id(subset, split, &example_key(&ex)));
+
+    let templated: Vec<&str> = opts.text_template.iter().flat_map(|t| t.fields()).collect();
+    for (k, v) in &row.fields {
+        // Fields that became `context` or `text` aren't repeated in meta.
+        if Some(k.as_str()) == context_field
+            || (deontology && k == "excuse")
+            || Some(k) == opts.score_field.as_ref()
+            || opts.label_fields.contains(k)
+        {
+            continue;
+        }
+        if opts.keeps_meta(k) || templated.contains(&k.as_str()) {
+            insert_meta(&mut ex.meta, k.clone(), v, opts, 0);
+        }
+    }
+    if let Some(t) = trait_ {
+        ex.meta.insert("trait".to_string(), t);
+    }
+    if let Some(n) = orig_len {
+        ex.meta.insert("orig_len".to_string(), n.to_string());
+    }
+    if opts.normalize_meta {
+        normalized |= normalize_meta(&mut ex.meta);
+    }
+    Ok((ex, normalized))
+}
+
+/// `--normalize-meta`: normalizes every meta value; returns whether any changed.
+fn normalize_meta(meta: &mut BTreeMap<String, String>) -> bool {
+    meta.values_mut()
+        .fold(false, |changed, v| normalize_in_place(v) | changed)
+}
+
+/// A `--score-field` value: a JSON number or a numeric string, finite either way.
+fn parse_score(v: &serde_json::Value) -> Result<f64, Reject> {
+    let score = match v {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    score
+        .filter(|s| s.is_finite())
+        .ok_or_else(|| Reject::BadScore(v.to_string()))
+}
+
+/// Context and text as one string, for content ids and `--dedup`; just the text
+/// when there is no context, so ids of context-free examples are unchanged.
+fn example_key(ex: &Example) -> String {
+    match &ex.context {
+        Some(context) => format!("{context}\u{1f}{}", ex.text),
+        None => ex.text.clone(),
+    }
+}
+
+/// The content id `row_to_example` gives an example without a source id.
+pub(crate) fn example_id(ex: &Example) -> String {
+    content_id(&ex.subset, &ex.split, &example_key(ex))
+}
+
+/// `--mode pair`: the first `--pair-fields` entry is the preferred text. The flag is as for `row_to_example`.
+pub fn row_to_pair(
+    row: &Row,
+    subset: &str,
+    split: &str,
+    opts: &RecordOptions,
+) -> Result<(PairExample, bool), Reject> {
+    let text = |field: &String| {
+        row.fields
+            .get(field)
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.trim().is_empty())
+            .map(str::to_string)
+    };
+    let (a, b) = (opts.pair_fields.first(), opts.pair_fields.get(1));
+    let mut pair = PairExample {
+        subset: subset.to_string(),
+        split: split.to_string(),
+        text_a: a.and_then(text).ok_or(Reject::EmptyText)?,
+        text_b: b.and_then(text).ok_or(Reject::EmptyText)?,
+        preferred: Preference::A.into(),
+        meta: Default::default(),
+        id: String::new(),
+    };
+    let mut normalized = false;
+    if opts.normalize {
+        normalized |= normalize_in_place(&mut pair.text_a) | normalize_in_place(&mut pair.text_b);
+        if pair.text_a.is_empty() || pair.text_b.is_empty() {
+            return Err(Reject::EmptyText);
+        }
+    }
+    let source_id = opts
+        .id_field
+        .as_ref()
+        .and_then(|f| row.fields.get(f))
+        .map(|v| meta_value(v, false));
+    pair.id = source_id.unwrap_or_else(|| content_id(subset, split, &pair_key(&pair)));
+    for (k, v) in &row.fields {
+        if opts.keeps_meta(k) {
+            insert_meta(&mut pair.meta, k.clone(), v, opts, 0);
+        }
+    }
+    if opts.normalize_meta {
+        normalized |= normalize_meta(&mut pair.meta);
+    }
+    Ok((pair, normalized))
+}
+
+/// Both texts of a pair as one string, for content ids and `--dedup`.
+fn pair_key(pair: &PairExample) -> String {
+    format!("{}\u{1f}{}", pair.text_a, pair.text_b)
+}
+
+/// State shared by every file converted in one run, behind locks so batch
+/// workers can convert files concurrently.
+#[derive(Default)]
+pub struct RunState {
+    /// Example id -> `file:line` of its first occurrence.
+    seen_ids: Mutex<HashMap<String, String>>,
+    seen_texts: Mutex<SeenTexts>,
+    rejects: Mutex<RejectSink>,
+    /// Keeps concurrent progress bars from drawing over each other.
+    bars: MultiProgress,
+    /// Set by [`RunState::cancel`]; conversions in flight stop at their next record.
+    cancelled: AtomicBool,
+}
+
+impl RunState {
+    /// `rejects_out` receives every rejected record as JSONL; `dedup` enables `--dedup`.
+    pub fn new(rejects_out: Option<PathBuf>, dedup: Option<DedupConfig>) -> Self {
+        RunState {
+            rejects: Mutex::new(RejectSink::new(rejects_out)),
+            seen_texts: Mutex::new(SeenTexts::new(dedup)),
+            ..Default::default()
+        }
+    }
+
+    /// Stops every conversion of the run at its next record, e.g. for `--fail-fast`.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Flushes the rejects file, returning how many records went to it and where.
+    pub fn finish_rejects(&self) -> Result<Option<(usize, PathBuf)>> {
+        self.rejects.lock().unwrap().finish()
+    }
+
+    fn reject(&self, reason: &str, input: &Path, pos: Position, raw: &str) -> Result<()> {
+        self.rejects.lock().unwrap().record(reason, input, pos, raw)
+    }
+
+    /// Records `id` as first seen at `loc`, or returns where it was first seen.
+    fn claim_id(&self, id: &str, loc: String) -> Option<String> {
+        let mut seen = self.seen_ids.lock().unwrap();
+        match seen.get(id) {
+            Some(first) => Some(first.clone()),
+            None => {
+                seen.insert(id.to_string(), loc);
+                None
+            }
+        }
+    }
+}
+
+/// Lazily created `--rejects-out` file.
+#[derive(Default)]
+struct RejectSink {
+    path: Option<PathBuf>,
+    writer: Option<BufWriter<File>>,
+    count: usize,
+}
+
+impl RejectSink {
+    fn new(path: Option<PathBuf>) -> Self {
+        RejectSink {
+            path,
+            writer: None,
+            count: 0,
+        }
+    }
+
+    fn record(&mut self, reason: &str, input: &Path, pos: Position, raw: &str) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.writer.is_none() {
+            let f = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            self.writer = Some(BufWriter::new(f));
+        }
+        let w = self.writer.as_mut().unwrap();
+        let mut rec = serde_json::json!({ "reason": reason, "input": input, "raw": raw });
+        rec[pos.kind()] = pos.index().into();
+        serde_json::to_writer(&mut *w, &rec)?;
+        w.write_all(b"\n")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flushes the file and says where the rejects went, if anywhere.
+    fn finish(&mut self) -> Result<Option<(usize, PathBuf)>> {
+        let (Some(w), Some(path)) = (self.writer.as_mut(), &self.path) else {
+            return Ok(None);
+        };
+        w.flush()?;
+        Ok(Some((self.count, path.clone())))
+    }
+}
+
+/// Progress for one input file on stderr: a bar on a TTY, a log line every
+/// `LOG_EVERY` otherwise, nothing with `--quiet`.
+struct Progress {
+    bar: ProgressBar,
+    mode: ProgressMode,
+    input: PathBuf,
+    last_log: Instant,
+    /// Where in the input the checkpoints fall, for plain JSONL read with `--resume`.
+    offsets: Option<InputOffsets>,
+}
+
+#[derive(PartialEq)]
+enum ProgressMode {
+    Bar,
+    Log,
+    Quiet,
+}
+
+impl Progress {
+    const LOG_EVERY: Duration = Duration::from_secs(10);
+
+    /// `total_bytes` is `None` for stdin, which shows a byte count without percentage or ETA.
+    fn new(bars: &MultiProgress, input: &Path, total_bytes: Option<u64>, quiet: bool) -> Self {
+        let mode = if quiet {
+            ProgressMode::Quiet
+        } else if std::io::stderr().is_terminal() {
+            ProgressMode::Bar
+        } else {
+            ProgressMode::Log
+        };
+        // Hidden bars still track position, which the log mode reads back.
+        let bar = if mode == ProgressMode::Bar {
+            bars.add(ProgressBar::no_length())
+        } else {
+            ProgressBar::hidden()
+        };
+        let template = match total_bytes {
+            Some(len) => {
+                bar.set_length(len);
+                "{prefix} [{elapsed_precise}] {wide_bar} {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ETA {eta} {msg}"
+            }
+            None => {
+                "{prefix} [{elapsed_precise}] {spinner} {binary_bytes} {binary_bytes_per_sec} {msg}"
+            }
+        };
+        bar.set_style(ProgressStyle::with_template(template).expect("valid progress template"));
+        bar.set_prefix(input.display().to_string());
+        Progress {
+            bar,
+            mode,
+            input: input.to_path_buf(),
+            last_log: Instant::now(),
+            offsets: None,
+        }
+    }
+
+    /// Counts bytes pulled through `r` towards the bar.
+    fn wrap<R: Read>(&self, r: R) -> indicatif::ProgressBarIter<R> {
+        self.bar.wrap_read(r)
+    }
+
+    /// Counts bytes reported to the returned callback, for inputs read without a reader.
+    fn counter(&self) -> impl FnMut(u64) + Send + 'static {
+        let bar = self.bar.clone();
+        move |n| bar.inc(n)
+    }
+
+    fn update(&mut self, lines: usize, examples: usize) {
+        if !lines.is_multiple_of(1024) {
+            return;
+        }
+        match self.mode {
+            ProgressMode::Bar => self
+                .bar
+                .set_message(format!("{lines} lines, {examples} examples")),
+            ProgressMode::Log if self.last_log.elapsed() >= Self::LOG_EVERY => {
+                let pos = self.bar.position();
+                let mbps = pos as f64 / 1e6 / self.bar.elapsed().as_secs_f64().max(1e-9);
+                let done = match self.bar.length() {
+                    Some(0) => "100.0% of input".to_string(),
+                    Some(len) => format!("{:.1}% of input", pos as f64 * 100.0 / len as f64),
+                    None => format!("{pos} bytes read"),
+                };
+                info!(
+                    lines,
+                    examples,
+                    bytes_read = pos,
+                    "{}: {lines} lines, {examples} examples, {done}, {mbps:.1} MB/s",
+                    self.input.display()
+                );
+                self.last_log = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    /// Share of the input read so far, if its size is known and it hasn't all
+    /// been buffered yet (at that point the position says nothing about progress).
+    fn fraction(&self) -> Option<f64> {
+        let len = self.bar.length().filter(|&l| l > 0)?;
+        Some(self.bar.position() as f64 / len as f64).filter(|&f| f < 1.0)
+    }
+
+    /// Clears the bar and returns the number of input bytes read.
+    fn finish(&self) -> u64 {
+        // Read the position first: finishing moves it to the end.
+        let pos = self.bar.position();
+        self.bar.finish_and_clear();
+        pos
+    }
+}
+
+/// First `max` characters of `line`, with an ellipsis if cut.
+fn preview(line: &str, max: usize) -> String {
+    let mut chars = line.chars();
+    let head: String = chars.by_ref().take(max).collect();
+    if chars.next().is_some() {
+        format!("{head}…")
+    } else {
+        head
+    }
+}
+
+/// What converting one input produced.
+pub struct JobOutput {
+    pub shards: Vec<ShardInfo>,
+    /// (Possibly compressed) input bytes read.
+    pub bytes_in: u64,
+    /// Set when `--limit` stopped the conversion early: the estimated number of
+    /// examples the whole input would have produced, if its size is known.
+    pub truncated: Option<Option<u64>>,
+}
+
+/// What became of one input record before the order-dependent checks.
+pub enum Parsed {
+    Blank,
+    /// Passed over by `--skip`.
+    Offset,
+    Invalid(serde_json::Error),
+    /// Longer than `--max-line-bytes`, by this many bytes; never read into memory.
+    TooLong(usize),
+    Rejected(Reject),
+    Encoded(Encoded),
+}
+
+/// A record ready to write, with what the order-dependent checks look at.
+pub struct Encoded {
+    pub id: String,
+    /// Text compared by `--dedup`.
+    pub text: String,
+    /// Length-delimited message.
+    pub buf: Vec<u8>,
+    /// `--virtue-split-sep` found no marker and the text was kept whole.
+    pub unsplit: bool,
+    /// `Example.label`; pairs have none.
+    pub label: Option<i32>,
+    /// `--normalize` changed the record.
+    pub normalized: bool,
+    /// `--truncate-chars`/`--truncate-bytes` cut the text.
+    pub truncated: bool,
+}
+
+impl Encoded {
+    pub fn new(msg: &impl Message, id: &str, text: String) -> Self {
+        Self::new_in(msg, id, text, Vec::new())
+    }
+
+    /// [`Encoded::new`] reusing `buf`, a written record's buffer, when it is big enough.
+    fn new_in(msg: &impl Message, id: &str, text: String, mut buf: Vec<u8>) -> Self {
+        buf.clear();
+        buf.reserve(msg.encoded_len() + 10);
+        msg.encode_length_delimited(&mut buf)
+            .expect("Vec grows as needed");
+        Encoded {
+            id: id.to_string(),
+            text,
+            buf,
+            unsplit: false,
+            label: None,
+            normalized: false,
+            truncated: false,
+        }
+    }
+
+    /// Decodes one message read back from a `mode` shard.
+    pub fn decode(mode: Mode, buf: &[u8]) -> Result<Self, prost::DecodeError> {
+        Ok(match mode {
+            Mode::Example => {
+                let ex = Example::decode(buf)?;
+                Encoded::new(&ex, &ex.id, example_key(&ex))
+            }
+            Mode::Pair => {
+                let pair = PairExample::decode(buf)?;
+                Encoded::new(&pair, &pair.id, pair_key(&pair))
+            }
+        })
+    }
+}
+
+/// A raw record with its parse result.
+type ParsedRecord = (Position, String, Parsed);
+
+/// Disallowed labels warned about per input; the rest are only counted.
+const BAD_LABELS_LOGGED: usize = 10;
+
+/// Records handed from the reader to `--parse-threads` workers at a time.
+const PARSE_BATCH: usize = 1024;
+
+/// Written buffers kept for reuse; larger ones, from outsized records, are freed.
+const SPARE_MAX_BYTES: usize = 64 << 10;
+
+/// Encode buffers of written records, handed back to the parser so encoding doesn't
+/// allocate per record. Buffers move a batch at a time to keep the lock cold.
+#[derive(Default)]
+struct SpareBufs(Mutex<Vec<Vec<u8>>>);
+
+impl SpareBufs {
+    /// Up to a batch of buffers for one parser.
+    fn take(&self) -> Vec<Vec<u8>> {
+        let mut pool = self.0.lock().unwrap();
+        let at = pool.len().saturating_sub(PARSE_BATCH);
+        pool.split_off(at)
+    }
+
+    /// Returns `bufs`, keeping a few batches' worth at most.
+    fn give(&self, bufs: &mut Vec<Vec<u8>>) {
+        let mut pool = self.0.lock().unwrap();
+        let room = (PARSE_BATCH * 4).saturating_sub(pool.len());
+        pool.extend(
+            bufs.drain(..)
+                .filter(|b| b.capacity() <= SPARE_MAX_BYTES)
+                .take(room),
+        );
+    }
+}
+
+/// Parses, converts and encodes one record. Pure, so it can run on any thread.
+pub fn parse_record(
+    line: &str,
+    offset: bool,
+    subset: &str,
+    split: &str,
+    opts: &RecordOptions,
+) -> Parsed {
+    parse_into(line, offset, (subset, split, opts), Vec::new())
+}
+
+/// [`parse_record`] encoding into `buf`, which it reuses.
+fn parse_into(
+    line: &str,
+    offset: bool,
+    (subset, split, opts): (&str, &str, &RecordOptions),
+    buf: Vec<u8>,
+) -> Parsed {
+    if line.trim().is_empty() {
+        return Parsed::Blank;
+    }
+    if offset {
+        return Parsed::Offset;
+    }
+    // `from_str` rather than `from_slice`: the line is already checked UTF-8, so
+    // serde_json skips validating its strings again.
+    let row: Row = match serde_json::from_str(line) {
+        Ok(row) => row,
+        Err(e) => return Parsed::Invalid(e),
+    };
+    let encoded = match opts.mode {
+        Mode::Example => {
+            row_to_example(&row, subset, split, opts).map(|(ex, normalized)| Encoded {
+                unsplit: opts.virtue_split_sep.is_some() && !ex.meta.contains_key("trait"),
+                truncated: opts.truncates() && ex.meta.contains_key("orig_len"),
+                label: Some(ex.label),
+                normalized,
+                ..Encoded::new_in(&ex, &ex.id, example_key(&ex), buf)
+            })
+        }
+        Mode::Pair => row_to_pair(&row, subset, split, opts).map(|(pair, normalized)| Encoded {
+            normalized,
+            ..Encoded::new_in(&pair, &pair.id, pair_key(&pair), buf)
+        }),
+    };
+    match encoded {
+        Ok(encoded) => Parsed::Encoded(encoded),
+        Err(reject) => Parsed::Rejected(reject),
+    }
+}
+
+/// A reader that counts the bytes consumed from it. Lines are consumed whole, so
+/// after each JSONL record the count is the input offset just past it.
+struct Consumed<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> Consumed<R> {
+    /// `inner`, which starts `offset` bytes into the input.
+    fn new(inner: R, offset: u64) -> Self {
+        Consumed {
+            inner,
+            count: Arc::new(AtomicU64::new(offset)),
+        }
+    }
+
+    /// The count, for reading once the reader has moved on.
+    fn counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.count)
+    }
+}
+
+impl<R: Read> Read for Consumed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> stdio::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Consumed<R> {
+    fn fill_buf(&mut self) -> stdio::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count.fetch_add(amt as u64, Ordering::Relaxed);
+        self.inner.consume(amt);
+    }
+}
+
+/// Input offsets at checkpoint boundaries, noted by the reader as it passes them
+/// (possibly well ahead of the writer) and picked up by [`checkpoint`].
+#[derive(Clone, Default)]
+struct InputOffsets(Arc<Mutex<BTreeMap<usize, u64>>>);
+
+impl InputOffsets {
+    /// Wraps `records`, read from record `base` on through a reader consuming `count`.
+    fn track(&self, records: RecordIter, count: Arc<AtomicU64>, base: usize) -> RecordIter {
+        let offsets = self.clone();
+        let mut consumed = base;
+        Box::new(records.inspect(move |_| {
+            consumed += 1;
+            if consumed.is_multiple_of(ResumeState::EVERY) {
+                let offset = count.load(Ordering::Relaxed);
+                offsets.0.lock().unwrap().insert(consumed, offset);
+            }
+        }))
+    }
+
+    /// The offset just past the first `records` records, forgetting it and any before.
+    fn take(&self, records: usize) -> Option<u64> {
+        let mut offsets = self.0.lock().unwrap();
+        let offset = offsets.remove(&records);
+        offsets.retain(|&n, _| n > records);
+        offset
+    }
+}
+
+/// Flags the first `skip` non-empty records for `--skip`; must run in input order.
+fn mark_offset(
+    records: RecordIter,
+    skip: usize,
+) -> impl Iterator<Item = std::io::Result<(Position, String, bool)>> + Send {
+    let mut offset = 0;
+    records.map(move |r| {
+        r.map(|(pos, line)| {
+            let skipped = offset < skip && !line.trim().is_empty();
+            if skipped {
+                offset += 1;
+            }
+            (pos, line, skipped)
+        })
+    })
+}
+
+/// Parses `records` on `pool`, fed by a reader thread, yielding results in input order.
+/// Channels are bounded and workers pull batches as they free up, so at most a few
+/// batches per worker are in flight; dropping the iterator shuts the pipeline down.
+fn parse_parallel<'scope, 'env: 'scope>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    records: RecordIter,
+    pool: &'env rayon::ThreadPool,
+    skip: usize,
+    (subset, split, opts): (&'env str, &'env str, &'env RecordOptions),
+    spares: &'env SpareBufs,
+) -> impl Iterator<Item = std::io::Result<ParsedRecord>> + 'scope {
+    let threads = pool.current_num_threads();
+    let (work_tx, work_rx) = mpsc::sync_channel::<(usize, Vec<_>)>(threads * 2);
+    let (done_tx, done_rx) =
+        mpsc::sync_channel::<(usize, Vec<std::io::Result<ParsedRecord>>)>(threads * 2);
+
+    scope.spawn(move || {
+        let mut records = mark_offset(records, skip);
+        for seq in 0.. {
+            let batch: Vec<_> = records.by_ref().take(PARSE_BATCH).collect();
+            if batch.is_empty() || work_tx.send((seq, batch)).is_err() {
+                break;
+            }
+        }
+    });
+    scope.spawn(move || {
+        pool.install(|| {
+            work_rx
+                .into_iter()
+                .par_bridge()
+                .try_for_each(|(seq, batch)| {
+                    let mut bufs = spares.take();
+                    let parsed = batch
+                        .into_iter()
+                        .map(|r| {
+                            r.map(|(pos, line, offset)| {
+                                let parsed = parse_into(
+                                    &line,
+                                    offset,
+                                    (subset, split, opts),
+                                    bufs.pop().unwrap_or_default(),
+                                );
+                                (pos, line, parsed)
+                            })
+                        })
+                        .collect();
+                    spares.give(&mut bufs);
+                    done_tx.send((seq, parsed))
+                })
+        })
+    });
+
+    // Workers finish out of order; hold batches back until their turn.
+    let (mut pending, mut next) = (BTreeMap::new(), 0);
+    std::iter::from_fn(move || loop {
+        if let Some(batch) = pending.remove(&next) {
+            next += 1;
+            return Some(batch);
+        }
+        let (seq, batch) = done_rx.recv().ok()?;
+        pending.insert(seq, batch);
+    })
+    .flatten()
+}
+
+/// Converts `job.input` into one or more shards, rotating per `opts`.
+/// Each zstd stream is finished before the next shard is opened.
+/// With `--parse-threads` > 1 parsing and encoding run on worker threads while this
+/// thread writes in input order, so the output is identical either way.
+/// [`convert_stream`] is the in-memory counterpart, for a reader and a writer.
+pub fn jsonl_to_pb(job: &Job, opts: &ConvertOptions, state: &RunState) -> Result<JobOutput> {
+    let input = &job.input;
+    let format = opts.record.format.for_path(input);
+    let mapped = if opts.mmap {
+        map_jsonl(input, format)?
+    } else {
+        None
+    };
+    let resume = if opts.resume {
+        ResumeState::load(job)?
+    } else {
+        None
+    };
+    let (progress, mut records, passed) = if let Some(mapped) = mapped {
+        let progress = Progress::new(&state.bars, input, Some(mapped.len()), opts.quiet);
+        let records = mapped.records(opts.record.max_line_bytes, progress.counter());
+        (progress, records, 0)
+    } else if is_stdio(input) {
+        let progress = Progress::new(&state.bars, input, None, opts.quiet);
+        let reader = decompress_reader(progress.wrap(stdio::stdin()))?;
+        (
+            progress,
+            records_with_limit(reader, format, opts.record.max_line_bytes)?,
+            0,
+        )
+    } else {
+        let f = File::open(input).with_context(|| format!("failed to open {}", input.display()))?;
+        let mut progress = Progress::new(&state.bars, input, Some(f.metadata()?.len()), opts.quiet);
+        let (records, passed) = file_records(f, format, opts, resume.as_ref(), &mut progress)?;
+        (progress, records, passed)
+    };
+    if let Some(r) = &resume {
+        records = Box::new(records.skip(r.records - passed));
+    }
+    let start = resume.map_or(ShardStart::Fresh, |r| ShardStart::Resume(Box::new(r)));
+    convert_records(job, opts, state, progress, records, start)
+}
+
+/// Records of the input file `f`, and how many were passed over by seeking. With
+/// `--resume`, plain JSONL inputs note where each checkpoint falls in `progress`, so a
+/// resumed run seeks straight to the last one; others are read from the start.
+fn file_records(
+    mut f: File,
+    format: InputFormat,
+    opts: &ConvertOptions,
+    resume: Option<&ResumeState>,
+    progress: &mut Progress,
+) -> Result<(RecordIter, usize)> {
+    let max_line_bytes = opts.record.max_line_bytes;
+    if let Some(SeekPoint { records, offset }) = resume.and_then(|r| r.seek) {
+        f.seek(SeekFrom::Start(offset))?;
+        progress.bar.inc(offset);
+        let reader = Consumed::new(BufReader::new(progress.wrap(f)), offset);
+        let count = reader.counter();
+        // Line numbers carry on from the records passed over.
+        let lines: RecordIter = Box::new(
+            lines(reader, max_line_bytes)
+                .map(move |line| line.map(|(n, l)| (Position::Line(records + n), l))),
+        );
+        let offsets = InputOffsets::default();
+        let lines = offsets.track(lines, count, records);
+        progress.offsets = Some(offsets);
+        return Ok((lines, records));
+    }
+    let mut head = Vec::new();
+    (&f).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut head)?;
+    f.rewind()?;
+    let reader = Consumed::new(decompress_reader(progress.wrap(f))?, 0);
+    let count = reader.counter();
+    let mut reader: Box<dyn BufRead + Send> = Box::new(reader);
+    let format = resolve_format(&mut reader, format)?;
+    let records = records_with_limit(reader, format, max_line_bytes)?;
+    if !opts.resume || format != InputFormat::Jsonl || is_compressed(&head) {
+        return Ok((records, 0));
+    }
+    let offsets = InputOffsets::default();
+    let records = offsets.track(records, count, 0);
+    progress.offsets = Some(offsets);
+    Ok((records, 0))
+}
+
+/// In-memory counterpart of [`jsonl_to_pb`]: converts JSONL read from `reader` into a
+/// single shard written to `out`. `name` stands in for the input path: it picks the input
+/// format by extension and labels warnings, rejects and the returned `ShardInfo`.
+/// Rotation, `--resume` and `--frame-every` need files and are refused.
+pub fn convert_stream<'a>(
+    reader: impl Read + Send + 'static,
+    name: &Path,
+    (subset, split): (&str, &str),
+    opts: &'a ConvertOptions,
+    state: &RunState,
+    out: &'a mut dyn Write,
+) -> Result<ShardInfo> {
+    ensure!(
+        !opts.rotates() && !opts.resume,
+        InvalidArgument,
+        "shard rotation and --resume need an output file"
+    );
+    let job = Job {
+        input: name.to_path_buf(),
+        subset: subset.to_string(),
+        split: split.to_string(),
+        out: PathBuf::new(),
+    };
+    let progress = Progress::new(&state.bars, name, None, true);
+    let reader = decompress_reader(progress.wrap(reader))?;
+    let records = records_with_limit(
+        reader,
+        opts.record.format.for_path(name),
+        opts.record.max_line_bytes,
+    )?;
+    let enc = opts
+        .configure(ExampleWriter::to_writer(out), opts.header_for(&job))
+        .open()?;
+    let output = convert_records(
+        &job,
+        opts,
+        state,
+        progress,
+        records,
+        ShardStart::Open(Box::new(enc)),
+    )?;
+    Ok(output
+        .shards
+        .into_iter()
+        .next()
+        .expect("one shard per stream"))
+}
+
+/// How `write_shards` gets its first shard.
+enum ShardStart<'a> {
+    /// Create it at `opts.shard_path(job, 0)`.
+    Fresh,
+    /// Continue an interrupted `--resume` run.
+    Resume(Box<ResumeState>),
+    /// Already open, from `convert_stream`.
+    Open(Box<ExampleWriter<'a>>),
+}
+
+/// The shared part of `jsonl_to_pb` and `convert_stream`, from the raw records on.
+fn convert_records<'a>(
+    job: &Job,
+    opts: &'a ConvertOptions,
+    state: &RunState,
+    progress: Progress,
+    mut records: RecordIter,
+    start: ShardStart<'a>,
+) -> Result<JobOutput> {
+    let (input, subset, split) = (&job.input, job.subset.as_str(), job.split.as_str());
+    if let Some(schema) = &opts.expect_schema {
+        records = check_schema(records, schema, opts.schema_sample, input)?;
+    }
+    let mut skip = opts.skip;
+    if let ShardStart::Resume(r) = &start {
+        info!(
+            records = r.records,
+            examples = r.examples,
+            "{}: resuming after {} record(s), {} example(s) already written",
+            input.display(),
+            r.records,
+            r.examples
+        );
+        let passed: usize = r
+            .finished
+            .iter()
+            .map(|s| s.counts.lines_offset)
+            .sum::<usize>()
+            + r.counts.lines_offset;
+        skip = skip.saturating_sub(passed);
+    }
+    let spares = SpareBufs::default();
+    if opts.parse_threads > 1 {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(opts.parse_threads)
+            .build()
+            .map_err(stdio::Error::other)
+            .context("failed to start the parse threads")?;
+        std::thread::scope(|scope| {
+            let parsed = parse_parallel(
+                scope,
+                records,
+                &pool,
+                skip,
+                (subset, split, &opts.record),
+                &spares,
+            );
+            write_shards(job, opts, state, progress, start, parsed, &spares)
+        })
+    } else {
+        let mut bufs = Vec::new();
+        let parsed = mark_offset(records, skip).map(|r| {
+            r.map(|(pos, line, offset)| {
+                if bufs.is_empty() {
+                    bufs = spares.take();
+                }
+                let parsed = parse_into(
+                    &line,
+                    offset,
+                    (subset, split, &opts.record),
+                    bufs.pop().unwrap_or_default(),
+                );
+                (pos, line, parsed)
+            })
+        });
+        write_shards(job, opts, state, progress, start, parsed, &spares)
+    }
+}
+
+/// Buffers the first `sample` JSON objects of `records` and fails with the keys they
+/// are missing if they don't fit `schema`; otherwise hands all records on unchanged.
+fn check_schema(
+    mut records: RecordIter,
+    schema: &Schema,
+    sample: usize,
+    input: &Path,
+) -> Result<RecordIter> {
+    let mut head = Vec::new();
+    let mut checked = 0;
+    let mut missing: BTreeMap<String, (usize, String)> = BTreeMap::new();
+    let mut present = BTreeSet::new();
+    while checked < sample {
+        let Some(record) = records.next() else { break };
+        if let Ok((pos, line)) = &record {
+            if let Ok(obj) =
+                serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line)
+            {
+                checked += 1;
+                for group in &schema.keys {
+                    if !group
+                        .iter()
+                        .any(|k| obj.get(k).is_some_and(|v| !v.is_null()))
+                    {
+                        missing
+                            .entry(group.join(" or "))
+                            .or_insert_with(|| (0, pos.locate(input)))
+                            .0 += 1;
+                    }
+                }
+                present.extend(obj.into_iter().map(|(k, _)| k));
+            }
+        }
+        head.push(record);
+    }
+    if !missing.is_empty() {
+        let missing: Vec<String> = missing
+            .into_iter()
+            .map(|(key, (n, first))| {
+                format!("`{key}` in {n} of {checked} record(s) (first at {first})")
+            })
+            .collect();
+        let present: Vec<&str> = present.iter().map(String::as_str).collect();
+        bail!(
+            SchemaMismatch,
+            "{} does not match the {} schema: missing {}; keys present: {}",
+            input.display(),
+            schema.name,
+            missing.join(", "),
+            present.join(", ")
+        );
+    }
+    Ok(Box::new(head.into_iter().chain(records)))
+}
+
+/// Re-registers the ids and texts an interrupted run already wrote, so id
+/// collisions and `--dedup` behave as if it had never stopped.
+fn restore_seen(state: &RunState, shards: &[PathBuf], opts: &ConvertOptions) -> Result<()> {
+    for path in shards {
+        let mut reader = ShardReader::open(path, opts.dict.as_ref())?;
+        let message = opts.record.mode.message();
+        loop {
+            let offset = reader.offset();
+            let Some(buf) = reader.next_record()? else {
+                break;
+            };
+            let seen = Encoded::decode(opts.record.mode, buf)
+                .map_err(|source| EthicsError::Decode {
+                    message,
+                    offset,
+                    source,
+                })
+                .with_context(|| {
+                    format!("{} holds a record that is not a {message}", path.display())
+                })?;
+            state.seen_texts.lock().unwrap().insert(&seen.text);
+            state.claim_id(&seen.id, format!("{} (earlier run)", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Makes everything written so far durable and records it in `<out>.progress`.
+fn checkpoint(
+    job: &Job,
+    enc: &mut ExampleWriter,
+    records: usize,
+    seek: Option<SeekPoint>,
+    examples: usize,
+    finished: &[ShardInfo],
+    counts: &ShardCounts,
+) -> Result<()> {
+    let tmp_bytes = enc.checkpoint()?;
+    let state = ResumeState {
+        version: ResumeState::VERSION,
+        input: job.input.clone(),
+        input_bytes: fs::metadata(&job.input)?.len(),
+        records,
+        seek,
+        examples,
+        tmp_bytes,
+        finished: finished.to_vec(),
+        counts: counts.clone(),
+        frames: enc.frames().to_vec(),
+    };
+    state.save(&job.out)
+}
+
+/// The in-order half of `jsonl_to_pb`: counting, reject handling, dedup, id
+/// tracking, shard rotation and `--limit`.
+fn write_shards<'a>(
+    job: &Job,
+    opts: &'a ConvertOptions,
+    state: &RunState,
+    mut progress: Progress,
+    start: ShardStart<'a>,
+    parsed: impl Iterator<Item = std::io::Result<ParsedRecord>>,
+    spares: &SpareBufs,
+) -> Result<JobOutput> {
+    let (input, subset, split) = (&job.input, job.subset.as_str(), job.split.as_str());
+    let header = opts.header_for(job);
+    let (mut shards, mut counts, mut written, base, mut path, mut enc);
+    // The latest checkpoint with a known input offset.
+    let mut seek = None;
+    match start {
+        ShardStart::Resume(r) => {
+            seek = r.seek;
+            path = opts.shard_path(job, r.finished.len());
+            enc = opts
+                .configure(ExampleWriter::create(&path), header.clone())
+                .resume(r.tmp_bytes, r.frames, r.counts.examples as u64)?;
+            let mut done: Vec<PathBuf> = r.finished.iter().map(|s| s.path.clone()).collect();
+            done.push(tmp_path(&path)?);
+            restore_seen(state, &done, opts)?;
+            (shards, counts, written, base) = (r.finished, r.counts, r.examples, r.records);
+        }
+        ShardStart::Fresh => {
+            path = opts.shard_path(job, 0);
+            enc = opts
+                .configure(ExampleWriter::create(&path), header.clone())
+                .open()?;
+            (shards, counts, written, base) = (Vec::new(), ShardCounts::default(), 0, 0);
+        }
+        ShardStart::Open(open) => {
+            (path, enc) = (job.out.clone(), *open);
+            (shards, counts, written, base) = (Vec::new(), ShardCounts::default(), 0, 0);
+        }
+    }
+    let mut warned = BTreeSet::new();
+    let mut reject_samples = BTreeMap::new();
+    // Written buffers on their way back to the parser.
+    let mut used = Vec::new();
+    let parse_failures = |c: &ShardCounts| c.rejected.get("parse_error").copied().unwrap_or(0);
+    let mut parse_errors = shards
+        .iter()
+        .map(|s| parse_failures(&s.counts))
+        .sum::<usize>()
+        + parse_failures(&counts);
+    let mut truncated = None;
+    let mut bad_labels = 0;
+    let shard_info = |path: &Path, counts, (bytes, sha256)| ShardInfo {
+        path: path.to_path_buf(),
+        input: input.clone(),
+        subset: subset.into(),
+        split: split.into(),
+        counts,
+        bytes,
+        sha256,
+    };
+
+    for (n, record) in parsed.enumerate() {
+        if state.cancelled() {
+            return Err(stdio::Error::from(ErrorKind::Interrupted))
+                .context("cancelled after another file failed");
+        }
+        // Checkpoints fall on fixed record counts, so a resumed run lays out frames
+        // exactly like an uninterrupted one.
+        let consumed = base + n;
+        if opts.resume && n > 0 && consumed.is_multiple_of(ResumeState::EVERY) {
+            if let Some(offset) = progress.offsets.as_ref().and_then(|o| o.take(consumed)) {
+                seek = Some(SeekPoint {
+                    records: consumed,
+                    offset,
+                });
+            }
+            checkpoint(job, &mut enc, consumed, seek, written, &shards, &counts)?;
+        }
+        let record = match record {
+            Err(e) => match line_too_long(&e) {
+                Some(long) => Ok((
+                    Position::Line(long.line),
+                    String::new(),
+                    Parsed::TooLong(long.bytes),
+                )),
+                None => Err(e),
+            },
+            ok => ok,
+        };
+        let (pos, line, parsed) =
+            record.with_context(|| format!("failed to read {}", input.display()))?;
+        let loc = pos.locate(input);
+        counts.lines_read += 1;
+        progress.update(consumed + 1, written);
+        let ex = match parsed {
+            Parsed::Blank => {
+                counts.lines_skipped += 1;
+                continue;
+            }
+            Parsed::Offset => {
+                counts.lines_offset += 1;
+                continue;
+            }
+            Parsed::Invalid(source) if opts.strict => {
+                return Err(EthicsError::JsonParse {
+                    location: loc,
+                    line: pos.index(),
+                    source,
+                })
+            }
+            Parsed::Invalid(e) => {
+                warn!(reason = "parse_error", location = %loc, "{loc}: invalid JSON ({e}): {}", preview(&line, 80));
+                counts.reject("parse_error");
+                state.reject("parse_error", input, pos, &line)?;
+                parse_errors += 1;
+                if let Some(max) = opts.max_errors {
+                    ensure!(
+                        parse_errors <= max,
+                        InvalidRecord,
+                        "{}: more than {max} parse failure(s), giving up",
+                        input.display()
+                    );
+                }
+                sample_reject(&mut reject_samples, "parse_error", &loc);
+                check_reject_budget(opts, input, &shards, &counts, &reject_samples, false)?;
+                continue;
+            }
+            Parsed::TooLong(bytes) => {
+                if warned.insert("line_too_long") {
+                    warn!(reason = "line_too_long", location = %loc, bytes, "{loc}: {bytes} bytes, over --max-line-bytes {}; skipped", opts.record.max_line_bytes);
+                }
+                counts.reject("line_too_long");
+                state.reject("line_too_long", input, pos, &line)?;
+                sample_reject(&mut reject_samples, "line_too_long", &loc);
+                check_reject_budget(opts, input, &shards, &counts, &reject_samples, false)?;
+                continue;
+            }
+            Parsed::Rejected(reject @ Reject::MissingTemplateField(_)) => {
+                bail!(InvalidRecord, "{loc}: {reject}")
+            }
+            Parsed::Rejected(reject) => {
+                // Log the first occurrence of each reason; the rest are only counted.
+                if warned.insert(reject.reason()) {
+                    warn!(reason = reject.reason(), location = %loc, "{loc}: {reject}");
+                }
+                counts.reject(reject.reason());
+                state.reject(reject.reason(), input, pos, &line)?;
+                sample_reject(&mut reject_samples, reject.reason(), &loc);
+                check_reject_budget(opts, input, &shards, &counts, &reject_samples, false)?;
+                continue;
+            }
+            Parsed::Encoded(encoded) => encoded,
+        };
+
+        let disallowed = match (ex.label, &opts.allowed_labels) {
+            (Some(label), Some(allowed)) => !allowed.contains(&label),
+            _ => false,
+        };
+        if disallowed {
+            bad_labels += 1;
+            if bad_labels <= BAD_LABELS_LOGGED {
+                warn!(reason = "disallowed_label", location = %loc, label = ex.label.unwrap_or_default(), "{loc}: label {} is not in --allowed-labels", ex.label.unwrap_or_default());
+            }
+            if opts.reject_bad_labels {
+                counts.reject("disallowed_label");
+                state.reject("disallowed_label", input, pos, &line)?;
+                sample_reject(&mut reject_samples, "disallowed_label", &loc);
+                check_reject_budget(opts, input, &shards, &counts, &reject_samples, false)?;
+                continue;
+            }
+        }
+
+        if !state.seen_texts.lock().unwrap().insert(&ex.text) {
+            counts.reject("duplicate");
+            if opts.dedup.is_some_and(|d| d.rejects) {
+                state.reject("duplicate", input, pos, &line)?;
+            }
+            continue;
+        }
+
+        if let Some(first) = state.claim_id(&ex.id, loc.clone()) {
+            if warned.insert("id_collision") {
+                warn!(reason = "id_collision", location = %loc, id = %ex.id, first = %first, "{loc}: id {} already used at {first}", ex.id);
+            }
+            counts.id_collisions += 1;
+        }
+
+        if ex.unsplit {
+            if warned.insert("unsplit") {
+                warn!(reason = "unsplit", location = %loc, "{loc}: no trait separator, keeping the text whole");
+            }
+            counts.unsplit += 1;
+        }
+        if ex.normalized {
+            counts.normalized += 1;
+        }
+        if ex.truncated {
+            counts.truncated += 1;
+        }
+
+        let full = opts
+            .max_examples_per_shard
+            .is_some_and(|n| counts.examples as u64 >= n)
+            || opts.max_shard_bytes.is_some_and(|n| {
+                counts.examples > 0 && counts.uncompressed_bytes + enc.record_len(ex.buf.len()) > n
+            });
+        if full {
+            shards.push(shard_info(
+                &path,
+                std::mem::take(&mut counts),
+                enc.finish()?,
+            ));
+            path = opts.shard_path(job, shards.len());
+            enc = opts
+                .configure(ExampleWriter::create(&path), header.clone())
+                .open()?;
+        }
+
+        enc.write_encoded(&ex.buf)?;
+        counts.examples += 1;
+        if let Some(label) = ex.label {
+            *counts.labels.entry(label).or_insert(0) += 1;
+        }
+        if disallowed {
+            counts.disallowed_labels += 1;
+        }
+        counts.uncompressed_bytes += enc.record_len(ex.buf.len());
+        written += 1;
+        used.push(ex.buf);
+        if used.len() >= PARSE_BATCH {
+            spares.give(&mut used);
+        }
+        if full && opts.resume {
+            // The previous shard's temp file is gone; point the sidecar at the new one.
+            checkpoint(job, &mut enc, consumed + 1, seek, written, &shards, &counts)?;
+        }
+        if opts.limit.is_some_and(|n| written as u64 >= n) {
+            // Extrapolate from how far into the input the limit was reached.
+            truncated = Some(
+                progress
+                    .fraction()
+                    .map(|f| (written as f64 / f).round() as u64),
+            );
+            break;
+        }
+    }
+    let bytes_in = progress.finish();
+    if bad_labels > BAD_LABELS_LOGGED {
+        warn!(
+            reason = "disallowed_label",
+            not_shown = bad_labels - BAD_LABELS_LOGGED,
+            "{}: {} more disallowed label(s) not shown",
+            input.display(),
+            bad_labels - BAD_LABELS_LOGGED
+        );
+    }
+    check_reject_budget(opts, input, &shards, &counts, &reject_samples, true)?;
+    shards.push(shard_info(&path, counts, enc.finish()?));
+    if opts.resume {
+        match fs::remove_file(ResumeState::path_for(&job.out)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).context("failed to remove the progress file")
+            }
+            _ => {}
+        }
+    }
+    Ok(JobOutput {
+        shards,
+        bytes_in,
+        truncated,
+    })
+}
+
+/// Runs one conversion after checking the input and creating the output directory.
+/// Logged under a `file` span, with `started` and `finished` (or `failed`) events
+/// carrying the job's totals.
+pub fn run_job(job: &Job, opts: &ConvertOptions, state: &RunState) -> Result<JobOutput> {
+    let _span =
+        info_span!("file", input = %job.input.display(), subset = %job.subset, split = %job.split)
+            .entered();
+    info!(target: RUN_TARGET, out = %job.out.display(), "started");
+    let started = Instant::now();
+    let output = start_job(job, opts, state);
+    let secs = started.elapsed().as_secs_f64();
+    match &output {
+        Ok(output) => {
+            let sum = |f: fn(&ShardCounts) -> usize| {
+                output.shards.iter().map(|s| f(&s.counts)).sum::<usize>()
+            };
+            let rejected = output
+                .shards
+                .iter()
+                .flat_map(|s| s.counts.rejected.values())
+                .sum::<usize>();
+            let bytes_out = output.shards.iter().map(|s| s.bytes).sum::<u64>();
+            info!(target: RUN_TARGET, lines_read = sum(|c| c.lines_read), examples = sum(|c| c.examples), rejected, shards = output.shards.len(), bytes_in = output.bytes_in, bytes_out, secs, "finished");
+        }
+        Err(e) => error!(target: RUN_TARGET, error = %e, secs, "failed"),
+    }
+    output
+}
+
+fn start_job(job: &Job, opts: &ConvertOptions, state: &RunState) -> Result<JobOutput> {
+    if !is_stdio(&job.input) && !job.input.is_file() {
+        return Err(stdio::Error::from(ErrorKind::NotFound))
+            .with_context(|| format!("input {} does not exist", job.input.display()));
+    }
+    if let Some(parent) = job
+        .out
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty() && !is_object_url(&job.out))
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create output dir {}", parent.display()))?;
+    }
+    jsonl_to_pb(job, opts, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn row(json: &str) -> Row {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn example(json: &str, opts: &RecordOptions) -> std::result::Result<Example, Reject> {
+        row_to_example(&row(json), "commonsense", "train", opts).map(|(ex, _)| ex)
+    }
+
+    /// `RecordOptions` as clap parses them from `args`, defaults included.
+    fn record(args: &[&str]) -> RecordOptions {
+        #[derive(Parser)]
+        struct Record {
+            #[command(flatten)]
+            record: RecordOptions,
+        }
+        Record::parse_from(std::iter::once("test").chain(args.iter().copied())).record
+    }
+
+    fn convert_opts(record: RecordOptions) -> ConvertOptions {
+        ConvertOptions {
+            zstd_level: Some(3),
+            quiet: true,
+            record,
+            ..ConvertOptions::default()
+        }
+    }
+
+    /// Converts `jsonl` as `subset` through a temporary file, returning its only shard and the bytes on disk.
+    fn shard(jsonl: &str, subset: &str, opts: &ConvertOptions) -> (ShardInfo, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let job = Job {
+            input: dir.path().join("test.jsonl"),
+            subset: subset.into(),
+            split: "train".into(),
+            out: dir.path().join("test.pb.zst"),
+        };
+        fs::write(&job.input, jsonl).unwrap();
+        let mut shards = jsonl_to_pb(&job, opts, &RunState::default())
+            .unwrap()
+            .shards;
+        (shards.remove(0), fs::read(&job.out).unwrap())
+    }
+
+    /// Converts `jsonl` and decodes the shard it wrote.
+    fn convert(jsonl: &str, opts: &ConvertOptions) -> (ShardInfo, Vec<Example>) {
+        let (info, bytes) = shard(jsonl, "commonsense", opts);
+        (info, decode(bytes))
+    }
+
+    fn decode(shard: Vec<u8>) -> Vec<Example> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), shard).unwrap();
+        let mut reader = ShardReader::open(file.path(), None).unwrap();
+        std::iter::from_fn(|| reader.next_example().unwrap()).collect()
+    }
+
+    #[test]
+    fn labels_of_every_shape_resolve() {
+        let opts = record(&["--label-map", "acceptable=0, unacceptable=1"]);
+        let label = |raw: &str| {
+            example(&format!(r#"{{"scenario": "s", "label": {raw}}}"#), &opts).map(|ex| ex.label)
+        };
+        for (raw, want) in [
+            ("1", 1),
+            ("0", 0),
+            ("1.0", 1),
+            (r#""1""#, 1),
+            (r#"" 0 ""#, 0),
+            ("true", 1),
+            ("false", 0),
+            (r#""true""#, 1),
+            (r#""unacceptable""#, 1),
+            (r#""acceptable""#, 0),
+        ] {
+            assert_eq!(label(raw).unwrap(), want, "label {raw}");
+        }
+        for raw in ["0.5", r#""maybe""#, "4294967296"] {
+            assert!(
+                matches!(label(raw), Err(Reject::UnmappableLabel(_))),
+                "label {raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn label_map_wins_over_numeric_strings() {
+        let opts = record(&["--label-map", "1=0"]);
+        assert_eq!(
+            example(r#"{"scenario": "s", "label": "1"}"#, &opts)
+                .unwrap()
+                .label,
+            0
+        );
+        assert_eq!(
+            example(r#"{"scenario": "s", "label": 1}"#, &opts)
+                .unwrap()
+                .label,
+            1
+        );
+        assert!(parse_label_map("acceptable").is_err());
+        assert!(parse_label_map("acceptable=yes").is_err());
+    }
+
+    #[test]
+    fn missing_labels_default_to_zero_unless_required() {
+        assert_eq!(
+            example(r#"{"scenario": "s"}"#, &record(&[])).unwrap().label,
+            0
+        );
+        assert_eq!(
+            example(r#"{"scenario": "s", "label": null}"#, &record(&[]))
+                .unwrap()
+                .label,
+            0
+        );
+        assert!(matches!(
+            example(r#"{"scenario": "s"}"#, &record(&["--require-label"])),
+            Err(Reject::MissingLabel)
+        ));
+    }
+
+    #[test]
+    fn unmappable_labels_are_counted_not_written() {
+        let jsonl = [
+            r#"{"scenario": "a", "label": 1}"#,
+            r#"{"scenario": "b", "label": "unsure"}"#,
+            r#"{"scenario": "c", "label": true}"#,
+            r#"{"scenario": "d", "label": 2.5}"#,
+        ]
+        .join("\n");
+        let (info, examples) = convert(&jsonl, &convert_opts(record(&[])));
+        assert_eq!(
+            examples
+                .iter()
+                .map(|ex| (ex.text.as_str(), ex.label))
+                .collect::<Vec<_>>(),
+            [("a", 1), ("c", 1)]
+        );
+        assert_eq!(info.counts.examples, 2);
+        assert_eq!(info.counts.rejected.get("bad_label"), Some(&2));
+    }
+
+    #[test]
+    fn meta_keeps_strings_verbatim_and_other_values_as_json() {
+        let json = r#"{"scenario": "s", "rationale": "it \"hurts\"", "action": 3, "answer": true, "input": null, "output": {"b": [1, 2], "a": "x"}}"#;
+        let meta = example(json, &record(&[])).unwrap().meta;
+        assert_eq!(meta["rationale"], r#"it "hurts""#);
+        assert_eq!(meta["action"], "3");
+        assert_eq!(meta["answer"], "true");
+        assert_eq!(meta["input"], "null");
+        assert_eq!(meta["output"], r#"{"a":"x","b":[1,2]}"#);
+
+        let legacy = example(json, &record(&["--legacy-meta"])).unwrap().meta;
+        assert_eq!(legacy["rationale"], r#""it \"hurts\"""#);
+        assert_eq!(legacy["action"], "3");
+    }
+
+    #[test]
+    fn meta_strings_survive_a_shard_round_trip() {
+        let original = "tab\there, \"quotes\", ünïcödé and a trailing space ";
+        let jsonl = serde_json::json!({"scenario": "s", "rationale": original}).to_string();
+        let (_, examples) = convert(&jsonl, &convert_opts(record(&[])));
+        assert_eq!(examples[0].meta["rationale"], original);
+    }
+
+    #[test]
+    fn flatten_meta_stops_at_max_depth() {
+        let v = serde_json::json!({"a": {"b": {"c": 1}}, "d": "x"});
+        let flatten = |args: &[&str]| {
+            let mut meta = BTreeMap::new();
+            insert_meta(&mut meta, "output".to_string(), &v, &record(args), 0);
+            meta.into_iter().collect::<Vec<_>>()
+        };
+        let pairs = |p: &[(&str, &str)]| {
+            p.iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            flatten(&[]),
+            pairs(&[("output", r#"{"a":{"b":{"c":1}},"d":"x"}"#)])
+        );
+        assert_eq!(
+            flatten(&["--flatten-meta"]),
+            pairs(&[("output.a.b.c", "1"), ("output.d", "x")])
+        );
+        assert_eq!(
+            flatten(&["--flatten-meta", "--max-depth", "2"]),
+            pairs(&[("output.a.b", r#"{"c":1}"#), ("output.d", "x")])
+        );
+        assert_eq!(
+            flatten(&["--flatten-meta", "--max-depth", "0"]),
+            pairs(&[("output", r#"{"a":{"b":{"c":1}},"d":"x"}"#)])
+        );
+        assert_eq!(
+            flatten(&["--flatten-meta", "--meta-separator", "/"]),
+            pairs(&[("output/a/b/c", "1"), ("output/d", "x")])
+        );
+    }
+
+    #[test]
+    fn flatten_arrays_indexes_elements() {
+        let v = serde_json::json!({"tags": ["x", {"y": true}], "empty": []});
+        let flatten = |args: &[&str]| {
+            let mut meta = BTreeMap::new();
+            insert_meta(&mut meta, "input".to_string(), &v, &record(args), 0);
+            meta
+        };
+        let off = flatten(&["--flatten-meta"]);
+        assert_eq!(off["input.tags"], r#"["x",{"y":true}]"#);
+        assert_eq!(off["input.empty"], "[]");
+        let on = flatten(&["--flatten-meta", "--flatten-arrays"]);
+        assert_eq!(on["input.tags.0"], "x");
+        assert_eq!(on["input.tags.1.y"], "true");
+        // Nothing to index, so the empty array is kept rather than dropped.
+        assert_eq!(on["input.empty"], "[]");
+        assert_eq!(on.len(), 3);
+    }
+
+    #[test]
+    fn same_input_gives_the_same_bytes() {
+        let jsonl = (0..200).map(|i| format!(r#"{{"scenario": "row {i}", "label": {}, "rationale": "r{i}", "action": {i}, "output": {{"z": 1, "a": [{i}]}}}}"#, i % 2)).collect::<Vec<_>>().join("\n");
+        // Options (and so headers) built afresh for each run, a clock second apart, as two
+        // separate invocations would be.
+        let (first, bytes) = shard(&jsonl, "commonsense", &convert_opts(record(&[])));
+        std::thread::sleep(Duration::from_millis(1100));
+        let opts = convert_opts(record(&[]));
+        let (second, again) = shard(&jsonl, "commonsense", &opts);
+        assert_eq!(bytes, again);
+        assert_eq!(first.sha256, second.sha256);
+        assert_eq!(first.sha256, format!("{:x}", Sha256::digest(&bytes)));
+
+        // Meta is a BTreeMap, so the key order in the source row doesn't reach the shard.
+        let reordered = (0..200).map(|i| format!(r#"{{"output": {{"a": [{i}], "z": 1}}, "action": {i}, "rationale": "r{i}", "label": {}, "scenario": "row {i}"}}"#, i % 2)).collect::<Vec<_>>().join("\n");
+        assert_eq!(
+            shard(&reordered, "commonsense", &opts).0.sha256,
+            first.sha256
+        );
+    }
+
+    #[test]
+    fn manifest_is_deterministic_unless_headers_carry_the_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let deterministic = |opts: &ConvertOptions| {
+            let path = crate::manifest::write_manifest(dir.path(), opts, Vec::new()).unwrap();
+            let manifest: serde_json::Value =
+                serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+            manifest["deterministic"].as_bool().unwrap()
+        };
+        let opts = convert_opts(record(&[]));
+        assert_eq!(
+            opts.header.as_ref().unwrap().created_unix,
+            shard::source_date_epoch().unwrap_or(0)
+        );
+        assert!(deterministic(&opts));
+
+        let stamped = ConvertOptions {
+            header_timestamp: true,
+            ..convert_opts(record(&[]))
+        };
+        assert_eq!(
+            deterministic(&stamped),
+            shard::source_date_epoch().is_some()
+        );
+        assert!(deterministic(&ConvertOptions {
+            header: None,
+            ..stamped
+        }));
+    }
+
+    #[test]
+    fn duplicates_go_to_the_rejects_file_only_with_dedup_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        let job = Job {
+            input: dir.path().join("cm-train.jsonl"),
+            subset: "commonsense".into(),
+            split: "train".into(),
+            out: dir.path().join("cm-train.pb.zst"),
+        };
+        fs::write(
+            &job.input,
+            "{\"scenario\": \"same\"}\n{\"scenario\": \" same \"}\n",
+        )
+        .unwrap();
+        let rejected = |rejects| {
+            let opts = ConvertOptions {
+                dedup: Some(DedupConfig {
+                    case_insensitive: false,
+                    hash_only: false,
+                    rejects,
+                }),
+                overwrite: true,
+                ..convert_opts(record(&[]))
+            };
+            let state = RunState::new(Some(dir.path().join("rejects.jsonl")), opts.dedup);
+            let shards = jsonl_to_pb(&job, &opts, &state).unwrap().shards;
+            assert_eq!(shards[0].counts.rejected.get("duplicate"), Some(&1));
+            state.finish_rejects().unwrap().map(|(count, _)| count)
+        };
+        assert_eq!(rejected(false), None);
+        assert_eq!(rejected(true), Some(1));
+    }
+
+    #[test]
+    fn resume_after_a_failure_matches_an_uninterrupted_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("cm-train.jsonl");
+        // The bad line falls after the first checkpoint, so the strict run leaves one behind.
+        let lines: Vec<String> = (0..ResumeState::EVERY * 2)
+            .map(|i| {
+                if i == ResumeState::EVERY + 100 {
+                    "{not json".to_string()
+                } else {
+                    format!(r#"{{"scenario": "row {i}", "label": {}}}"#, i % 2)
+                }
+            })
+            .collect();
+        fs::write(&input, lines.join("\n")).unwrap();
+        let job = |out: &str| Job {
+            input: input.clone(),
+            subset: "commonsense".into(),
+            split: "train".into(),
+            out: dir.path().join(out),
+        };
+        let (resumed, fresh) = (job("resumed.pb.zst"), job("fresh.pb.zst"));
+
+        let mut opts = ConvertOptions {
+            resume: true,
+            strict: true,
+            ..convert_opts(record(&[]))
+        };
+        let Err(err) = run_job(&resumed, &opts, &RunState::default()) else {
+            panic!("the invalid line should abort a --strict run")
+        };
+        assert!(matches!(err, EthicsError::JsonParse { .. }), "{err}");
+        assert!(ResumeState::path_for(&resumed.out).exists());
+        assert!(!resumed.out.exists());
+
+        opts.strict = false;
+        let output = run_job(&resumed, &opts, &RunState::default()).unwrap();
+        assert!(!ResumeState::path_for(&resumed.out).exists());
+        let expected = run_job(&fresh, &opts, &RunState::default()).unwrap();
+
+        assert_eq!(
+            fs::read(&resumed.out).unwrap(),
+            fs::read(&fresh.out).unwrap()
+        );
+        let (got, want) = (&output.shards[0], &expected.shards[0]);
+        assert_eq!(
+            (
+                got.counts.examples,
+                got.counts.lines_read,
+                &got.counts.rejected
+            ),
+            (
+                want.counts.examples,
+                want.counts.lines_read,
+                &want.counts.rejected
+            )
+        );
+        assert_eq!(got.sha256, want.sha256);
+    }
+
+    #[test]
+    fn resume_seeks_past_the_checkpointed_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("cm-train.jsonl");
+        let lines: Vec<String> = (0..ResumeState::EVERY * 2)
+            .map(|i| match i {
+                _ if i == ResumeState::EVERY + 100 => "{not json".to_string(),
+                _ => format!(r#"{{"scenario": "row {i}", "label": {}}}"#, i % 2),
+            })
+            .collect();
+        fs::write(&input, lines.join("\n")).unwrap();
+        let job = |out: &str| Job {
+            input: input.clone(),
+            subset: "commonsense".into(),
+            split: "train".into(),
+            out: dir.path().join(out),
+        };
+        let (resumed, fresh) = (job("resumed.pb.zst"), job("fresh.pb.zst"));
+        let mut opts = ConvertOptions {
+            resume: true,
+            strict: true,
+            ..convert_opts(record(&[]))
+        };
+        assert!(run_job(&resumed, &opts, &RunState::default()).is_err());
+        let seek = ResumeState::load(&resumed).unwrap().unwrap().seek.unwrap();
+        let covered: usize = lines[..ResumeState::EVERY]
+            .iter()
+            .map(|l| l.len() + 1)
+            .sum();
+        assert_eq!(
+            (seek.records, seek.offset),
+            (ResumeState::EVERY, covered as u64)
+        );
+
+        opts.strict = false;
+        run_job(&fresh, &opts, &RunState::default()).unwrap();
+        // Join the first two lines without changing the size: only a run that skips
+        // records from the start would notice.
+        let mut bytes = fs::read(&input).unwrap();
+        bytes[lines[0].len()] = b' ';
+        fs::write(&input, bytes).unwrap();
+        run_job(&resumed, &opts, &RunState::default()).unwrap();
+        assert_eq!(
+            fs::read(&resumed.out).unwrap(),
+            fs::read(&fresh.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn deontology_context_round_trips() {
+        let jsonl = [r#"{"scenario": "Could you walk the dog?", "excuse": "But the dog was walked an hour ago.", "label": 1}"#, r#"{"scenario": "Could you cook dinner?", "excuse": "But I cooked it last night.", "label": 0}"#].join("\n");
+        let examples = decode(shard(&jsonl, "deontology", &convert_opts(record(&[]))).1);
+        assert_eq!(
+            examples[0].context.as_deref(),
+            Some("Could you walk the dog?")
+        );
+        assert_eq!(examples[0].text, "But the dog was walked an hour ago.");
+        assert_eq!(examples[1].label, 0);
+        assert!(examples
+            .iter()
+            .all(|ex| !ex.meta.contains_key("scenario") && !ex.meta.contains_key("excuse")));
+
+        let opts = convert_opts(record(&["--context-field", "question"]));
+        let (_, examples) = convert(
+            r#"{"question": "Is it fine?", "observation": "I left early.", "label": 0}"#,
+            &opts,
+        );
+        assert_eq!(
+            (examples[0].context.as_deref(), examples[0].text.as_str()),
+            (Some("Is it fine?"), "I left early.")
+        );
+    }
+
+    #[test]
+    fn shards_without_context_still_decode() {
+        let opts = convert_opts(record(&["--no-context"]));
+        let examples = decode(shard(r#"{"scenario": "s", "excuse": "e"}"#, "deontology", &opts).1);
+        assert_eq!(
+            (examples[0].context.as_deref(), examples[0].text.as_str()),
+            (None, "s")
+        );
+
+        // What a shard written before `context` existed holds: fields 1-6 only.
+        let old = Example {
+            subset: "deontology".into(),
+            split: "train".into(),
+            text: "e".into(),
+            label: 1,
+            id: "x".into(),
+            ..Example::default()
+        };
+        assert_eq!(decode(old.encode_length_delimited_to_vec()), [old]);
+    }
+
+    #[test]
+    fn empty_text_is_rejected_and_counted() {
+        let jsonl = [
+            r#"{"scenario": "kept", "label": 1}"#,
+            r#"{"scenario": "", "label": 0}"#,
+            r#"{"scenario": " \t\n ", "label": 0}"#,
+            r#"{"label": 1, "rationale": "no text field"}"#,
+            r#"{"question": "also kept"}"#,
+        ]
+        .join("\n");
+        let (info, examples) = convert(&jsonl, &convert_opts(record(&[])));
+        assert_eq!(
+            examples
+                .iter()
+                .map(|ex| ex.text.as_str())
+                .collect::<Vec<_>>(),
+            ["kept", "also kept"]
+        );
+        assert_eq!((info.counts.lines_read, info.counts.examples), (5, 2));
+        assert_eq!(
+            info.counts.rejected,
+            BTreeMap::from([("empty_text".to_string(), 3)])
+        );
+
+        let (info, _) = convert(&jsonl, &convert_opts(record(&["--allow-empty-text"])));
+        assert_eq!((info.counts.examples, info.counts.rejected.len()), (5, 0));
+    }
+
+    #[test]
+    fn empty_text_check_follows_field_selection() {
+        // A blank first choice falls through to the next field instead of being rejected.
+        assert_eq!(
+            example(r#"{"scenario": "  ", "question": "q"}"#, &record(&[]))
+                .unwrap()
+                .text,
+            "q"
+        );
+        // Text only in a field outside `--text-fields` doesn't count.
+        let opts = record(&["--text-fields", "question"]);
+        assert!(matches!(
+            example(r#"{"scenario": "s"}"#, &opts),
+            Err(Reject::EmptyText)
+        ));
+        assert_eq!(
+            example(r#"{"scenario": "s", "question": "q"}"#, &opts)
+                .unwrap()
+                .text,
+            "q"
+        );
+    }
+
+    #[test]
+    fn normalize_leaves_meta_alone_unless_asked() {
+        let json = r#"{"scenario": "I  fed\u00a0the cat\u200b", "rationale": "kind\u00a0 act"}"#;
+        let ex = example(json, &record(&["--normalize"])).unwrap();
+        assert_eq!(
+            (ex.text.as_str(), ex.meta["rationale"].as_str()),
+            ("I fed the cat", "kind\u{a0} act")
+        );
+        let ex = example(json, &record(&["--normalize", "--normalize-meta"])).unwrap();
+        assert_eq!(ex.meta["rationale"], "kind act");
+
+        let jsonl = [json, r#"{"scenario": "clean"}"#].join("\n");
+        let (info, _) = convert(&jsonl, &convert_opts(record(&["--normalize"])));
+        assert_eq!(info.counts.normalized, 1);
+    }
+
+    #[test]
+    fn rows_split_off_the_label() {
+        let r = row(r#"{"label": "2", "scenario": "s", "extra": [1]}"#);
+        assert!(matches!(r.label, Some(RawLabel::Str(ref s)) if s == "2"));
+        assert_eq!(r.fields.keys().collect::<Vec<_>>(), ["extra", "scenario"]);
+        assert!(row(r#"{"label": null}"#).label.is_none());
+        assert!(serde_json::from_str::<Row>(r#"{"label": {"nested": 1}}"#).is_err());
+        assert!(serde_json::from_str::<Row>("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn encoding_into_a_used_buffer_matches_a_fresh_one() {
+        let opts = RecordOptions::default();
+        let line = r#"{"scenario": "I helped a stranger.", "label": 0, "rationale": "kind"}"#;
+        let Parsed::Encoded(fresh) = parse_record(line, false, "commonsense", "train", &opts)
+        else {
+            panic!("not encoded")
+        };
+        let mut used = vec![0xaa; 4096];
+        used.truncate(7);
+        let ptr = used.as_ptr();
+        let Parsed::Encoded(reused) =
+            parse_into(line, false, ("commonsense", "train", &opts), used)
+        else {
+            panic!("not encoded")
+        };
+        assert_eq!(reused.buf, fresh.buf);
+        assert_eq!(reused.buf.as_ptr(), ptr, "the buffer was reallocated");
+        assert_eq!(
+            Example::decode_length_delimited(reused.buf.as_slice())
+                .unwrap()
+                .text,
+            "I helped a stranger."
+        );
+    }
+
+    #[test]
+    fn spare_buffers_are_capped() {
+        let spares = SpareBufs::default();
+        let mut bufs = vec![
+            Vec::with_capacity(SPARE_MAX_BYTES + 1),
+            Vec::with_capacity(16),
+        ];
+        spares.give(&mut bufs);
+        assert!(bufs.is_empty());
+        assert_eq!(
+            spares.take().iter().map(Vec::capacity).collect::<Vec<_>>(),
+            [16]
+        );
+
+        spares.give(
+            &mut (0..PARSE_BATCH * 5)
+                .map(|_| Vec::with_capacity(16))
+                .collect(),
+        );
+        assert_eq!(spares.0.lock().unwrap().len(), PARSE_BATCH * 4);
+        assert_eq!(spares.take().len(), PARSE_BATCH);
+    }
+
+    #[test]
+    fn parse_threads_give_the_same_shard() {
+        // Enough records for several batches, so workers hand buffers back and forth.
+        let jsonl = (0..PARSE_BATCH * 5)
+            .map(|i| match i % 7 {
+                0 => "{broken".to_string(),
+                1 => String::new(),
+                _ => format!(
+                    r#"{{"scenario": "row {i} {}", "label": {}}}"#,
+                    "word ".repeat(i % 50),
+                    i % 2
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut opts = ConvertOptions::default();
+        let (single, bytes) = shard(&jsonl, "commonsense", &opts);
+        opts.parse_threads = 4;
+        let (parallel, parallel_bytes) = shard(&jsonl, "commonsense", &opts);
+        assert_eq!(parallel_bytes, bytes);
+        assert_eq!(
+            (parallel.counts.examples, &parallel.counts.rejected),
+            (single.counts.examples, &single.counts.rejected)
+        );
+    }
+
+    #[test]
+    fn mmap_converts_like_the_streaming_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("cm-test.jsonl");
+        let mut data = b"\xef\xbb\xbf{\"scenario\": \"bom first\", \"label\": 1}\r\n\n".to_vec();
+        for i in 0..3000 {
+            data.extend(format!("{{\"scenario\": \"row {i}\", \"label\": {}}}\n", i % 2).bytes());
+        }
+        data.extend(b"{broken\n\r\n");
+        data.extend(format!("{{\"scenario\": \"{}\"}}\n", "x".repeat(600)).bytes());
+        data.extend(b"{\"scenario\": \"\"}\n{\"scenario\": \"no newline at the end\"}");
+        fs::write(&input, &data).unwrap();
+
+        let mut opts = ConvertOptions {
+            quiet: true,
+            ..ConvertOptions::default()
+        };
+        opts.record.max_line_bytes = 512;
+        for threads in [1, 4] {
+            opts.parse_threads = threads;
+            let mut outputs = Vec::new();
+            for mmap in [false, true] {
+                opts.mmap = mmap;
+                let job = Job {
+                    input: input.clone(),
+                    subset: "commonsense".into(),
+                    split: "test".into(),
+                    out: dir.path().join(format!("{threads}-{mmap}.pb.zst")),
+                };
+                let output = run_job(&job, &opts, &RunState::default()).unwrap();
+                outputs.push((fs::read(&job.out).unwrap(), output.shards[0].counts.clone()));
+            }
+            let (streamed, mapped) = (&outputs[0], &outputs[1]);
+            assert_eq!(mapped.0, streamed.0, "--parse-threads {threads}");
+            assert_eq!(
+                serde_json::to_value(&mapped.1).unwrap(),
+                serde_json::to_value(&streamed.1).unwrap()
+            );
+            assert_eq!(streamed.1.examples, 3002);
+            assert_eq!(streamed.1.rejected.values().sum::<usize>(), 3);
+        }
+
+        // Invalid UTF-8 stops the run either way.
+        fs::write(&input, b"{\"scenario\": \"ok\"}\n\xff\xfe\n").unwrap();
+        for mmap in [false, true] {
+            opts.mmap = mmap;
+            let job = Job {
+                input: input.clone(),
+                subset: "commonsense".into(),
+                split: "test".into(),
+                out: dir.path().join(format!("utf8-{mmap}.pb.zst")),
+            };
+            let Err(err) = run_job(&job, &opts, &RunState::default()) else {
+                panic!("invalid UTF-8 was accepted with --mmap {mmap}")
+            };
+            assert!(
+                matches!(err.root(), EthicsError::Io { source, .. } if source.kind() == ErrorKind::InvalidData),
+                "{err}"
+            );
+        }
+    }
+}
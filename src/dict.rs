@@ -0,0 +1,62 @@
+//! zstd dictionaries trained by `ethics-pipeline train-dict`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use zstd::zstd_safe;
+
+use crate::error::{bail, Context, Result};
+
+/// A dictionary loaded from disk, with the identifiers recorded in manifests.
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+    /// Dictionary id stored in the dictionary header and in every frame compressed with it.
+    pub id: Option<u32>,
+    /// Hex SHA-256 of the dictionary file.
+    pub sha256: String,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("failed to read dictionary {}", path.display()))?;
+        Ok(Self::from_bytes(path.to_path_buf(), bytes))
+    }
+
+    pub fn from_bytes(path: PathBuf, bytes: Vec<u8>) -> Self {
+        let id = zstd_safe::get_dict_id_from_dict(&bytes).map(|id| id.get());
+        let sha256 = format!("{:x}", Sha256::digest(&bytes));
+        Dictionary {
+            path,
+            bytes,
+            id,
+            sha256,
+        }
+    }
+}
+
+/// Checks that the zstd frame starting at `head` can be decoded with `dict`,
+/// so a missing or wrong dictionary is reported up front instead of as a
+/// corrupt-data error from deep inside the decoder.
+pub fn check_frame(head: &[u8], dict: Option<&Dictionary>, shard: &Path) -> Result<()> {
+    let needed = zstd_safe::get_dict_id_from_frame(head).map(|id| id.get());
+    match (needed, dict) {
+        (Some(needed), None) => bail!(
+            Dictionary,
+            "{} was compressed with zstd dictionary id {needed}; pass it with --dict",
+            shard.display()
+        ),
+        (Some(needed), Some(dict)) if dict.id != Some(needed) => bail!(
+            Dictionary,
+            "dictionary mismatch: {} needs dictionary id {needed}, but {} (sha256 {}) has id {}",
+            shard.display(),
+            dict.path.display(),
+            dict.sha256,
+            dict.id.map_or("none".to_string(), |id| id.to_string()),
+        ),
+        _ => Ok(()),
+    }
+}
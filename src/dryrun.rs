@@ -0,0 +1,233 @@
+//! `--dry-run` for the converter, `prune_data_by_length` and the shard tools, so
+//! they all report a plan the same way. Each input is opened and its first
+//! [`SAMPLE`] records parsed, its records counted, and each output checked for
+//! an existing file; nothing is written or removed. Problems are collected rather
+//! than returned one at a time, and [`DryRun::finish`] fails if there were any.
+
+use std::fmt::Display;
+use std::io::BufRead;
+use std::path::Path;
+
+use prost::Message;
+
+use crate::dict::Dictionary;
+use crate::error::{EthicsError, Result};
+use crate::ethics::{Example, PairExample};
+use crate::input::{
+    is_pairs_shard, is_stdio, line_too_long, open_maybe_compressed, records, InputFormat,
+};
+use crate::shard::ShardReader;
+
+/// Records parsed from the start of each input.
+pub const SAMPLE: usize = 5;
+
+/// The plan being built: one line per file read, written or removed.
+#[derive(Debug, Default)]
+pub struct DryRun {
+    steps: Vec<String>,
+    problems: Vec<String>,
+}
+
+impl DryRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn problem(&mut self, problem: impl Display) {
+        self.problems.push(problem.to_string());
+    }
+
+    pub fn note(&mut self, note: impl Display) {
+        self.steps.push(format!("  {note}"));
+    }
+
+    /// A JSONL, JSON array, CSV or TSV input: the first records must be JSON
+    /// objects. The count comes from a line count, so it is an estimate for inputs
+    /// with blank lines or multi-line CSV cells.
+    pub fn read_records(&mut self, path: &Path, format: InputFormat) {
+        if is_stdio(path) {
+            self.steps.push("read   <stdin> (not sampled)".to_string());
+            return;
+        }
+        let format = format.for_path(path);
+        match sample_records(path, format).and_then(|()| count_records(path, format)) {
+            Ok(n) => self.steps.push(format!(
+                "read   {}: ~{n} record(s), first {SAMPLE} parse",
+                path.display()
+            )),
+            Err(e) => {
+                self.steps.push(format!("read   {}", path.display()));
+                self.problem(format!("{}: {e}", path.display()));
+            }
+        }
+    }
+
+    /// A shard: the first records must decode as the message it holds, and the
+    /// rest are counted by their length prefixes.
+    pub fn read_shard(&mut self, path: &Path, dict: Option<&Dictionary>) {
+        if is_stdio(path) {
+            self.steps.push("read   <stdin> (not sampled)".to_string());
+            return;
+        }
+        match scan_shard(path, dict) {
+            Ok((message, n)) => self.steps.push(format!(
+                "read   {}: {n} {message} record(s), first {SAMPLE} decode",
+                path.display()
+            )),
+            Err(e) => {
+                self.steps.push(format!("read   {}", path.display()));
+                self.problem(format!("{}: {e}", path.display()));
+            }
+        }
+    }
+
+    /// An output file, which may only exist already with `overwrite`.
+    pub fn write(&mut self, path: &Path, overwrite: bool) {
+        if is_stdio(path) {
+            self.steps.push("write  <stdout>".to_string());
+        } else if !path.exists() {
+            self.steps.push(format!("write  {}", path.display()));
+        } else if overwrite {
+            self.steps
+                .push(format!("write  {} (replacing it)", path.display()));
+        } else {
+            self.steps.push(format!("write  {}", path.display()));
+            self.problem(format!(
+                "{} already exists (pass --overwrite to replace it)",
+                path.display()
+            ));
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.steps.push(format!("remove {}", path.display()));
+    }
+
+    /// Prints the plan and any problems; fails if there were problems.
+    pub fn finish(self) -> Result<()> {
+        println!("dry run, nothing written:");
+        for step in &self.steps {
+            println!("  {step}");
+        }
+        if self.problems.is_empty() {
+            return Ok(());
+        }
+        for problem in &self.problems {
+            eprintln!("problem: {problem}");
+        }
+        Err(EthicsError::InvalidArgument(format!(
+            "dry run found {} problem(s)",
+            self.problems.len()
+        )))
+    }
+}
+
+fn sample_records(path: &Path, format: InputFormat) -> Result<()> {
+    let reader = open_maybe_compressed(path)?;
+    let mut sampled = 0;
+    for record in records(reader, format)? {
+        // Overlong lines are rejects, not a reason the run would fail.
+        let (pos, text) = match record {
+            Err(e) if line_too_long(&e).is_some() => continue,
+            record => record?,
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value =
+            serde_json::from_str(&text).map_err(|source| EthicsError::JsonParse {
+                location: pos.locate(path),
+                line: pos.index(),
+                source,
+            })?;
+        if !value.is_object() {
+            return Err(EthicsError::InvalidRecord(format!(
+                "{}: not a JSON object",
+                pos.locate(path)
+            )));
+        }
+        sampled += 1;
+        if sampled == SAMPLE {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Non-blank lines, less the header row for CSV/TSV; array elements for JSON.
+fn count_records(path: &Path, format: InputFormat) -> Result<u64> {
+    let mut reader = open_maybe_compressed(path)?;
+    let format = match format {
+        InputFormat::Auto => {
+            let first = reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace());
+            if first == Some(&b'[') {
+                InputFormat::Json
+            } else {
+                InputFormat::Jsonl
+            }
+        }
+        other => other,
+    };
+    if format == InputFormat::Json {
+        return Ok(records(reader, format)?.count() as u64);
+    }
+    let mut lines = 0u64;
+    let mut blank = true;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for &b in buf {
+            if b == b'\n' {
+                lines += u64::from(!blank);
+                blank = true;
+            } else if !b.is_ascii_whitespace() {
+                blank = false;
+            }
+        }
+        let n = buf.len();
+        reader.consume(n);
+    }
+    lines += u64::from(!blank);
+    Ok(match format {
+        InputFormat::Csv | InputFormat::Tsv => lines.saturating_sub(1),
+        _ => lines,
+    })
+}
+
+/// The shard's message type and record count, decoding the first few records.
+fn scan_shard(path: &Path, dict: Option<&Dictionary>) -> Result<(&'static str, u64)> {
+    let mut reader = ShardReader::open(path, dict)?;
+    let pairs = match &reader.header {
+        Some(header) => header.message == "ethics.v1.PairExample",
+        None => is_pairs_shard(path),
+    };
+    let message = if pairs {
+        "ethics.v1.PairExample"
+    } else {
+        "ethics.v1.Example"
+    };
+    let mut n = 0u64;
+    while (n as usize) < SAMPLE {
+        let offset = reader.offset();
+        let Some(buf) = reader.next_record()? else {
+            return Ok((message, n));
+        };
+        let decoded = if pairs {
+            PairExample::decode(buf).map(drop)
+        } else {
+            Example::decode(buf).map(drop)
+        };
+        decoded.map_err(|source| EthicsError::Decode {
+            message,
+            offset,
+            source,
+        })?;
+        n += 1;
+    }
+    while reader.skip_record()? {
+        n += 1;
+    }
+    Ok((message, n))
+}
@@ -0,0 +1,172 @@
+//! `EthicsError`, the error type of the library modules. Binaries wrap it in
+//! `anyhow`; library callers can match on what went wrong.
+
+use std::fmt::Display;
+use std::io;
+
+/// What went wrong, with the context that used to go into `anyhow` messages.
+/// Each variant displays its own layer; the underlying error is its `source()`.
+#[derive(Debug, thiserror::Error)]
+pub enum EthicsError {
+    /// Reading or writing a file or stream failed. A missing input is `NotFound`.
+    #[error("{}", io_context(.context))]
+    Io { context: String, source: io::Error },
+
+    /// An input record isn't valid JSON.
+    #[error("invalid JSON at {location}")]
+    JsonParse {
+        /// `file:line` or `file[element N]`.
+        location: String,
+        /// 1-based line, or element index for JSON array inputs.
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    /// A shard record doesn't decode as the message it should hold.
+    #[error("failed to decode {message} at byte offset {offset}")]
+    Decode {
+        message: &'static str,
+        /// Position of the record in the decompressed stream.
+        offset: u64,
+        source: prost::DecodeError,
+    },
+
+    /// A shard or sidecar is truncated, fails its checksums, or is otherwise damaged.
+    #[error("{0}")]
+    Corrupt(String),
+
+    /// Input or shard doesn't have the expected shape: `--expect-schema` keys,
+    /// message type, schema or format version.
+    #[error("{0}")]
+    SchemaMismatch(String),
+
+    /// A label that can't be used, e.g. a `--label-map` entry that isn't an integer.
+    #[error("{0}")]
+    LabelInvalid(String),
+
+    /// A zstd dictionary is missing or doesn't match the shard.
+    #[error("{0}")]
+    Dictionary(String),
+
+    /// Options that can't work, alone or together; also bad templates and existing outputs.
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    /// A record the conversion can't get past, or too many bad ones.
+    #[error("{0}")]
+    InvalidRecord(String),
+
+    /// Another error, with what was being done when it happened.
+    #[error("{context}")]
+    Context {
+        context: String,
+        source: Box<EthicsError>,
+    },
+}
+
+fn io_context(context: &str) -> &str {
+    if context.is_empty() {
+        "I/O error"
+    } else {
+        context
+    }
+}
+
+/// `Result` with `EthicsError` as the default error.
+pub type Result<T, E = EthicsError> = std::result::Result<T, E>;
+
+impl EthicsError {
+    /// Adds `context`: I/O errors without any take it directly, others are wrapped.
+    pub fn context(self, context: impl Display) -> Self {
+        match self {
+            EthicsError::Io { context: c, source } if c.is_empty() => EthicsError::Io {
+                context: context.to_string(),
+                source,
+            },
+            e => EthicsError::Context {
+                context: context.to_string(),
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// The error under any `Context` layers, for matching on.
+    pub fn root(&self) -> &EthicsError {
+        match self {
+            EthicsError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+}
+
+impl From<io::Error> for EthicsError {
+    fn from(source: io::Error) -> Self {
+        EthicsError::Io {
+            context: String::new(),
+            source,
+        }
+    }
+}
+
+/// Writing sidecars and reject lines only fails on I/O; JSON read back from
+/// disk is mapped to a variant where it is parsed.
+impl From<serde_json::Error> for EthicsError {
+    fn from(source: serde_json::Error) -> Self {
+        io::Error::from(source).into()
+    }
+}
+
+/// Export writers only fail on I/O once their columns are built; anything else
+/// is a bug in `export`, reported as I/O rather than given a variant of its own.
+impl From<arrow::error::ArrowError> for EthicsError {
+    fn from(source: arrow::error::ArrowError) -> Self {
+        io::Error::other(source).into()
+    }
+}
+
+impl From<parquet::errors::ParquetError> for EthicsError {
+    fn from(source: parquet::errors::ParquetError) -> Self {
+        io::Error::other(source).into()
+    }
+}
+
+impl From<rusqlite::Error> for EthicsError {
+    fn from(source: rusqlite::Error) -> Self {
+        io::Error::other(source).into()
+    }
+}
+
+/// `with_context` for results whose error converts into `EthicsError`, as with `anyhow`.
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+
+    fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<EthicsError>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+
+    fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+/// `anyhow::bail!` for the message-only variants: `bail!(Corrupt, "...", args)`.
+macro_rules! bail {
+    ($variant:ident, $($arg:tt)+) => {
+        return Err($crate::error::EthicsError::$variant(format!($($arg)+)))
+    };
+}
+
+/// `anyhow::ensure!` likewise: `ensure!(cond, InvalidArgument, "...", args)`.
+macro_rules! ensure {
+    ($cond:expr, $variant:ident, $($arg:tt)+) => {
+        if !$cond {
+            $crate::error::bail!($variant, $($arg)+);
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
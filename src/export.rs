@@ -0,0 +1,928 @@
+//! `ethics-pipeline export`: `Example` shards rewritten as columnar files that
+//! pandas, polars and DuckDB read directly.
+//!
+//! Every format shares one column layout, versioned by [`EXPORT_SCHEMA_VERSION`]
+//! and stored under [`SCHEMA_VERSION_KEY`] in the file's schema metadata:
+//!
+//! | column           | type                                     | null |
+//! |------------------|------------------------------------------|------|
+//! | `subset`         | utf8 (dictionary-encoded in Arrow)       | no   |
+//! | `split`          | utf8 (dictionary-encoded in Arrow)       | no   |
+//! | `text`           | utf8                                     | no   |
+//! | `label`          | int32                                    | no   |
+//! | `meta`           | map<utf8, utf8>, or utf8 JSON object     | no   |
+//! | `id`             | utf8                                     | no   |
+//! | `context`        | utf8                                     | yes  |
+//! | `score`          | float64                                  | yes  |
+//! | `labels_by_name` | map<utf8, int32>, or utf8 JSON object    | no   |
+//!
+//! Map entries are in key order; an example without any is an empty map (`{}` as
+//! JSON). Columns are only ever added at the end, with a version bump.
+//! TFRecord has no columns; its features are listed in [`crate::tfrecord`], the
+//! WebDataset tar layout in [`crate::webdataset`], and the SQLite tables in
+//! [`crate::sqlite`].
+//! [`ExportReader`] reads any export back as `Example`s.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, AsArray, Float64Builder, Int32Builder, MapBuilder, StringBuilder,
+    StringDictionaryBuilder,
+};
+use arrow::compute::{cast, cast_with_options, CastOptions};
+use arrow::datatypes::{DataType, Field, Float64Type, Int32Type, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use prost::Message;
+use serde::de::DeserializeOwned;
+
+use crate::error::{ensure, Context, EthicsError, Result};
+use crate::ethics::Example;
+use crate::sqlite::{SqliteReader, SqliteWriter};
+use crate::tfrecord::{self, TfExample};
+use crate::webdataset::{TarKey, TarReader, TarWriter};
+use crate::writer::tmp_path;
+
+/// Bumped whenever the exported columns change.
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Schema metadata key holding [`EXPORT_SCHEMA_VERSION`].
+pub const SCHEMA_VERSION_KEY: &str = "ethics.export_schema_version";
+
+/// The exported columns, in order; also the `Example` fields `import` reads.
+pub(crate) const COLUMNS: [&str; 9] = [
+    "subset",
+    "split",
+    "text",
+    "label",
+    "meta",
+    "id",
+    "context",
+    "score",
+    "labels_by_name",
+];
+
+/// File format `export` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Apache Parquet, zstd-compressed column chunks.
+    Parquet,
+    /// Arrow IPC stream (`.arrows`), for zero-copy reads from pyarrow, polars or DuckDB.
+    Arrow,
+    /// TFRecord of `tf.train.Example`, for `tf.data.TFRecordDataset`.
+    Tfrecord,
+    /// WebDataset tars of `<key>.json` and `<key>.txt` members.
+    Webdataset,
+    /// SQLite database with `examples` and `meta` tables, for ad-hoc SQL.
+    Sqlite,
+}
+
+impl ExportFormat {
+    /// `ex` as reading it back from this format gives it: TFRecord keeps `score`
+    /// as an f32 feature.
+    pub fn round_trip(self, mut ex: Example) -> Example {
+        if self == ExportFormat::Tfrecord {
+            ex.score = ex.score.map(|s| s as f32 as f64);
+        }
+        ex
+    }
+}
+
+/// How the map fields `meta` and `labels_by_name` are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MapColumns {
+    /// Native map columns.
+    #[default]
+    Map,
+    /// JSON object strings, for readers without map support.
+    Json,
+}
+
+/// Settings shared by every exported file.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Rows per record batch, the most held in memory at once.
+    pub batch_rows: usize,
+    /// Rows per Parquet row group.
+    pub row_group_size: usize,
+    /// zstd level of the column chunks (0 selects the format's default); `None`
+    /// leaves them uncompressed.
+    pub zstd_level: Option<i32>,
+    pub maps: MapColumns,
+    /// Gzip the whole file, as `TFRecordOptions(compression_type="GZIP")` does;
+    /// TFRecord only.
+    pub gzip: bool,
+    /// Examples per WebDataset tar.
+    pub examples_per_tar: u64,
+    pub tar_key: TarKey,
+    /// zstd-compress each WebDataset tar at `zstd_level`.
+    pub zstd_tars: bool,
+    pub overwrite: bool,
+}
+
+impl ExportOptions {
+    /// Extension of the files written, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self.format {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Arrow => "arrows",
+            ExportFormat::Tfrecord if self.gzip => "tfrecord.gz",
+            ExportFormat::Tfrecord => "tfrecord",
+            ExportFormat::Webdataset if self.zstd_tars => "tar.zst",
+            ExportFormat::Webdataset => "tar",
+            ExportFormat::Sqlite => "db",
+        }
+    }
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: ExportFormat::Parquet,
+            batch_rows: 8192,
+            row_group_size: 100_000,
+            zstd_level: Some(3),
+            maps: MapColumns::Map,
+            gzip: false,
+            examples_per_tar: 10_000,
+            tar_key: TarKey::Index,
+            zstd_tars: false,
+            overwrite: false,
+        }
+    }
+}
+
+/// A string column, dictionary-encoded when its values repeat a lot.
+enum Strings {
+    Plain(StringBuilder),
+    Dictionary(StringDictionaryBuilder<Int32Type>),
+}
+
+impl Strings {
+    fn new(dictionary: bool) -> Self {
+        if dictionary {
+            Strings::Dictionary(StringDictionaryBuilder::new())
+        } else {
+            Strings::Plain(StringBuilder::new())
+        }
+    }
+
+    fn append(&mut self, value: &str) {
+        match self {
+            Strings::Plain(b) => b.append_value(value),
+            Strings::Dictionary(b) => b.append_value(value),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Strings::Plain(b) => Arc::new(b.finish()),
+            Strings::Dictionary(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// The map columns being built, in whichever form `MapColumns` asked for.
+enum Maps {
+    // Boxed: map builders are far larger than the JSON ones.
+    Native {
+        meta: Box<MapBuilder<StringBuilder, StringBuilder>>,
+        labels_by_name: Box<MapBuilder<StringBuilder, Int32Builder>>,
+    },
+    Json {
+        meta: StringBuilder,
+        labels_by_name: StringBuilder,
+    },
+}
+
+/// One record batch of examples, column by column.
+struct Columns {
+    subset: Strings,
+    split: Strings,
+    text: StringBuilder,
+    label: Int32Builder,
+    id: StringBuilder,
+    context: StringBuilder,
+    score: Float64Builder,
+    maps: Maps,
+    rows: usize,
+}
+
+impl Columns {
+    fn new(format: ExportFormat, maps: MapColumns) -> Self {
+        // Parquet dictionary-encodes repeated values on its own.
+        let dictionary = format == ExportFormat::Arrow;
+        let maps = match maps {
+            MapColumns::Map => Maps::Native {
+                meta: Box::new(MapBuilder::new(
+                    None,
+                    StringBuilder::new(),
+                    StringBuilder::new(),
+                )),
+                labels_by_name: Box::new(MapBuilder::new(
+                    None,
+                    StringBuilder::new(),
+                    Int32Builder::new(),
+                )),
+            },
+            MapColumns::Json => Maps::Json {
+                meta: StringBuilder::new(),
+                labels_by_name: StringBuilder::new(),
+            },
+        };
+        Columns {
+            subset: Strings::new(dictionary),
+            split: Strings::new(dictionary),
+            text: StringBuilder::new(),
+            label: Int32Builder::new(),
+            id: StringBuilder::new(),
+            context: StringBuilder::new(),
+            score: Float64Builder::new(),
+            maps,
+            rows: 0,
+        }
+    }
+
+    fn push(&mut self, ex: &Example) -> Result<()> {
+        self.subset.append(&ex.subset);
+        self.split.append(&ex.split);
+        self.text.append_value(&ex.text);
+        self.label.append_value(ex.label);
+        self.id.append_value(&ex.id);
+        self.context.append_option(ex.context.as_deref());
+        self.score.append_option(ex.score);
+        match &mut self.maps {
+            Maps::Native {
+                meta,
+                labels_by_name,
+            } => {
+                for (k, v) in &ex.meta {
+                    meta.keys().append_value(k);
+                    meta.values().append_value(v);
+                }
+                meta.append(true)?;
+                for (k, v) in &ex.labels_by_name {
+                    labels_by_name.keys().append_value(k);
+                    labels_by_name.values().append_value(*v);
+                }
+                labels_by_name.append(true)?;
+            }
+            Maps::Json {
+                meta,
+                labels_by_name,
+            } => {
+                meta.append_value(serde_json::to_string(&ex.meta)?);
+                labels_by_name.append_value(serde_json::to_string(&ex.labels_by_name)?);
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// The buffered rows as a batch, leaving the builders empty. The schema is
+    /// taken from the arrays, so it always matches them.
+    fn finish(&mut self) -> Result<RecordBatch> {
+        let (meta, labels_by_name): (ArrayRef, ArrayRef) = match &mut self.maps {
+            Maps::Native {
+                meta,
+                labels_by_name,
+            } => (Arc::new(meta.finish()), Arc::new(labels_by_name.finish())),
+            Maps::Json {
+                meta,
+                labels_by_name,
+            } => (Arc::new(meta.finish()), Arc::new(labels_by_name.finish())),
+        };
+        let columns: Vec<(&str, ArrayRef, bool)> = vec![
+            ("subset", self.subset.finish(), false),
+            ("split", self.split.finish(), false),
+            ("text", Arc::new(self.text.finish()), false),
+            ("label", Arc::new(self.label.finish()), false),
+            ("meta", meta, false),
+            ("id", Arc::new(self.id.finish()), false),
+            ("context", Arc::new(self.context.finish()), true),
+            ("score", Arc::new(self.score.finish()), true),
+            ("labels_by_name", labels_by_name, false),
+        ];
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|(name, array, nullable)| Field::new(*name, array.data_type().clone(), *nullable))
+            .collect();
+        let metadata = HashMap::from([(
+            SCHEMA_VERSION_KEY.to_string(),
+            EXPORT_SCHEMA_VERSION.to_string(),
+        )]);
+        let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+        self.rows = 0;
+        let arrays = columns.into_iter().map(|(_, array, _)| array).collect();
+        Ok(RecordBatch::try_new(schema, arrays)?)
+    }
+}
+
+/// The Arrow schema of every export in `format` written with `maps`.
+pub fn schema(format: ExportFormat, maps: MapColumns) -> SchemaRef {
+    Columns::new(format, maps)
+        .finish()
+        .expect("an empty batch matches its own schema")
+        .schema()
+}
+
+/// Writes examples to one exported file, or for WebDataset to the numbered tars
+/// named after it. Data goes to `.<name>.tmp` beside the destination and is moved
+/// into place by `finish`; dropping the writer before that removes it.
+pub struct ExportWriter {
+    writer: Option<Output>,
+    columns: Columns,
+    batch_rows: usize,
+    rows: u64,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+enum Output {
+    Parquet(ArrowWriter<File>),
+    Arrow(StreamWriter<BufWriter<File>>),
+    Tfrecord(BufWriter<File>),
+    TfrecordGzip(GzEncoder<BufWriter<File>>),
+    Webdataset(TarWriter),
+    Sqlite(SqliteWriter),
+}
+
+impl ExportWriter {
+    pub fn create(path: &Path, opts: &ExportOptions) -> Result<Self> {
+        ensure!(
+            opts.overwrite || !path.exists() || opts.format == ExportFormat::Webdataset,
+            InvalidArgument,
+            "{} already exists (pass --overwrite to replace it)",
+            path.display()
+        );
+        ensure!(
+            opts.row_group_size > 0 && opts.batch_rows > 0,
+            InvalidArgument,
+            "row group and batch sizes must be at least 1"
+        );
+        ensure!(
+            !opts.gzip || opts.format == ExportFormat::Tfrecord,
+            InvalidArgument,
+            "--gzip only applies to --format tfrecord"
+        );
+        ensure!(
+            !opts.zstd_tars
+                || (opts.format == ExportFormat::Webdataset && opts.zstd_level.is_some()),
+            InvalidArgument,
+            "--zstd-tars only applies to --format webdataset, with compression on"
+        );
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let tmp = tmp_path(path)?;
+        if opts.format == ExportFormat::Webdataset {
+            let zstd_level = opts.zstd_level.filter(|_| opts.zstd_tars);
+            let tars = TarWriter::create(
+                path,
+                opts.examples_per_tar,
+                opts.tar_key,
+                zstd_level,
+                opts.overwrite,
+            )?;
+            return Ok(ExportWriter {
+                writer: Some(Output::Webdataset(tars)),
+                columns: Columns::new(opts.format, opts.maps),
+                batch_rows: opts.batch_rows,
+                rows: 0,
+                tmp,
+                path: path.to_path_buf(),
+            });
+        }
+        if opts.format == ExportFormat::Sqlite {
+            return Ok(ExportWriter {
+                writer: Some(Output::Sqlite(SqliteWriter::create(&tmp)?)),
+                columns: Columns::new(opts.format, opts.maps),
+                batch_rows: opts.batch_rows,
+                rows: 0,
+                tmp,
+                path: path.to_path_buf(),
+            });
+        }
+        let file =
+            File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+        let schema = schema(opts.format, opts.maps);
+        let writer = match opts.format {
+            ExportFormat::Parquet => {
+                let compression = match opts.zstd_level {
+                    None => Compression::UNCOMPRESSED,
+                    Some(0) => Compression::ZSTD(ZstdLevel::default()),
+                    Some(level) => Compression::ZSTD(ZstdLevel::try_new(level)?),
+                };
+                let props = WriterProperties::builder()
+                    .set_compression(compression)
+                    .set_max_row_group_size(opts.row_group_size)
+                    .build();
+                Output::Parquet(ArrowWriter::try_new(file, schema, Some(props))?)
+            }
+            // Left uncompressed, so readers can map the buffers without decoding them.
+            ExportFormat::Arrow => {
+                Output::Arrow(StreamWriter::try_new(BufWriter::new(file), &schema)?)
+            }
+            ExportFormat::Tfrecord if opts.gzip => Output::TfrecordGzip(GzEncoder::new(
+                BufWriter::new(file),
+                flate2::Compression::default(),
+            )),
+            ExportFormat::Tfrecord => Output::Tfrecord(BufWriter::new(file)),
+            ExportFormat::Webdataset | ExportFormat::Sqlite => unreachable!("handled above"),
+        };
+        Ok(ExportWriter {
+            writer: Some(writer),
+            columns: Columns::new(opts.format, opts.maps),
+            batch_rows: opts.batch_rows,
+            rows: 0,
+            tmp,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn write(&mut self, ex: &Example) -> Result<()> {
+        // TFRecord, WebDataset and SQLite are written example by example; only the
+        // columnar formats batch.
+        let record = match self.writer.as_mut().expect("writer already finished") {
+            Output::Tfrecord(w) => Some(w as &mut dyn Write),
+            Output::TfrecordGzip(w) => Some(w as &mut dyn Write),
+            Output::Webdataset(tars) => {
+                tars.write(ex)?;
+                self.rows += 1;
+                return Ok(());
+            }
+            Output::Sqlite(db) => {
+                db.write(ex)?;
+                self.rows += 1;
+                return Ok(());
+            }
+            _ => None,
+        };
+        if let Some(mut w) = record {
+            tfrecord::write_record(&mut w, &TfExample::from(ex).encode_to_vec())?;
+            self.rows += 1;
+            return Ok(());
+        }
+        self.columns.push(ex)?;
+        self.rows += 1;
+        if self.columns.rows >= self.batch_rows {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Examples written so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Marks the end of one input shard; SQLite commits its examples as one
+    /// transaction, other formats carry on.
+    pub fn end_shard(&mut self) -> Result<()> {
+        match self.writer.as_mut().expect("writer already finished") {
+            Output::Sqlite(db) => db.commit(),
+            _ => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.columns.rows == 0 {
+            return Ok(());
+        }
+        let batch = self.columns.finish()?;
+        match self.writer.as_mut().expect("writer already finished") {
+            Output::Parquet(w) => w.write(&batch)?,
+            Output::Arrow(w) => w.write(&batch)?,
+            Output::Tfrecord(_)
+            | Output::TfrecordGzip(_)
+            | Output::Webdataset(_)
+            | Output::Sqlite(_) => unreachable!("only columnar formats are batched"),
+        }
+        Ok(())
+    }
+
+    /// Writes the footer (or end-of-stream marker) and moves the file into place,
+    /// returning the total size and number of files written (tars for WebDataset,
+    /// otherwise 1).
+    pub fn finish(mut self) -> Result<(u64, u64)> {
+        self.flush()?;
+        match self.writer.take().expect("finish called twice") {
+            Output::Parquet(w) => {
+                w.close()?;
+            }
+            Output::Arrow(w) => {
+                let mut file = w.into_inner()?;
+                file.flush()?;
+            }
+            Output::Tfrecord(mut w) => w.flush()?,
+            Output::TfrecordGzip(w) => w.finish()?.flush()?,
+            Output::Webdataset(tars) => return tars.finish(),
+            Output::Sqlite(db) => db.finish()?,
+        }
+        fs::rename(&self.tmp, &self.path).with_context(|| {
+            format!(
+                "failed to move {} to {}",
+                self.tmp.display(),
+                self.path.display()
+            )
+        })?;
+        Ok((fs::metadata(&self.path)?.len(), 1))
+    }
+}
+
+impl Drop for ExportWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// Reads an export back, one `Example` per row, whichever way its maps are stored.
+pub struct ExportReader {
+    source: Source,
+}
+
+enum Source {
+    Batches {
+        batches: Box<dyn Iterator<Item = std::result::Result<RecordBatch, ArrowError>> + Send>,
+        batch: Option<Rows>,
+        row: usize,
+    },
+    Tfrecord(Box<dyn Read + Send>),
+    Tars(TarReader),
+    Sqlite(SqliteReader),
+}
+
+impl ExportReader {
+    /// Opens an export in `format`; a TFRecord whose name ends in `.gz` is gunzipped.
+    /// For WebDataset, `path` is the name the tars were numbered after; an export of
+    /// no examples has no tars and reads back empty.
+    pub fn open(path: &Path, format: ExportFormat) -> Result<Self> {
+        if format == ExportFormat::Webdataset {
+            return Ok(ExportReader {
+                source: Source::Tars(TarReader::open(path)),
+            });
+        }
+        if format == ExportFormat::Sqlite {
+            return Ok(ExportReader {
+                source: Source::Sqlite(SqliteReader::open(path)?),
+            });
+        }
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let (schema, batches): (SchemaRef, Box<dyn Iterator<Item = _> + Send>) = match format {
+            ExportFormat::Parquet => {
+                let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+                (builder.schema().clone(), Box::new(builder.build()?))
+            }
+            ExportFormat::Arrow => {
+                let reader = StreamReader::try_new(BufReader::new(file), None)?;
+                (reader.schema(), Box::new(reader))
+            }
+            ExportFormat::Tfrecord => {
+                let file = BufReader::new(file);
+                let input: Box<dyn Read + Send> = if path.extension().is_some_and(|ext| ext == "gz")
+                {
+                    Box::new(MultiGzDecoder::new(file))
+                } else {
+                    Box::new(file)
+                };
+                return Ok(ExportReader {
+                    source: Source::Tfrecord(input),
+                });
+            }
+            ExportFormat::Webdataset | ExportFormat::Sqlite => unreachable!("handled above"),
+        };
+        let version = schema.metadata().get(SCHEMA_VERSION_KEY);
+        ensure!(
+            version.is_some_and(|v| *v == EXPORT_SCHEMA_VERSION.to_string()),
+            SchemaMismatch,
+            "{} has export schema version {}, not {EXPORT_SCHEMA_VERSION}",
+            path.display(),
+            version.map_or("(none)", String::as_str)
+        );
+        for name in COLUMNS {
+            ensure!(
+                schema.column_with_name(name).is_some(),
+                SchemaMismatch,
+                "{} has no `{name}` column",
+                path.display()
+            );
+        }
+        Ok(ExportReader {
+            source: Source::Batches {
+                batches,
+                batch: None,
+                row: 0,
+            },
+        })
+    }
+}
+
+impl Iterator for ExportReader {
+    type Item = Result<Example>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (batches, batch, row) = match &mut self.source {
+            Source::Tfrecord(input) => {
+                return tfrecord::read_record(input)
+                    .transpose()
+                    .map(|data| tfrecord::decode(&data?));
+            }
+            Source::Tars(tars) => return tars.next(),
+            Source::Sqlite(db) => return db.next(),
+            Source::Batches {
+                batches,
+                batch,
+                row,
+            } => (batches, batch, row),
+        };
+        loop {
+            if let Some(rows) = batch {
+                if *row < rows.len {
+                    *row += 1;
+                    return Some(rows.example(*row - 1));
+                }
+            }
+            let next = match batches.next()? {
+                Ok(next) => next,
+                Err(e) => return Some(Err(e.into())),
+            };
+            match Rows::new(&next, &BTreeMap::new()) {
+                Ok(rows) => *batch = Some(rows),
+                Err(e) => return Some(Err(e)),
+            }
+            *row = 0;
+        }
+    }
+}
+
+/// A map field of one batch, cast to the types `Example` needs.
+enum MapField {
+    /// JSON object strings.
+    Json(ArrayRef),
+    /// A map column: row `i` holds entries `offsets[i]..offsets[i + 1]`.
+    Entries {
+        offsets: Vec<i32>,
+        keys: ArrayRef,
+        values: ArrayRef,
+    },
+}
+
+impl MapField {
+    fn new(col: &ArrayRef, value_type: &DataType) -> Result<Self> {
+        if let DataType::Map(..) = col.data_type() {
+            let map = col.as_map();
+            return Ok(MapField::Entries {
+                offsets: map.value_offsets().to_vec(),
+                keys: cast(map.keys(), &DataType::Utf8)?,
+                values: cast(map.values(), value_type)?,
+            });
+        }
+        Ok(MapField::Json(cast(col, &DataType::Utf8)?))
+    }
+}
+
+/// Row `row` of a map field; a null or a missing column is an empty map.
+fn map_at<V: DeserializeOwned>(
+    field: &Option<MapField>,
+    row: usize,
+    value: impl Fn(&ArrayRef, usize) -> V,
+) -> Result<BTreeMap<String, V>> {
+    match field {
+        None => Ok(BTreeMap::new()),
+        Some(MapField::Json(col)) if col.is_null(row) => Ok(BTreeMap::new()),
+        Some(MapField::Json(col)) => {
+            let json = col.as_string::<i32>().value(row);
+            serde_json::from_str(json).map_err(|e| {
+                EthicsError::SchemaMismatch(format!("map value {json:?} is not a JSON object: {e}"))
+            })
+        }
+        Some(MapField::Entries {
+            offsets,
+            keys,
+            values,
+        }) => {
+            let keys = keys.as_string::<i32>();
+            let range = offsets[row] as usize..offsets[row + 1] as usize;
+            Ok(range
+                .map(|i| (keys.value(i).to_string(), value(values, i)))
+                .collect())
+        }
+    }
+}
+
+/// The columns of one batch, cast to the types `Example` needs: strings from any
+/// string or dictionary-encoded type, integers from any integer type. Fields are
+/// read from the column `rename` maps them to, if any; a missing column or a null
+/// reads as the field's default.
+pub(crate) struct Rows {
+    subset: Option<ArrayRef>,
+    split: Option<ArrayRef>,
+    text: Option<ArrayRef>,
+    label: Option<ArrayRef>,
+    meta: Option<MapField>,
+    id: Option<ArrayRef>,
+    context: Option<ArrayRef>,
+    score: Option<ArrayRef>,
+    labels_by_name: Option<MapField>,
+    pub(crate) len: usize,
+}
+
+impl Rows {
+    pub(crate) fn new(batch: &RecordBatch, rename: &BTreeMap<String, String>) -> Result<Self> {
+        let column = |field: &str| {
+            let name = rename.get(field).map_or(field, String::as_str);
+            batch.column_by_name(name)
+        };
+        // Out-of-range integers fail instead of turning into nulls.
+        let options = CastOptions {
+            safe: false,
+            ..Default::default()
+        };
+        let cast_to = |field: &str, to: &DataType| -> Result<Option<ArrayRef>> {
+            Ok(column(field)
+                .map(|col| cast_with_options(col, to, &options))
+                .transpose()?)
+        };
+        let map = |field: &str, value_type: &DataType| -> Result<Option<MapField>> {
+            column(field)
+                .map(|col| MapField::new(col, value_type))
+                .transpose()
+        };
+        Ok(Rows {
+            subset: cast_to("subset", &DataType::Utf8)?,
+            split: cast_to("split", &DataType::Utf8)?,
+            text: cast_to("text", &DataType::Utf8)?,
+            label: cast_to("label", &DataType::Int32)?,
+            meta: map("meta", &DataType::Utf8)?,
+            id: cast_to("id", &DataType::Utf8)?,
+            context: cast_to("context", &DataType::Utf8)?,
+            score: cast_to("score", &DataType::Float64)?,
+            labels_by_name: map("labels_by_name", &DataType::Int32)?,
+            len: batch.num_rows(),
+        })
+    }
+
+    /// The label of row `row`, `None` when it is null or there is no label column.
+    pub(crate) fn label(&self, row: usize) -> Option<i32> {
+        let label = self.label.as_ref()?.as_primitive::<Int32Type>();
+        label.is_valid(row).then(|| label.value(row))
+    }
+
+    pub(crate) fn example(&self, row: usize) -> Result<Example> {
+        let string = |col: &Option<ArrayRef>| {
+            col.as_ref()
+                .map(|col| col.as_string::<i32>())
+                .filter(|col| col.is_valid(row))
+                .map(|col| col.value(row).to_string())
+        };
+        let score = self
+            .score
+            .as_ref()
+            .map(|col| col.as_primitive::<Float64Type>())
+            .filter(|col| col.is_valid(row))
+            .map(|col| col.value(row));
+        Ok(Example {
+            subset: string(&self.subset).unwrap_or_default(),
+            split: string(&self.split).unwrap_or_default(),
+            text: string(&self.text).unwrap_or_default(),
+            label: self.label(row).unwrap_or_default(),
+            meta: map_at(&self.meta, row, |values, i| {
+                values.as_string::<i32>().value(i).to_string()
+            })?,
+            id: string(&self.id).unwrap_or_default(),
+            context: string(&self.context),
+            score,
+            labels_by_name: map_at(&self.labels_by_name, row, |values, i| {
+                values.as_primitive::<Int32Type>().value(i)
+            })?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::MapArray;
+
+    use super::*;
+
+    fn examples(n: usize) -> Vec<Example> {
+        (0..n)
+            .map(|i| Example {
+                subset: ["justice", "virtue"][i % 2].into(),
+                split: "test".into(),
+                text: format!("example {i}"),
+                label: (i % 3) as i32,
+                id: format!("id-{i}"),
+                meta: BTreeMap::from([("n".to_string(), i.to_string())]),
+                context: (i % 4 == 0).then(|| format!("context {i}")),
+                score: (i % 5 == 0).then_some(i as f64 / 2.0),
+                labels_by_name: BTreeMap::from([("label".to_string(), (i % 3) as i32)]),
+            })
+            .collect()
+    }
+
+    fn export(path: &Path, opts: &ExportOptions, examples: &[Example]) {
+        let mut writer = ExportWriter::create(path, opts).unwrap();
+        for ex in examples {
+            writer.write(ex).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn arrow_ipc_reads_back_with_arrow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.arrows");
+        let written = examples(25);
+        let opts = ExportOptions {
+            format: ExportFormat::Arrow,
+            batch_rows: 10,
+            ..ExportOptions::default()
+        };
+        export(&path, &opts, &written);
+
+        let reader = StreamReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        let schema = reader.schema();
+        assert_eq!(
+            schema.metadata()[SCHEMA_VERSION_KEY],
+            EXPORT_SCHEMA_VERSION.to_string()
+        );
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        assert_eq!(
+            schema.field_with_name("subset").unwrap().data_type(),
+            &dictionary
+        );
+        assert_eq!(
+            schema.field_with_name("split").unwrap().data_type(),
+            &dictionary
+        );
+
+        let batches: Vec<RecordBatch> = reader.collect::<std::result::Result<_, _>>().unwrap();
+        assert_eq!(
+            batches
+                .iter()
+                .map(RecordBatch::num_rows)
+                .collect::<Vec<_>>(),
+            [10, 10, 5]
+        );
+        // Row 13 of the input is row 3 of the second batch.
+        let (batch, row, ex) = (&batches[1], 3, &written[13]);
+        let subset = cast(batch.column_by_name("subset").unwrap(), &DataType::Utf8).unwrap();
+        assert_eq!(subset.as_string::<i32>().value(row), ex.subset);
+        let text = batch.column_by_name("text").unwrap().as_string::<i32>();
+        assert_eq!(text.value(row), ex.text);
+        let label = batch.column_by_name("label").unwrap();
+        assert_eq!(label.as_primitive::<Int32Type>().value(row), ex.label);
+        assert!(batch.column_by_name("context").unwrap().is_null(row));
+        let meta = batch
+            .column_by_name("meta")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<MapArray>()
+            .unwrap()
+            .value(row);
+        assert_eq!(meta.column(0).as_string::<i32>().value(0), "n");
+        assert_eq!(meta.column(1).as_string::<i32>().value(0), "13");
+
+        let read: Vec<Example> = ExportReader::open(&path, ExportFormat::Arrow)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, written);
+    }
+
+    #[test]
+    fn arrow_ipc_with_json_maps_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.arrows");
+        let written = examples(7);
+        let opts = ExportOptions {
+            format: ExportFormat::Arrow,
+            maps: MapColumns::Json,
+            ..ExportOptions::default()
+        };
+        export(&path, &opts, &written);
+        let read: Vec<Example> = ExportReader::open(&path, ExportFormat::Arrow)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, written);
+    }
+}
@@ -0,0 +1,311 @@
+//! Downloads the raw ETHICS CSVs into `data/raw`, for `ethics-pipeline fetch`.
+//!
+//! Files are named `<subset>-<split>.csv`, which `--glob` infers subset and split
+//! from. Each is downloaded to `.<name>.part` beside its final path and renamed into
+//! place only once its SHA-256 checks out, so an interrupted download never looks
+//! finished; the next run continues the `.part` file with a Range request where the
+//! server allows it, and starts over where it doesn't.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::{Client, Response};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::convert::{canonical_split, canonical_subset};
+use crate::error::{bail, ensure, Context, EthicsError, Result};
+
+/// The ETHICS dataset on the Hugging Face hub.
+pub const DEFAULT_REPO: &str = "hendrycks/ethics";
+
+/// Where a hub repo keeps a split, with `{repo}` and `{revision}` filled in from
+/// `--repo` and `--revision`, then `{subset}` and `{split}` per file.
+pub const HUB_TEMPLATE: &str =
+    "https://huggingface.co/datasets/{repo}/resolve/{revision}/data/{subset}/{split}.csv";
+
+pub const SUBSETS: [&str; 5] = [
+    "commonsense",
+    "deontology",
+    "justice",
+    "utilitarianism",
+    "virtue",
+];
+
+pub const SPLITS: [&str; 3] = ["train", "test", "test_hard"];
+
+/// Pinned SHA-256s of the upstream files by canonical name. Entries are added from
+/// the `sha256sum`-style lines `fetch` prints for files it could not verify, once
+/// a download has been checked by hand.
+const BUILTIN_SHA256: &[(&str, &str)] = &[];
+
+/// Attempts after the first for a failed request or a dropped connection.
+const RETRIES: u32 = 5;
+
+/// Wait before the first retry; doubled for each one after.
+const BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// URL of each file, with `{subset}` and `{split}` placeholders.
+    pub url_template: String,
+    pub out_dir: PathBuf,
+    pub splits: Vec<String>,
+    /// Expected SHA-256 by canonical name; the built-in table plus `--checksums`.
+    pub checksums: BTreeMap<String, String>,
+    /// Fail on files without a known checksum instead of keeping them unverified.
+    pub require_checksums: bool,
+    /// Download again even when a verified file is already in place.
+    pub force: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            url_template: hub_template(DEFAULT_REPO, "main"),
+            out_dir: PathBuf::from("data/raw"),
+            splits: SPLITS.iter().map(|s| s.to_string()).collect(),
+            checksums: builtin_checksums(),
+            require_checksums: false,
+            force: false,
+        }
+    }
+}
+
+/// [`HUB_TEMPLATE`] for `repo` at `revision` (a branch, tag or commit).
+pub fn hub_template(repo: &str, revision: &str) -> String {
+    HUB_TEMPLATE
+        .replace("{repo}", repo)
+        .replace("{revision}", revision)
+}
+
+pub fn builtin_checksums() -> BTreeMap<String, String> {
+    BUILTIN_SHA256
+        .iter()
+        .map(|(name, sha)| (name.to_string(), sha.to_string()))
+        .collect()
+}
+
+/// Reads a checksum table in `sha256sum` format (`<hex>  <name>` per line; `#`
+/// starts a comment). Only the file name of each entry is kept.
+pub fn load_checksums(path: &Path) -> Result<BTreeMap<String, String>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut table = BTreeMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line
+            .split_once(char::is_whitespace)
+            .and_then(|(sha, name)| {
+                let name = name.trim_start().trim_start_matches('*');
+                let name = Path::new(name).file_name()?.to_str()?;
+                let valid = sha.len() == 64 && sha.bytes().all(|b| b.is_ascii_hexdigit());
+                valid.then(|| (name.to_string(), sha.to_ascii_lowercase()))
+            });
+        let Some((name, sha)) = entry else {
+            bail!(
+                InvalidArgument,
+                "{}:{}: expected `<sha256>  <file name>`",
+                path.display(),
+                n + 1
+            );
+        };
+        table.insert(name, sha);
+    }
+    Ok(table)
+}
+
+/// What happened to one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchStatus {
+    /// Already in place and matching its checksum.
+    Verified,
+    /// Already in place, with no checksum to check it against.
+    KeptUnverified,
+    /// Downloaded and matching its checksum.
+    Downloaded,
+    /// Downloaded, with no checksum to check it against.
+    DownloadedUnverified,
+}
+
+impl FetchStatus {
+    pub fn verified(self) -> bool {
+        matches!(self, FetchStatus::Verified | FetchStatus::Downloaded)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    pub name: String,
+    pub path: PathBuf,
+    pub status: FetchStatus,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Downloads the configured splits of `subset` into `opts.out_dir`.
+pub fn fetch(subset: &str, opts: &FetchOptions) -> Result<Vec<Fetched>> {
+    let Some(subset) = canonical_subset(subset) else {
+        bail!(
+            InvalidArgument,
+            "unknown subset {subset:?} (expected one of {})",
+            SUBSETS.join(", ")
+        );
+    };
+    fs::create_dir_all(&opts.out_dir)
+        .with_context(|| format!("failed to create {}", opts.out_dir.display()))?;
+    let client = Client::builder()
+        .connect_timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let mut fetched = Vec::new();
+    for split in &opts.splits {
+        let Some(split) = canonical_split(split) else {
+            bail!(
+                InvalidArgument,
+                "unknown split {split:?} (expected one of {})",
+                SPLITS.join(", ")
+            );
+        };
+        let url = opts
+            .url_template
+            .replace("{subset}", subset)
+            .replace("{split}", split);
+        let name = format!("{subset}-{split}.csv");
+        fetched.push(fetch_file(&client, &url, &name, opts)?);
+    }
+    Ok(fetched)
+}
+
+fn fetch_file(client: &Client, url: &str, name: &str, opts: &FetchOptions) -> Result<Fetched> {
+    let path = opts.out_dir.join(name);
+    let expected = opts.checksums.get(name);
+    ensure!(
+        expected.is_some() || !opts.require_checksums,
+        InvalidArgument,
+        "no checksum known for {name}; pass a table with --checksums"
+    );
+    let result = |status, (bytes, sha256)| Fetched {
+        name: name.to_string(),
+        path: path.clone(),
+        status,
+        bytes,
+        sha256,
+    };
+    if path.exists() && !opts.force {
+        let (bytes, sha256) = hash_file(&path)?;
+        match expected {
+            Some(expected) if *expected == sha256 => {
+                return Ok(result(FetchStatus::Verified, (bytes, sha256)))
+            }
+            Some(_) => warn!(
+                "{} does not match its checksum; downloading it again",
+                path.display()
+            ),
+            None => return Ok(result(FetchStatus::KeptUnverified, (bytes, sha256))),
+        }
+    }
+    let part = opts.out_dir.join(format!(".{name}.part"));
+    if opts.force {
+        remove_if_exists(&part)?;
+    }
+    download(client, url, &part)?;
+    let (bytes, sha256) = hash_file(&part)?;
+    let status = match expected {
+        Some(expected) if *expected != sha256 => {
+            // A bad resume would fail the same way again, so start over next time.
+            remove_if_exists(&part)?;
+            bail!(
+                Corrupt,
+                "{url}: downloaded {bytes} bytes with sha256 {sha256}, expected {expected}"
+            );
+        }
+        Some(_) => FetchStatus::Downloaded,
+        None => FetchStatus::DownloadedUnverified,
+    };
+    fs::rename(&part, &path)
+        .with_context(|| format!("failed to move {} to {}", part.display(), path.display()))?;
+    Ok(result(status, (bytes, sha256)))
+}
+
+/// Downloads `url` into `part`, continuing from its current length and retrying
+/// failed requests and dropped connections.
+fn download(client: &Client, url: &str, part: &Path) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let error = match get_from(client, url, part) {
+            Ok(Some(mut response)) => {
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .open(part)
+                    .with_context(|| format!("failed to open {}", part.display()))?;
+                match io::copy(&mut response, &mut file) {
+                    Ok(_) => return Ok(file.sync_all()?),
+                    Err(e) => EthicsError::from(e).context(format!("{url}: download interrupted")),
+                }
+            }
+            Ok(None) => return Ok(()),
+            // Connection failures and 5xx; anything else won't go away by asking again.
+            Err(e @ EthicsError::Io { .. }) => e,
+            Err(e) => return Err(e),
+        };
+        if attempt == RETRIES {
+            return Err(error);
+        }
+        warn!("{error}; retrying");
+        thread::sleep(BACKOFF * 2u32.pow(attempt));
+        attempt += 1;
+    }
+}
+
+/// Requests the bytes of `url` past what `part` already holds, truncating `part`
+/// when the server sends the whole file instead. `None` when `part` is already
+/// complete.
+fn get_from(client: &Client, url: &str, part: &Path) -> Result<Option<Response>> {
+    let have = fs::metadata(part).map_or(0, |m| m.len());
+    let mut request = client.get(url);
+    if have > 0 {
+        request = request.header(RANGE, format!("bytes={have}-"));
+    }
+    let response = request
+        .send()
+        .map_err(|e| io::Error::other(format!("{url}: {e}")))?;
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => Ok(Some(response)),
+        StatusCode::RANGE_NOT_SATISFIABLE if have > 0 => Ok(None),
+        status if status.is_success() => {
+            File::create(part).with_context(|| format!("failed to create {}", part.display()))?;
+            Ok(Some(response))
+        }
+        StatusCode::NOT_FOUND => bail!(InvalidArgument, "{url}: not found"),
+        status if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+            Err(io::Error::other(format!("{url}: {status}")).into())
+        }
+        status => bail!(InvalidArgument, "{url}: {status}"),
+    }
+}
+
+fn hash_file(path: &Path) -> Result<(u64, String)> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut BufReader::new(file), &mut hasher)?;
+    Ok((bytes, format!("{:x}", hasher.finalize())))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,483 @@
+//! Parquet or canonical proto3 JSON -> `.pb.zst`, for `ethics-pipeline import`:
+//! the reverse of `export --format parquet` and `pb_to_jsonl --format proto-json`,
+//! also usable on files written elsewhere.
+//!
+//! For Parquet, each `Example` field is read from the column of the same name (the export
+//! schema), or from the column `--column FIELD=COLUMN` names. `text` and `label`
+//! are required, as are `subset` and `split` unless `--subset`/`--split` supply
+//! them; every other field defaults when its column is absent. Strings may be any
+//! string or dictionary-encoded type, `label` any integer type, and `meta` and
+//! `labels_by_name` map columns or JSON object strings. Proto JSON is read as
+//! [`crate::protojson`] describes. Rows are validated as the converter validates
+//! JSONL: empty text, a null label and a disallowed label are rejected under the
+//! same reasons.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::compute::{can_cast_types, cast};
+use arrow::datatypes::{DataType, Schema};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use tracing::warn;
+
+use crate::convert::{example_id, Mode, Reject};
+use crate::dict::Dictionary;
+use crate::error::{bail, ensure, Context, EthicsError, Result};
+use crate::ethics::Example;
+use crate::export::{Rows, COLUMNS};
+use crate::protojson::example_from_json;
+use crate::shard;
+use crate::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+
+/// Disallowed labels warned about per input before going quiet.
+const BAD_LABELS_LOGGED: u64 = 10;
+
+/// Formats `import` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// Apache Parquet, columns matched to fields by name.
+    Parquet,
+    /// Canonical proto3 JSON, one `Example` object per line, as `pb_to_jsonl --format
+    /// proto-json` writes it.
+    ProtoJson,
+}
+
+/// Parses a `--column FIELD=COLUMN` remapping.
+pub fn parse_column(s: &str) -> Result<(String, String)> {
+    let Some((field, column)) = s.split_once('=') else {
+        bail!(InvalidArgument, "expected FIELD=COLUMN, got {s:?}");
+    };
+    let (field, column) = (field.trim(), column.trim());
+    ensure!(
+        COLUMNS.contains(&field),
+        InvalidArgument,
+        "unknown field {field:?} (expected one of {})",
+        COLUMNS.join(", ")
+    );
+    ensure!(
+        !column.is_empty(),
+        InvalidArgument,
+        "no column given for {field}"
+    );
+    Ok((field.to_string(), column.to_string()))
+}
+
+/// Settings for one import. `Default` matches the command line without flags.
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    /// Field -> the column it is read from, for fields not in a column of their own name.
+    pub columns: BTreeMap<String, String>,
+    /// Extra columns copied into `meta` under their own names.
+    pub meta_columns: Vec<String>,
+    /// Subset and split of rows when there is no such column, or it is null or empty.
+    pub subset: Option<String>,
+    pub split: Option<String>,
+    pub allowed_labels: Option<BTreeSet<i32>>,
+    pub reject_bad_labels: bool,
+    pub allow_empty_text: bool,
+    /// Rows per record batch read.
+    pub batch_rows: usize,
+    /// `None` writes uncompressed `.pb` output.
+    pub zstd_level: Option<i32>,
+    pub dict: Option<Dictionary>,
+    pub checksums: bool,
+    pub overwrite: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            format: ImportFormat::Parquet,
+            columns: BTreeMap::new(),
+            meta_columns: Vec::new(),
+            subset: None,
+            split: None,
+            allowed_labels: None,
+            reject_bad_labels: false,
+            allow_empty_text: false,
+            batch_rows: 8192,
+            zstd_level: Some(DEFAULT_ZSTD_LEVEL),
+            dict: None,
+            checksums: false,
+            overwrite: false,
+        }
+    }
+}
+
+impl ImportOptions {
+    /// The column `field` is read from.
+    fn column<'a>(&'a self, field: &'a str) -> &'a str {
+        self.columns.get(field).map_or(field, String::as_str)
+    }
+}
+
+/// What an import wrote and left out.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub rows: u64,
+    pub examples: u64,
+    /// Rejected rows per reason.
+    pub rejects: BTreeMap<&'static str, u64>,
+    /// Rows with a label outside `allowed_labels`, kept or not.
+    pub disallowed_labels: u64,
+    /// Output size and hex SHA-256.
+    pub bytes: u64,
+    pub hash: String,
+}
+
+/// Checks that `schema` has a column of a usable type for every required field and
+/// for every field it has at all, listing the columns found when it doesn't.
+fn check_schema(schema: &Schema, opts: &ImportOptions, path: &Path) -> Result<()> {
+    let string = |t: &DataType| can_cast_types(t, &DataType::Utf8);
+    let map = |t: &DataType| matches!(t, DataType::Map(..)) || string(t);
+    let mut problems = Vec::new();
+    for field in COLUMNS {
+        let name = opts.column(field);
+        let required = match field {
+            "text" | "label" => true,
+            "subset" => opts.subset.is_none(),
+            "split" => opts.split.is_none(),
+            _ => false,
+        };
+        let Ok(column) = schema.field_with_name(name) else {
+            if required {
+                problems.push(format!("no `{name}` column for {field}"));
+            }
+            continue;
+        };
+        let t = column.data_type();
+        let usable = match field {
+            "label" => t.is_integer(),
+            "score" => t.is_numeric(),
+            "meta" | "labels_by_name" => map(t),
+            _ => string(t),
+        };
+        if !usable {
+            problems.push(format!("`{name}` ({field}) has type {t}"));
+        }
+    }
+    for name in &opts.meta_columns {
+        match schema.field_with_name(name) {
+            Ok(column) if string(column.data_type()) => {}
+            Ok(column) => problems.push(format!("`{name}` (meta) has type {}", column.data_type())),
+            Err(_) => problems.push(format!("no `{name}` column for meta")),
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let found: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|f| format!("{}: {}", f.name(), f.data_type()))
+        .collect();
+    bail!(
+        SchemaMismatch,
+        "{}: {}; found columns: {}",
+        path.display(),
+        problems.join(", "),
+        found.join(", ")
+    )
+}
+
+/// The subset or split for the shard header: the single value of `field` across
+/// all rows, or "" when rows differ. Reads only that column.
+fn header_value(path: &Path, opts: &ImportOptions, field: &str) -> Result<String> {
+    let fallback = || {
+        let value = if field == "subset" {
+            &opts.subset
+        } else {
+            &opts.split
+        };
+        value.clone().unwrap_or_default()
+    };
+    let builder = ParquetRecordBatchReaderBuilder::try_new(open(path)?)?;
+    let name = opts.column(field);
+    if builder.schema().field_with_name(name).is_err() {
+        return Ok(fallback());
+    }
+    let mask = ProjectionMask::columns(builder.parquet_schema(), [name]);
+    let mut seen = HashSet::new();
+    for batch in builder.with_projection(mask).build()? {
+        let col = cast(batch?.column(0), &DataType::Utf8)?;
+        let col = col.as_string::<i32>();
+        for row in 0..col.len() {
+            let value = match col.is_valid(row) && !col.value(row).is_empty() {
+                true => col.value(row).to_string(),
+                false => fallback(),
+            };
+            if seen.insert(value) && seen.len() > 1 {
+                return Ok(String::new());
+            }
+        }
+    }
+    Ok(seen.into_iter().next().unwrap_or_else(fallback))
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("failed to open {}", path.display()))
+}
+
+/// Counts rows as they are checked and warns about the first of each kind of reject.
+struct Checks<'a> {
+    input: &'a Path,
+    opts: &'a ImportOptions,
+    summary: ImportSummary,
+    warned: HashSet<&'static str>,
+}
+
+impl Checks<'_> {
+    fn reject(&mut self, row: u64, reject: Reject) {
+        if self.warned.insert(reject.reason()) {
+            warn!("{} row {row}: {reject}", self.input.display());
+        }
+        *self.summary.rejects.entry(reject.reason()).or_default() += 1;
+    }
+
+    /// Validates the next row, filling in defaults; `false` when it is rejected.
+    /// `labelled` is whether the row has a label at all.
+    fn admit(&mut self, ex: &mut Example, labelled: bool) -> bool {
+        let opts = self.opts;
+        let row = self.summary.rows;
+        self.summary.rows += 1;
+        if !labelled {
+            self.reject(row, Reject::MissingLabel);
+            return false;
+        }
+        if !opts.allow_empty_text && ex.text.trim().is_empty() {
+            self.reject(row, Reject::EmptyText);
+            return false;
+        }
+        if let Some(score) = ex.score.filter(|s| !s.is_finite()) {
+            self.reject(row, Reject::BadScore(score.to_string()));
+            return false;
+        }
+        if opts
+            .allowed_labels
+            .as_ref()
+            .is_some_and(|allowed| !allowed.contains(&ex.label))
+        {
+            self.summary.disallowed_labels += 1;
+            if self.summary.disallowed_labels <= BAD_LABELS_LOGGED {
+                warn!(
+                    "{} row {row}: label {} is not in --allowed-labels",
+                    self.input.display(),
+                    ex.label
+                );
+            }
+            if opts.reject_bad_labels {
+                *self.summary.rejects.entry("disallowed_label").or_default() += 1;
+                return false;
+            }
+        }
+        if ex.subset.is_empty() {
+            ex.subset = opts.subset.clone().unwrap_or_default();
+        }
+        if ex.split.is_empty() {
+            ex.split = opts.split.clone().unwrap_or_default();
+        }
+        if ex.id.is_empty() {
+            ex.id = example_id(ex);
+        }
+        true
+    }
+
+    fn finish(self) -> ImportSummary {
+        if self.summary.disallowed_labels > BAD_LABELS_LOGGED {
+            warn!(
+                "{}: {} more disallowed label(s) not shown",
+                self.input.display(),
+                self.summary.disallowed_labels - BAD_LABELS_LOGGED
+            );
+        }
+        self.summary
+    }
+}
+
+/// Imports `input` into the shard `out`, validating each row.
+pub fn import(input: &Path, out: &Path, opts: &ImportOptions) -> Result<ImportSummary> {
+    ensure!(
+        opts.format == ImportFormat::Parquet
+            || (opts.columns.is_empty() && opts.meta_columns.is_empty()),
+        InvalidArgument,
+        "--column and --meta-columns only apply to --from parquet"
+    );
+    let (subset, split) = match opts.format {
+        ImportFormat::Parquet => {
+            let builder = ParquetRecordBatchReaderBuilder::try_new(open(input)?)?;
+            check_schema(builder.schema(), opts, input)?;
+            (
+                header_value(input, opts, "subset")?,
+                header_value(input, opts, "split")?,
+            )
+        }
+        ImportFormat::ProtoJson => {
+            let (mut subsets, mut splits) = (HashSet::new(), HashSet::new());
+            for ex in proto_json(input)? {
+                let ex = ex?;
+                subsets.insert(ex.subset);
+                splits.insert(ex.split);
+            }
+            let single = |values: HashSet<String>, fallback: &Option<String>| {
+                let mut values = values.into_iter().map(|v| match v.is_empty() {
+                    true => fallback.clone().unwrap_or_default(),
+                    false => v,
+                });
+                match (values.next(), values.next()) {
+                    (Some(value), None) => value,
+                    (None, _) => fallback.clone().unwrap_or_default(),
+                    (Some(_), Some(_)) => String::new(),
+                }
+            };
+            (single(subsets, &opts.subset), single(splits, &opts.split))
+        }
+    };
+
+    let mut writer = ExampleWriter::create(out)
+        .with_header(shard::header(Mode::Example.message(), &subset, &split))
+        .checksums(opts.checksums)
+        .overwrite(opts.overwrite);
+    writer = match opts.zstd_level {
+        Some(level) => writer.zstd_level(level),
+        None => writer.uncompressed(),
+    };
+    if let Some(dict) = &opts.dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+    let mut checks = Checks {
+        input,
+        opts,
+        summary: ImportSummary::default(),
+        warned: HashSet::new(),
+    };
+    let mut write = |ex: &Example, row: u64| {
+        enc.write(ex)
+            .with_context(|| format!("failed to write row {row} of {}", input.display()))
+    };
+
+    match opts.format {
+        ImportFormat::Parquet => {
+            let builder = ParquetRecordBatchReaderBuilder::try_new(open(input)?)?;
+            for batch in builder.with_batch_size(opts.batch_rows).build()? {
+                let batch = batch?;
+                let rows = Rows::new(&batch, &opts.columns)?;
+                let meta_columns = opts
+                    .meta_columns
+                    .iter()
+                    .map(|name| {
+                        let col = batch.column_by_name(name).expect("checked by check_schema");
+                        Ok((name, cast(col, &DataType::Utf8)?))
+                    })
+                    .collect::<Result<Vec<(&String, ArrayRef)>>>()?;
+                for i in 0..rows.len {
+                    let mut ex = rows.example(i)?;
+                    for (name, col) in &meta_columns {
+                        let col = col.as_string::<i32>();
+                        if col.is_valid(i) {
+                            ex.meta.insert(name.to_string(), col.value(i).to_string());
+                        }
+                    }
+                    let row = checks.summary.rows;
+                    if checks.admit(&mut ex, rows.label(i).is_some()) {
+                        write(&ex, row)?;
+                        checks.summary.examples += 1;
+                    }
+                }
+            }
+        }
+        // Proto3 has no null label: an absent one is 0.
+        ImportFormat::ProtoJson => {
+            for ex in proto_json(input)? {
+                let mut ex = ex?;
+                let row = checks.summary.rows;
+                if checks.admit(&mut ex, true) {
+                    write(&ex, row)?;
+                    checks.summary.examples += 1;
+                }
+            }
+        }
+    }
+    let mut summary = checks.finish();
+    (summary.bytes, summary.hash) = enc.finish()?;
+    Ok(summary)
+}
+
+/// The examples of a canonical proto3 JSON file, one object per line; blank lines
+/// are skipped.
+fn proto_json(input: &Path) -> Result<impl Iterator<Item = Result<Example>> + '_> {
+    let reader = BufReader::new(open(input)?);
+    Ok(reader.lines().enumerate().filter_map(move |(i, line)| {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => return None,
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let location = format!("{}:{}", input.display(), i + 1);
+        let value: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(source) => {
+                return Some(Err(EthicsError::JsonParse {
+                    location,
+                    line: i + 1,
+                    source,
+                }))
+            }
+        };
+        Some(example_from_json(&value).map_err(|e| e.context(location)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::protojson::example_to_json;
+    use crate::reader::ExampleReader;
+
+    #[test]
+    fn proto_json_round_trips_through_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let (json, shard) = (dir.path().join("in.json"), dir.path().join("out.pb.zst"));
+        let examples: Vec<Example> = (0..10)
+            .map(|i| Example {
+                subset: "virtue".into(),
+                split: "train".into(),
+                text: format!("She shared her lunch. [SEP] generous {i}"),
+                label: i % 2,
+                meta: BTreeMap::from([("n".to_string(), i.to_string())]),
+                id: format!("v-{i}"),
+                context: (i % 3 == 0).then(String::new),
+                score: (i % 4 == 0).then_some(f64::from(i) / 4.0),
+                labels_by_name: BTreeMap::new(),
+            })
+            .collect();
+        let lines: Vec<String> = examples
+            .iter()
+            .map(|ex| example_to_json(ex, false).to_string())
+            .collect();
+        fs::write(&json, lines.join("\n")).unwrap();
+
+        let opts = ImportOptions {
+            format: ImportFormat::ProtoJson,
+            ..ImportOptions::default()
+        };
+        let summary = import(&json, &shard, &opts).unwrap();
+        assert_eq!((summary.rows, summary.examples), (10, 10));
+        assert!(summary.rejects.is_empty());
+
+        let reader = ExampleReader::open(&shard).unwrap();
+        let header = reader.header().unwrap();
+        assert_eq!(
+            (header.subset.as_str(), header.split.as_str()),
+            ("virtue", "train")
+        );
+        let read: Vec<Example> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(read, examples);
+    }
+}
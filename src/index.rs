@@ -0,0 +1,113 @@
+//! `.idx` sidecars for shards written with `--frame-every`, mapping zstd frame
+//! offsets to example indices so readers can seek instead of decompressing
+//! everything in front of the examples they want.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{ensure, Context, EthicsError, Result};
+
+/// Bumped whenever the sidecar layout changes.
+pub const INDEX_VERSION: u32 = 1;
+
+/// One zstd frame of a shard.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEntry {
+    /// Byte offset of the frame in the shard file.
+    pub offset: u64,
+    /// Index of the frame's first example within the shard.
+    pub first: u64,
+    /// Examples in the frame.
+    pub count: u64,
+}
+
+/// Contents of `<shard>.idx`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardIndex {
+    pub version: u32,
+    /// Size and hex SHA-256 of the shard the index was written for.
+    pub shard_bytes: u64,
+    pub shard_sha256: String,
+    pub frames: Vec<FrameEntry>,
+}
+
+impl ShardIndex {
+    /// `shard.pb.zst` -> `shard.pb.zst.idx`.
+    pub fn path_for(shard: &Path) -> PathBuf {
+        let mut name = shard.as_os_str().to_owned();
+        name.push(".idx");
+        PathBuf::from(name)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Loads `path` and checks that it describes `shard`: same version, size and
+    /// SHA-256. The hash check reads the shard once but decompresses nothing.
+    pub fn load_for(path: &Path, shard: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read index {}", path.display()))?;
+        let index = Self::parse(&text, path)?;
+
+        let mut file = File::open(shard)
+            .with_context(|| format!("failed to open shard {}", shard.display()))?;
+        let bytes = file.metadata()?.len();
+        ensure!(
+            bytes == index.shard_bytes,
+            InvalidArgument,
+            "{} does not belong to {}: shard is {bytes} bytes, index expects {}",
+            path.display(),
+            shard.display(),
+            index.shard_bytes
+        );
+        let sha256 = sha256_reader(&mut file)?;
+        ensure!(
+            sha256 == index.shard_sha256,
+            InvalidArgument,
+            "{} does not belong to {}: shard sha256 {sha256}, index expects {}",
+            path.display(),
+            shard.display(),
+            index.shard_sha256
+        );
+        Ok(index)
+    }
+
+    /// Parses a sidecar's contents without checking them against a shard; `name`
+    /// is only used in errors.
+    pub fn parse(text: &str, name: &Path) -> Result<Self> {
+        let index: ShardIndex = serde_json::from_str(text).map_err(|e| {
+            EthicsError::Corrupt(format!("{} is not a shard index: {e}", name.display()))
+        })?;
+        ensure!(
+            index.version == INDEX_VERSION,
+            SchemaMismatch,
+            "{}: unsupported index version {} (expected {INDEX_VERSION})",
+            name.display(),
+            index.version
+        );
+        Ok(index)
+    }
+
+    /// The frame holding example `n`, if the shard has that many examples.
+    pub fn frame_for(&self, n: u64) -> Option<&FrameEntry> {
+        let i = self.frames.partition_point(|f| f.first + f.count <= n);
+        self.frames.get(i)
+    }
+
+    /// Total examples across all frames.
+    pub fn examples(&self) -> u64 {
+        self.frames.iter().map(|f| f.count).sum()
+    }
+}
+
+fn sha256_reader(r: &mut impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(r, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,532 @@
+//! Opening JSONL inputs that may be plain, gzip- or zstd-compressed.
+//! The path `-` stands for stdin (and, for outputs, stdout).
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::error::{Context, Result};
+
+/// First bytes of a gzip member.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+pub(crate) const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+pub(crate) const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Default longest line [`lines`] and [`records`] will read; longer ones are skipped
+/// and reported instead, so one unterminated line can't take all memory.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 16 << 20;
+
+/// Compression extensions stripped by [`input_stem`].
+const COMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+/// Wraps `reader` in a gzip or zstd decoder if its first bytes carry the
+/// matching magic number; otherwise returns it buffered as-is.
+pub fn decompress_reader<R: Read + Send + 'static>(
+    reader: R,
+) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(reader);
+    let head = reader.fill_buf()?;
+    if head.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(ZstdDecoder::with_buffer(reader)?)))
+    } else if head.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Whether `head`, the first bytes of a file, starts a zstd or gzip stream.
+pub fn is_compressed(head: &[u8]) -> bool {
+    head.starts_with(&ZSTD_MAGIC) || head.starts_with(&GZIP_MAGIC)
+}
+
+/// Whether `path` is `-`, i.e. stdin or stdout.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Whether `path` is an `s3://` or `gs://` URL rather than a file; see [`crate::cloud`].
+pub fn is_object_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("s3://") || s.starts_with("gs://"))
+}
+
+/// Opens `path` for line-oriented reading, decompressing `.gz` / `.zst`
+/// content transparently (detected by magic bytes, not extension).
+/// `-` reads stdin.
+pub fn open_maybe_compressed(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    if is_stdio(path) {
+        return decompress_reader(io::stdin()).context("failed to read stdin");
+    }
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    decompress_reader(file).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// File name of `path` without a trailing `.gz` / `.zst`
+/// (`cm_train.jsonl.gz` -> `cm_train.jsonl`).
+pub fn decompressed_name(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match name.rsplit_once('.') {
+        Some((base, ext)) if COMPRESSED_EXTENSIONS.contains(&ext) => base.to_string(),
+        _ => name.into_owned(),
+    }
+}
+
+/// Whether `path` names a `PairExample` shard (`*.pairs.pb`, optionally `.zst`).
+pub fn is_pairs_shard(path: &Path) -> bool {
+    decompressed_name(path).ends_with(".pairs.pb")
+}
+
+/// File stem of `path` ignoring any compression extension
+/// (`cm_train.jsonl.gz` -> `cm_train`).
+pub fn input_stem(path: &Path) -> String {
+    let name = decompressed_name(path);
+    match name.rsplit_once('.') {
+        Some((base, _)) if !base.is_empty() => base.to_string(),
+        _ => name,
+    }
+}
+
+/// How records are laid out in an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum InputFormat {
+    /// `.csv` / `.tsv` by extension; otherwise decide from the first non-whitespace
+    /// byte: `[` means `json`, anything else `jsonl`.
+    #[default]
+    Auto,
+    /// One JSON object per line.
+    Jsonl,
+    /// A single top-level JSON array of objects.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// Tab-separated values with a header row.
+    Tsv,
+}
+
+impl InputFormat {
+    /// Resolves `Auto` to `Csv` / `Tsv` when `path` carries that extension
+    /// (compression suffixes ignored); JSON vs JSONL is sniffed later by [`records`].
+    pub fn for_path(self, path: &Path) -> InputFormat {
+        if self != InputFormat::Auto {
+            return self;
+        }
+        let name = decompressed_name(path);
+        match name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("csv") => InputFormat::Csv,
+            Some("tsv") => InputFormat::Tsv,
+            _ => InputFormat::Auto,
+        }
+    }
+}
+
+/// Where a record came from, for error messages and reject logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    /// 1-based line number in a JSONL file, or the line a CSV record starts on.
+    Line(usize),
+    /// 0-based index into a top-level JSON array.
+    Element(usize),
+}
+
+impl Position {
+    /// `file:12` for lines, `file[element 11]` for array elements.
+    pub fn locate(&self, input: &Path) -> String {
+        match self {
+            Position::Line(n) => format!("{}:{n}", input.display()),
+            Position::Element(i) => format!("{}[element {i}]", input.display()),
+        }
+    }
+
+    /// The bare number, as written to reject logs.
+    pub fn index(&self) -> usize {
+        match self {
+            Position::Line(n) | Position::Element(n) => *n,
+        }
+    }
+
+    /// `"line"` or `"element"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Position::Line(_) => "line",
+            Position::Element(_) => "element",
+        }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind(), self.index())
+    }
+}
+
+/// Raw record text paired with where it came from. JSONL blank lines are
+/// yielded too (as empty/whitespace strings) so callers can count them.
+/// CSV rows are handed over re-encoded as a JSON object of their non-empty cells.
+pub type RawRecord = (Position, String);
+
+/// Iterator returned by [`records`]; `Send` so it can feed a reader thread.
+pub type RecordIter = Box<dyn Iterator<Item = io::Result<RawRecord>> + Send>;
+
+/// Iterates the raw records of `reader` in the requested layout. Array
+/// elements are split out incrementally, so huge arrays never have to fit in
+/// memory as one value. JSONL goes through [`lines`] with the default limit.
+pub fn records(reader: Box<dyn BufRead + Send>, format: InputFormat) -> io::Result<RecordIter> {
+    records_with_limit(reader, format, DEFAULT_MAX_LINE_BYTES)
+}
+
+/// [`records`] with JSONL lines over `max_line_bytes` yielded as [`LineTooLong`]
+/// errors, which callers can count and carry on past (see [`line_too_long`]).
+pub fn records_with_limit(
+    mut reader: Box<dyn BufRead + Send>,
+    format: InputFormat,
+    max_line_bytes: usize,
+) -> io::Result<RecordIter> {
+    let format = resolve_format(&mut reader, format)?;
+    Ok(match format {
+        InputFormat::Json => Box::new(ArrayElements::new(reader)),
+        InputFormat::Csv => csv_records(reader, b',')?,
+        InputFormat::Tsv => csv_records(reader, b'\t')?,
+        _ => Box::new(
+            lines(reader, max_line_bytes).map(|line| line.map(|(n, l)| (Position::Line(n), l))),
+        ),
+    })
+}
+
+/// Skips a leading BOM and settles `Auto` by the first non-blank byte: a JSON array
+/// if it is `[`, JSONL otherwise. [`records_with_limit`] does this itself; call it
+/// first to learn the layout before reading.
+pub fn resolve_format(reader: &mut dyn BufRead, format: InputFormat) -> io::Result<InputFormat> {
+    skip_bom(reader)?;
+    Ok(match format {
+        InputFormat::Auto => {
+            skip_whitespace(reader)?;
+            if reader.fill_buf()?.first() == Some(&b'[') {
+                InputFormat::Json
+            } else {
+                InputFormat::Jsonl
+            }
+        }
+        other => other,
+    })
+}
+
+/// A line longer than the reader's limit. It was skipped without being buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTooLong {
+    /// 1-based line number.
+    pub line: usize,
+    /// Length of the line, without its `\n`.
+    pub bytes: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} is {} bytes, over the {}-byte limit (--max-line-bytes)",
+            self.line, self.bytes, self.max
+        )
+    }
+}
+
+impl std::error::Error for LineTooLong {}
+
+/// The [`LineTooLong`] inside `e`, if that is what it is.
+pub fn line_too_long(e: &io::Error) -> Option<&LineTooLong> {
+    e.get_ref()?.downcast_ref()
+}
+
+/// Numbered lines of `reader`, like [`BufRead::lines`] but tolerant of files from
+/// Windows tools: a leading UTF-8 BOM is dropped and `\r\n` endings are trimmed
+/// along with `\n`. A line longer than `max_bytes` (terminator excluded) is skipped
+/// and yielded as a [`LineTooLong`] error; iteration carries on after it.
+pub fn lines<R: BufRead>(reader: R, max_bytes: usize) -> Lines<R> {
+    Lines {
+        reader,
+        max_bytes,
+        line: 0,
+        buf: Vec::new(),
+    }
+}
+
+/// Iterator returned by [`lines`]: `(1-based line number, text)`.
+pub struct Lines<R> {
+    reader: R,
+    max_bytes: usize,
+    line: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = io::Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        // Room for the longest allowed line plus `\r\n` (and a BOM on the first); the
+        // rest is only counted.
+        let bom = if self.line == 0 { UTF8_BOM.len() } else { 0 };
+        let keep = self.max_bytes.saturating_add(2 + bom);
+        let (mut bytes, mut ended) = (0, false);
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if available.is_empty() {
+                if bytes == 0 {
+                    return None;
+                }
+                break;
+            }
+            let (n, done) = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (available.len(), false),
+            };
+            let room = keep.saturating_sub(self.buf.len()).min(n);
+            self.buf.extend_from_slice(&available[..room]);
+            bytes += n;
+            self.reader.consume(n);
+            if done {
+                ended = true;
+                break;
+            }
+        }
+        self.line += 1;
+        let complete = self.buf.len() == bytes;
+        if self.line == 1 && self.buf.starts_with(UTF8_BOM) {
+            self.buf.drain(..UTF8_BOM.len());
+            bytes -= UTF8_BOM.len();
+        }
+        if ended {
+            bytes -= 1;
+            if complete {
+                self.buf.pop();
+            }
+        }
+        if complete && self.buf.last() == Some(&b'\r') {
+            self.buf.pop();
+            bytes -= 1;
+        }
+        if !complete || self.buf.len() > self.max_bytes {
+            let too_long = LineTooLong {
+                line: self.line,
+                bytes,
+                max: self.max_bytes,
+            };
+            return Some(Err(io::Error::new(io::ErrorKind::InvalidData, too_long)));
+        }
+        let line = self.line;
+        // Copied out at its exact size; `buf` keeps its capacity for the next line.
+        let text = std::str::from_utf8(&self.buf).map(str::to_owned);
+        Some(
+            text.map(|text| (line, text))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        )
+    }
+}
+
+/// Drops a UTF-8 byte order mark from the start of `reader`, if there is one.
+fn skip_bom(reader: &mut dyn BufRead) -> io::Result<()> {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(())
+}
+
+/// Maps each CSV row onto a JSON object keyed by the header row, so it goes
+/// through the same `Row` handling as JSONL. Cells are kept as strings (labels
+/// like `"1"` still parse as numbers); empty cells are left out, so an empty
+/// `label` behaves like a missing one. Quoted cells may span lines.
+fn csv_records(reader: Box<dyn BufRead + Send>, delimiter: u8) -> io::Result<RecordIter> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .trim(csv::Trim::Headers)
+        .from_reader(reader);
+    let headers = reader.headers().map_err(io::Error::other)?.clone();
+    Ok(Box::new(reader.into_records().map(move |record| {
+        let record = record.map_err(io::Error::other)?;
+        let line = record.position().map_or(0, |p| p.line() as usize);
+        let row: serde_json::Map<String, serde_json::Value> = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(|(h, cell)| (h.to_string(), serde_json::Value::String(cell.to_string())))
+            .collect();
+        Ok((
+            Position::Line(line),
+            serde_json::Value::Object(row).to_string(),
+        ))
+    })))
+}
+
+fn skip_whitespace(reader: &mut dyn BufRead) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let n = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        let done = n < buf.len();
+        reader.consume(n);
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Splits a top-level JSON array into the raw text of each element by tracking
+/// nesting depth and string state, without parsing the elements themselves.
+struct ArrayElements {
+    reader: Box<dyn BufRead + Send>,
+    index: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl ArrayElements {
+    fn new(reader: Box<dyn BufRead + Send>) -> Self {
+        ArrayElements {
+            reader,
+            index: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    fn next_element(&mut self) -> io::Result<Option<String>> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !self.started {
+            skip_whitespace(&mut self.reader)?;
+            if self.reader.fill_buf()?.first() != Some(&b'[') {
+                return Err(invalid("expected a top-level JSON array".into()));
+            }
+            self.reader.consume(1);
+            self.started = true;
+        }
+
+        let mut element = Vec::new();
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(invalid(format!(
+                    "unexpected end of input in array element {}",
+                    self.index
+                )));
+            }
+            // Set to (bytes used, hit closing `]`) when this element ends inside `buf`.
+            let mut end = None;
+            for (i, &b) in buf.iter().enumerate() {
+                if in_string {
+                    element.push(b);
+                    match (escaped, b) {
+                        (true, _) => escaped = false,
+                        (false, b'\\') => escaped = true,
+                        (false, b'"') => in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                match b {
+                    b',' | b']' if depth == 0 => {
+                        end = Some((i + 1, b == b']'));
+                        break;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth = depth.saturating_sub(1),
+                    b'"' => in_string = true,
+                    _ => {}
+                }
+                if !(depth == 0 && element.is_empty() && b.is_ascii_whitespace()) {
+                    element.push(b);
+                }
+            }
+
+            let Some((used, closing)) = end else {
+                let n = buf.len();
+                self.reader.consume(n);
+                continue;
+            };
+            self.reader.consume(used);
+            self.finished = closing;
+            let text = String::from_utf8(element)
+                .map_err(|_| invalid(format!("array element {} is not valid UTF-8", self.index)))?;
+            if text.trim().is_empty() {
+                // `[]`, or nothing between the last `,` and `]`.
+                if closing {
+                    return Ok(None);
+                }
+                return Err(invalid(format!("empty array element {}", self.index)));
+            }
+            return Ok(Some(text));
+        }
+    }
+}
+
+impl Iterator for ArrayElements {
+    type Item = io::Result<RawRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let index = self.index;
+        match self.next_element() {
+            Ok(Some(text)) => {
+                self.index += 1;
+                Some(Ok((Position::Element(index), text)))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_reuse_their_buffer() {
+        let long = "x".repeat(4000);
+        let text = format!("\u{feff}{long}\r\nshort\n\nlast");
+        let mut it = lines(text.as_bytes(), 8192);
+        assert_eq!(it.next().unwrap().unwrap(), (1, long));
+        let capacity = it.buf.capacity();
+        // Nothing of the long line is left over in the shorter ones after it.
+        assert_eq!(it.next().unwrap().unwrap(), (2, "short".to_string()));
+        assert_eq!(it.next().unwrap().unwrap(), (3, String::new()));
+        assert_eq!(it.next().unwrap().unwrap(), (4, "last".to_string()));
+        assert!(it.next().is_none());
+        assert_eq!(it.buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn lines_check_utf8_and_length() {
+        let mut it = lines(&b"ok\n\xff\xfe\ntoo long\nok now"[..], 7);
+        assert_eq!(it.next().unwrap().unwrap().1, "ok");
+        assert_eq!(
+            it.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let err = it.next().unwrap().unwrap_err();
+        let long = line_too_long(&err).unwrap();
+        assert_eq!((long.line, long.bytes), (3, 8));
+        assert_eq!(it.next().unwrap().unwrap(), (4, "ok now".to_string()));
+    }
+}
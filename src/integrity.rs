@@ -0,0 +1,298 @@
+//! `MANIFEST.toml`: the size, BLAKE3 hash, example count and label histogram of
+//! every shard in a directory, written before the directory is shipped somewhere
+//! and checked against it later (`ethics-pipeline manifest create|verify`).
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indicatif::ProgressBar;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::convert::Mode;
+use crate::dict::Dictionary;
+use crate::error::{ensure, Context, EthicsError, Result};
+use crate::input::is_pairs_shard;
+use crate::shard::{self, ShardReader};
+
+/// File name `manifest create` writes into a directory by default.
+pub const MANIFEST_NAME: &str = "MANIFEST.toml";
+
+/// Bumped whenever the manifest layout changes.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// One shard as it was when the manifest was created.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShardEntry {
+    /// Relative to the manifest's directory, unless the shard is outside it.
+    pub path: PathBuf,
+    /// Bytes on disk.
+    pub bytes: u64,
+    /// Hex BLAKE3 of the file on disk.
+    pub blake3: String,
+    pub message: String,
+    pub examples: u64,
+    /// Examples per label; `Example` shards only.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, u64>,
+}
+
+/// Contents of `MANIFEST.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IntegrityManifest {
+    pub version: u32,
+    pub created_unix: i64,
+    pub converter_version: String,
+    pub shards: Vec<ShardEntry>,
+}
+
+/// What `verify` found for one listed shard.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    Ok,
+    Missing,
+    /// One line per field that differs, e.g. `examples 100 -> 99`.
+    Changed(Vec<String>),
+    /// The shard exists but could not be read through, e.g. a truncated zstd frame.
+    Unreadable(String),
+}
+
+/// `Example` with only its label decoded; prost passes over the other fields by
+/// their length prefixes without building them.
+#[derive(Clone, PartialEq, Message)]
+struct LabelOnly {
+    #[prost(int32, tag = "4")]
+    label: i32,
+}
+
+/// BLAKE3 of everything read through a [`Hashing`] reader so far.
+#[derive(Default)]
+struct Hashed {
+    hasher: blake3::Hasher,
+    bytes: u64,
+}
+
+/// Hashes the compressed bytes as the decoder pulls them, so a shard is read once.
+/// The state is shared because `ShardReader` takes ownership of its reader.
+struct Hashing<R> {
+    inner: R,
+    state: Arc<Mutex<Hashed>>,
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let mut state = self.state.lock().unwrap();
+        state.hasher.update(&buf[..n]);
+        state.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads `path` once, hashing the file and counting its records (and, for
+/// `Example` shards, their labels), with the bytes read added to `bar`.
+/// The returned entry's `path` is `path` as given.
+pub fn scan_shard(path: &Path, dict: Option<&Dictionary>, bar: &ProgressBar) -> Result<ShardEntry> {
+    let file =
+        File::open(path).with_context(|| format!("failed to open shard {}", path.display()))?;
+    let size = file.metadata()?.len();
+    let state = Arc::new(Mutex::new(Hashed::default()));
+    let tee = Hashing {
+        inner: file,
+        state: Arc::clone(&state),
+    };
+    let mut reader = ShardReader::from_reader(bar.wrap_read(tee), dict, path)?;
+    let message = match &reader.header {
+        Some(h) => h.message.clone(),
+        None if is_pairs_shard(path) => Mode::Pair.message().to_string(),
+        None => Mode::Example.message().to_string(),
+    };
+
+    let mut examples = 0;
+    let mut labels = BTreeMap::new();
+    if message == Mode::Example.message() {
+        loop {
+            let offset = reader.offset();
+            let Some(record) = reader.next_record()? else {
+                break;
+            };
+            let LabelOnly { label } =
+                LabelOnly::decode(record).map_err(|source| EthicsError::Decode {
+                    message: "Example",
+                    offset,
+                    source,
+                })?;
+            *labels.entry(label.to_string()).or_insert(0) += 1;
+            examples += 1;
+        }
+    } else {
+        while reader.skip_record()? {
+            examples += 1;
+        }
+    }
+    drop(reader);
+
+    // The decoder may stop short of the end of the file, e.g. before padding;
+    // the hash covers every byte regardless.
+    let mut state = Arc::into_inner(state)
+        .expect("reader dropped")
+        .into_inner()
+        .unwrap();
+    if state.bytes < size {
+        let mut rest = File::open(path)?;
+        rest.seek(SeekFrom::Start(state.bytes))?;
+        let copied = io::copy(&mut bar.wrap_read(rest), &mut state.hasher)?;
+        state.bytes += copied;
+    }
+    ensure!(
+        state.bytes == size,
+        Corrupt,
+        "{} changed size while it was read ({size} -> {} bytes)",
+        path.display(),
+        state.bytes
+    );
+    Ok(ShardEntry {
+        path: path.to_path_buf(),
+        bytes: size,
+        blake3: state.hasher.finalize().to_hex().to_string(),
+        message,
+        examples,
+        labels,
+    })
+}
+
+/// `shard` as it is listed in a manifest kept in `root`.
+fn relative_to(shard: &Path, root: &Path) -> PathBuf {
+    shard.strip_prefix(root).unwrap_or(shard).to_path_buf()
+}
+
+impl IntegrityManifest {
+    /// Scans `shards` one after another, listing them relative to `root`.
+    pub fn create(
+        shards: &[PathBuf],
+        root: &Path,
+        dict: Option<&Dictionary>,
+        bar: &ProgressBar,
+    ) -> Result<Self> {
+        let mut entries = Vec::with_capacity(shards.len());
+        for path in shards {
+            let mut entry = scan_shard(path, dict, bar)?;
+            entry.path = relative_to(path, root);
+            entries.push(entry);
+        }
+        let created_unix = shard::source_date_epoch().unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64)
+        });
+        Ok(IntegrityManifest {
+            version: MANIFEST_VERSION,
+            created_unix,
+            converter_version: shard::CONVERTER_VERSION.to_string(),
+            shards: entries,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let manifest: IntegrityManifest = toml::from_str(&text).map_err(|e| {
+            EthicsError::Corrupt(format!("{} is not a shard manifest: {e}", path.display()))
+        })?;
+        ensure!(
+            manifest.version == MANIFEST_VERSION,
+            SchemaMismatch,
+            "{}: unsupported manifest version {} (expected {MANIFEST_VERSION})",
+            path.display(),
+            manifest.version
+        );
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self).map_err(|e| {
+            EthicsError::InvalidArgument(format!("cannot write {}: {e}", path.display()))
+        })?;
+        fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Total bytes of the listed shards, for sizing a progress bar.
+    pub fn bytes(&self) -> u64 {
+        self.shards.iter().map(|s| s.bytes).sum()
+    }
+
+    /// Re-scans every listed shard under `root` and compares it with its entry,
+    /// calling `report` as each one is done.
+    pub fn verify(
+        &self,
+        root: &Path,
+        dict: Option<&Dictionary>,
+        bar: &ProgressBar,
+        mut report: impl FnMut(&ShardEntry, &Finding),
+    ) -> Vec<Finding> {
+        let mut findings = Vec::with_capacity(self.shards.len());
+        for expected in &self.shards {
+            let path = root.join(&expected.path);
+            let finding = match fs::metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    bar.inc(expected.bytes);
+                    Finding::Missing
+                }
+                Err(e) => Finding::Unreadable(e.to_string()),
+                // A different size settles it without reading the shard.
+                Ok(meta) if meta.len() != expected.bytes => {
+                    bar.inc(expected.bytes);
+                    Finding::Changed(vec![format!("bytes {} -> {}", expected.bytes, meta.len())])
+                }
+                Ok(_) => match scan_shard(&path, dict, bar) {
+                    Ok(found) => compare(expected, &found),
+                    Err(e) => Finding::Unreadable(describe(&e)),
+                },
+            };
+            report(expected, &finding);
+            findings.push(finding);
+        }
+        findings
+    }
+}
+
+/// `e` with its sources, as `anyhow` prints it with `{:#}`.
+fn describe(e: &EthicsError) -> String {
+    let chain: Vec<String> =
+        std::iter::successors(Some(e as &dyn std::error::Error), |e| e.source())
+            .map(ToString::to_string)
+            .collect();
+    chain.join(": ")
+}
+
+/// The fields of `found` that differ from `expected`.
+fn compare(expected: &ShardEntry, found: &ShardEntry) -> Finding {
+    let mut changes = Vec::new();
+    if found.blake3 != expected.blake3 {
+        changes.push(format!("blake3 {} -> {}", expected.blake3, found.blake3));
+    }
+    if found.message != expected.message {
+        changes.push(format!("message {} -> {}", expected.message, found.message));
+    }
+    if found.examples != expected.examples {
+        changes.push(format!(
+            "examples {} -> {}",
+            expected.examples, found.examples
+        ));
+    }
+    if found.labels != expected.labels {
+        changes.push(format!(
+            "labels {:?} -> {:?}",
+            expected.labels, found.labels
+        ));
+    }
+    if changes.is_empty() {
+        Finding::Ok
+    } else {
+        Finding::Changed(changes)
+    }
+}
@@ -0,0 +1,46 @@
+//! The conversion pipeline as a library: [`convert`] turns JSONL into shards through
+//! [`writer`], [`shard`] and [`reader`] read them back, and the binaries are thin
+//! CLIs around both. Library functions fail with [`error::EthicsError`].
+
+pub mod ethics {
+    include!(concat!(env!("OUT_DIR"), "/ethics.v1.rs"));
+
+    /// The serialized `FileDescriptorSet` of `proto/ethics.proto`, for decoding shards
+    /// from other languages.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/ethics.v1.desc"));
+
+    /// The source of `proto/ethics.proto`.
+    pub const PROTO_SOURCE: &str = include_str!("../proto/ethics.proto");
+}
+
+#[cfg(feature = "cloud")]
+pub mod cloud;
+pub mod convert;
+pub mod dict;
+pub mod dryrun;
+pub mod error;
+pub mod export;
+#[cfg(feature = "http")]
+pub mod fetch;
+pub mod import;
+pub mod index;
+pub mod input;
+pub mod integrity;
+pub mod logging;
+pub mod manifest;
+pub mod mmap;
+pub mod protojson;
+pub mod reader;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod sample;
+pub mod shard;
+pub mod sqlite;
+pub mod stats;
+pub mod text;
+pub mod tfrecord;
+#[cfg(feature = "tokenizer")]
+pub mod tokens;
+pub mod webdataset;
+pub mod writer;
@@ -0,0 +1,116 @@
+//! Logging for the binaries: `--log-level` and `--log-format`, shared through
+//! [`LogArgs`].
+//!
+//! Text output, the default, prints each event's message alone, prefixed with
+//! `warning:` or `error:` at those levels, so it reads like plain `eprintln!`
+//! output. JSON output is one object per line on stderr with the event's fields and
+//! the span it happened in (`file` with the input's path, subset and split).
+//!
+//! Events under [`RUN_TARGET`] (file started and finished, with records written,
+//! rejects and duration) only appear in JSON: text runs already print the same
+//! numbers in their summary.
+
+use std::fmt;
+use std::io;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{filter_fn, EnvFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::error::{EthicsError, Result};
+
+/// Target of the per-file lifecycle events, which text output leaves out.
+pub const RUN_TARGET: &str = "protobuf_ethics::run";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Plain messages, as a person would read them.
+    #[default]
+    Text,
+    /// One JSON object per event, with structured fields.
+    Json,
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// Lowest level logged (`error`, `warn`, `info`, `debug`, `trace`), or a `RUST_LOG`
+    /// style filter such as `warn,protobuf_ethics=debug`. Defaults to `RUST_LOG`, then
+    /// `info`.
+    #[arg(long, value_name = "LEVEL", global = true)]
+    pub log_level: Option<String>,
+
+    /// How log events are written to stderr.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+}
+
+impl LogArgs {
+    /// Installs the global subscriber; call once, first thing in `main`.
+    pub fn init(&self) -> Result<()> {
+        let filter = match &self.log_level {
+            Some(level) => EnvFilter::try_new(level),
+            None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info")),
+        }
+        .map_err(|e| EthicsError::InvalidArgument(format!("invalid --log-level: {e}")))?;
+        let layer = match self.log_format {
+            LogFormat::Text => tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .event_format(Plain)
+                .with_filter(filter_fn(|meta| meta.target() != RUN_TARGET))
+                .boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .boxed(),
+        };
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(layer)
+            .try_init()
+            .map_err(|e| EthicsError::InvalidArgument(format!("failed to set up logging: {e}")))
+    }
+}
+
+/// The message of an event, with a level prefix for warnings and errors.
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let prefix = match *event.metadata().level() {
+            Level::ERROR => "error: ",
+            Level::WARN => "warning: ",
+            _ => "",
+        };
+        let mut message = Message::default();
+        event.record(&mut message);
+        writeln!(writer, "{prefix}{}", message.0)
+    }
+}
+
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
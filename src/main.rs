@@ -1,63 +1,2070 @@
 use anyhow::*;
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
 use prost::Message;
-use serde::Deserialize;
-use std::{fs::File, io::{BufRead, BufReader, Write}};
-use zstd::stream::write::Encoder as ZstdEncoder;
-
-pub mod ethics { include!(concat!(env!("OUT_DIR"), "/ethics.v1.rs")); }
-use ethics::Example;
-
-#[derive(Deserialize)]
-struct Row {
-    #[serde(default)] scenario: String,
-    #[serde(default)] question: String,
-    #[serde(default)] observation: String,
-    #[serde(default)] label: i32,
-    #[serde(flatten)] rest: serde_json::Value, // capture anything else
-}
-
-fn pick_text(r: &Row) -> String {
-    if !r.scenario.is_empty() { r.scenario.clone() }
-    else if !r.question.is_empty() { r.question.clone() }
-    else { r.observation.clone() }
-}
-
-fn jsonl_to_pb(input: &str, subset: &str, split: &str, out_pbzst: &str) -> Result<()> {
-    let f = File::open(input)?;
-    let mut enc = ZstdEncoder::new(File::create(out_pbzst)?, 9)?; // zstd level 9
-    let reader = BufReader::new(f);
-
-    for line in reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() { continue; }
-        let row: Row = serde_json::from_str(&line)?;
-
-        let mut ex = Example {
-            subset: subset.to_string(),
-            split:  split.to_string(),
-            text:   pick_text(&row),
-            label:  row.label,
-            meta:   Default::default(),
-        };
-
-        if let Some(obj) = row.rest.as_object() {
-            for (k, v) in obj {
-                if ["rationale","action","answer","input","output"].contains(&k.as_str()) {
-                    ex.meta.insert(k.clone(), v.to_string());
+use protobuf_ethics::convert::{
+    infer_subset_split, parse_record, parse_reject_rate, parse_schema, records_read, reject_rate,
+    row_to_example, row_to_pair, run_job, ConvertOptions, DedupConfig, Encoded, Job, JobOutput,
+    Mode, Parsed, RecordOptions, RejectBudget, Row, RunState, Schema, DEFAULT_SHARD_TEMPLATE,
+};
+use protobuf_ethics::dict::Dictionary;
+use protobuf_ethics::dryrun::DryRun;
+use protobuf_ethics::error::EthicsError;
+use protobuf_ethics::ethics::{
+    Example, PairExample, ShardHeader, FILE_DESCRIPTOR_SET, PROTO_SOURCE,
+};
+use protobuf_ethics::export::{
+    ExportFormat, ExportOptions, ExportReader, ExportWriter, MapColumns,
+};
+#[cfg(feature = "http")]
+use protobuf_ethics::fetch::{
+    builtin_checksums, fetch, hub_template, load_checksums, FetchOptions, FetchStatus,
+    DEFAULT_REPO, SUBSETS,
+};
+use protobuf_ethics::import::{import, parse_column, ImportFormat, ImportOptions};
+use protobuf_ethics::input::{
+    decompressed_name, input_stem, is_object_url, is_pairs_shard, is_stdio, line_too_long,
+    open_maybe_compressed, records_with_limit,
+};
+use protobuf_ethics::integrity::{Finding, IntegrityManifest, MANIFEST_NAME};
+use protobuf_ethics::logging::LogArgs;
+use protobuf_ethics::manifest::{
+    budget_rejects, label_histogram, ratio, size_totals, write_manifest, ShardCounts, ShardInfo,
+};
+use protobuf_ethics::reader::ExampleReader;
+use protobuf_ethics::shard::{self, ShardReader, SCHEMA_VERSION};
+use protobuf_ethics::webdataset::{tar_path, TarKey};
+use protobuf_ethics::writer::{ExampleWriter, DEFAULT_ZSTD_LEVEL};
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::IsTerminal;
+use std::{
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+use tracing::{error, warn};
+
+/// CLI arguments.
+///
+/// Single-file mode takes `--input/--subset/--split/--out`. Batch mode takes
+/// `--glob` and writes `<out-dir>/<input stem>.pb.zst` for every match, inferring
+/// subset and split from filenames shaped like `<subset>[-_]<split>.jsonl`
+/// (`cm_train.jsonl` -> commonsense/train, `justice_test_hard.jsonl` -> justice/test_hard).
+/// When inference fails, `--subset`/`--split` act as fallbacks; if those are not
+/// given either, the file is listed as skipped in the final table.
+#[derive(Parser, Debug)]
+#[command(
+    name = "ethics-pipeline",
+    about = "Convert ETHICS JSONL files into zstd-compressed protobuf shards."
+)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    /// Input JSONL file, or `-` for stdin.
+    #[arg(long, value_name = "JSONL", required_unless_present = "glob", conflicts_with = "glob", requires_all = ["subset", "split", "out"])]
+    input: Option<PathBuf>,
+
+    /// Glob of input JSONL files to convert in one run (e.g. "data/raw/*.jsonl").
+    #[arg(long, value_name = "GLOB")]
+    glob: Option<String>,
+
+    /// Subset name stored on every example (e.g. "justice"); fallback in batch mode.
+    #[arg(long)]
+    subset: Option<String>,
+
+    /// Split name stored on every example (e.g. "test"); fallback in batch mode.
+    #[arg(long)]
+    split: Option<String>,
+
+    /// Output shard path; parent directories are created if missing.
+    /// `-` streams the shard to stdout (no rotation, no manifest; the summary goes to stderr).
+    /// `s3://bucket/key` or `gs://bucket/key` uploads it as it is written, with the
+    /// `cloud` feature (no `--resume`, `--frame-every` or manifest).
+    #[arg(long, value_name = "PB_ZST", conflicts_with = "glob")]
+    out: Option<PathBuf>,
+
+    /// Output directory for batch mode.
+    #[arg(long, value_name = "DIR", default_value = "data/processed")]
+    out_dir: PathBuf,
+
+    /// zstd compression level (0 selects zstd's default).
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+    zstd_level: i32,
+
+    /// Write raw length-delimited protobuf (`.pb`) without the zstd wrapper.
+    #[arg(long, conflicts_with = "zstd_level")]
+    no_compress: bool,
+
+    /// Compress with a dictionary from `train-dict`; its sha256 and id go into the manifest,
+    /// and decoding needs the same `--dict`.
+    #[arg(long, value_name = "DICT", conflicts_with = "no_compress")]
+    dict: Option<PathBuf>,
+
+    /// Start a new zstd frame every N examples and write a `<shard>.idx` frame table, so
+    /// readers (`pb_to_jsonl --start`) can seek instead of decompressing from the beginning.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "no_compress")]
+    frame_every: Option<u64>,
+
+    /// Compress each shard on N zstd worker threads (0 compresses on the writing thread).
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        conflicts_with = "no_compress"
+    )]
+    zstd_workers: u32,
+
+    /// Enable long-distance matching with a 2^LOG byte window. Above 27, plain `zstd -d`
+    /// needs `--long=LOG` to decode; this crate's readers accept up to 31.
+    #[arg(long, value_name = "LOG", value_parser = clap::value_parser!(u32).range(10..=31), conflicts_with = "no_compress")]
+    zstd_long: Option<u32>,
+
+    /// Follow every record with a CRC32 of its bytes, announced in the shard header, so
+    /// readers can pinpoint corruption.
+    #[arg(long)]
+    checksums: bool,
+
+    /// Leave out the `ShardHeader` at the start of each shard, for consumers that expect
+    /// nothing but length-delimited records.
+    #[arg(long, conflicts_with = "checksums")]
+    no_header: bool,
+
+    /// Stamp each `ShardHeader` with the current time instead of 0 (or
+    /// `SOURCE_DATE_EPOCH`, which wins when set); shards then differ between runs.
+    #[arg(long, conflicts_with = "no_header")]
+    header_timestamp: bool,
+
+    /// Rotate to a new shard after this many examples.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_examples_per_shard: Option<u64>,
+
+    /// Rotate to a new shard once this many uncompressed protobuf bytes have been written.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    max_shard_bytes: Option<u64>,
+
+    /// Filename template for rotated shards, placed next to `--out` (or in `--out-dir`).
+    /// Placeholders: `{subset}`, `{split}`, `{stem}` (input file stem), `{ext}`
+    /// (`pb.zst` or `pb`), and `{index}` / `{index:05}` (0-based shard number).
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_SHARD_TEMPLATE)]
+    shard_template: String,
+
+    /// Abort a file once it has produced more than this many JSON parse failures.
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// Fail once more than this share (0 to 1) of non-blank records has been rejected,
+    /// per input and over the run. Checked as records are read, from the 1000th on,
+    /// and again at the end; duplicates dropped by `--dedup` don't count.
+    #[arg(long, value_name = "RATE", value_parser = parse_reject_rate)]
+    max_reject_rate: Option<f64>,
+
+    /// Fail once more than N records have been rejected, per input and over the run.
+    #[arg(long, value_name = "N")]
+    max_rejects: Option<usize>,
+
+    /// Fail on the first malformed line instead of skipping it.
+    #[arg(long, conflicts_with = "max_errors")]
+    strict: bool,
+
+    /// Suppress the progress bar / periodic progress lines.
+    #[arg(long)]
+    quiet: bool,
+
+    /// Print the end-of-run summary as a single JSON object on stdout instead of the
+    /// table, for scripts. Warnings still go to stderr.
+    #[arg(long)]
+    summary_json: bool,
+
+    /// Replace existing shards instead of refusing to run.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Check every input (sampling its first records and counting the rest) and print
+    /// the shards that would be written, without writing anything. Exits non-zero if
+    /// the run would fail, e.g. on an existing output without `--overwrite`.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Checkpoint to `<out>.progress` every few thousand records and, when that file is
+    /// left over from an interrupted run, continue from it instead of starting over.
+    #[arg(long, conflicts_with = "glob")]
+    resume: bool,
+
+    /// Files converted concurrently in `--glob` mode (default: number of cores, or 1 with `--dedup`).
+    #[arg(long, short = 'j', value_name = "N", conflicts_with = "input")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Stop the batch at the first file that fails, cancelling the conversions in flight.
+    /// By default the other files still convert and the failures are listed at the end.
+    #[arg(long, conflicts_with = "input")]
+    fail_fast: bool,
+
+    /// Threads parsing and encoding each input while the main thread writes; output is unchanged.
+    #[arg(long, value_name = "N", default_value = "1")]
+    parse_threads: NonZeroUsize,
+
+    /// Read plain JSONL inputs through a memory map instead of a buffered reader; stdin and
+    /// compressed, CSV or JSON array inputs are streamed as usual. Output is unchanged.
+    /// The inputs must not be written to during the run (see the `mmap` module docs).
+    #[arg(long)]
+    mmap: bool,
+
+    /// Drop examples whose normalized text (trimmed, whitespace collapsed) was already
+    /// written earlier in the run, across all inputs; reported as `duplicate`.
+    #[arg(long)]
+    dedup: bool,
+
+    /// Also lowercase text before comparing for `--dedup`.
+    #[arg(long, requires = "dedup")]
+    dedup_case_insensitive: bool,
+
+    /// Remember 128-bit hashes instead of full texts for `--dedup`, to bound memory on huge inputs.
+    #[arg(long, requires = "dedup")]
+    dedup_hash_only: bool,
+
+    /// Also write the duplicates `--dedup` drops to `--rejects-out`, as `duplicate`.
+    #[arg(long, requires = "dedup")]
+    dedup_rejects: bool,
+
+    /// Ignore the first N non-empty lines of each input (e.g. to resume a partial run).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip: usize,
+
+    /// Stop each input after writing N examples; the shard is still finished cleanly.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    limit: Option<u64>,
+
+    /// Check the first `--schema-sample` records of each input against a subset's schema
+    /// (`commonsense`, `deontology`, `justice`, `utilitarianism`, `virtue`) or a comma-separated
+    /// list of required keys (`a|b` accepts either), and abort if keys are missing.
+    #[arg(long, value_name = "SCHEMA", value_parser = parse_schema)]
+    expect_schema: Option<Schema>,
+
+    /// Records checked by `--expect-schema`.
+    #[arg(
+        long,
+        value_name = "K",
+        default_value_t = 100,
+        requires = "expect_schema"
+    )]
+    schema_sample: usize,
+
+    /// Labels considered valid, e.g. `0,1`; others are counted and their lines reported.
+    /// Defaults to `0,1` for the binary `--expect-schema` presets.
+    #[arg(long, value_name = "LABELS", value_delimiter = ',')]
+    allowed_labels: Option<Vec<i32>>,
+
+    /// Drop examples whose label isn't allowed (reported as `disallowed_label`) instead of keeping them.
+    #[arg(long)]
+    reject_bad_labels: bool,
+
+    /// Exit non-zero at the end of the run if any label wasn't allowed.
+    #[arg(long)]
+    fail_on_bad_label: bool,
+
+    /// Write every rejected line to this JSONL file as `{"reason", "input", "line"|"element", "raw"}`.
+    /// Only created if something is rejected.
+    #[arg(long, value_name = "JSONL")]
+    rejects_out: Option<PathBuf>,
+
+    #[command(flatten)]
+    record: RecordOptions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that a shard matches what converting its source JSONL would produce.
+    Verify {
+        /// Original JSONL input (`-` for stdin).
+        #[arg(long, value_name = "JSONL")]
+        jsonl: PathBuf,
+
+        /// Shard produced from it (`-` for stdin).
+        #[arg(long, value_name = "PB_ZST")]
+        shard: PathBuf,
+
+        /// Number of mismatches to print before summarising.
+        #[arg(long, default_value_t = 10)]
+        max_mismatches: usize,
+
+        /// zstd dictionary the shard was compressed with.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+
+        /// Fail if the shard's schema version is older than N (headerless shards count as 0).
+        #[arg(long, value_name = "N")]
+        require_schema_version: Option<u32>,
+
+        #[command(flatten)]
+        record: RecordOptions,
+    },
+
+    /// Rewrite a shard with this build's schema version, filling fields added since it
+    /// was written with their defaults.
+    Migrate {
+        /// Shard to migrate (`-` for stdin).
+        #[arg(value_name = "PB_ZST")]
+        shard: PathBuf,
+
+        /// Where to write the migrated shard; the input itself with `--overwrite`.
+        #[arg(long, value_name = "PB_ZST")]
+        out: PathBuf,
+
+        /// zstd compression level of the output (0 selects zstd's default).
+        #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+        zstd_level: i32,
+
+        /// Write the output as raw length-delimited protobuf.
+        #[arg(long, conflicts_with = "zstd_level")]
+        no_compress: bool,
+
+        /// zstd dictionary the shard was compressed with; the output is compressed with it too.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+
+        /// Add record checksums (kept anyway when the input has them).
+        #[arg(long)]
+        checksums: bool,
+
+        /// Replace `--out` if it exists.
+        #[arg(long)]
+        overwrite: bool,
+    },
+
+    /// Train a zstd dictionary on encoded examples sampled from JSONL inputs or shards.
+    TrainDict {
+        /// JSONL/CSV inputs (converted with the record flags below) or `.pb`/`.pb.zst` shards.
+        #[arg(required = true, value_name = "INPUT")]
+        inputs: Vec<PathBuf>,
+
+        /// Where to write the dictionary.
+        #[arg(long, value_name = "DICT", default_value = "ethics.dict")]
+        out: PathBuf,
+
+        /// Maximum dictionary size in bytes (zstd's default is 110 KiB).
+        #[arg(long, value_name = "BYTES", default_value_t = 112_640)]
+        max_size: usize,
+
+        /// Examples to train on, taken from the start of each input in equal shares.
+        #[arg(long, value_name = "N", default_value_t = 100_000)]
+        max_samples: usize,
+
+        #[command(flatten)]
+        record: RecordOptions,
+    },
+
+    /// Record or check the size, BLAKE3 hash, example count and label histogram of every
+    /// shard in a directory, e.g. around an upload.
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestCommand,
+    },
+
+    /// Write `Example` shards out as Parquet or an Arrow IPC stream for pandas/polars/DuckDB,
+    /// as TFRecord for TensorFlow, as WebDataset tars, or as a SQLite database, one output
+    /// per shard or all merged into one. The columns, features, tar layout and tables are
+    /// listed in the README.
+    Export {
+        /// Shards to export.
+        #[arg(required = true, value_name = "PB_ZST")]
+        inputs: Vec<PathBuf>,
+
+        /// Output format.
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+
+        /// Directory for one file per input, named `<shard stem>.<format>` (`test-00000.parquet`).
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+
+        /// Write every input, in order, into this single file instead.
+        #[arg(long, value_name = "FILE", conflicts_with = "out_dir")]
+        merge_into: Option<PathBuf>,
+
+        /// Rows per record batch, which bounds the memory used on huge shards.
+        #[arg(long, value_name = "ROWS", default_value_t = 8192, value_parser = clap::value_parser!(u64).range(1..))]
+        batch_size: u64,
+
+        /// Rows per Parquet row group.
+        #[arg(long, value_name = "ROWS", default_value_t = 100_000, value_parser = clap::value_parser!(u64).range(1..))]
+        row_group_size: u64,
+
+        /// zstd level of the Parquet column chunks (0 selects the format's default).
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(0..=22))]
+        zstd_level: i32,
+
+        /// Leave the Parquet column chunks uncompressed.
+        #[arg(long, conflicts_with = "zstd_level")]
+        no_compress: bool,
+
+        /// How `meta` and `labels_by_name` are stored: map columns, or JSON object strings.
+        #[arg(long, value_enum, default_value_t = MapColumns::Map)]
+        maps: MapColumns,
+
+        /// Gzip TFRecord files (`.tfrecord.gz`), for `TFRecordDataset(compression_type="GZIP")`.
+        #[arg(long)]
+        gzip: bool,
+
+        /// Examples per WebDataset tar; an output `name.tar` becomes `name-000000.tar`, `name-000001.tar`, ...
+        #[arg(long, value_name = "N", default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+        examples_per_tar: u64,
+
+        /// What WebDataset members are named after: the zero-padded example index, or the example id.
+        #[arg(long, value_enum, default_value_t = TarKey::Index)]
+        tar_key: TarKey,
+
+        /// zstd-compress each WebDataset tar (`.tar.zst`) at --zstd-level.
+        #[arg(long, conflicts_with = "no_compress")]
+        zstd_tars: bool,
+
+        /// Read each written file back and compare N examples spread across it with the shards.
+        #[arg(long, value_name = "N")]
+        check: Option<u64>,
+
+        /// zstd dictionary the shards were compressed with.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+
+        /// Replace output files that exist.
+        #[arg(long)]
+        overwrite: bool,
+    },
+
+    /// Convert a Parquet file, such as an export, or canonical proto3 JSON lines back into
+    /// an `Example` shard, validating rows as conversion does. Parquet columns are matched
+    /// to fields by name unless remapped.
+    Import {
+        /// File to import.
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+
+        /// Input format: Parquet, or `pb_to_jsonl --format proto-json` output.
+        #[arg(long, value_enum, default_value_t = ImportFormat::Parquet)]
+        from: ImportFormat,
+
+        /// Shard to write.
+        #[arg(long, value_name = "PB_ZST")]
+        out: PathBuf,
+
+        /// Read a field from a differently named column, e.g. `text=scenario`; repeatable.
+        #[arg(long, value_name = "FIELD=COLUMN", value_parser = parse_column)]
+        column: Vec<(String, String)>,
+
+        /// Columns copied into `meta` under their own names, e.g. `source,annotator`.
+        #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+        meta_columns: Vec<String>,
+
+        /// Subset of rows without a subset column, or with a null or empty one.
+        #[arg(long)]
+        subset: Option<String>,
+
+        /// Split of rows without a split column, likewise.
+        #[arg(long)]
+        split: Option<String>,
+
+        /// Labels considered valid, e.g. `0,1`; others are counted and reported.
+        #[arg(long, value_name = "LABELS", value_delimiter = ',')]
+        allowed_labels: Option<Vec<i32>>,
+
+        /// Drop rows whose label isn't allowed (reported as `disallowed_label`) instead of keeping them.
+        #[arg(long)]
+        reject_bad_labels: bool,
+
+        /// Keep rows whose text is empty or whitespace-only instead of rejecting them as `empty_text`.
+        #[arg(long)]
+        allow_empty_text: bool,
+
+        /// Rows read per record batch.
+        #[arg(long, value_name = "ROWS", default_value_t = 8192, value_parser = clap::value_parser!(u64).range(1..))]
+        batch_size: u64,
+
+        /// zstd compression level of the shard (0 selects zstd's default).
+        #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(0..=22))]
+        zstd_level: i32,
+
+        /// Write the shard as raw length-delimited protobuf.
+        #[arg(long, conflicts_with = "zstd_level")]
+        no_compress: bool,
+
+        /// zstd dictionary to compress the shard with.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+
+        /// Write a CRC32 after every record.
+        #[arg(long)]
+        checksums: bool,
+
+        /// Replace `--out` if it exists.
+        #[arg(long)]
+        overwrite: bool,
+    },
+
+    /// Write the compiled `ethics.v1` schema as a serialized `FileDescriptorSet` for
+    /// Python, Go and other protobuf consumers, or print the `.proto` source.
+    Schema {
+        /// Where to write the descriptor set (e.g. `ethics_v1.pb`); without it the
+        /// `.proto` source is printed instead.
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// Replace `--out` if it exists.
+        #[arg(long, requires = "out")]
+        overwrite: bool,
+    },
+
+    /// Download the raw ETHICS CSVs into `data/raw` as `<subset>-<split>.csv`, checking
+    /// each against its SHA-256. Files already in place and verified are skipped;
+    /// interrupted downloads continue from their `.part` file.
+    #[cfg(feature = "http")]
+    Fetch {
+        /// Subsets to download (commonsense, deontology, justice, utilitarianism, virtue); all by default.
+        #[arg(value_name = "SUBSET")]
+        subsets: Vec<String>,
+
+        /// Splits to download.
+        #[arg(
+            long,
+            value_name = "SPLIT,...",
+            value_delimiter = ',',
+            default_value = "train,test,test_hard"
+        )]
+        splits: Vec<String>,
+
+        /// Where to put the files.
+        #[arg(long, value_name = "DIR", default_value = "data/raw")]
+        out_dir: PathBuf,
+
+        /// Hugging Face hub dataset to download from.
+        #[arg(long, value_name = "OWNER/NAME", default_value = DEFAULT_REPO)]
+        repo: String,
+
+        /// Branch, tag or commit of `--repo`.
+        #[arg(long, default_value = "main")]
+        revision: String,
+
+        /// Download from elsewhere: a URL with `{subset}` and `{split}` placeholders
+        /// (e.g. `https://mirror.internal/ethics/{subset}/{split}.csv`).
+        #[arg(long, value_name = "URL", conflicts_with_all = ["repo", "revision"])]
+        url_template: Option<String>,
+
+        /// Checksum table in `sha256sum` format, adding to and overriding the built-in one.
+        #[arg(long, value_name = "FILE")]
+        checksums: Option<PathBuf>,
+
+        /// Fail on files with no known checksum instead of keeping them unverified.
+        #[arg(long)]
+        require_checksums: bool,
+
+        /// Download again even when a verified file is already in place.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ManifestCommand {
+    /// Scan a directory (every `*.pb.zst` and `*.pb` below it) or a glob of shards into a manifest.
+    Create {
+        /// Directory or quoted glob of shards.
+        #[arg(value_name = "DIR|GLOB")]
+        shards: String,
+
+        /// Where to write the manifest; shard paths in it are relative to its directory.
+        /// Defaults to `MANIFEST.toml` in DIR, or in the current directory for a glob.
+        #[arg(long, value_name = "TOML")]
+        out: Option<PathBuf>,
+
+        /// zstd dictionary the shards were compressed with.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+
+        /// Replace the manifest if it exists.
+        #[arg(long)]
+        overwrite: bool,
+    },
+
+    /// Re-read every shard a manifest lists and report each one that is missing or
+    /// changed; exits non-zero if any is.
+    Verify {
+        /// The manifest, or the directory holding `MANIFEST.toml`.
+        #[arg(value_name = "TOML|DIR", default_value = MANIFEST_NAME)]
+        manifest: PathBuf,
+
+        /// zstd dictionary the shards were compressed with.
+        #[arg(long, value_name = "DICT")]
+        dict: Option<PathBuf>,
+    },
+}
+
+/// `println!`, or `eprintln!` when stdout carries shard data.
+macro_rules! say {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+/// Compares `shard` record-by-record against a fresh conversion of `jsonl`.
+/// Line numbers are 1-based positions in the JSONL, counting the blank lines the converter skips;
+/// JSON array inputs are reported by element index instead.
+fn verify(
+    jsonl: &Path,
+    shard: &Path,
+    max_mismatches: usize,
+    dict: Option<&Dictionary>,
+    require_schema_version: Option<u32>,
+    record: &RecordOptions,
+) -> Result<()> {
+    ensure!(
+        !(is_stdio(jsonl) && is_stdio(shard)),
+        "only one of --jsonl and --shard can be read from stdin"
+    );
+    ensure!(
+        is_stdio(shard) || is_pairs_shard(shard) == (record.mode == Mode::Pair),
+        "{} holds {} messages; pass {}",
+        shard.display(),
+        if is_pairs_shard(shard) {
+            "PairExample"
+        } else {
+            "Example"
+        },
+        if is_pairs_shard(shard) {
+            "--mode pair"
+        } else {
+            "--mode example"
+        },
+    );
+    let reader = open_maybe_compressed(jsonl)?;
+    let mut shard_reader = ShardReader::open(shard, dict)?;
+    let warning = shard::check_schema_version(shard_reader.header.as_ref(), require_schema_version)
+        .with_context(|| format!("cannot verify {}", shard.display()))?;
+    if let Some(warning) = warning {
+        warn!(shard = %shard.display(), "{}: {warning}", shard.display());
+    }
+    if let Some(header) = &shard_reader.header {
+        ensure!(
+            header.message == record.mode.message(),
+            "{} holds {} messages according to its header, but --mode {} expects {}",
+            shard.display(),
+            header.message,
+            if record.mode == Mode::Pair {
+                "pair"
+            } else {
+                "example"
+            },
+            record.mode.message(),
+        );
+    }
+    let (mut compared, mut mismatches) = (0usize, 0usize);
+    // The shard's next example, read ahead so its subset/split are known before the row is built.
+    let mut next: Option<Example> = None;
+    let mut shard_subset_split = (String::new(), String::new());
+    let mut report = |msg: String| {
+        mismatches += 1;
+        if mismatches <= max_mismatches {
+            println!("mismatch: {msg}");
+        }
+    };
+
+    for rec in records_with_limit(reader, record.format.for_path(jsonl), record.max_line_bytes)? {
+        // Rows the converter rejects (overlong lines and parse failures included) never reach the shard.
+        let (pos, line) = match rec {
+            Err(e) if line_too_long(&e).is_some() => continue,
+            rec => rec?,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let std::result::Result::Ok(row) = serde_json::from_str::<Row>(&line) else {
+            continue;
+        };
+        if record.mode == Mode::Pair {
+            if row_to_pair(&row, "", "", record).is_err() {
+                continue;
+            }
+            let Some(got) = shard_reader.next_pair()? else {
+                report(format!("{pos}: shard ended after {compared} record(s)"));
+                continue;
+            };
+            compared += 1;
+            let (want, _) =
+                row_to_pair(&row, &got.subset, &got.split, record).map_err(|r| anyhow!("{r}"))?;
+            let mut fields = Vec::new();
+            if want.text_a != got.text_a {
+                fields.push("text_a");
+            }
+            if want.text_b != got.text_b {
+                fields.push("text_b");
+            }
+            if want.preferred != got.preferred {
+                fields.push("preferred");
+            }
+            if want.meta != got.meta {
+                fields.push("meta");
+            }
+            if want.id != got.id {
+                fields.push("id");
+            }
+            if !fields.is_empty() {
+                report(format!(
+                    "{pos} (record {compared}): {} differ",
+                    fields.join(", ")
+                ));
+            }
+            continue;
+        }
+        if next.is_none() {
+            next = shard_reader.next_example()?;
+            if let Some(got) = &next {
+                shard_subset_split = (got.subset.clone(), got.split.clone());
+            }
+        }
+        // Rebuild with the shard's subset/split so content ids (and subset defaults) match.
+        let std::result::Result::Ok((want, _)) =
+            row_to_example(&row, &shard_subset_split.0, &shard_subset_split.1, record)
+        else {
+            continue;
+        };
+        let Some(got) = next.take() else {
+            report(format!("{pos}: shard ended after {compared} record(s)"));
+            continue;
+        };
+        compared += 1;
+
+        let mut fields = Vec::new();
+        if want.context != got.context {
+            fields.push("context");
+        }
+        if want.text != got.text {
+            fields.push("text");
+        }
+        if want.label != got.label {
+            fields.push("label");
+        }
+        if want.score != got.score {
+            fields.push("score");
+        }
+        if want.labels_by_name != got.labels_by_name {
+            fields.push("labels_by_name");
+        }
+        if want.meta != got.meta {
+            fields.push("meta");
+        }
+        if want.id != got.id {
+            fields.push("id");
+        }
+        if !fields.is_empty() {
+            report(format!(
+                "{pos} (record {compared}): {} differ",
+                fields.join(", ")
+            ));
+        }
+    }
+
+    let mut extra = usize::from(next.is_some());
+    while shard_reader.next_record()?.is_some() {
+        extra += 1;
+    }
+    if extra > 0 {
+        report(format!(
+            "shard has {extra} record(s) beyond the end of the JSONL"
+        ));
+    }
+
+    ensure!(
+        mismatches == 0,
+        "{} mismatch(es) between {} and {}",
+        mismatches,
+        jsonl.display(),
+        shard.display()
+    );
+    println!(
+        "ok: {} record(s) match between {} and {}",
+        compared,
+        jsonl.display(),
+        shard.display()
+    );
+    Ok(())
+}
+
+/// Rewrites `input` to `out` behind a fresh header with this build's schema version.
+/// Decoding fills fields added since the shard was written with their defaults, so
+/// re-encoding each record is all a migration takes.
+fn migrate(
+    input: &Path,
+    out: &Path,
+    zstd_level: Option<i32>,
+    dict: Option<Dictionary>,
+    checksums: bool,
+    overwrite: bool,
+) -> Result<()> {
+    let mut reader = ShardReader::open(input, dict.as_ref())?;
+    let old = reader.header.clone();
+    let from = shard::schema_version(old.as_ref());
+    ensure!(
+        from <= SCHEMA_VERSION,
+        "{} has schema version {from}, newer than this build ({SCHEMA_VERSION})",
+        input.display()
+    );
+    let mode = match &old {
+        Some(h) if h.message == Mode::Pair.message() => Mode::Pair,
+        Some(h) => {
+            ensure!(
+                h.message == Mode::Example.message(),
+                "{} holds unknown {} messages",
+                input.display(),
+                h.message
+            );
+            Mode::Example
+        }
+        None if is_pairs_shard(input) => Mode::Pair,
+        None => Mode::Example,
+    };
+    let checksums = checksums || old.as_ref().is_some_and(|h| h.checksums);
+
+    // Headerless shards only say what subset and split they hold in their records.
+    let (mut subset, mut split) = old
+        .as_ref()
+        .map(|h| (h.subset.clone(), h.split.clone()))
+        .unwrap_or_default();
+    let not_a = || {
+        format!(
+            "{} holds a record that is not a {}",
+            input.display(),
+            mode.message()
+        )
+    };
+    let mut first = None;
+    if let Some(buf) = reader.next_record()? {
+        if old.is_none() {
+            (subset, split) = match mode {
+                Mode::Example => {
+                    let ex = Example::decode(buf).with_context(not_a)?;
+                    (ex.subset, ex.split)
+                }
+                Mode::Pair => {
+                    let pair = PairExample::decode(buf).with_context(not_a)?;
+                    (pair.subset, pair.split)
+                }
+            };
+        }
+        first = Some(Encoded::decode(mode, buf).with_context(not_a)?.buf);
+    }
+
+    let mut writer = ExampleWriter::create(out)
+        .with_header(shard::header(mode.message(), &subset, &split))
+        .checksums(checksums)
+        .overwrite(overwrite);
+    writer = match zstd_level {
+        Some(level) => writer.zstd_level(level),
+        None => writer.uncompressed(),
+    };
+    if let Some(dict) = &dict {
+        writer = writer.dict(dict);
+    }
+    let mut enc = writer.open()?;
+    if let Some(buf) = first {
+        enc.write_encoded(&buf)?;
+    }
+    while let Some(buf) = reader.next_record()? {
+        let encoded = Encoded::decode(mode, buf).with_context(not_a)?;
+        enc.write_encoded(&encoded.buf)?;
+    }
+    let records = enc.examples();
+    let (bytes, sha256) = enc.finish()?;
+    println!(
+        "{}: migrated {records} record(s) from schema version {from} to {SCHEMA_VERSION} -> {} ({bytes} bytes, sha256 {sha256})",
+        input.display(), out.display(),
+    );
+    Ok(())
+}
+
+/// Samples up to `max_samples` encoded examples from `inputs` and trains a zstd dictionary on them.
+fn train_dict(
+    inputs: &[PathBuf],
+    out: &Path,
+    max_size: usize,
+    max_samples: usize,
+    record: &RecordOptions,
+) -> Result<()> {
+    let per_input = max_samples.div_ceil(inputs.len());
+    let mut samples = Vec::new();
+    for input in inputs {
+        let before = samples.len();
+        if decompressed_name(input).ends_with(".pb") {
+            let mut reader = ShardReader::open(input, None)?;
+            while samples.len() - before < per_input {
+                let Some(buf) = reader.next_record()? else {
+                    break;
+                };
+                let mut sample = Vec::with_capacity(buf.len() + 10);
+                prost::encoding::encode_varint(buf.len() as u64, &mut sample);
+                sample.extend_from_slice(buf);
+                samples.push(sample);
+            }
+        } else {
+            // Encode as the converter would, so the dictionary sees the real byte layout.
+            let (subset, split) = infer_subset_split(input).unwrap_or_default();
+            for rec in records_with_limit(
+                open_maybe_compressed(input)?,
+                record.format.for_path(input),
+                record.max_line_bytes,
+            )? {
+                if samples.len() - before >= per_input {
+                    break;
+                }
+                let (_, line) = match rec {
+                    Err(e) if line_too_long(&e).is_some() => continue,
+                    rec => rec.with_context(|| format!("failed to read {}", input.display()))?,
+                };
+                if let Parsed::Encoded(encoded) =
+                    parse_record(&line, false, &subset, &split, record)
+                {
+                    samples.push(encoded.buf);
                 }
             }
         }
+        println!("{}: {} sample(s)", input.display(), samples.len() - before);
+    }
+    ensure!(!samples.is_empty(), "no examples found to train on");
+
+    let bytes = zstd::dict::from_samples(&samples, max_size).with_context(|| {
+        format!(
+            "dictionary training failed on {} sample(s); try more or larger inputs",
+            samples.len()
+        )
+    })?;
+    fs::write(out, &bytes).with_context(|| format!("failed to write {}", out.display()))?;
+    let dict = Dictionary::from_bytes(out.to_path_buf(), bytes);
+    println!(
+        "trained {} byte dictionary (id {}, sha256 {}) from {} sample(s) -> {}",
+        dict.bytes.len(),
+        dict.id.map_or("none".to_string(), |id| id.to_string()),
+        dict.sha256,
+        samples.len(),
+        out.display(),
+    );
+    Ok(())
+}
 
-        let mut buf = Vec::with_capacity(ex.encoded_len());
-        ex.encode_length_delimited(&mut buf)?;
-        enc.write_all(&buf)?;
+/// A byte progress bar over `total` on a TTY, hidden otherwise.
+fn bytes_bar(total: u64) -> ProgressBar {
+    if !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
     }
-    enc.finish()?;
+    let bar = ProgressBar::new(total);
+    let template = "[{elapsed_precise}] {wide_bar} {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ETA {eta} {msg}";
+    bar.set_style(ProgressStyle::with_template(template).expect("valid progress template"));
+    bar
+}
+
+/// Scans the shards under `dir` or matching a glob into `MANIFEST.toml`.
+fn manifest_create(
+    shards: &str,
+    out: Option<&Path>,
+    dict: Option<&Dictionary>,
+    overwrite: bool,
+) -> Result<()> {
+    let dir = Path::new(shards);
+    let (patterns, out) = if dir.is_dir() {
+        let patterns =
+            ["**/*.pb.zst", "**/*.pb"].map(|p| dir.join(p).to_string_lossy().into_owned());
+        (
+            patterns.to_vec(),
+            out.map_or_else(|| dir.join(MANIFEST_NAME), Path::to_path_buf),
+        )
+    } else {
+        (
+            vec![shards.to_string()],
+            out.map_or_else(|| PathBuf::from(MANIFEST_NAME), Path::to_path_buf),
+        )
+    };
+    ensure!(
+        overwrite || !out.exists(),
+        "{} already exists (pass --overwrite to replace it)",
+        out.display()
+    );
+    let mut files = BTreeSet::new();
+    for pattern in &patterns {
+        files.extend(
+            glob::glob(pattern)
+                .with_context(|| format!("invalid glob: {pattern}"))?
+                .flatten()
+                .filter(|p| p.is_file()),
+        );
+    }
+    ensure!(!files.is_empty(), "no shards found in {shards}");
+    let files: Vec<PathBuf> = files.into_iter().collect();
+    let total = files
+        .iter()
+        .map(|p| fs::metadata(p).map_or(0, |m| m.len()))
+        .sum();
+
+    let bar = bytes_bar(total);
+    let root = out.parent().unwrap_or(Path::new(""));
+    let manifest = IntegrityManifest::create(&files, root, dict, &bar)?;
+    bar.finish_and_clear();
+    manifest.write(&out)?;
+    let examples: u64 = manifest.shards.iter().map(|s| s.examples).sum();
+    println!(
+        "{} shard(s), {examples} example(s), {} bytes -> {}",
+        manifest.shards.len(),
+        manifest.bytes(),
+        out.display()
+    );
+    Ok(())
+}
+
+/// Checks every shard listed in a manifest, printing one line per shard.
+fn manifest_verify(manifest: &Path, dict: Option<&Dictionary>) -> Result<()> {
+    let path = if manifest.is_dir() {
+        manifest.join(MANIFEST_NAME)
+    } else {
+        manifest.to_path_buf()
+    };
+    let manifest = IntegrityManifest::load(&path)?;
+    let root = path.parent().unwrap_or(Path::new(""));
+    let bar = bytes_bar(manifest.bytes());
+    let findings = manifest.verify(root, dict, &bar, |entry, finding| {
+        let line = match finding {
+            Finding::Ok => format!("ok          {}", entry.path.display()),
+            Finding::Missing => format!("MISSING     {}", entry.path.display()),
+            Finding::Changed(changes) => format!(
+                "CHANGED     {}: {}",
+                entry.path.display(),
+                changes.join("; ")
+            ),
+            Finding::Unreadable(e) => format!("UNREADABLE  {}: {e}", entry.path.display()),
+        };
+        bar.suspend(|| println!("{line}"));
+    });
+    bar.finish_and_clear();
+    let bad = findings.iter().filter(|f| **f != Finding::Ok).count();
+    println!(
+        "{} of {} shard(s) match {}",
+        findings.len() - bad,
+        findings.len(),
+        path.display()
+    );
+    ensure!(
+        bad == 0,
+        "{bad} shard(s) missing or changed since {} was created",
+        path.display()
+    );
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    jsonl_to_pb("data/virtue-train.jsonl", "virtue", "train", "shards/virtue-train.pb.zst")?;
+/// Exports each of `inputs` to its own file in `out_dir`, or all of them to `merge_into`,
+/// checking `check` examples of each file written.
+fn export(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    merge_into: Option<&Path>,
+    opts: &ExportOptions,
+    check: Option<u64>,
+    dict: Option<&Dictionary>,
+) -> Result<()> {
+    for input in inputs {
+        ensure!(!is_stdio(input), "export reads shard files, not stdin");
+    }
+    let copy = |input: &Path, out: &mut ExportWriter| -> Result<u64> {
+        let before = out.rows();
+        for ex in ExampleReader::open_with_dict(input, dict)? {
+            out.write(&ex.with_context(|| format!("failed to read {}", input.display()))?)?;
+        }
+        out.end_shard()?;
+        Ok(out.rows() - before)
+    };
+    // WebDataset outputs are numbered tars named after `path`.
+    let written = |path: &Path, (bytes, files): (u64, u64)| match opts.format {
+        ExportFormat::Webdataset if files > 0 => format!(
+            "{} .. {} ({files} tar(s), {bytes} bytes)",
+            tar_path(path, 0).display(),
+            tar_path(path, files - 1).display()
+        ),
+        ExportFormat::Webdataset => "no tars".to_string(),
+        _ => format!("{} ({bytes} bytes)", path.display()),
+    };
+
+    if let Some(path) = merge_into {
+        let mut out = ExportWriter::create(path, opts)?;
+        for input in inputs {
+            let rows = copy(input, &mut out)?;
+            println!("{}: {rows} example(s)", input.display());
+        }
+        let rows = out.rows();
+        let done = out.finish()?;
+        println!(
+            "{} shard(s), {rows} example(s) -> {}",
+            inputs.len(),
+            written(path, done)
+        );
+        if let Some(n) = check {
+            check_export(path, opts.format, inputs, rows, n, dict)?;
+        }
+        return Ok(());
+    }
+    // Distinct shards can share a stem (`justice/test-00000`, `virtue/test-00000`).
+    let target = |input: &Path| out_dir.join(format!("{}.{}", input_stem(input), opts.extension()));
+    let mut targets = BTreeMap::new();
+    for input in inputs {
+        let path = target(input);
+        if let Some(other) = targets.insert(path.clone(), input) {
+            bail!("{} and {} would both be exported to {}; use another --out-dir for one of them, or --merge-into", other.display(), input.display(), path.display());
+        }
+    }
+    for input in inputs {
+        let path = target(input);
+        let mut out = ExportWriter::create(&path, opts)?;
+        let rows = copy(input, &mut out)?;
+        let done = out.finish()?;
+        println!(
+            "{}: {rows} example(s) -> {}",
+            input.display(),
+            written(&path, done)
+        );
+        if let Some(n) = check {
+            check_export(
+                &path,
+                opts.format,
+                std::slice::from_ref(input),
+                rows,
+                n,
+                dict,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetches every subset, printing a line per file and the checksums nothing vouched for.
+#[cfg(feature = "http")]
+fn fetch_subsets(subsets: &[String], opts: &FetchOptions) -> Result<()> {
+    let mut unverified = Vec::new();
+    for subset in subsets {
+        for file in fetch(subset, opts)? {
+            let what = match file.status {
+                FetchStatus::Verified => "verified, skipped",
+                FetchStatus::KeptUnverified => "already present, no checksum to verify",
+                FetchStatus::Downloaded => "downloaded, verified",
+                FetchStatus::DownloadedUnverified => "downloaded, no checksum to verify",
+            };
+            println!("{}: {} bytes ({what})", file.path.display(), file.bytes);
+            if !file.status.verified() {
+                unverified.push(file);
+            }
+        }
+    }
+    if !unverified.is_empty() {
+        println!(
+            "{} file(s) unverified; once checked, pin them with --checksums:",
+            unverified.len()
+        );
+        for file in &unverified {
+            println!("{}  {}", file.sha256, file.name);
+        }
+    }
+    Ok(())
+}
+
+/// Imports `input` into `out` and prints what was written and rejected.
+fn import_file(input: &Path, out: &Path, opts: &ImportOptions) -> Result<()> {
+    ensure!(!is_stdio(input), "import reads a file, not stdin");
+    let summary = import(input, out, opts)?;
+    println!(
+        "{}: {} row(s), {} example(s) -> {} ({} bytes, sha256 {})",
+        input.display(),
+        summary.rows,
+        summary.examples,
+        out.display(),
+        summary.bytes,
+        summary.hash
+    );
+    for (reason, n) in &summary.rejects {
+        println!("  rejected {n} row(s): {reason}");
+    }
+    if summary.disallowed_labels > 0 && !opts.reject_bad_labels {
+        println!(
+            "  kept {} row(s) with a label not in --allowed-labels",
+            summary.disallowed_labels
+        );
+    }
+    Ok(())
+}
+
+/// Reads `path` back and compares every `rows / sample`-th example with `inputs`, in order.
+fn check_export(
+    path: &Path,
+    format: ExportFormat,
+    inputs: &[PathBuf],
+    rows: u64,
+    sample: u64,
+    dict: Option<&Dictionary>,
+) -> Result<()> {
+    let stride = (rows / sample.max(1)).max(1);
+    let mut exported = ExportReader::open(path, format)?;
+    let (mut i, mut compared) = (0u64, 0u64);
+    for input in inputs {
+        for ex in ExampleReader::open_with_dict(input, dict)? {
+            let ex = format
+                .round_trip(ex.with_context(|| format!("failed to read {}", input.display()))?);
+            let back = exported.next().with_context(|| {
+                format!(
+                    "{} ends after {i} example(s), before {}",
+                    path.display(),
+                    input.display()
+                )
+            })??;
+            if i % stride == 0 && compared < sample {
+                ensure!(
+                    back == ex,
+                    "example {i} of {} differs from {}: {back:?} vs {ex:?}",
+                    path.display(),
+                    input.display()
+                );
+                compared += 1;
+            }
+            i += 1;
+        }
+    }
+    ensure!(
+        exported.next().is_none(),
+        "{} holds more than the {i} example(s) of its shard(s)",
+        path.display()
+    );
+    println!(
+        "  checked {compared} example(s) of {} against the shard(s)",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Expands `--glob` into jobs; files whose subset/split can't be resolved are returned separately.
+fn batch_jobs(
+    args: &Args,
+    pattern: &str,
+    opts: &ConvertOptions,
+) -> Result<(Vec<Job>, Vec<PathBuf>)> {
+    let mut jobs = Vec::new();
+    let mut skipped = Vec::new();
+    for path in glob::glob(pattern)
+        .with_context(|| format!("invalid glob: {pattern}"))?
+        .flatten()
+    {
+        let inferred =
+            infer_subset_split(&path).or_else(|| Some((args.subset.clone()?, args.split.clone()?)));
+        let Some((subset, split)) = inferred else {
+            skipped.push(path);
+            continue;
+        };
+        let stem = input_stem(&path);
+        let out = args.out_dir.join(format!("{stem}.{}", opts.extension()));
+        jobs.push(Job {
+            input: path,
+            subset,
+            split,
+            out,
+        });
+    }
+    Ok((jobs, skipped))
+}
+
+/// `--dry-run`: checks the inputs of `jobs` and prints what converting them would write.
+fn plan_jobs(
+    jobs: &[Job],
+    skipped: &[PathBuf],
+    opts: &ConvertOptions,
+    rejects_out: Option<&Path>,
+    manifest_dir: Option<&Path>,
+) -> Result<()> {
+    let mut plan = DryRun::new();
+    for job in jobs {
+        plan.read_records(&job.input, opts.record.format);
+        plan.note(format!("as {}/{}", job.subset, job.split));
+        plan.write(&opts.shard_path(job, 0), opts.overwrite);
+        if opts.rotates() {
+            plan.note("and further shards as rotation fills them");
+        }
+    }
+    for path in skipped {
+        plan.note(format!(
+            "skip {}: no subset/split in its name and no --subset/--split",
+            path.display()
+        ));
+    }
+    // Both are replaced on every run.
+    if let Some(path) = rejects_out {
+        plan.write(path, true);
+    }
+    if let Some(dir) = manifest_dir {
+        plan.write(&dir.join("manifest.json"), true);
+    }
+    Ok(plan.finish()?)
+}
+
+/// Converts `jobs` on `pool`. Jobs start in order and results come back in job order;
+/// a failing file doesn't stop the others unless `fail_fast`, which cancels the files
+/// in flight and leaves the rest unstarted.
+fn convert_all(
+    jobs: &[Job],
+    pool: &rayon::ThreadPool,
+    fail_fast: bool,
+    opts: &ConvertOptions,
+    state: &RunState,
+) -> Vec<Result<JobOutput>> {
+    let mut results: Vec<(usize, Result<JobOutput>)> = pool.install(|| {
+        jobs.iter()
+            .enumerate()
+            .par_bridge()
+            .map(|(i, job)| {
+                if state.cancelled() {
+                    return (
+                        i,
+                        Err(anyhow!("not started: an earlier file failed (--fail-fast)")),
+                    );
+                }
+                let result = run_job(job, opts, state).map_err(Error::from);
+                if fail_fast && result.is_err() {
+                    state.cancel();
+                }
+                (i, result)
+            })
+            .collect()
+    });
+    // `par_bridge` hands jobs out in order but collects them as they finish.
+    results.sort_unstable_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Prints one warning per input and reject reason, plus the run's id collisions.
+fn report_rejects(shards: &[ShardInfo]) {
+    let collisions: usize = shards.iter().map(|s| s.counts.id_collisions).sum();
+    if collisions > 0 {
+        warn!(
+            reason = "id_collision",
+            count = collisions,
+            "{collisions} example(s) share an id with an earlier example in this run"
+        );
+    }
+    let mut per_input: BTreeMap<(&Path, &str), usize> = BTreeMap::new();
+    for shard in shards {
+        for (reason, n) in &shard.counts.rejected {
+            *per_input
+                .entry((&shard.input, reason.as_str()))
+                .or_insert(0) += n;
+        }
+    }
+    for ((input, reason), n) in per_input {
+        warn!(input = %input.display(), reason, count = n, "{}: {} row(s) skipped ({})", input.display(), n, reason);
+    }
+}
+
+/// `--summary-json`: the run's totals as one object; callers add what only they know,
+/// such as the manifest path.
+fn run_summary(
+    files: Vec<serde_json::Value>,
+    shards: &[ShardInfo],
+    bytes_in: u64,
+    rejects: Option<(usize, PathBuf)>,
+    notes: Vec<String>,
+) -> serde_json::Value {
+    let sum = |f: fn(&ShardCounts) -> usize| shards.iter().map(|s| f(&s.counts)).sum::<usize>();
+    let mut rejected: BTreeMap<&str, usize> = BTreeMap::new();
+    for shard in shards {
+        for (reason, n) in &shard.counts.rejected {
+            *rejected.entry(reason.as_str()).or_insert(0) += n;
+        }
+    }
+    let bytes_out: u64 = shards.iter().map(|s| s.bytes).sum();
+    json!({
+        "files": files,
+        "shards": shards.iter().map(|s| json!({"path": s.path, "examples": s.counts.examples, "bytes": s.bytes, "sha256": s.sha256})).collect::<Vec<_>>(),
+        "lines_read": sum(|c| c.lines_read),
+        "examples": sum(|c| c.examples),
+        "lines_skipped": sum(|c| c.lines_skipped),
+        "rejected": rejected,
+        "id_collisions": sum(|c| c.id_collisions),
+        "disallowed_labels": sum(|c| c.disallowed_labels),
+        "bytes_in": bytes_in,
+        "bytes_out": bytes_out,
+        "ratio": ratio(bytes_in, bytes_out),
+        "reject_rate": reject_rate(budget_rejects(shards), shards.iter().map(|s| records_read(&s.counts)).sum()),
+        "rejects": rejects.map(|(n, path)| json!({"count": n, "path": path})),
+        "notes": notes,
+    })
+}
+
+/// The run-wide `--max-reject-rate` / `--max-rejects` check, once every input is done;
+/// each input was already checked on its own as it was converted.
+fn run_budget_error(budget: RejectBudget, shards: &[ShardInfo]) -> Option<EthicsError> {
+    let records = shards.iter().map(|s| records_read(&s.counts)).sum();
+    if !budget.exceeded(budget_rejects(shards), records, true) {
+        return None;
+    }
+    let mut rejected = BTreeMap::new();
+    for (reason, n) in shards.iter().flat_map(|s| &s.counts.rejected) {
+        *rejected.entry(reason.clone()).or_insert(0) += n;
+    }
+    Some(budget.error("run", &rejected, records, &BTreeMap::new()))
+}
+
+/// One-line line/example accounting across `shards`.
+fn line_summary(shards: &[ShardInfo]) -> String {
+    let sum = |f: fn(&ShardCounts) -> usize| shards.iter().map(|s| f(&s.counts)).sum::<usize>();
+    let mut line = format!(
+        "{} line(s) read, {} example(s) written, {} empty line(s) skipped, {} parse failure(s)",
+        sum(|c| c.lines_read),
+        sum(|c| c.examples),
+        sum(|c| c.lines_skipped),
+        sum(|c| c.rejected.get("parse_error").copied().unwrap_or(0)),
+    );
+    let empty = sum(|c| c.rejected.get("empty_text").copied().unwrap_or(0));
+    if empty > 0 {
+        line.push_str(&format!(", {empty} empty text(s) rejected"));
+    }
+    let normalized = sum(|c| c.normalized);
+    if normalized > 0 {
+        line.push_str(&format!(", {normalized} example(s) changed by --normalize"));
+    }
+    let cut = sum(|c| c.truncated);
+    if cut > 0 {
+        line.push_str(&format!(", {cut} text(s) truncated"));
+    }
+    let unsplit = sum(|c| c.unsplit);
+    if unsplit > 0 {
+        line.push_str(&format!(", {unsplit} example(s) without a trait separator"));
+    }
+    let offset = sum(|c| c.lines_offset);
+    if offset > 0 {
+        line.push_str(&format!(", {offset} line(s) passed over by --skip"));
+    }
+    line
+}
+
+/// `wrote 1000 of ~134000 example(s), truncated by --limit`, for inputs cut short.
+fn truncation_note(input: &Path, out: &JobOutput) -> Option<String> {
+    let estimate = out.truncated?;
+    let written: usize = out.shards.iter().map(|s| s.counts.examples).sum();
+    let of = match estimate {
+        Some(total) => format!(" of ~{total}"),
+        None => String::new(),
+    };
+    Some(format!(
+        "{}: wrote {written}{of} example(s), truncated by --limit",
+        input.display()
+    ))
+}
+
+/// Protobuf bytes before vs after zstd, i.e. the ratio the compression settings achieved.
+fn compression_summary(shards: &[ShardInfo]) -> String {
+    let raw: u64 = shards.iter().map(|s| s.counts.uncompressed_bytes).sum();
+    let packed: u64 = shards.iter().map(|s| s.bytes).sum();
+    format!(
+        "zstd: {raw} protobuf bytes -> {packed} ({:.2}x)",
+        ratio(raw, packed)
+    )
+}
+
+/// Label histogram over `shards`, e.g. `labels: 0 x5012, 1 x4988, 3 x2 (2 not allowed)`.
+fn label_summary(shards: &[ShardInfo]) -> String {
+    let mut line = format!(
+        "labels: {}",
+        label_histogram(shards)
+            .iter()
+            .map(|(l, n)| format!("{l} x{n}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let bad = disallowed_labels(shards);
+    if bad > 0 {
+        line.push_str(&format!(" ({bad} not allowed)"));
+    }
+    line
+}
+
+/// Labels outside `--allowed-labels`, whether kept or rejected.
+fn disallowed_labels(shards: &[ShardInfo]) -> usize {
+    shards
+        .iter()
+        .map(|s| {
+            s.counts.disallowed_labels
+                + s.counts
+                    .rejected
+                    .get("disallowed_label")
+                    .copied()
+                    .unwrap_or(0)
+        })
+        .sum()
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    args.log.init()?;
+
+    match &args.command {
+        Some(Command::Verify {
+            jsonl,
+            shard,
+            max_mismatches,
+            dict,
+            require_schema_version,
+            record,
+        }) => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            return verify(
+                jsonl,
+                shard,
+                *max_mismatches,
+                dict.as_ref(),
+                *require_schema_version,
+                record,
+            );
+        }
+        Some(Command::Migrate {
+            shard,
+            out,
+            zstd_level,
+            no_compress,
+            dict,
+            checksums,
+            overwrite,
+        }) => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            return migrate(
+                shard,
+                out,
+                (!no_compress).then_some(*zstd_level),
+                dict,
+                *checksums,
+                *overwrite,
+            );
+        }
+        Some(Command::TrainDict {
+            inputs,
+            out,
+            max_size,
+            max_samples,
+            record,
+        }) => {
+            return train_dict(inputs, out, *max_size, *max_samples, record);
+        }
+        Some(Command::Manifest {
+            action:
+                ManifestCommand::Create {
+                    shards,
+                    out,
+                    dict,
+                    overwrite,
+                },
+        }) => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            return manifest_create(shards, out.as_deref(), dict.as_ref(), *overwrite);
+        }
+        Some(Command::Manifest {
+            action: ManifestCommand::Verify { manifest, dict },
+        }) => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            return manifest_verify(manifest, dict.as_ref());
+        }
+        Some(Command::Export {
+            inputs,
+            format,
+            out_dir,
+            merge_into,
+            batch_size,
+            row_group_size,
+            zstd_level,
+            no_compress,
+            maps,
+            gzip,
+            examples_per_tar,
+            tar_key,
+            zstd_tars,
+            check,
+            dict,
+            overwrite,
+        }) => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            let opts = ExportOptions {
+                format: *format,
+                batch_rows: *batch_size as usize,
+                row_group_size: *row_group_size as usize,
+                zstd_level: (!no_compress).then_some(*zstd_level),
+                maps: *maps,
+                gzip: *gzip,
+                examples_per_tar: *examples_per_tar,
+                tar_key: *tar_key,
+                zstd_tars: *zstd_tars,
+                overwrite: *overwrite,
+            };
+            return export(
+                inputs,
+                out_dir,
+                merge_into.as_deref(),
+                &opts,
+                *check,
+                dict.as_ref(),
+            );
+        }
+        Some(Command::Import {
+            input,
+            from,
+            out,
+            column,
+            meta_columns,
+            subset,
+            split,
+            allowed_labels,
+            reject_bad_labels,
+            allow_empty_text,
+            batch_size,
+            zstd_level,
+            no_compress,
+            dict,
+            checksums,
+            overwrite,
+        }) => {
+            let opts = ImportOptions {
+                format: *from,
+                columns: column.iter().cloned().collect(),
+                meta_columns: meta_columns.clone(),
+                subset: subset.clone(),
+                split: split.clone(),
+                allowed_labels: allowed_labels
+                    .as_ref()
+                    .map(|labels| labels.iter().copied().collect()),
+                reject_bad_labels: *reject_bad_labels,
+                allow_empty_text: *allow_empty_text,
+                batch_rows: *batch_size as usize,
+                zstd_level: (!no_compress).then_some(*zstd_level),
+                dict: dict.as_deref().map(Dictionary::load).transpose()?,
+                checksums: *checksums,
+                overwrite: *overwrite,
+            };
+            return import_file(input, out, &opts);
+        }
+        Some(Command::Schema { out, overwrite }) => {
+            let Some(out) = out else {
+                print!("{PROTO_SOURCE}");
+                return Ok(());
+            };
+            ensure!(
+                *overwrite || !out.exists(),
+                "{} already exists (pass --overwrite to replace it)",
+                out.display()
+            );
+            fs::write(out, FILE_DESCRIPTOR_SET)
+                .with_context(|| format!("failed to write {}", out.display()))?;
+            println!(
+                "{} ({} bytes, sha256 {})",
+                out.display(),
+                FILE_DESCRIPTOR_SET.len(),
+                shard::descriptor_sha256()
+            );
+            return Ok(());
+        }
+        #[cfg(feature = "http")]
+        Some(Command::Fetch {
+            subsets,
+            splits,
+            out_dir,
+            repo,
+            revision,
+            url_template,
+            checksums,
+            require_checksums,
+            force,
+        }) => {
+            let mut table = builtin_checksums();
+            if let Some(path) = checksums {
+                table.extend(load_checksums(path)?);
+            }
+            let opts = FetchOptions {
+                url_template: url_template
+                    .clone()
+                    .unwrap_or_else(|| hub_template(repo, revision)),
+                out_dir: out_dir.clone(),
+                splits: splits.clone(),
+                checksums: table,
+                require_checksums: *require_checksums,
+                force: *force,
+            };
+            let subsets = if subsets.is_empty() {
+                SUBSETS.map(String::from).to_vec()
+            } else {
+                subsets.clone()
+            };
+            return fetch_subsets(&subsets, &opts);
+        }
+        None => {}
+    }
+
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    // One timestamp for the whole run; the rest is filled in per job.
+    let header = (!args.no_header).then(|| ShardHeader {
+        created_unix: shard::created_unix(args.header_timestamp),
+        ..shard::header(args.record.mode.message(), "", "")
+    });
+    let opts = ConvertOptions {
+        zstd_level: (!args.no_compress).then_some(args.zstd_level),
+        zstd_workers: args.zstd_workers,
+        zstd_long: args.zstd_long,
+        dict,
+        frame_every: args.frame_every,
+        checksums: args.checksums,
+        header,
+        header_timestamp: args.header_timestamp,
+        max_examples_per_shard: args.max_examples_per_shard,
+        max_shard_bytes: args.max_shard_bytes,
+        shard_template: args.shard_template.clone(),
+        max_errors: args.max_errors,
+        reject_budget: RejectBudget {
+            max_rate: args.max_reject_rate,
+            max_rejects: args.max_rejects,
+        },
+        strict: args.strict,
+        overwrite: args.overwrite,
+        resume: args.resume,
+        quiet: args.quiet,
+        dedup: args.dedup.then_some(DedupConfig {
+            case_insensitive: args.dedup_case_insensitive,
+            hash_only: args.dedup_hash_only,
+            rejects: args.dedup_rejects,
+        }),
+        parse_threads: args.parse_threads.get(),
+        mmap: args.mmap,
+        skip: args.skip,
+        limit: args.limit,
+        expect_schema: args.expect_schema.clone(),
+        schema_sample: args.schema_sample,
+        allowed_labels: args
+            .allowed_labels
+            .clone()
+            .or_else(|| args.expect_schema.as_ref()?.labels.clone())
+            .map(BTreeSet::from_iter),
+        reject_bad_labels: args.reject_bad_labels,
+        record: args.record.clone(),
+    };
+    ensure!(
+        opts.allowed_labels.is_some() || !(args.reject_bad_labels || args.fail_on_bad_label),
+        "--reject-bad-labels and --fail-on-bad-label need --allowed-labels (or an --expect-schema preset with fixed labels)"
+    );
+    let state = RunState::new(args.rejects_out.clone(), opts.dedup);
+
+    let Some(pattern) = args.glob.as_deref() else {
+        // Single-file mode; clap guarantees the other flags are present.
+        let job = Job {
+            input: args.input.clone().unwrap(),
+            subset: args.subset.clone().unwrap(),
+            split: args.split.clone().unwrap(),
+            out: args.out.clone().unwrap(),
+        };
+        // With `--out -` stdout carries the shard, so everything else goes to stderr.
+        let to_stdout = is_stdio(&job.out);
+        ensure!(
+            !(to_stdout && args.summary_json),
+            "--summary-json prints to stdout, which --out - is using for the shard"
+        );
+        ensure!(
+            !(to_stdout && opts.rotates()),
+            "--out - writes a single stream and cannot be combined with shard rotation"
+        );
+        ensure!(
+            !(to_stdout && opts.frame_every.is_some()),
+            "--frame-every writes an index sidecar and needs a file for --out"
+        );
+        ensure!(
+            !(opts.resume && (to_stdout || is_stdio(&job.input))),
+            "--resume needs files for --input and --out"
+        );
+        let to_bucket = is_object_url(&job.out);
+        ensure!(
+            !(to_bucket && opts.resume),
+            "--resume needs a local file for --out, not an object storage URL"
+        );
+        ensure!(
+            !(to_bucket && opts.frame_every.is_some()),
+            "--frame-every writes an index sidecar and needs a local file for --out"
+        );
+        // Readers pick the message type from the name, so it has to match what is written.
+        if !to_stdout {
+            match (opts.record.mode, is_pairs_shard(&opts.shard_path(&job, 0))) {
+                (Mode::Pair, false) => bail!("--mode pair writes PairExample shards; name --out *.pairs.pb.zst so readers can tell"),
+                (Mode::Example, true) => bail!("*.pairs.pb.zst names are reserved for --mode pair shards"),
+                _ => {}
+            }
+        }
+        if args.dry_run {
+            let manifest_dir =
+                (!to_stdout && !to_bucket).then(|| job.out.parent().unwrap_or(Path::new("")));
+            return plan_jobs(
+                std::slice::from_ref(&job),
+                &[],
+                &opts,
+                args.rejects_out.as_deref(),
+                manifest_dir,
+            );
+        }
+        let output = run_job(&job, &opts, &state)?;
+        let note = truncation_note(&job.input, &output);
+        let JobOutput {
+            shards, bytes_in, ..
+        } = output;
+        let bytes_out: u64 = shards.iter().map(|s| s.bytes).sum();
+        if args.summary_json {
+            report_rejects(&shards);
+            let file = json!({"input": job.input, "subset": job.subset, "split": job.split, "status": "converted", "examples": shards.iter().map(|s| s.counts.examples).sum::<usize>(), "shards": shards.len(), "bytes_in": bytes_in, "bytes_out": bytes_out});
+            let mut summary = run_summary(
+                vec![file],
+                &shards,
+                bytes_in,
+                state.finish_rejects()?,
+                note.into_iter().collect(),
+            );
+            let bad_labels = disallowed_labels(&shards);
+            if !to_stdout && !to_bucket {
+                summary["manifest"] = json!(write_manifest(
+                    job.out.parent().unwrap_or(Path::new("")),
+                    &opts,
+                    shards
+                )?);
+            }
+            println!("{summary}");
+            ensure!(
+                !args.fail_on_bad_label || bad_labels == 0,
+                "{bad_labels} label(s) outside --allowed-labels"
+            );
+            return Ok(());
+        }
+        for shard in &shards {
+            say!(
+                to_stdout,
+                "{}: wrote {} example(s) -> {}",
+                job.input.display(),
+                shard.counts.examples,
+                shard.path.display()
+            );
+        }
+        if let Some(note) = note {
+            say!(to_stdout, "{note}");
+        }
+        say!(to_stdout, "{}", line_summary(&shards));
+        say!(
+            to_stdout,
+            "{} bytes in, {} bytes out ({:.2}x)",
+            bytes_in,
+            bytes_out,
+            ratio(bytes_in, bytes_out)
+        );
+        if opts.zstd_level.is_some() {
+            say!(to_stdout, "{}", compression_summary(&shards));
+        }
+        if opts.allowed_labels.is_some() {
+            say!(to_stdout, "{}", label_summary(&shards));
+        }
+        report_rejects(&shards);
+        if let Some((n, path)) = state.finish_rejects()? {
+            say!(to_stdout, "{n} rejected line(s) -> {}", path.display());
+        }
+        let bad_labels = disallowed_labels(&shards);
+        if !to_stdout && !to_bucket {
+            let manifest =
+                write_manifest(job.out.parent().unwrap_or(Path::new("")), &opts, shards)?;
+            println!("manifest -> {}", manifest.display());
+        }
+        ensure!(
+            !args.fail_on_bad_label || bad_labels == 0,
+            "{bad_labels} label(s) outside --allowed-labels"
+        );
+        return Ok(());
+    };
+
+    ensure!(!is_object_url(&args.out_dir), "--out-dir must be a local directory; upload to object storage one file at a time with --out");
+    let (jobs, skipped) = batch_jobs(&args, pattern, &opts)?;
+    ensure!(
+        !jobs.is_empty() || !skipped.is_empty(),
+        "no files matched pattern: {pattern}"
+    );
+    if args.dry_run {
+        return plan_jobs(
+            &jobs,
+            &skipped,
+            &opts,
+            args.rejects_out.as_deref(),
+            Some(&args.out_dir),
+        );
+    }
+
+    // Which file keeps a duplicate depends on processing order, so dedup runs stay sequential.
+    let workers = match args.jobs {
+        Some(n) => {
+            ensure!(
+                opts.dedup.is_none() || n.get() == 1,
+                "--dedup compares across files and needs --jobs 1"
+            );
+            n.get()
+        }
+        None if opts.dedup.is_some() => 1,
+        None => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .context("failed to start the conversion threads")?;
+    let results = convert_all(&jobs, &pool, args.fail_fast, &opts, &state);
+
+    let mut rows = Vec::new();
+    let mut files = Vec::new();
+    let mut all_shards = Vec::new();
+    let mut notes = Vec::new();
+    let mut failed = 0;
+    let (mut total_in, mut total_out, mut total) = (0, 0, 0);
+    for (job, result) in jobs.iter().zip(results) {
+        let output = match result {
+            Result::Ok(output) => output,
+            Err(e) => {
+                error!(input = %job.input.display(), "{}: {e:#}", job.input.display());
+                rows.push([
+                    job.input.display().to_string(),
+                    job.subset.clone(),
+                    job.split.clone(),
+                    "failed".into(),
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                ]);
+                files.push(json!({"input": job.input, "subset": job.subset, "split": job.split, "status": "failed", "error": format!("{e:#}")}));
+                failed += 1;
+                continue;
+            }
+        };
+        notes.extend(truncation_note(&job.input, &output));
+        let JobOutput {
+            shards, bytes_in, ..
+        } = output;
+        let examples: usize = shards.iter().map(|s| s.counts.examples).sum();
+        let bytes_out: u64 = shards.iter().map(|s| s.bytes).sum();
+        total_in += bytes_in;
+        total_out += bytes_out;
+        total += examples;
+        rows.push([
+            job.input.display().to_string(),
+            job.subset.clone(),
+            job.split.clone(),
+            examples.to_string(),
+            shards.len().to_string(),
+            bytes_in.to_string(),
+            bytes_out.to_string(),
+        ]);
+        files.push(json!({"input": job.input, "subset": job.subset, "split": job.split, "status": "converted", "examples": examples, "shards": shards.len(), "bytes_in": bytes_in, "bytes_out": bytes_out}));
+        all_shards.extend(shards);
+    }
+    for path in &skipped {
+        rows.push([
+            path.display().to_string(),
+            "?".into(),
+            "?".into(),
+            "skipped".into(),
+            "-".into(),
+            "-".into(),
+            "-".into(),
+        ]);
+        files.push(json!({"input": path, "status": "skipped"}));
+    }
+
+    if args.summary_json {
+        report_rejects(&all_shards);
+        let mut summary = run_summary(files, &all_shards, total_in, state.finish_rejects()?, notes);
+        summary["converted"] = json!(jobs.len() - failed);
+        summary["skipped"] = json!(skipped.len());
+        summary["failed"] = json!(failed);
+        let bad_labels = disallowed_labels(&all_shards);
+        let over_budget = run_budget_error(opts.reject_budget, &all_shards);
+        if !all_shards.is_empty() {
+            summary["manifest"] = json!(write_manifest(&args.out_dir, &opts, all_shards)?);
+        }
+        println!("{summary}");
+        ensure!(failed == 0, "{failed} file(s) failed to convert");
+        if let Some(e) = over_budget {
+            return Err(e.into());
+        }
+        ensure!(
+            !args.fail_on_bad_label || bad_labels == 0,
+            "{bad_labels} label(s) outside --allowed-labels"
+        );
+        return Ok(());
+    }
+
+    let w = rows.iter().map(|r| r[0].len()).max().unwrap_or(0).max(5);
+    println!(
+        "{:<w$}  {:<15}  {:<10}  {:>8}  {:>6}  {:>12}  {:>12}",
+        "input", "subset", "split", "examples", "shards", "bytes in", "bytes out"
+    );
+    for [input, subset, split, n, shards, bytes_in, bytes_out] in &rows {
+        println!("{input:<w$}  {subset:<15}  {split:<10}  {n:>8}  {shards:>6}  {bytes_in:>12}  {bytes_out:>12}");
+    }
+    println!(
+        "{} file(s) converted, {} skipped, {} failed, {} example(s) total",
+        jobs.len() - failed,
+        skipped.len(),
+        failed,
+        total
+    );
+    let sizes = size_totals(&all_shards);
+    if !sizes.is_empty() {
+        let w = sizes.keys().map(String::len).max().unwrap_or(0).max(12);
+        println!();
+        println!(
+            "{:<w$}  {:>8}  {:>14}  {:>12}  {:>7}",
+            "subset/split", "examples", "protobuf bytes", "bytes out", "ratio"
+        );
+        for (key, t) in &sizes {
+            println!(
+                "{key:<w$}  {:>8}  {:>14}  {:>12}  {:>6.2}x",
+                t.examples, t.uncompressed_bytes, t.compressed_bytes, t.ratio
+            );
+        }
+        println!();
+    }
+    for note in &notes {
+        println!("{note}");
+    }
+    println!("{}", line_summary(&all_shards));
+    println!(
+        "{} bytes in, {} bytes out ({:.2}x)",
+        total_in,
+        total_out,
+        ratio(total_in, total_out)
+    );
+    if opts.zstd_level.is_some() {
+        println!("{}", compression_summary(&all_shards));
+    }
+    if opts.allowed_labels.is_some() {
+        println!("{}", label_summary(&all_shards));
+    }
+    report_rejects(&all_shards);
+    if let Some((n, path)) = state.finish_rejects()? {
+        println!("{n} rejected line(s) -> {}", path.display());
+    }
+    let bad_labels = disallowed_labels(&all_shards);
+    let over_budget = run_budget_error(opts.reject_budget, &all_shards);
+    if !all_shards.is_empty() {
+        let manifest = write_manifest(&args.out_dir, &opts, all_shards)?;
+        println!("manifest -> {}", manifest.display());
+    }
+    ensure!(failed == 0, "{failed} file(s) failed to convert");
+    if let Some(e) = over_budget {
+        return Err(e.into());
+    }
+    ensure!(
+        !args.fail_on_bad_label || bad_labels == 0,
+        "{bad_labels} label(s) outside --allowed-labels"
+    );
     Ok(())
 }
@@ -0,0 +1,317 @@
+//! `manifest.json` and the per-shard counts it is built from.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::convert::{
+    counts_against_budget, records_read, reject_rate, ConvertOptions, DedupConfig, RecordOptions,
+    RejectBudget,
+};
+use crate::error::{Context, Result};
+use crate::ethics::ShardHeader;
+use crate::shard;
+
+/// One shard written by a conversion.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardInfo {
+    pub path: PathBuf,
+    pub input: PathBuf,
+    pub subset: String,
+    pub split: String,
+    #[serde(flatten)]
+    pub counts: ShardCounts,
+    /// Bytes on disk.
+    pub bytes: u64,
+    /// Hex SHA-256 of the file on disk.
+    pub sha256: String,
+}
+
+/// Running totals for the shard currently open.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ShardCounts {
+    /// Input lines consumed while this shard was open, blank ones included.
+    pub lines_read: usize,
+    pub examples: usize,
+    /// Blank input lines passed over while this shard was open.
+    pub lines_skipped: usize,
+    /// Non-empty lines ignored because of `--skip`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub lines_offset: usize,
+    /// Rows dropped, keyed by reason (`parse_error`, `bad_label`, `missing_label`, `empty_text`, `missing_sep`, `bad_score`, `disallowed_label`, `line_too_long`, `duplicate`).
+    pub rejected: BTreeMap<String, usize>,
+    /// Examples whose id was already seen earlier in the run.
+    pub id_collisions: usize,
+    /// Examples kept whole because `--virtue-split-sep` found no marker.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub unsplit: usize,
+    /// Examples `--normalize` changed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub normalized: usize,
+    /// Examples whose text `--truncate-chars`/`--truncate-bytes` cut.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub truncated: usize,
+    /// Examples written per label value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<i32, usize>,
+    /// Examples written with a label outside `--allowed-labels`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disallowed_labels: usize,
+    /// Length-delimited protobuf bytes before compression, `--checksums` included.
+    pub uncompressed_bytes: u64,
+}
+
+impl ShardCounts {
+    pub fn reject(&mut self, reason: &str) {
+        *self.rejected.entry(reason.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn u32_is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// The `--dict` a run compressed with; readers need the same dictionary.
+#[derive(Serialize, Debug)]
+struct DictInfo {
+    path: PathBuf,
+    /// zstd dictionary id, also stored in each frame header.
+    id: Option<u32>,
+    sha256: String,
+}
+
+/// `manifest.json` written next to the output of every run.
+#[derive(Serialize, Debug)]
+struct Manifest {
+    /// Message type of every record in the shards.
+    message: &'static str,
+    /// `None` for `--no-compress` runs.
+    zstd_level: Option<i32>,
+    #[serde(skip_serializing_if = "u32_is_zero")]
+    zstd_workers: u32,
+    /// Long-distance matching window log, if enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zstd_long: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dict: Option<DictInfo>,
+    /// Examples per zstd frame; each shard then has a `.idx` sidecar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_every: Option<u64>,
+    /// Records are followed by a CRC32, as announced in each shard's header.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    checksums: bool,
+    /// What every shard header says besides subset and split; absent with `--no-header`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<HeaderInfo>,
+    /// Meta entries are encoded in key order, so the same input and options produce
+    /// byte-identical shards (and `sha256`s) at a given zstd level. `false` only when
+    /// headers carry the wall-clock time: `--header-timestamp` without `SOURCE_DATE_EPOCH`.
+    deterministic: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<DedupConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reject_budget: Option<RejectBudgetInfo>,
+    /// `--skip` / `--limit`, when the run converted only part of each input.
+    #[serde(skip_serializing_if = "is_zero")]
+    skip: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+    meta: MetaConfig,
+    total_examples: usize,
+    /// Totals per `subset/split`.
+    sizes: BTreeMap<String, SizeTotals>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_labels: Option<BTreeSet<i32>>,
+    /// Examples written per label, across all shards.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<i32, usize>,
+    shards: Vec<ShardInfo>,
+}
+
+/// `--max-reject-rate` / `--max-rejects` and what the run came to against them.
+#[derive(Serialize, Debug)]
+struct RejectBudgetInfo {
+    #[serde(flatten)]
+    limits: RejectBudget,
+    /// Non-blank records read across all shards.
+    records: usize,
+    /// Rejects that count against the budget (all but `duplicate`).
+    rejected: usize,
+    rate: f64,
+}
+
+impl RejectBudgetInfo {
+    fn new(limits: RejectBudget, shards: &[ShardInfo]) -> Self {
+        let records = shards.iter().map(|s| records_read(&s.counts)).sum();
+        let rejected = budget_rejects(shards);
+        RejectBudgetInfo {
+            limits,
+            records,
+            rejected,
+            rate: reject_rate(rejected, records),
+        }
+    }
+}
+
+/// Rejects across `shards` that count against the reject budget.
+pub fn budget_rejects(shards: &[ShardInfo]) -> usize {
+    shards
+        .iter()
+        .flat_map(|s| &s.counts.rejected)
+        .filter(|(reason, _)| counts_against_budget(reason))
+        .map(|(_, n)| n)
+        .sum()
+}
+
+/// The run-wide fields of the `ShardHeader` written to each shard.
+#[derive(Serialize, Debug)]
+struct HeaderInfo {
+    format_version: u32,
+    schema_version: u32,
+    created_unix: i64,
+    converter_version: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    converter_git: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    descriptor_sha256: String,
+}
+
+impl HeaderInfo {
+    fn new(header: &ShardHeader) -> Self {
+        HeaderInfo {
+            format_version: header.format_version,
+            schema_version: header.schema_version,
+            created_unix: header.created_unix,
+            converter_version: header.converter_version.clone(),
+            converter_git: header.converter_git.clone(),
+            descriptor_sha256: header.descriptor_sha256.clone(),
+        }
+    }
+}
+
+/// Examples and bytes written for one `subset/split`.
+#[derive(Serialize, Debug, Default)]
+pub struct SizeTotals {
+    pub examples: usize,
+    /// Length-delimited protobuf bytes fed to the encoder.
+    pub uncompressed_bytes: u64,
+    /// Shard bytes on disk.
+    pub compressed_bytes: u64,
+    /// `uncompressed_bytes / compressed_bytes`.
+    pub ratio: f64,
+}
+
+/// Sums `shards` per `subset/split`.
+pub fn size_totals(shards: &[ShardInfo]) -> BTreeMap<String, SizeTotals> {
+    let mut totals: BTreeMap<String, SizeTotals> = BTreeMap::new();
+    for shard in shards {
+        let t = totals
+            .entry(format!("{}/{}", shard.subset, shard.split))
+            .or_default();
+        t.examples += shard.counts.examples;
+        t.uncompressed_bytes += shard.counts.uncompressed_bytes;
+        t.compressed_bytes += shard.bytes;
+    }
+    for t in totals.values_mut() {
+        t.ratio = ratio(t.uncompressed_bytes, t.compressed_bytes);
+    }
+    totals
+}
+
+/// The meta selection a run used, recorded for reproducibility.
+#[derive(Serialize, Debug)]
+struct MetaConfig {
+    /// `None` in `--meta-all` mode.
+    keys: Option<Vec<String>>,
+    all: bool,
+    exclude: Vec<String>,
+    legacy: bool,
+    flatten: bool,
+    max_depth: usize,
+    separator: String,
+    flatten_arrays: bool,
+}
+
+impl MetaConfig {
+    fn from_opts(opts: &RecordOptions) -> Self {
+        MetaConfig {
+            keys: (!opts.meta_all).then(|| opts.meta_keys.clone()),
+            all: opts.meta_all,
+            exclude: opts.meta_exclude.clone(),
+            legacy: opts.legacy_meta,
+            flatten: opts.flatten_meta,
+            max_depth: opts.max_depth,
+            separator: opts.meta_separator.clone(),
+            flatten_arrays: opts.flatten_arrays,
+        }
+    }
+}
+
+/// Writes `dir/manifest.json`; shard paths are stored relative to `dir`.
+pub fn write_manifest(
+    dir: &Path,
+    opts: &ConvertOptions,
+    mut shards: Vec<ShardInfo>,
+) -> Result<PathBuf> {
+    let path = dir.join("manifest.json");
+    for shard in &mut shards {
+        if let Ok(rel) = shard.path.strip_prefix(dir) {
+            shard.path = rel.to_path_buf();
+        }
+    }
+    let manifest = Manifest {
+        message: opts.record.mode.message(),
+        zstd_level: opts.zstd_level,
+        zstd_workers: opts.zstd_workers,
+        zstd_long: opts.zstd_long,
+        dict: opts.dict.as_ref().map(|d| DictInfo {
+            path: d.path.clone(),
+            id: d.id,
+            sha256: d.sha256.clone(),
+        }),
+        frame_every: opts.frame_every,
+        checksums: opts.checksums,
+        header: opts.header.as_ref().map(HeaderInfo::new),
+        deterministic: opts.header.is_none() || !shard::wall_clock(opts.header_timestamp),
+        dedup: opts.dedup,
+        reject_budget: opts
+            .reject_budget
+            .is_set()
+            .then(|| RejectBudgetInfo::new(opts.reject_budget, &shards)),
+        skip: opts.skip,
+        limit: opts.limit,
+        meta: MetaConfig::from_opts(&opts.record),
+        total_examples: shards.iter().map(|s| s.counts.examples).sum(),
+        sizes: size_totals(&shards),
+        allowed_labels: opts.allowed_labels.clone(),
+        labels: label_histogram(&shards),
+        shards,
+    };
+    fs::write(&path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+pub fn label_histogram(shards: &[ShardInfo]) -> BTreeMap<i32, usize> {
+    let mut labels = BTreeMap::new();
+    for (label, n) in shards.iter().flat_map(|s| &s.counts.labels) {
+        *labels.entry(*label).or_insert(0) += n;
+    }
+    labels
+}
+
+/// Input/output size ratio for the summary line.
+pub fn ratio(bytes_in: u64, bytes_out: u64) -> f64 {
+    if bytes_out == 0 {
+        0.0
+    } else {
+        bytes_in as f64 / bytes_out as f64
+    }
+}
@@ -0,0 +1,163 @@
+//! `--mmap`: reading a plain JSONL file through a memory map instead of a
+//! `BufReader`. Lines are found with `memchr` straight in the mapped pages, so the
+//! bytes are never copied through a read buffer; each line is checked as UTF-8 and
+//! copied out once, as the streaming reader does. Records come out exactly as from
+//! [`crate::input::records`] (same BOM, `\r\n` and `--max-line-bytes` handling, same
+//! line numbers), so counts, rejects and shards don't depend on the flag.
+//!
+//! Only plain files can be mapped: stdin, object URLs, compressed files, CSV/TSV and
+//! JSON arrays go through the streaming path instead, as do empty files.
+//!
+//! # Safety
+//!
+//! A map shows the file as it is on disk *now*, not as it was when it was opened. If
+//! another process writes to the file while it is being converted, the converter can
+//! see a mix of old and new contents; if the file is truncated, touching the pages
+//! past the new end kills the process with `SIGBUS` instead of returning an error.
+//! Only pass `--mmap` for inputs nothing else writes to during the run, such as the
+//! raw downloads under `data/raw`; files still being appended to, or on network file
+//! systems that other hosts write to, should be read with the default streaming path.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memchr::memchr;
+use memmap2::Mmap;
+
+use crate::error::{Context, Result};
+use crate::input::{
+    is_object_url, is_stdio, InputFormat, LineTooLong, Position, RecordIter, GZIP_MAGIC, UTF8_BOM,
+    ZSTD_MAGIC,
+};
+
+/// A mapped JSONL file, positioned past its BOM (and, for `auto`, leading whitespace).
+pub struct MappedJsonl {
+    map: Mmap,
+    start: usize,
+}
+
+/// Maps `path` when it is a plain, non-empty JSONL file; `None` means use the
+/// streaming reader. `format` is the input's format after [`InputFormat::for_path`].
+pub fn map_jsonl(path: &Path, format: InputFormat) -> Result<Option<MappedJsonl>> {
+    if is_stdio(path) || is_object_url(path) {
+        return Ok(None);
+    }
+    if !matches!(format, InputFormat::Auto | InputFormat::Jsonl) {
+        return Ok(None);
+    }
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the map is only read, and is valid for as long as the file isn't
+    // changed underneath it; see the module docs for what `--mmap` asks of its inputs.
+    let map =
+        unsafe { Mmap::map(&file) }.with_context(|| format!("failed to map {}", path.display()))?;
+    if map.starts_with(&GZIP_MAGIC) || map.starts_with(&ZSTD_MAGIC) {
+        return Ok(None);
+    }
+    let mut start = if map.starts_with(UTF8_BOM) {
+        UTF8_BOM.len()
+    } else {
+        0
+    };
+    if format == InputFormat::Auto {
+        start += map[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        if map.get(start) == Some(&b'[') {
+            return Ok(None);
+        }
+    }
+    Ok(Some(MappedJsonl { map, start }))
+}
+
+impl MappedJsonl {
+    /// Length of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The records of the file, calling `on_read` with the bytes each one took up
+    /// (all of them add up to [`MappedJsonl::len`]).
+    pub fn records(
+        self,
+        max_line_bytes: usize,
+        mut on_read: impl FnMut(u64) + Send + 'static,
+    ) -> RecordIter {
+        on_read(self.start as u64);
+        let mut lines = MappedLines {
+            pos: self.start,
+            map: self.map,
+            max_bytes: max_line_bytes,
+            line: 0,
+        };
+        Box::new(std::iter::from_fn(move || {
+            let (read, line) = lines.next_line()?;
+            on_read(read as u64);
+            Some(line.map(|(n, text)| (Position::Line(n), text)))
+        }))
+    }
+}
+
+/// [`crate::input::lines`] over a map: the same line numbers, trimming and limits.
+struct MappedLines {
+    map: Mmap,
+    pos: usize,
+    max_bytes: usize,
+    line: usize,
+}
+
+impl MappedLines {
+    /// The next line and the bytes it took up, terminator included.
+    fn next_line(&mut self) -> Option<(usize, io::Result<(usize, String)>)> {
+        let rest = &self.map[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        let (raw, ended) = match memchr(b'\n', rest) {
+            Some(i) => (&rest[..=i], true),
+            None => (rest, false),
+        };
+        self.pos += raw.len();
+        self.line += 1;
+        // Lines past the streaming reader's buffer are only counted there; mirror
+        // that, so the length reported for an overlong line is the same.
+        let bom = if self.line == 1 { UTF8_BOM.len() } else { 0 };
+        let complete = raw.len() <= self.max_bytes.saturating_add(2 + bom);
+        let mut text = raw;
+        let mut bytes = raw.len();
+        if self.line == 1 && text.starts_with(UTF8_BOM) {
+            text = &text[UTF8_BOM.len()..];
+            bytes -= UTF8_BOM.len();
+        }
+        if ended {
+            text = &text[..text.len() - 1];
+            bytes -= 1;
+        }
+        if complete && text.last() == Some(&b'\r') {
+            text = &text[..text.len() - 1];
+            bytes -= 1;
+        }
+        if !complete || text.len() > self.max_bytes {
+            let too_long = LineTooLong {
+                line: self.line,
+                bytes,
+                max: self.max_bytes,
+            };
+            let err = io::Error::new(io::ErrorKind::InvalidData, too_long);
+            return Some((raw.len(), Err(err)));
+        }
+        let line = self.line;
+        let text = std::str::from_utf8(text)
+            .map(|text| (line, text.to_owned()))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        Some((raw.len(), text))
+    }
+}
@@ -0,0 +1,248 @@
+//! The canonical proto3 JSON mapping of `Example` and `PairExample`, for
+//! `pb_to_jsonl --format proto-json` and `import --from proto-json`.
+//!
+//! Fields are named in lowerCamelCase (`labelsByName`, `textA`), maps are objects
+//! with string keys, enums are their value names (`PREFERENCE_A`), and non-finite
+//! doubles are the strings `"NaN"`, `"Infinity"` and `"-Infinity"`. Fields at
+//! their default (`""`, `0`, empty maps, the zero enum value) are omitted unless
+//! `emit_defaults` is set; `optional` fields are written exactly when they are set,
+//! defaults included. Parsing an `Example` accepts both lowerCamelCase and the
+//! proto field names, `null` for a default, and integers as strings or integral
+//! numbers, as the spec requires.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::error::{bail, EthicsError, Result};
+use crate::ethics::{Example, PairExample};
+
+/// An object being built, dropping default values unless asked to keep them.
+struct Object {
+    map: Map<String, Value>,
+    emit_defaults: bool,
+}
+
+impl Object {
+    fn new(emit_defaults: bool) -> Self {
+        Object {
+            map: Map::new(),
+            emit_defaults,
+        }
+    }
+
+    /// A field without presence: written unless it is the default.
+    fn field(&mut self, name: &str, value: Value, is_default: bool) {
+        if self.emit_defaults || !is_default {
+            self.map.insert(name.to_string(), value);
+        }
+    }
+
+    /// An `optional` field: written exactly when set.
+    fn optional(&mut self, name: &str, value: Option<Value>) {
+        if let Some(value) = value {
+            self.map.insert(name.to_string(), value);
+        }
+    }
+}
+
+fn double(value: f64) -> Value {
+    if value.is_nan() {
+        json!("NaN")
+    } else if value.is_infinite() {
+        json!(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        json!(value)
+    }
+}
+
+pub fn example_to_json(ex: &Example, emit_defaults: bool) -> Value {
+    let mut obj = Object::new(emit_defaults);
+    obj.field("subset", json!(ex.subset), ex.subset.is_empty());
+    obj.field("split", json!(ex.split), ex.split.is_empty());
+    obj.field("text", json!(ex.text), ex.text.is_empty());
+    obj.field("label", json!(ex.label), ex.label == 0);
+    obj.field("meta", json!(ex.meta), ex.meta.is_empty());
+    obj.field("id", json!(ex.id), ex.id.is_empty());
+    obj.optional("context", ex.context.as_ref().map(|c| json!(c)));
+    obj.optional("score", ex.score.map(double));
+    obj.field(
+        "labelsByName",
+        json!(ex.labels_by_name),
+        ex.labels_by_name.is_empty(),
+    );
+    Value::Object(obj.map)
+}
+
+pub fn pair_to_json(pair: &PairExample, emit_defaults: bool) -> Value {
+    let mut obj = Object::new(emit_defaults);
+    obj.field("subset", json!(pair.subset), pair.subset.is_empty());
+    obj.field("split", json!(pair.split), pair.split.is_empty());
+    obj.field("textA", json!(pair.text_a), pair.text_a.is_empty());
+    obj.field("textB", json!(pair.text_b), pair.text_b.is_empty());
+    obj.field(
+        "preferred",
+        json!(pair.preferred().as_str_name()),
+        pair.preferred == 0,
+    );
+    obj.field("meta", json!(pair.meta), pair.meta.is_empty());
+    obj.field("id", json!(pair.id), pair.id.is_empty());
+    Value::Object(obj.map)
+}
+
+fn mismatch(field: &str, expected: &str, value: &Value) -> EthicsError {
+    EthicsError::SchemaMismatch(format!("field `{field}`: expected {expected}, got {value}"))
+}
+
+fn string(field: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        _ => Err(mismatch(field, "a string", value)),
+    }
+}
+
+/// An int32 as a number without a fractional part, or a string holding one.
+fn int32(field: &str, value: &Value) -> Result<i32> {
+    let n = match value {
+        Value::Number(n) => n
+            .as_i64()
+            .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64)),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    n.and_then(|n| i32::try_from(n).ok())
+        .ok_or_else(|| mismatch(field, "an int32", value))
+}
+
+fn float64(field: &str, value: &Value) -> Result<f64> {
+    match value {
+        Value::Number(n) => n.as_f64().ok_or_else(|| mismatch(field, "a double", value)),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            s => s.parse().map_err(|_| mismatch(field, "a double", value)),
+        },
+        _ => Err(mismatch(field, "a double", value)),
+    }
+}
+
+fn map<V>(
+    field: &str,
+    value: &Value,
+    entry: impl Fn(&str, &Value) -> Result<V>,
+) -> Result<BTreeMap<String, V>> {
+    let Value::Object(object) = value else {
+        return Err(mismatch(field, "an object", value));
+    };
+    object
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), entry(field, v)?)))
+        .collect()
+}
+
+/// Parses one canonical-JSON `Example`; unknown fields are an error.
+pub fn example_from_json(value: &Value) -> Result<Example> {
+    let Value::Object(object) = value else {
+        return Err(mismatch("Example", "an object", value));
+    };
+    let mut ex = Example::default();
+    for (name, value) in object {
+        if value.is_null() {
+            continue;
+        }
+        match name.as_str() {
+            "subset" => ex.subset = string(name, value)?,
+            "split" => ex.split = string(name, value)?,
+            "text" => ex.text = string(name, value)?,
+            "label" => ex.label = int32(name, value)?,
+            "meta" => ex.meta = map(name, value, string)?,
+            "id" => ex.id = string(name, value)?,
+            "context" => ex.context = Some(string(name, value)?),
+            "score" => ex.score = Some(float64(name, value)?),
+            "labelsByName" | "labels_by_name" => ex.labels_by_name = map(name, value, int32)?,
+            _ => bail!(SchemaMismatch, "unknown Example field `{name}`"),
+        }
+    }
+    Ok(ex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Example {
+        Example {
+            subset: "justice".into(),
+            split: "test_hard".into(),
+            text: "I deserve a raise because I worked hard.".into(),
+            label: 1,
+            meta: BTreeMap::from([("rationale".into(), "effort".into())]),
+            id: "j-7".into(),
+            context: Some(String::new()),
+            score: Some(-0.25),
+            labels_by_name: BTreeMap::from([("reasonable".into(), 1)]),
+        }
+    }
+
+    #[test]
+    fn defaults_are_omitted_unless_asked() {
+        let ex = Example {
+            text: "t".into(),
+            ..Example::default()
+        };
+        assert_eq!(example_to_json(&ex, false), json!({"text": "t"}));
+        assert_eq!(
+            example_to_json(&ex, true),
+            json!({"subset": "", "split": "", "text": "t", "label": 0, "meta": {}, "id": "", "labelsByName": {}})
+        );
+        // `optional` fields are written when set, even to their default.
+        let ex = Example {
+            context: Some(String::new()),
+            score: Some(0.0),
+            ..Example::default()
+        };
+        assert_eq!(
+            example_to_json(&ex, false),
+            json!({"context": "", "score": 0.0})
+        );
+    }
+
+    #[test]
+    fn examples_round_trip() {
+        let ex = example();
+        let value = example_to_json(&ex, false);
+        assert_eq!(value["labelsByName"], json!({"reasonable": 1}));
+        assert_eq!(example_from_json(&value).unwrap(), ex);
+        assert_eq!(example_from_json(&example_to_json(&ex, true)).unwrap(), ex);
+        assert_eq!(
+            example_from_json(&example_to_json(&Example::default(), false)).unwrap(),
+            Example::default()
+        );
+    }
+
+    #[test]
+    fn parsing_follows_the_spec() {
+        let value =
+            json!({"label": "3", "labels_by_name": {"a": 2.0}, "score": "-Infinity", "id": null});
+        let ex = example_from_json(&value).unwrap();
+        assert_eq!(ex.label, 3);
+        assert_eq!(ex.labels_by_name["a"], 2);
+        assert_eq!(ex.score, Some(f64::NEG_INFINITY));
+        assert_eq!(ex.id, "");
+        assert_eq!(double(f64::NAN), json!("NaN"));
+
+        for bad in [
+            json!({"label": 1.5}),
+            json!({"label": "4294967296"}),
+            json!({"text": 1}),
+            json!({"surprise": true}),
+            json!([]),
+        ] {
+            assert!(
+                matches!(example_from_json(&bad), Err(EthicsError::SchemaMismatch(_))),
+                "{bad}"
+            );
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! `ExampleReader`: shards as an iterator of `Example`s, for training and eval
+//! code that wants them without going through `pb_to_jsonl`. Shards with a `.idx`
+//! sidecar also support random access by example index. With the `http` feature,
+//! shards can also be read from a URL; see [`crate::remote`].
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::dict::Dictionary;
+use crate::error::{bail, ensure, Context, Result};
+use crate::ethics::{Example, ShardHeader};
+use crate::index::ShardIndex;
+use crate::input::is_pairs_shard;
+#[cfg(feature = "http")]
+use crate::remote::{Remote, RemoteOptions};
+use crate::shard::{self, ShardReader};
+
+/// Decodes the `Example`s of one `.pb.zst` or plain `.pb` shard as they are read,
+/// without buffering the shard. An error ends the iteration; it carries the byte
+/// offset of the record that failed (truncated, corrupt or not an `Example`).
+pub struct ExampleReader {
+    inner: ShardReader,
+    done: bool,
+    /// Set by `open_indexed`, for `get` and `range`.
+    seekable: Option<Seekable>,
+}
+
+/// What `get` and `range` need to reopen the shard at one of its frames.
+struct Seekable {
+    path: PathBuf,
+    dict: Option<Dictionary>,
+    index: ShardIndex,
+    /// Set by `open_url`: frames are fetched with Range requests instead.
+    #[cfg(feature = "http")]
+    remote: Option<Remote>,
+}
+
+impl Seekable {
+    /// The shard from byte `offset` on.
+    fn open_at(&self, offset: u64) -> Result<Box<dyn Read + Send>> {
+        #[cfg(feature = "http")]
+        if let Some(remote) = &self.remote {
+            return Ok(Box::new(remote.open_at(offset)?));
+        }
+        let path = &self.path;
+        let mut file =
+            File::open(path).with_context(|| format!("failed to open shard {}", path.display()))?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+}
+
+impl ExampleReader {
+    /// Opens `path` (`-` for stdin).
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with_dict(path, None)
+    }
+
+    /// Opens a shard compressed with `--dict`.
+    pub fn open_with_dict(path: &Path, dict: Option<&Dictionary>) -> Result<Self> {
+        let reader = Self::new(ShardReader::open(path, dict)?, path)?;
+        // Headerless pair shards are only recognisable by name.
+        ensure!(
+            reader.header().is_some() || !is_pairs_shard(path),
+            SchemaMismatch,
+            "{} holds PairExample records, not Example",
+            path.display()
+        );
+        Ok(reader)
+    }
+
+    /// Reads a shard from `r`, compressed or not.
+    pub fn from_reader(r: impl Read + Send + 'static) -> Result<Self> {
+        let name = Path::new("<reader>");
+        Self::new(ShardReader::from_reader(r, None, name)?, name)
+    }
+
+    fn new(inner: ShardReader, name: &Path) -> Result<Self> {
+        if let Some(header) = &inner.header {
+            ensure!(
+                header.message == "ethics.v1.Example",
+                SchemaMismatch,
+                "{} holds {} records, not ethics.v1.Example",
+                name.display(),
+                header.message
+            );
+            shard::check_schema_version(Some(header), None)?;
+        }
+        Ok(ExampleReader {
+            inner,
+            done: false,
+            seekable: None,
+        })
+    }
+
+    /// Opens `path` along with its `<path>.idx` sidecar (written by `--frame-every`
+    /// or `index_shard`), so `get` and `range` can seek. A sidecar written for a
+    /// different version of the shard is rejected.
+    pub fn open_indexed(path: &Path, dict: Option<&Dictionary>) -> Result<Self> {
+        let index = ShardIndex::load_for(&ShardIndex::path_for(path), path)?;
+        let mut reader = Self::open_with_dict(path, dict)?;
+        reader.seekable = Some(Seekable {
+            path: path.to_path_buf(),
+            dict: dict.cloned(),
+            index,
+            #[cfg(feature = "http")]
+            remote: None,
+        });
+        Ok(reader)
+    }
+
+    /// Streams the shard at `url` (http or https) with the default
+    /// [`RemoteOptions`]. If `<url>.idx` exists, `get` and `range` fetch only the
+    /// frames they need with Range requests.
+    #[cfg(feature = "http")]
+    pub fn open_url(url: &str) -> Result<Self> {
+        Self::open_url_with(url, None, &RemoteOptions::default())
+    }
+
+    /// [`open_url`](Self::open_url) for a shard compressed with `--dict`, with
+    /// retries, read-ahead and the sidecar location set by `opts`.
+    #[cfg(feature = "http")]
+    pub fn open_url_with(
+        url: &str,
+        dict: Option<&Dictionary>,
+        opts: &RemoteOptions,
+    ) -> Result<Self> {
+        let remote = Remote::new(url, opts)?;
+        // Named by its URL's path, so headerless pair shards are still recognised.
+        let name = PathBuf::from(url.split(['?', '#']).next().unwrap_or(url));
+        let index = remote.index()?;
+        let inner = ShardReader::from_reader(remote.open_at(0)?, dict, &name)?;
+        let mut reader = Self::new(inner, &name)?;
+        ensure!(
+            reader.header().is_some() || !is_pairs_shard(&name),
+            SchemaMismatch,
+            "{} holds PairExample records, not Example",
+            remote.url()
+        );
+        reader.seekable = index.map(|index| Seekable {
+            path: name,
+            dict: dict.cloned(),
+            index,
+            remote: Some(remote),
+        });
+        Ok(reader)
+    }
+
+    /// Example `n` of the shard, or `None` past its end. Only the zstd frame
+    /// holding it is decompressed; needs `open_indexed`. Independent of where the
+    /// iterator is.
+    pub fn get(&self, n: u64) -> Result<Option<Example>> {
+        self.range(n..n.saturating_add(1))?.next().transpose()
+    }
+
+    /// Examples `range.start` up to `range.end` (or the end of the shard), decoding
+    /// from the frame holding the first one on; needs `open_indexed`.
+    pub fn range(&self, range: Range<u64>) -> Result<std::iter::Take<ExampleReader>> {
+        let Some(seekable) = &self.seekable else {
+            bail!(
+                InvalidArgument,
+                "random access needs a reader from ExampleReader::open_indexed, or open_url on an indexed shard"
+            );
+        };
+        // Past the last example the reader starts at the end of the file and yields nothing.
+        let (offset, skip) = match seekable.index.frame_for(range.start) {
+            Some(frame) => (frame.offset, range.start - frame.first),
+            None => (seekable.index.shard_bytes, 0),
+        };
+        let path = &seekable.path;
+        let file = seekable.open_at(offset)?;
+        let dict = seekable.dict.as_ref();
+        // The first frame starts with the header; later ones go straight into records.
+        let inner = if offset == 0 {
+            ShardReader::from_reader(file, dict, path)?
+        } else {
+            ShardReader::from_frame(file, dict, path, self.header().cloned())?
+        };
+        let mut reader = ExampleReader {
+            inner,
+            done: false,
+            seekable: None,
+        };
+        for _ in 0..skip {
+            ensure!(
+                reader.inner.skip_record()?,
+                Corrupt,
+                "{} ends inside the frame at byte {offset}, before its index says",
+                path.display()
+            );
+        }
+        let len = range.end.saturating_sub(range.start) as usize;
+        Ok(reader.take(len))
+    }
+
+    /// The shard header; `None` for shards written without one.
+    pub fn header(&self) -> Option<&ShardHeader> {
+        self.inner.header.as_ref()
+    }
+
+    /// Position of the next record in the decompressed stream, as used in errors.
+    pub fn offset(&self) -> u64 {
+        self.inner.offset()
+    }
+
+    /// Counts the remaining examples by reading only their length prefixes; the
+    /// bodies are skipped undecoded (and their checksums unchecked).
+    pub fn count(mut self) -> Result<u64> {
+        let mut n = 0;
+        while !self.done && self.inner.skip_record()? {
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl Iterator for ExampleReader {
+    type Item = Result<Example>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.inner.next_example();
+        self.done = !matches!(next, Ok(Some(_)));
+        next.transpose()
+    }
+}
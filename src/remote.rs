@@ -0,0 +1,263 @@
+//! Shards behind HTTP(S), e.g. S3 presigned URLs, for [`ExampleReader::open_url`].
+//! Built with the `http` feature.
+//!
+//! The shard is streamed rather than downloaded: a GET from the first byte for
+//! sequential reads, or a `Range: bytes=<offset>-` request from the frame
+//! [`ShardIndex`] says holds the first example wanted, so only the frames read are
+//! transferred. A connection that drops mid-body is resumed where it stopped, and
+//! 5xx/429 responses and connection errors are retried with exponential backoff.
+//!
+//! The sidecar is looked for at the shard URL with `.idx` added to its path (in
+//! front of any query string); a presigned URL needs its own, given as
+//! [`RemoteOptions::index_url`]. Since checking the shard's SHA-256 would mean
+//! downloading it, a remote sidecar is only checked against the shard's size.
+//!
+//! Requests use reqwest's blocking client, which drives its own tokio runtime:
+//! call from a plain thread, or `spawn_blocking` inside async code.
+//!
+//! [`ExampleReader::open_url`]: crate::reader::ExampleReader::open_url
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+
+use crate::error::{bail, ensure, EthicsError, Result};
+use crate::index::ShardIndex;
+
+/// How remote shards are fetched.
+#[derive(Debug, Clone)]
+pub struct RemoteOptions {
+    /// Bytes buffered ahead of the decoder.
+    pub read_ahead: usize,
+    /// Attempts after the first for a failed request or a dropped connection.
+    pub retries: u32,
+    /// Wait before the first retry; doubled for each one after.
+    pub backoff: Duration,
+    /// Per-request connect and read timeout.
+    pub timeout: Duration,
+    /// URL of the `.idx` sidecar, when it isn't the shard's URL plus `.idx`.
+    pub index_url: Option<String>,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        RemoteOptions {
+            read_ahead: 8 << 20,
+            retries: 5,
+            backoff: Duration::from_millis(250),
+            timeout: Duration::from_secs(60),
+            index_url: None,
+        }
+    }
+}
+
+fn http_error(url: &str, what: impl std::fmt::Display) -> EthicsError {
+    io::Error::other(format!("{url}: {what}")).into()
+}
+
+/// Whether a response status is worth retrying.
+fn transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// A shard URL and the client fetching it.
+#[derive(Clone)]
+pub(crate) struct Remote {
+    client: Client,
+    url: String,
+    opts: RemoteOptions,
+}
+
+impl Remote {
+    pub(crate) fn new(url: &str, opts: &RemoteOptions) -> Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(opts.timeout)
+            .timeout(opts.timeout)
+            .build()
+            .map_err(|e| http_error(url, e))?;
+        Ok(Remote {
+            client,
+            url: url.to_string(),
+            opts: opts.clone(),
+        })
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Waits before retry `attempt` (0-based).
+    fn back_off(&self, attempt: u32) {
+        thread::sleep(self.opts.backoff * 2u32.saturating_pow(attempt.min(16)));
+    }
+
+    /// GETs `url` from byte `from` on, retrying transient failures. `None` when
+    /// the server says there is nothing there (404, or 403 as S3 answers for a
+    /// missing key without list permission).
+    fn get(&self, url: &str, from: u64) -> Result<Option<Response>> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url);
+            if from > 0 {
+                request = request.header(RANGE, format!("bytes={from}-"));
+            }
+            let error = match request.send() {
+                Ok(response) if response.status().is_success() => {
+                    ensure!(
+                        from == 0 || response.status() == StatusCode::PARTIAL_CONTENT,
+                        InvalidArgument,
+                        "{url}: the server ignores Range requests"
+                    );
+                    return Ok(Some(response));
+                }
+                Ok(response)
+                    if matches!(
+                        response.status(),
+                        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Ok(response) if transient(response.status()) => http_error(url, response.status()),
+                Ok(response) => return Err(http_error(url, response.status())),
+                Err(e) => http_error(url, e),
+            };
+            if attempt == self.opts.retries {
+                return Err(error);
+            }
+            self.back_off(attempt);
+            attempt += 1;
+        }
+    }
+
+    /// Streams the shard from byte `offset` on.
+    pub(crate) fn open_at(&self, offset: u64) -> Result<HttpReader> {
+        let Some(response) = self.get(&self.url, offset)? else {
+            bail!(InvalidArgument, "{}: no such shard", self.url);
+        };
+        Ok(HttpReader {
+            remote: self.clone(),
+            pos: offset,
+            body: Some(BufReader::with_capacity(self.opts.read_ahead, response)),
+        })
+    }
+
+    /// The shard's size, from a HEAD request or failing that a one-byte range.
+    fn len(&self) -> Result<u64> {
+        if let Ok(response) = self.client.head(&self.url).send() {
+            if response.status().is_success() {
+                if let Some(len) = header_u64(&response, CONTENT_LENGTH, |v| v) {
+                    return Ok(len);
+                }
+            }
+        }
+        let response = self
+            .client
+            .get(&self.url)
+            .header(RANGE, "bytes=0-0")
+            .send()
+            .map_err(|e| http_error(&self.url, e))?;
+        // `bytes 0-0/12345`
+        header_u64(&response, CONTENT_RANGE, |v| {
+            v.rsplit('/').next().unwrap_or("")
+        })
+        .ok_or_else(|| http_error(&self.url, "the server reports no size"))
+    }
+
+    /// The shard's `.idx` sidecar, if it has one; checked against the shard's size.
+    pub(crate) fn index(&self) -> Result<Option<ShardIndex>> {
+        let url = match &self.opts.index_url {
+            Some(url) => url.clone(),
+            None => match self.url.split_once('?') {
+                Some((path, query)) => format!("{path}.idx?{query}"),
+                None => format!("{}.idx", self.url),
+            },
+        };
+        let Some(response) = self.get(&url, 0)? else {
+            return Ok(None);
+        };
+        let text = response.text().map_err(|e| http_error(&url, e))?;
+        let index = ShardIndex::parse(&text, Path::new(&url))?;
+        let bytes = self.len()?;
+        ensure!(
+            bytes == index.shard_bytes,
+            InvalidArgument,
+            "{url} does not belong to {}: shard is {bytes} bytes, index expects {}",
+            self.url,
+            index.shard_bytes
+        );
+        Ok(Some(index))
+    }
+}
+
+fn header_u64(
+    response: &Response,
+    name: reqwest::header::HeaderName,
+    part: impl Fn(&str) -> &str,
+) -> Option<u64> {
+    let value = response.headers().get(name)?.to_str().ok()?;
+    part(value).trim().parse().ok()
+}
+
+/// A shard body being streamed, reopened with a Range request at the byte it
+/// stopped at when the connection fails.
+pub(crate) struct HttpReader {
+    remote: Remote,
+    pos: u64,
+    body: Option<BufReader<Response>>,
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for HttpReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let mut attempt = 0;
+        loop {
+            if self.body.is_none() {
+                let response = match self.remote.get(&self.remote.url, self.pos) {
+                    Ok(Some(response)) => response,
+                    Ok(None) => return Err(io::Error::other("shard disappeared mid-read")),
+                    Err(e) => return Err(io::Error::other(e)),
+                };
+                let read_ahead = self.remote.opts.read_ahead;
+                self.body = Some(BufReader::with_capacity(read_ahead, response));
+            }
+            let body = self.body.as_mut().expect("opened above");
+            match body.fill_buf() {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if attempt == self.remote.opts.retries => return Err(e),
+                Err(_) => {
+                    self.body = None;
+                    self.remote.back_off(attempt);
+                    attempt += 1;
+                }
+            }
+        }
+        // Already filled, so this only hands back the buffer.
+        self.body.as_mut().expect("opened above").fill_buf()
+    }
+
+    fn consume(&mut self, n: usize) {
+        if let Some(body) = &mut self.body {
+            body.consume(n);
+            self.pos += n as u64;
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Single-pass sampling for the shard tools: [`Reservoir`] keeps a uniform
+//! sample of a stream of unknown length in bounded memory.
+
+use rand::Rng;
+
+/// Keeps `capacity` of the items pushed, each with equal probability (Algorithm R),
+/// along with the position it was pushed at.
+#[derive(Debug)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<(u64, T)>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity.min(1 << 16)),
+        }
+    }
+
+    /// Offers the next item of the stream.
+    pub fn push(&mut self, rng: &mut impl Rng, item: T) {
+        let i = self.seen;
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push((i, item));
+            return;
+        }
+        let j = rng.random_range(0..=i);
+        if j < self.capacity as u64 {
+            self.items[j as usize] = (i, item);
+        }
+    }
+
+    /// Items offered so far, kept or not.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// The sample, in the order its items were pushed, each with its position.
+    pub fn into_sorted(mut self) -> Vec<(u64, T)> {
+        self.items.sort_by_key(|(i, _)| *i);
+        self.items
+    }
+}
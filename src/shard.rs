@@ -0,0 +1,416 @@
+//! Framing inside a shard's (decompressed) stream: the `ShardHeader` in front of
+//! the records, saying what they are and who wrote them, and the CRC32 after each
+//! record when the header asks for it. Shards without a header (written before it
+//! existed, or with `--no-header`) are read exactly as before. [`ShardReader`]
+//! reads the records back.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use sha2::{Digest, Sha256};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::dict::{check_frame, Dictionary};
+use crate::error::{bail, ensure, Context, EthicsError, Result};
+use crate::ethics::{Example, PairExample, ShardHeader, FILE_DESCRIPTOR_SET};
+use crate::input::is_stdio;
+
+/// Opens the header. A legacy shard would need an empty first record followed by
+/// one of 69 bytes to start the same way.
+pub const SHARD_MAGIC: [u8; 4] = *b"\0EPB";
+
+/// Bumped whenever the framing changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Version of `Example`/`PairExample` this build writes, stamped into every header.
+/// Bump it with every change to those messages:
+///
+/// 1. subset, split, text, label, meta
+/// 2. `Example.id`
+/// 3. `PairExample`
+/// 4. `Example.context`
+/// 5. `Example.score`
+/// 6. `Example.labels_by_name`
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// First bytes of every zstd frame.
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Largest window `--zstd-long` can produce.
+pub const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+
+/// Bytes a checksum adds after each record.
+pub const CHECKSUM_LEN: u64 = 4;
+
+/// Version of the crate writing headers.
+pub const CONVERTER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short commit hash the crate was built from, empty outside a git checkout.
+pub const CONVERTER_GIT: &str = env!("ETHICS_PIPELINE_GIT");
+
+/// Hex SHA-256 of [`FILE_DESCRIPTOR_SET`], stamped into every header so readers in
+/// other languages can tell whether their copy of the schema matches.
+pub fn descriptor_sha256() -> String {
+    format!("{:x}", Sha256::digest(FILE_DESCRIPTOR_SET))
+}
+
+/// `SOURCE_DATE_EPOCH`, which pins `created_unix` so reruns produce identical shards.
+pub fn source_date_epoch() -> Option<i64> {
+    std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
+}
+
+/// `created_unix` for a new header: `SOURCE_DATE_EPOCH` when set, otherwise the current
+/// time if `timestamp` and 0 if not, so that by default reruns write identical shards.
+pub fn created_unix(timestamp: bool) -> i64 {
+    source_date_epoch().unwrap_or_else(|| {
+        if !timestamp {
+            return 0;
+        }
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    })
+}
+
+/// Whether a header from [`created_unix`]`(timestamp)` carries the wall-clock time.
+pub fn wall_clock(timestamp: bool) -> bool {
+    timestamp && source_date_epoch().is_none()
+}
+
+/// The header for a new shard of `message` records, stamped with this converter's
+/// version and a `created_unix` of `SOURCE_DATE_EPOCH` or 0; see [`created_unix`].
+pub fn header(message: &str, subset: &str, split: &str) -> ShardHeader {
+    ShardHeader {
+        format_version: FORMAT_VERSION,
+        checksums: false,
+        message: message.to_string(),
+        subset: subset.to_string(),
+        split: split.to_string(),
+        created_unix: created_unix(false),
+        converter_version: CONVERTER_VERSION.to_string(),
+        converter_git: CONVERTER_GIT.to_string(),
+        dict_sha256: None,
+        schema_version: SCHEMA_VERSION,
+        descriptor_sha256: descriptor_sha256(),
+    }
+}
+
+/// Schema version of a shard; 0 when it has no header (or predates the field).
+pub fn schema_version(header: Option<&ShardHeader>) -> u32 {
+    header.map_or(0, |h| h.schema_version)
+}
+
+/// Checks a shard's schema version: below `required` is an error, anything older
+/// than this build's returns a warning (fields added since then read as defaults).
+pub fn check_schema_version(
+    header: Option<&ShardHeader>,
+    required: Option<u32>,
+) -> Result<Option<String>> {
+    let version = schema_version(header);
+    if let Some(required) = required {
+        ensure!(
+            version >= required,
+            SchemaMismatch,
+            "shard has schema version {version}, older than the required {required} (`ethics-pipeline migrate` rewrites it)"
+        );
+    }
+    ensure!(
+        version <= SCHEMA_VERSION,
+        SchemaMismatch,
+        "shard has schema version {version}, newer than this reader ({SCHEMA_VERSION})"
+    );
+    Ok((header.is_some() && version < SCHEMA_VERSION).then(|| {
+        format!("shard has schema version {version}, older than {SCHEMA_VERSION}; newer fields read as defaults")
+    }))
+}
+
+/// Writes the magic and `header` as a length-delimited message.
+pub fn write_header(w: &mut (impl Write + ?Sized), header: &ShardHeader) -> io::Result<()> {
+    w.write_all(&SHARD_MAGIC)?;
+    w.write_all(&header.encode_length_delimited_to_vec())
+}
+
+/// A stream with its header, if it had one, taken off the front.
+pub struct Framed<R> {
+    pub header: Option<ShardHeader>,
+    /// Bytes the magic and header took up; record offsets start here.
+    pub header_len: u64,
+    pub reader: io::Chain<Cursor<Vec<u8>>, R>,
+}
+
+impl<R> Framed<R> {
+    /// Whether records are followed by a CRC32.
+    pub fn checksums(&self) -> bool {
+        self.header.as_ref().is_some_and(|h| h.checksums)
+    }
+}
+
+/// Reads the header at the start of `r`. For legacy shards the bytes looked at
+/// are handed back in front of the rest of the stream.
+pub fn read_header<R: Read>(mut r: R) -> Result<Framed<R>> {
+    let mut head = Vec::with_capacity(SHARD_MAGIC.len());
+    (&mut r)
+        .take(SHARD_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    if head != SHARD_MAGIC {
+        return Ok(Framed {
+            header: None,
+            header_len: 0,
+            reader: Cursor::new(head).chain(r),
+        });
+    }
+    let (len, prefix) =
+        read_varint(&mut r).map_err(|e| truncated(e, "truncated shard header".into()))?;
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)
+        .map_err(|e| truncated(e, "truncated shard header".into()))?;
+    let header = ShardHeader::decode(buf.as_slice()).map_err(|source| EthicsError::Decode {
+        message: "ShardHeader",
+        offset: SHARD_MAGIC.len() as u64,
+        source,
+    })?;
+    ensure!(
+        header.format_version <= FORMAT_VERSION,
+        SchemaMismatch,
+        "shard format version {} is newer than this reader ({FORMAT_VERSION})",
+        header.format_version
+    );
+    let header_len = SHARD_MAGIC.len() as u64 + prefix + len;
+    Ok(Framed {
+        header: Some(header),
+        header_len,
+        reader: Cursor::new(Vec::new()).chain(r),
+    })
+}
+
+/// A varint and the number of bytes it took.
+fn read_varint(r: &mut impl Read) -> io::Result<(u64, u64)> {
+    let (mut value, mut n, mut byte) = (0u64, 0, [0u8; 1]);
+    loop {
+        r.read_exact(&mut byte)?;
+        if n == 10 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "varint longer than 10 bytes",
+            ));
+        }
+        value |= u64::from(byte[0] & 0x7f) << (7 * n);
+        n += 1;
+        if byte[0] & 0x80 == 0 {
+            return Ok((value, n));
+        }
+    }
+}
+
+/// `Corrupt` when `e` is an early end of stream, otherwise the I/O error itself,
+/// either way described by `context`.
+fn truncated(e: io::Error, context: String) -> EthicsError {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        EthicsError::Corrupt(context)
+    } else {
+        EthicsError::from(e).context(context)
+    }
+}
+
+/// The checksum written after `record`.
+pub fn checksum(record: &[u8]) -> [u8; 4] {
+    crc32fast::hash(record).to_le_bytes()
+}
+
+/// Reads the checksum following `record` and fails if it doesn't match.
+/// `offset` is where the record's length prefix starts, for the error.
+pub fn check_record(r: &mut impl Read, record: &[u8], offset: u64) -> Result<()> {
+    let mut stored = [0u8; 4];
+    r.read_exact(&mut stored).map_err(|e| {
+        truncated(
+            e,
+            format!("truncated checksum after the record at byte offset {offset}"),
+        )
+    })?;
+    let computed = checksum(record);
+    if stored != computed {
+        bail!(
+            Corrupt,
+            "corrupt record at byte offset {offset}: checksum {:08x}, computed {:08x}",
+            u32::from_le_bytes(stored),
+            u32::from_le_bytes(computed)
+        );
+    }
+    Ok(())
+}
+
+/// `r`, decompressed if it starts with the zstd magic bytes.
+fn decompressed(
+    r: impl Read + Send + 'static,
+    dict: Option<&Dictionary>,
+    name: &Path,
+) -> Result<Box<dyn Read + Send>> {
+    let mut f = BufReader::new(r);
+    let head = f.fill_buf()?;
+    if !head.starts_with(&ZSTD_MAGIC) {
+        return Ok(Box::new(f));
+    }
+    check_frame(head, dict, name)?;
+    let mut dec = ZstdDecoder::with_dictionary(f, dict.map_or(&[][..], |d| &d.bytes))?;
+    // Accept `--zstd-long` windows beyond the default 2^27 limit.
+    dec.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+    Ok(Box::new(BufReader::new(dec)))
+}
+
+/// Streams length-delimited `Example`s out of a `.pb.zst` or plain `.pb` shard.
+pub struct ShardReader {
+    inner: Box<dyn Read + Send>,
+    offset: u64,
+    buf: Vec<u8>,
+    /// `None` for shards written without one.
+    pub header: Option<ShardHeader>,
+}
+
+impl ShardReader {
+    /// Opens `path` (`-` for stdin), sniffing the zstd magic bytes to decide whether to decompress.
+    /// Shards compressed with a dictionary need it passed as `dict`.
+    pub fn open(path: &Path, dict: Option<&Dictionary>) -> Result<Self> {
+        let f: Box<dyn Read + Send> = if is_stdio(path) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(
+                File::open(path)
+                    .with_context(|| format!("failed to open shard {}", path.display()))?,
+            )
+        };
+        Self::from_reader(f, dict, path)
+    }
+
+    /// Like `open`, for a shard already open as `r`; `name` is only used in errors.
+    pub fn from_reader(
+        r: impl Read + Send + 'static,
+        dict: Option<&Dictionary>,
+        name: &Path,
+    ) -> Result<Self> {
+        let inner = decompressed(r, dict, name)?;
+        let framed =
+            read_header(inner).with_context(|| format!("failed to read {}", name.display()))?;
+        Ok(Self {
+            offset: framed.header_len,
+            header: framed.header,
+            inner: Box::new(framed.reader),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Like `from_reader`, for `r` positioned at a later zstd frame of a shard
+    /// whose header, read from its start, is `header`. Offsets in errors count
+    /// from the start of that frame.
+    pub fn from_frame(
+        r: impl Read + Send + 'static,
+        dict: Option<&Dictionary>,
+        name: &Path,
+        header: Option<ShardHeader>,
+    ) -> Result<Self> {
+        Ok(Self {
+            offset: 0,
+            header,
+            inner: decompressed(r, dict, name)?,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Position of the next record in the decompressed stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns `Ok(None)` at a clean end of stream; truncation is an error carrying the byte offset.
+    pub fn next_example(&mut self) -> Result<Option<Example>> {
+        self.next_message("Example")
+    }
+
+    pub fn next_pair(&mut self) -> Result<Option<PairExample>> {
+        self.next_message("PairExample")
+    }
+
+    fn next_message<M: Message + Default>(&mut self, name: &'static str) -> Result<Option<M>> {
+        let start = self.offset;
+        let Some(buf) = self.next_record()? else {
+            return Ok(None);
+        };
+        let msg = M::decode(buf).map_err(|source| EthicsError::Decode {
+            message: name,
+            offset: start,
+            source,
+        })?;
+        Ok(Some(msg))
+    }
+
+    /// The next message's bytes, without its length prefix.
+    pub fn next_record(&mut self) -> Result<Option<&[u8]>> {
+        let start = self.offset;
+        let Some(len) = self.next_len()? else {
+            return Ok(None);
+        };
+        self.buf.resize(len as usize, 0);
+        self.inner.read_exact(&mut self.buf).map_err(|e| {
+            truncated(
+                e,
+                format!("truncated record at byte offset {start}: expected {len} byte(s)"),
+            )
+        })?;
+        self.offset += len;
+        if self.header.as_ref().is_some_and(|h| h.checksums) {
+            check_record(&mut self.inner, &self.buf, start)?;
+            self.offset += CHECKSUM_LEN;
+        }
+        Ok(Some(&self.buf))
+    }
+
+    /// Passes over the next record without decoding it, or checking its checksum.
+    /// Returns `false` at a clean end of stream.
+    pub fn skip_record(&mut self) -> Result<bool> {
+        let start = self.offset;
+        let Some(mut len) = self.next_len()? else {
+            return Ok(false);
+        };
+        if self.header.as_ref().is_some_and(|h| h.checksums) {
+            len += CHECKSUM_LEN;
+        }
+        let skipped = io::copy(&mut (&mut self.inner).take(len), &mut io::sink())
+            .with_context(|| format!("read error at byte offset {start}"))?;
+        ensure!(
+            skipped == len,
+            Corrupt,
+            "truncated record at byte offset {start}: expected {len} byte(s), found {skipped}"
+        );
+        self.offset += len;
+        Ok(true)
+    }
+
+    /// Reads a length prefix; `None` at a clean end of stream.
+    fn next_len(&mut self) -> Result<Option<u64>> {
+        let start = self.offset;
+        let (mut len, mut shift, mut byte) = (0u64, 0, [0u8; 1]);
+        loop {
+            match self.inner.read_exact(&mut byte) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && shift == 0 => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    bail!(Corrupt, "truncated length prefix at byte offset {start}")
+                }
+                Err(e) => return Err(e).context(format!("read error at byte offset {start}")),
+            }
+            ensure!(
+                shift < 64,
+                Corrupt,
+                "invalid varint length prefix at byte offset {start}"
+            );
+            len |= u64::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            self.offset += 1;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(len));
+            }
+        }
+    }
+}
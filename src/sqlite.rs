@@ -0,0 +1,225 @@
+//! SQLite databases of examples, for `export --format sqlite`.
+//!
+//! A database holds two tables:
+//!
+//! ```sql
+//! CREATE TABLE examples (
+//!     id TEXT NOT NULL, subset TEXT NOT NULL, split TEXT NOT NULL,
+//!     text TEXT NOT NULL, label INTEGER NOT NULL,
+//!     context TEXT, score REAL, labels_by_name TEXT NOT NULL  -- JSON object
+//! );
+//! CREATE TABLE meta (
+//!     example INTEGER NOT NULL,  -- examples.rowid
+//!     key TEXT NOT NULL, value TEXT NOT NULL,
+//!     PRIMARY KEY (example, key)
+//! ) WITHOUT ROWID;
+//! ```
+//!
+//! Rows keep the order they were written in as their `rowid`. Each shard is loaded
+//! in one transaction with `synchronous=OFF` and no journal, and the indexes on
+//! `subset`, `split` and `label` are built once at the end. `PRAGMA user_version`
+//! holds [`EXPORT_SCHEMA_VERSION`].
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::error::{ensure, Context, EthicsError, Result};
+use crate::ethics::Example;
+use crate::export::EXPORT_SCHEMA_VERSION;
+
+const CREATE: &str = "
+    CREATE TABLE examples (
+        id TEXT NOT NULL,
+        subset TEXT NOT NULL,
+        split TEXT NOT NULL,
+        text TEXT NOT NULL,
+        label INTEGER NOT NULL,
+        context TEXT,
+        score REAL,
+        labels_by_name TEXT NOT NULL
+    );
+    CREATE TABLE meta (
+        example INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (example, key)
+    ) WITHOUT ROWID;
+";
+
+const INDEXES: &str = "
+    CREATE INDEX examples_subset ON examples (subset);
+    CREATE INDEX examples_split ON examples (split);
+    CREATE INDEX examples_label ON examples (label);
+";
+
+const INSERT_EXAMPLE: &str = "INSERT INTO examples
+    (rowid, id, subset, split, text, label, context, score, labels_by_name)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+
+const INSERT_META: &str = "INSERT INTO meta (example, key, value) VALUES (?1, ?2, ?3)";
+
+/// Rows fetched per query when reading back.
+const PAGE_ROWS: i64 = 4096;
+
+/// Loads examples into a new database at `path`, one transaction per shard.
+pub struct SqliteWriter {
+    conn: Connection,
+    /// Whether a transaction is open.
+    loading: bool,
+    rows: i64,
+}
+
+impl SqliteWriter {
+    /// Creates the tables in a new database at `path`, replacing any file there.
+    pub fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        // The file is moved into place only once complete, so a crash mid-load
+        // loses nothing a journal would have saved.
+        conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+        conn.execute_batch(CREATE)?;
+        conn.pragma_update(None, "user_version", EXPORT_SCHEMA_VERSION)?;
+        Ok(SqliteWriter {
+            conn,
+            loading: false,
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, ex: &Example) -> Result<()> {
+        if !self.loading {
+            self.conn.execute_batch("BEGIN")?;
+            self.loading = true;
+        }
+        let rowid = self.rows + 1;
+        self.conn.prepare_cached(INSERT_EXAMPLE)?.execute(params![
+            rowid,
+            ex.id,
+            ex.subset,
+            ex.split,
+            ex.text,
+            ex.label,
+            ex.context,
+            ex.score,
+            serde_json::to_string(&ex.labels_by_name)?,
+        ])?;
+        let mut meta = self.conn.prepare_cached(INSERT_META)?;
+        for (key, value) in &ex.meta {
+            meta.execute(params![rowid, key, value])?;
+        }
+        self.rows = rowid;
+        Ok(())
+    }
+
+    /// Commits the examples written since the last commit, at the end of a shard.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.loading {
+            self.conn.execute_batch("COMMIT")?;
+            self.loading = false;
+        }
+        Ok(())
+    }
+
+    /// Commits, builds the indexes and closes the database.
+    pub fn finish(mut self) -> Result<()> {
+        self.commit()?;
+        self.conn.execute_batch(INDEXES)?;
+        self.conn
+            .execute_batch("PRAGMA synchronous = FULL; ANALYZE;")?;
+        self.conn.close().map_err(|(_, e)| e)?;
+        Ok(())
+    }
+}
+
+/// Reads a database [`SqliteWriter`] wrote back in `rowid` order, a page of rows at a time.
+pub struct SqliteReader {
+    conn: Connection,
+    last: i64,
+    pending: VecDeque<Example>,
+    done: bool,
+}
+
+impl SqliteReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        ensure!(
+            version == EXPORT_SCHEMA_VERSION,
+            SchemaMismatch,
+            "{} has export schema version {version}, not {EXPORT_SCHEMA_VERSION}",
+            path.display()
+        );
+        Ok(SqliteReader {
+            conn,
+            last: 0,
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Reads the rows after `last`, with their meta entries.
+    fn load(&mut self) -> Result<()> {
+        let mut select = self.conn.prepare_cached(
+            "SELECT rowid, id, subset, split, text, label, context, score, labels_by_name
+             FROM examples WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+        )?;
+        let mut page: BTreeMap<i64, Example> = BTreeMap::new();
+        let mut rows = select.query(params![self.last, PAGE_ROWS])?;
+        while let Some(row) = rows.next()? {
+            let labels_by_name: String = row.get(8)?;
+            let ex = Example {
+                id: row.get(1)?,
+                subset: row.get(2)?,
+                split: row.get(3)?,
+                text: row.get(4)?,
+                label: row.get(5)?,
+                context: row.get(6)?,
+                score: row.get(7)?,
+                labels_by_name: serde_json::from_str(&labels_by_name).map_err(|e| {
+                    EthicsError::Corrupt(format!("labels_by_name is not a JSON object: {e}"))
+                })?,
+                meta: BTreeMap::new(),
+            };
+            page.insert(row.get(0)?, ex);
+        }
+        let (Some(&first), Some(&last)) = (page.keys().next(), page.keys().next_back()) else {
+            self.done = true;
+            return Ok(());
+        };
+        let mut meta = self.conn.prepare_cached(
+            "SELECT example, key, value FROM meta WHERE example BETWEEN ?1 AND ?2",
+        )?;
+        let mut rows = meta.query(params![first, last])?;
+        while let Some(row) = rows.next()? {
+            let example: i64 = row.get(0)?;
+            if let Some(ex) = page.get_mut(&example) {
+                ex.meta.insert(row.get(1)?, row.get(2)?);
+            }
+        }
+        self.last = last;
+        self.pending.extend(page.into_values());
+        Ok(())
+    }
+}
+
+impl Iterator for SqliteReader {
+    type Item = Result<Example>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.done {
+            if let Err(e) = self.load() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
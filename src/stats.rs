@@ -0,0 +1,1571 @@
+//! Length statistics behind `calculate_raw_text_length_stats` (JSONL) and
+//! `calculate_shard_stats` (shards), so both views of the data write the same
+//! TOML [`Report`].
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::convert::{dedup_hash, dedup_key};
+use crate::text::truncate;
+
+/// Percentiles reported when `--percentiles` isn't given.
+pub const DEFAULT_PERCENTILES: [f64; 3] = [0.25, 0.5, 0.75];
+
+/// Newtype for text length in a [`LengthUnit`], or the value itself for numeric fields like `score`.
+#[derive(Debug, Clone, Copy)]
+pub struct TextLen(pub f64);
+
+/// What a text length is counted in (`--unit`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LengthUnit {
+    /// UTF-8 bytes; overstates non-ASCII text.
+    #[default]
+    Bytes,
+    /// Unicode scalar values.
+    Chars,
+    /// Extended grapheme clusters, what a reader sees as one character.
+    Graphemes,
+    /// Whitespace-separated tokens.
+    Words,
+    /// Tokens under `--tokenizer` (the `tokenizer` feature).
+    Tokens,
+}
+
+impl LengthUnit {
+    /// Name of the unit's table when a report has several (`[overall.chars]`).
+    pub fn name(self) -> &'static str {
+        match self {
+            LengthUnit::Bytes => "bytes",
+            LengthUnit::Chars => "chars",
+            LengthUnit::Graphemes => "graphemes",
+            LengthUnit::Words => "words",
+            LengthUnit::Tokens => "tokens",
+        }
+    }
+
+    /// Length of `text` in this unit; `None` for [`LengthUnit::Tokens`], which takes
+    /// a tokenizer to count.
+    pub fn measure(self, text: &str) -> Option<TextLen> {
+        let n = match self {
+            LengthUnit::Bytes => text.len(),
+            LengthUnit::Chars => text.chars().count(),
+            LengthUnit::Graphemes => text.graphemes(true).count(),
+            LengthUnit::Words => text.split_whitespace().count(),
+            LengthUnit::Tokens => return None,
+        };
+        Some(TextLen(n as f64))
+    }
+}
+
+/// Per-file / overall statistics.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub count: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub std: Option<f64>,
+    /// `(quantile, value)` pairs in the order asked for, written as `p25`, `p99.9`, ...
+    pub percentiles: Vec<(f64, Option<f64>)>,
+    /// Written as a `histogram` sub-table after the other keys.
+    pub histogram: Option<Histogram>,
+    /// The same stats per label, [`MISSING_LABEL`] left out; written as `by_label`
+    /// sub-tables (`by_label.0`, `by_label.1`) after the histogram.
+    pub by_label: BTreeMap<String, Stats>,
+    /// Point-biserial correlation of length with label, see [`label_correlation`].
+    pub label_correlation: Option<f64>,
+}
+
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Missing values are left out, as TOML has no null.
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("count", &self.count)?;
+        let fixed = [
+            ("min", self.min),
+            ("max", self.max),
+            ("mean", self.mean),
+            ("std", self.std),
+        ];
+        for (key, value) in fixed {
+            if let Some(value) = value {
+                map.serialize_entry(key, &value)?;
+            }
+        }
+        for &(q, value) in &self.percentiles {
+            if let Some(value) = value {
+                map.serialize_entry(&percentile_key(q), &value)?;
+            }
+        }
+        if let Some(r) = self.label_correlation {
+            map.serialize_entry("label_correlation", &r)?;
+        }
+        if let Some(histogram) = &self.histogram {
+            map.serialize_entry("histogram", histogram)?;
+        }
+        if !self.by_label.is_empty() {
+            map.serialize_entry("by_label", &self.by_label)?;
+        }
+        map.end()
+    }
+}
+
+/// TOML key for quantile `q`: `p25` for 0.25, `p99.9` for 0.999.
+pub fn percentile_key(q: f64) -> String {
+    // Rounded so that e.g. 0.29 * 100 comes out as 29, not 28.999999999999996.
+    let pct = (q * 100.0 * 1e6).round() / 1e6;
+    format!("p{pct}")
+}
+
+/// A parsed `--percentiles` list. A type of its own so clap takes the list as one
+/// value; a bare `Vec<f64>` would be read as one `f64` per occurrence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantiles(pub Vec<f64>);
+
+/// Parses `--percentiles`: comma-separated quantiles, each in (0, 1), in increasing order.
+pub fn parse_percentiles(s: &str) -> Result<Quantiles, String> {
+    let mut out: Vec<f64> = Vec::new();
+    for part in s.split(',') {
+        let part = part.trim();
+        let q: f64 = part
+            .parse()
+            .map_err(|_| format!("`{part}` is not a number"))?;
+        if !(q > 0.0 && q < 1.0) {
+            return Err(format!("{part} is not between 0 and 1 (exclusive)"));
+        }
+        if let Some(&prev) = out.last() {
+            if q <= prev || percentile_key(q) == percentile_key(prev) {
+                return Err(format!(
+                    "percentiles must be increasing, but {part} follows {prev}"
+                ));
+            }
+        }
+        out.push(q);
+    }
+    Ok(Quantiles(out))
+}
+
+/// Stats of one section in each of several units: serialized as the plain [`Stats`]
+/// table for a single unit, so byte-only reports look as they always have, and as
+/// one sub-table per unit (`[overall.bytes]`, `[overall.chars]`, ...) otherwise.
+#[derive(Debug, Clone)]
+pub struct UnitStats(pub Vec<(LengthUnit, Stats)>);
+
+impl Serialize for UnitStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [(_, stats)] => stats.serialize(serializer),
+            all => {
+                let mut map = serializer.serialize_map(Some(all.len()))?;
+                for (unit, stats) in all {
+                    map.serialize_entry(unit.name(), stats)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// `label_counts` key of records without a label.
+pub const MISSING_LABEL: &str = "missing";
+
+/// A [`Report`] section with what was counted besides lengths: a file's `format`, the
+/// stats' own keys as they are, then `label_balance`, `no_text` and the `lines`,
+/// `label_counts` and `field_counts` sub-tables and the `fields` array.
+#[derive(Debug, Clone, Serialize)]
+pub struct Section<S> {
+    /// How the file was read, e.g. `jsonl.gz` or `pb.zst`; set for files only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<&'static str>,
+    #[serde(flatten)]
+    pub stats: S,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_balance: Option<f64>,
+    /// Records without any of the `--field` names, or with only blank ones; set for
+    /// files and overall, even when 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_text: Option<usize>,
+    /// What the lines read were; set for files and overall.
+    #[serde(rename = "lines", skip_serializing_if = "Option::is_none")]
+    pub line_counts: Option<LineCounts>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub label_counts: BTreeMap<String, usize>,
+    /// Records per `--field` name their text was taken from.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub field_counts: BTreeMap<String, usize>,
+    /// `--show-extremes`; set for files only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extremes: Option<Extremes>,
+    /// `--detect-duplicates`; set for files and overall.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<DuplicateCounts>,
+    /// `--profile-fields`, by coverage; set for files and overall.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldProfile>>,
+    /// `--sample-rate` / `--sample-max`; set for files and overall.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleCounts>,
+}
+
+impl<S> Section<S> {
+    pub fn new(stats: S, label_counts: BTreeMap<String, usize>) -> Self {
+        Self {
+            format: None,
+            stats,
+            label_balance: label_balance(&label_counts),
+            no_text: None,
+            line_counts: None,
+            label_counts,
+            field_counts: BTreeMap::new(),
+            extremes: None,
+            duplicates: None,
+            fields: None,
+            sample: None,
+        }
+    }
+}
+
+/// Lines of a sampled file: all those read, and those kept, which are all the
+/// section's other figures describe (`lines.total` is `lines_kept`).
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SampleCounts {
+    pub lines_read: usize,
+    pub lines_kept: usize,
+}
+
+impl SampleCounts {
+    pub fn add(&mut self, other: &Self) {
+        self.lines_read += other.lines_read;
+        self.lines_kept += other.lines_kept;
+    }
+}
+
+/// How the lines of a sampled [`Report`] were drawn. Every count in such a report is
+/// of the sample only, and its `min`, `max`, `mean`, `std` and percentiles are
+/// estimates for the whole files, not exact figures.
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleInfo {
+    /// `bernoulli` (each line kept with probability `rate`) or `reservoir` (a uniform
+    /// sample of at most `max_per_file` lines per file).
+    pub method: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_file: Option<usize>,
+    pub seed: u64,
+    pub lines_read: usize,
+    pub lines_kept: usize,
+    /// Always `false`: the stats are estimates.
+    pub exact: bool,
+}
+
+/// What the lines of a file turned out to be, so that schema drift shows up as more
+/// than a low `count`. Every line is exactly one of `empty`, `too_long`,
+/// `parse_errors` or a record; every record is measured, `missing_text` or `empty_text`.
+/// Shards have one line per record.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LineCounts {
+    pub total: usize,
+    /// Blank or whitespace-only lines.
+    pub empty: usize,
+    /// Lines over `--max-line-bytes`.
+    pub too_long: usize,
+    /// Lines that aren't JSON.
+    pub parse_errors: usize,
+    /// Records without any of the `--field` names holding a string or a number.
+    pub missing_text: usize,
+    /// Records whose `--field` text is empty or whitespace only.
+    pub empty_text: usize,
+}
+
+impl LineCounts {
+    /// Lines that parsed as JSON.
+    pub fn records(&self) -> usize {
+        self.total - self.empty - self.too_long - self.parse_errors
+    }
+
+    /// Records that weren't measured, i.e. `no_text`.
+    pub fn no_text(&self) -> usize {
+        self.missing_text + self.empty_text
+    }
+
+    /// `no_text` over records; `None` without records.
+    pub fn missing_rate(&self) -> Option<f64> {
+        let records = self.records();
+        (records > 0).then(|| self.no_text() as f64 / records as f64)
+    }
+
+    pub fn add(&mut self, other: &LineCounts) {
+        self.total += other.total;
+        self.empty += other.empty;
+        self.too_long += other.too_long;
+        self.parse_errors += other.parse_errors;
+        self.missing_text += other.missing_text;
+        self.empty_text += other.empty_text;
+    }
+}
+
+/// The rarest label's count over the most common one's, [`MISSING_LABEL`] left out:
+/// 1.0 when the classes are balanced, towards 0 the more they aren't. `None` without
+/// labels.
+pub fn label_balance(label_counts: &BTreeMap<String, usize>) -> Option<f64> {
+    let counts = label_counts
+        .iter()
+        .filter(|(label, _)| *label != MISSING_LABEL)
+        .map(|(_, &n)| n);
+    let max = counts.clone().max()?;
+    let min = counts.min()?;
+    Some(min as f64 / max as f64)
+}
+
+/// Top-level TOML structure; `S` is a [`Section`] of [`UnitStats`] for JSONL reports.
+#[derive(Debug, Serialize)]
+pub struct Report<S = Stats> {
+    pub overall: S,
+    pub files: BTreeMap<String, S>,
+    /// Per value of `--group-by` (per `subset/split` for shards), across all files.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, S>,
+    /// Examples per label; only shards have labels to count.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub label_counts: BTreeMap<String, usize>,
+    /// Percentage of examples carrying each meta key.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta_coverage: BTreeMap<String, f64>,
+    /// The tokenizer behind `tokens` lengths.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<TokenizerInfo>,
+    /// The most repeated texts and the files sharing texts, with `--detect-duplicates`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<DuplicateSummary>,
+    /// Changes since an earlier report, with `--compare`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deltas: Option<Deltas>,
+    /// Set when the stats come from `--sample-rate` / `--sample-max`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleInfo>,
+}
+
+/// Which tokenizer counted the `tokens` lengths of a [`Report`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenizerInfo {
+    pub path: String,
+    pub sha256: String,
+    /// Records the tokenizer failed on; they are left out of every unit's stats.
+    pub encode_errors: usize,
+}
+
+/// Streaming aggregator for overall stats (mean/std/min/max, and percentiles from a
+/// [`QuantileSketch`]), so no run has to hold every length in memory.
+#[derive(Debug, Default)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64, // sum of squared deviations
+    min: Option<f64>,
+    max: Option<f64>,
+    sketch: QuantileSketch,
+    histogram: Option<HistogramBuilder>,
+}
+
+impl RunningStats {
+    /// Also counts values into a histogram laid out by `spec`.
+    pub fn with_histogram(spec: Option<&HistogramSpec>) -> Self {
+        Self {
+            histogram: spec.cloned().map(HistogramBuilder::new),
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, len: TextLen) {
+        let x = len.0;
+        // update count, min, max
+        self.count += 1;
+        self.min = Some(self.min.map_or(x, |m| m.min(x)));
+        self.max = Some(self.max.map_or(x, |m| m.max(x)));
+
+        // Welford's online algorithm for mean/std
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        let delta2 = x - self.mean;
+        self.m2 += delta * delta2;
+
+        self.sketch.push(len);
+        if let Some(histogram) = &mut self.histogram {
+            histogram.push(len);
+        }
+    }
+
+    /// Values pushed so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Adds everything pushed to `other` (built with the same histogram spec), as if it
+    /// had been pushed here. Mean and variance are combined with Chan et al.'s
+    /// pairwise update, so they match a single pass up to rounding; count, min, max,
+    /// sketch and histogram match exactly.
+    pub fn merge(&mut self, other: RunningStats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other;
+            return;
+        }
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+        self.mean += delta * n_b / n;
+        self.m2 += other.m2 + delta * delta * n_a * n_b / n;
+        self.count += other.count;
+        self.min = self.min.zip(other.min).map(|(a, b)| a.min(b));
+        self.max = self.max.zip(other.max).map(|(a, b)| a.max(b));
+
+        self.sketch.merge(&other.sketch);
+        if let (Some(histogram), Some(other)) = (&mut self.histogram, other.histogram) {
+            histogram.merge(other);
+        }
+    }
+
+    /// Percentiles are estimated by the sketch, to within
+    /// [`SKETCH_RELATIVE_ACCURACY`] of the exact ones.
+    pub fn finalize(self, quantiles: &[f64]) -> Stats {
+        let percentiles = self.sketch.percentiles(quantiles);
+        let histogram = self.histogram.and_then(HistogramBuilder::finish);
+        if self.count == 0 {
+            return Stats {
+                count: 0,
+                min: None,
+                max: None,
+                mean: None,
+                std: None,
+                percentiles,
+                histogram,
+                by_label: BTreeMap::new(),
+                label_correlation: None,
+            };
+        }
+
+        let var = if self.count > 1 {
+            self.m2 / (self.count as f64 - 1.0)
+        } else {
+            0.0
+        };
+
+        Stats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: Some(self.mean),
+            std: Some(var.sqrt()),
+            percentiles,
+            histogram,
+            by_label: BTreeMap::new(),
+            label_correlation: None,
+        }
+    }
+}
+
+/// Relative error of [`QuantileSketch`] estimates: within 0.5% of the exact
+/// percentile for values of one sign (lengths), however widely they are spread.
+pub const SKETCH_RELATIVE_ACCURACY: f64 = 0.005;
+
+/// Streaming quantile sketch over log-spaced buckets (as in DDSketch): a value `x > 0`
+/// is counted in bucket `ceil(log_gamma(x))`, with `gamma = (1 + a) / (1 - a)`, and a
+/// bucket stands for the one value within relative error `a` of all it holds. Memory
+/// depends on the range of the values, not their number: about 1,800 buckets cover
+/// lengths from 1 byte to 100 MB. Negative values (e.g. `score`) are bucketed by
+/// magnitude, and zeros counted apart.
+#[derive(Debug, Clone)]
+pub struct QuantileSketch {
+    ln_gamma: f64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::new(SKETCH_RELATIVE_ACCURACY)
+    }
+}
+
+impl QuantileSketch {
+    /// A sketch whose estimates are within `relative_accuracy` (in (0, 1)) of the exact values.
+    pub fn new(relative_accuracy: f64) -> Self {
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Self {
+            ln_gamma: gamma.ln(),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Adds a value; NaNs are ignored.
+    pub fn push(&mut self, len: TextLen) {
+        let x = len.0;
+        if x.is_nan() {
+            return;
+        }
+        if x > 0.0 {
+            *self.positive.entry(self.index(x)).or_default() += 1;
+        } else if x < 0.0 {
+            *self.negative.entry(self.index(-x)).or_default() += 1;
+        } else {
+            self.zeros += 1;
+        }
+        self.count += 1;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+
+    /// Adds the values counted by `other`, which must have the same accuracy.
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        debug_assert_eq!(self.ln_gamma, other.ln_gamma);
+        for (&index, &n) in &other.positive {
+            *self.positive.entry(index).or_default() += n;
+        }
+        for (&index, &n) in &other.negative {
+            *self.negative.entry(index).or_default() += n;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn index(&self, magnitude: f64) -> i32 {
+        // `as` saturates, so infinities and subnormals land in the outermost buckets.
+        (magnitude.ln() / self.ln_gamma).ceil() as i32
+    }
+
+    /// The value bucket `index` stands for: the midpoint of its range, in relative terms.
+    fn value(&self, index: i32) -> f64 {
+        let gamma = self.ln_gamma.exp();
+        2.0 * (self.ln_gamma * index as f64).exp() / (gamma + 1.0)
+    }
+
+    /// Estimate of the `rank`-th smallest value (0-based).
+    fn value_at_rank(&self, rank: u64) -> f64 {
+        let mut seen = 0;
+        // Negatives from the most negative, i.e. the largest magnitude, up.
+        for (&index, &n) in self.negative.iter().rev() {
+            seen += n;
+            if seen > rank {
+                return -self.value(index);
+            }
+        }
+        seen += self.zeros;
+        if seen > rank {
+            return 0.0;
+        }
+        for (&index, &n) in &self.positive {
+            seen += n;
+            if seen > rank {
+                return self.value(index);
+            }
+        }
+        self.max
+    }
+
+    /// Estimate of quantile `q`, interpolated between ranks like [`percentile`].
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let idx = q * (self.count as f64 - 1.0);
+        let lo = idx.floor() as u64;
+        let hi = (lo + 1).min(self.count - 1);
+        let frac = idx - lo as f64;
+        let lo_val = self.value_at_rank(lo).clamp(self.min, self.max);
+        let hi_val = self.value_at_rank(hi).clamp(self.min, self.max);
+        Some(lo_val * (1.0 - frac) + hi_val * frac)
+    }
+
+    /// Each of `quantiles`, paired with the quantile, like [`percentiles`].
+    pub fn percentiles(&self, quantiles: &[f64]) -> Vec<(f64, Option<f64>)> {
+        quantiles.iter().map(|&q| (q, self.quantile(q))).collect()
+    }
+}
+
+pub fn percentile(sorted_vals: &[TextLen], q: f64) -> Option<f64> {
+    if sorted_vals.is_empty() {
+        return None;
+    }
+    let n = sorted_vals.len();
+    if n == 1 {
+        return Some(sorted_vals[0].0);
+    }
+
+    let idx = q * (n as f64 - 1.0);
+    let lo = idx.floor() as usize;
+    let hi = (lo + 1).min(n - 1);
+    let frac = idx - lo as f64;
+
+    let lo_val = sorted_vals[lo].0;
+    let hi_val = sorted_vals[hi].0;
+
+    Some(lo_val * (1.0 - frac) + hi_val * frac)
+}
+
+/// Each of `quantiles` of `sorted_vals`, paired with the quantile.
+pub fn percentiles(sorted_vals: &[TextLen], quantiles: &[f64]) -> Vec<(f64, Option<f64>)> {
+    quantiles
+        .iter()
+        .map(|&q| (q, percentile(sorted_vals, q)))
+        .collect()
+}
+
+pub fn summarize_per_file(vals: &[TextLen], quantiles: &[f64]) -> Stats {
+    if vals.is_empty() {
+        return Stats {
+            count: 0,
+            min: None,
+            max: None,
+            mean: None,
+            std: None,
+            percentiles: percentiles(&[], quantiles),
+            histogram: None,
+            by_label: BTreeMap::new(),
+            label_correlation: None,
+        };
+    }
+
+    let mut s = vals.to_vec();
+    s.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+    let n = s.len();
+    let sum: f64 = s.iter().map(|&x| x.0).sum();
+    let mean = sum / n as f64;
+
+    let var = if n > 1 {
+        let mut acc = 0.0;
+        for &x in &s {
+            let dx = x.0 - mean;
+            acc += dx * dx;
+        }
+        acc / (n as f64 - 1.0)
+    } else {
+        0.0
+    };
+
+    Stats {
+        count: n,
+        min: Some(s[0].0),
+        max: Some(s[n - 1].0),
+        mean: Some(mean),
+        std: Some(var.sqrt()),
+        percentiles: percentiles(&s, quantiles),
+        histogram: None,
+        by_label: BTreeMap::new(),
+        label_correlation: None,
+    }
+}
+
+/// Point-biserial correlation between length and label, from the stats of each label:
+/// for exactly two labels, positive when the second (in key order, so `1` for `0`/`1`)
+/// has the longer texts. It is Pearson's r with the label coded 0 and 1, i.e.
+/// `(mean_1 - mean_0) / s * sqrt(n_0 * n_1) / n`, with `s` the population standard
+/// deviation of all their lengths. `None` for any other number of labels, or when
+/// every length is the same.
+pub fn label_correlation(by_label: &BTreeMap<String, Stats>) -> Option<f64> {
+    let [a, b] = by_label.values().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let (n_a, n_b) = (a.count as f64, b.count as f64);
+    let n = n_a + n_b;
+    let (mean_a, mean_b) = (a.mean?, b.mean?);
+    let mean = (n_a * mean_a + n_b * mean_b) / n;
+    // Both stds are sample ones (n - 1); back to sums of squared deviations.
+    let ss = |count: f64, m: f64, std: f64| (count - 1.0) * std * std + count * (m - mean).powi(2);
+    let sd = ((ss(n_a, mean_a, a.std?) + ss(n_b, mean_b, b.std?)) / n).sqrt();
+    if sd == 0.0 {
+        return None;
+    }
+    Some((mean_b - mean_a) / sd * (n_a * n_b).sqrt() / n)
+}
+
+/// Bucket layout of `--histogram-buckets` / `--histogram-auto`.
+#[derive(Debug, Clone)]
+pub enum HistogramSpec {
+    /// Lower edges of the buckets, increasing; the last bucket is open-ended.
+    Edges(Vec<f64>),
+    /// This many equal-width buckets from the smallest value to the largest.
+    Auto(usize),
+}
+
+/// A parsed `--histogram-buckets` list, its own type for the same reason as [`Quantiles`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramEdges(pub Vec<f64>);
+
+/// Parses `--histogram-buckets`: comma-separated bucket edges, finite and increasing.
+pub fn parse_histogram_edges(s: &str) -> Result<HistogramEdges, String> {
+    let mut out: Vec<f64> = Vec::new();
+    for part in s.split(',') {
+        let part = part.trim();
+        let edge: f64 = part
+            .parse()
+            .ok()
+            .filter(|e: &f64| e.is_finite())
+            .ok_or_else(|| format!("`{part}` is not a number"))?;
+        if let Some(&prev) = out.last() {
+            if edge <= prev {
+                return Err(format!(
+                    "bucket edges must be increasing, but {part} follows {prev}"
+                ));
+            }
+        }
+        out.push(edge);
+    }
+    Ok(HistogramEdges(out))
+}
+
+/// Bucket counts of one section, with the edges that define them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    /// Bucket `i` holds the values in `[edges[i], edges[i + 1])`, and the last bucket
+    /// its upper edge too; that edge is `inf` for `--histogram-buckets`.
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+    /// Values under the first edge.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub below: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl Histogram {
+    /// The histogram as text bars, one line per bucket, the longest `width` wide.
+    pub fn chart(&self, width: usize) -> String {
+        let most = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let labels: Vec<String> = self
+            .edges
+            .windows(2)
+            .map(|e| format!("[{}, {})", e[0], e[1]))
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let mut out = String::new();
+        if self.below > 0 {
+            out += &format!("below {}: {}\n", self.edges[0], self.below);
+        }
+        for (label, &n) in labels.iter().zip(&self.counts) {
+            let bar = "#".repeat((n * width).div_ceil(most));
+            let line = format!("{label:>label_width$} {n:>10} {bar}");
+            out += line.trim_end();
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Counts values into a [`Histogram`] as they stream past. Fixed edges are counted
+/// straight away; auto buckets depend on the final range, so until then each distinct
+/// value is counted instead (few, for lengths).
+#[derive(Debug, Clone)]
+pub struct HistogramBuilder {
+    spec: HistogramSpec,
+    counts: Vec<usize>,
+    below: usize,
+    values: HashMap<u64, usize>,
+}
+
+impl HistogramBuilder {
+    pub fn new(spec: HistogramSpec) -> Self {
+        let buckets = match &spec {
+            HistogramSpec::Edges(edges) => edges.len(),
+            HistogramSpec::Auto(_) => 0,
+        };
+        Self {
+            spec,
+            counts: vec![0; buckets],
+            below: 0,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Adds a value; NaNs are ignored.
+    pub fn push(&mut self, len: TextLen) {
+        let x = len.0;
+        if x.is_nan() {
+            return;
+        }
+        match &self.spec {
+            HistogramSpec::Edges(edges) => match edges.partition_point(|&e| e <= x) {
+                0 => self.below += 1,
+                i => self.counts[i - 1] += 1,
+            },
+            HistogramSpec::Auto(_) => *self.values.entry(x.to_bits()).or_default() += 1,
+        }
+    }
+
+    /// Adds the values counted by `other`, which must have the same spec.
+    pub fn merge(&mut self, other: HistogramBuilder) {
+        debug_assert_eq!(self.counts.len(), other.counts.len());
+        for (count, n) in self.counts.iter_mut().zip(other.counts) {
+            *count += n;
+        }
+        self.below += other.below;
+        for (bits, n) in other.values {
+            *self.values.entry(bits).or_default() += n;
+        }
+    }
+
+    /// The histogram; `None` for auto buckets without any values to span.
+    pub fn finish(self) -> Option<Histogram> {
+        let buckets = match self.spec {
+            HistogramSpec::Edges(mut edges) => {
+                edges.push(f64::INFINITY);
+                return Some(Histogram {
+                    edges,
+                    counts: self.counts,
+                    below: self.below,
+                });
+            }
+            HistogramSpec::Auto(buckets) => buckets,
+        };
+        let values: Vec<(f64, usize)> = self
+            .values
+            .into_iter()
+            .map(|(bits, n)| (f64::from_bits(bits), n))
+            .collect();
+        let min = values.iter().map(|v| v.0).min_by(f64::total_cmp)?;
+        let max = values.iter().map(|v| v.0).max_by(f64::total_cmp)?;
+        // All values equal: one bucket, `[min, max]`.
+        let buckets = if max > min { buckets } else { 1 };
+        let width = (max - min) / buckets as f64;
+        let mut counts = vec![0; buckets];
+        for (x, n) in values {
+            let i = if width > 0.0 {
+                ((x - min) / width) as usize
+            } else {
+                0
+            };
+            counts[i.min(buckets - 1)] += n;
+        }
+        let mut edges: Vec<f64> = (0..buckets).map(|i| min + i as f64 * width).collect();
+        edges.push(max);
+        Some(Histogram {
+            edges,
+            counts,
+            below: 0,
+        })
+    }
+}
+
+/// Histogram of `vals` laid out by `spec`.
+pub fn histogram(vals: &[TextLen], spec: &HistogramSpec) -> Option<Histogram> {
+    let mut builder = HistogramBuilder::new(spec.clone());
+    vals.iter().for_each(|&x| builder.push(x));
+    builder.finish()
+}
+
+/// Characters of text kept in an [`Extreme`]'s preview.
+pub const PREVIEW_CHARS: usize = 200;
+
+/// A record at one end of a file's length distribution.
+#[derive(Debug, Clone, Serialize)]
+pub struct Extreme {
+    pub length: f64,
+    /// 1-based line of the record; for shards, its 1-based position.
+    pub line: usize,
+    /// The text cut to [`PREVIEW_CHARS`] on a grapheme boundary (`…` marks a cut),
+    /// with line breaks and tabs escaped so it stays on one line.
+    pub preview: String,
+}
+
+/// The `n` longest records of a file, longest first, and its `n` shortest, shortest
+/// first.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Extremes {
+    pub longest: Vec<Extreme>,
+    pub shortest: Vec<Extreme>,
+}
+
+/// [`Extreme::preview`] of `text`.
+pub fn preview(text: &str) -> String {
+    let cut = truncate(text, Some(PREVIEW_CHARS), None, "…");
+    let text = cut.as_deref().unwrap_or(text);
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A heap entry of [`ExtremesBuilder`]. The top of a heap is the entry to drop first:
+/// the largest `key`, and of equal keys the latest line.
+#[derive(Debug)]
+struct Ranked {
+    key: f64,
+    line: usize,
+    preview: String,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .total_cmp(&other.key)
+            .then(self.line.cmp(&other.line))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// Keeps the `n` longest and `n` shortest records pushed, each in a heap of at most `n`
+/// entries, so memory stays O(n) however long the file. Records must be pushed in line
+/// order; of equally long ones, the earlier are kept. Previews are only made for
+/// records that make it into a heap.
+#[derive(Debug)]
+pub struct ExtremesBuilder {
+    n: usize,
+    /// Keyed by negated length, so the shortest of them is on top.
+    longest: BinaryHeap<Ranked>,
+    shortest: BinaryHeap<Ranked>,
+}
+
+impl ExtremesBuilder {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            longest: BinaryHeap::with_capacity(n),
+            shortest: BinaryHeap::with_capacity(n),
+        }
+    }
+
+    /// Offers a record; NaNs are ignored.
+    pub fn push(&mut self, len: TextLen, line: usize, text: &str) {
+        if len.0.is_nan() {
+            return;
+        }
+        offer(&mut self.longest, self.n, -len.0, line, text);
+        offer(&mut self.shortest, self.n, len.0, line, text);
+    }
+
+    pub fn finish(self) -> Extremes {
+        let extremes = |heap: BinaryHeap<Ranked>, sign: f64| {
+            heap.into_sorted_vec()
+                .into_iter()
+                .map(|r| Extreme {
+                    length: sign * r.key,
+                    line: r.line,
+                    preview: r.preview,
+                })
+                .collect()
+        };
+        Extremes {
+            longest: extremes(self.longest, -1.0),
+            shortest: extremes(self.shortest, 1.0),
+        }
+    }
+}
+
+/// Adds a record to `heap` if it has room or the record ranks before its top. Later
+/// lines never do on a tie.
+fn offer(heap: &mut BinaryHeap<Ranked>, n: usize, key: f64, line: usize, text: &str) {
+    if heap.len() == n {
+        match heap.peek() {
+            Some(top) if key.total_cmp(&top.key).is_lt() => {
+                heap.pop();
+            }
+            _ => return,
+        }
+    }
+    heap.push(Ranked {
+        key,
+        line,
+        preview: preview(text),
+    });
+}
+
+/// How many texts repeat an earlier one, in a file or across all files.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DuplicateCounts {
+    /// Texts compared.
+    pub texts: usize,
+    pub distinct: usize,
+    /// `texts - distinct`: the records `--dedup` would drop.
+    pub duplicates: usize,
+    /// `duplicates` over `texts`; 0 without texts.
+    pub rate: f64,
+}
+
+impl DuplicateCounts {
+    pub fn new(texts: usize, distinct: usize) -> Self {
+        let duplicates = texts - distinct;
+        Self {
+            texts,
+            distinct,
+            duplicates,
+            rate: if texts > 0 {
+                duplicates as f64 / texts as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// A text found more than once, in `[[duplicates.top]]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateText {
+    /// The 128-bit hash texts are compared by, in hex.
+    pub hash: String,
+    pub count: usize,
+    /// Files it was found in, in run order.
+    pub files: Vec<String>,
+    /// [`preview`] of its first occurrence.
+    pub preview: String,
+}
+
+/// Two files with texts in common, in `[[duplicates.shared]]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedTexts {
+    pub files: [String; 2],
+    /// Distinct texts found in both.
+    pub texts: usize,
+}
+
+/// The `[duplicates]` table of a [`Report`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DuplicateSummary {
+    pub top: Vec<DuplicateText>,
+    pub shared: Vec<SharedTexts>,
+}
+
+/// Hash `--detect-duplicates` compares `text` by: as `ethics-pipeline --dedup
+/// --hash-only` (whitespace runs collapsed, first 128 bits of the SHA-256), so the
+/// counts are what deduplicating would drop.
+pub fn duplicate_hash(text: &str) -> u128 {
+    dedup_hash(&dedup_key(text, false))
+}
+
+/// The texts of one file: how often each hash occurs and the line it is first on.
+/// Only hashes are kept, never the texts.
+#[derive(Debug, Default)]
+pub struct FileTexts {
+    counts: HashMap<u128, (usize, usize)>,
+    texts: usize,
+}
+
+impl FileTexts {
+    /// Adds the text on `line`; lines must come in order.
+    pub fn push(&mut self, text: &str, line: usize) {
+        self.texts += 1;
+        self.counts
+            .entry(duplicate_hash(text))
+            .or_insert((0, line))
+            .0 += 1;
+    }
+
+    pub fn counts(&self) -> DuplicateCounts {
+        DuplicateCounts::new(self.texts, self.counts.len())
+    }
+}
+
+/// A distinct text across the files of a run.
+#[derive(Debug)]
+struct RunText {
+    count: usize,
+    /// Files it is in, by index, in order.
+    files: Vec<u32>,
+    /// Line of its first occurrence, in `files[0]`.
+    line: usize,
+}
+
+/// A text [`RunTexts::top`] ranks among the most repeated.
+#[derive(Debug, Clone)]
+pub struct TopText {
+    pub hash: u128,
+    pub count: usize,
+    /// Files it is in, by index, in order.
+    pub files: Vec<usize>,
+    /// Line of its first occurrence, in `files[0]`.
+    pub line: usize,
+}
+
+/// The texts of all files of a run, by hash: about 64 bytes per distinct text,
+/// whatever its length.
+#[derive(Debug, Default)]
+pub struct RunTexts {
+    texts: HashMap<u128, RunText>,
+    total: usize,
+    files: u32,
+}
+
+impl RunTexts {
+    /// Adds the texts of the next file; files are numbered from 0 in the order added.
+    pub fn add_file(&mut self, file: FileTexts) {
+        let index = self.files;
+        self.files += 1;
+        self.total += file.texts;
+        for (hash, (count, line)) in file.counts {
+            let text = self.texts.entry(hash).or_insert(RunText {
+                count: 0,
+                files: Vec::new(),
+                line,
+            });
+            text.count += count;
+            text.files.push(index);
+        }
+    }
+
+    /// Distinct texts held.
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    pub fn counts(&self) -> DuplicateCounts {
+        DuplicateCounts::new(self.total, self.texts.len())
+    }
+
+    /// The `k` texts found most often, if more than once; most often first, ties by
+    /// hash.
+    pub fn top(&self, k: usize) -> Vec<TopText> {
+        let mut repeated: Vec<(&u128, &RunText)> =
+            self.texts.iter().filter(|(_, t)| t.count > 1).collect();
+        repeated.sort_unstable_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        repeated
+            .into_iter()
+            .take(k)
+            .map(|(&hash, t)| TopText {
+                hash,
+                count: t.count,
+                files: t.files.iter().map(|&f| f as usize).collect(),
+                line: t.line,
+            })
+            .collect()
+    }
+
+    /// Pairs of files (by index, lower first) with the number of distinct texts they
+    /// share; most shared first, then by index. A text in `n` files counts towards all
+    /// `n * (n - 1) / 2` of their pairs.
+    pub fn shared(&self) -> Vec<((usize, usize), usize)> {
+        let mut pairs: HashMap<(u32, u32), usize> = HashMap::new();
+        for text in self.texts.values() {
+            for (i, &a) in text.files.iter().enumerate() {
+                for &b in &text.files[i + 1..] {
+                    *pairs.entry((a, b)).or_default() += 1;
+                }
+            }
+        }
+        let mut pairs: Vec<((usize, usize), usize)> = pairs
+            .into_iter()
+            .map(|((a, b), n)| ((a as usize, b as usize), n))
+            .collect();
+        pairs.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pairs
+    }
+}
+
+/// How one value changed between two reports.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Delta {
+    pub before: f64,
+    pub after: f64,
+    /// `after - before`.
+    pub change: f64,
+    /// `change` as a percentage of `before`; left out when `before` is 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pct: Option<f64>,
+}
+
+impl Delta {
+    pub fn new(before: f64, after: f64) -> Self {
+        let change = after - before;
+        Self {
+            before,
+            after,
+            change,
+            pct: (before != 0.0).then(|| change / before * 100.0),
+        }
+    }
+}
+
+/// The deltas of one stats table, keyed as in it (`count`, `min`, `max`, `mean`, `std`,
+/// then percentiles), in that order.
+#[derive(Debug, Clone, Default)]
+pub struct StatsDeltas(pub Vec<(String, Delta)>);
+
+impl Serialize for StatsDeltas {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, delta) in &self.0 {
+            map.serialize_entry(key, delta)?;
+        }
+        map.end()
+    }
+}
+
+/// The deltas of a [`Section`]: flat for single-unit reports, per unit otherwise.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum SectionDeltas {
+    Flat(StatsDeltas),
+    ByUnit(BTreeMap<String, StatsDeltas>),
+}
+
+/// The `[deltas]` table of a [`Report`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Deltas {
+    /// The earlier report.
+    pub compared_with: String,
+    /// Files only in this report.
+    pub added: Vec<String>,
+    /// Files only in the earlier one.
+    pub removed: Vec<String>,
+    /// Percentiles and units only in one of the reports; they aren't compared.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+    pub overall: SectionDeltas,
+    /// Files in both reports.
+    pub files: BTreeMap<String, SectionDeltas>,
+}
+
+/// Compares two reports, as JSON values (an earlier one may come from TOML or JSON):
+/// overall and each file, matched by name. Only what both have is compared, so
+/// reports with different percentiles or units still compare on the ones they share.
+pub fn compare_reports(compared_with: &str, before: &Value, after: &Value) -> Deltas {
+    let mut skipped = BTreeSet::new();
+    let overall = compare_sections(&before["overall"], &after["overall"], &mut skipped);
+    let empty = serde_json::Map::new();
+    let before_files = before["files"].as_object().unwrap_or(&empty);
+    let after_files = after["files"].as_object().unwrap_or(&empty);
+    let mut files = BTreeMap::new();
+    let mut added = Vec::new();
+    for (name, section) in after_files {
+        match before_files.get(name) {
+            Some(earlier) => {
+                files.insert(
+                    name.clone(),
+                    compare_sections(earlier, section, &mut skipped),
+                );
+            }
+            None => added.push(name.clone()),
+        }
+    }
+    let removed = before_files
+        .keys()
+        .filter(|name| !after_files.contains_key(*name))
+        .cloned()
+        .collect();
+    Deltas {
+        compared_with: compared_with.to_string(),
+        added,
+        removed,
+        skipped: skipped.into_iter().collect(),
+        overall,
+        files,
+    }
+}
+
+/// Whether `table` is a stats table rather than a section of per-unit ones.
+fn is_stats(table: &Value) -> bool {
+    table.get("count").is_some_and(Value::is_number)
+}
+
+fn compare_sections(
+    before: &Value,
+    after: &Value,
+    skipped: &mut BTreeSet<String>,
+) -> SectionDeltas {
+    if is_stats(before) && is_stats(after) {
+        return SectionDeltas::Flat(compare_stats(before, after, skipped));
+    }
+    let (before_units, after_units) = (unit_tables(before), unit_tables(after));
+    if is_stats(before) || is_stats(after) {
+        skipped.insert("units: one report has a single unit, the other several".to_string());
+    }
+    let mut by_unit = BTreeMap::new();
+    for (&unit, &table) in &after_units {
+        match before_units.get(unit) {
+            Some(earlier) => {
+                by_unit.insert(unit.clone(), compare_stats(earlier, table, skipped));
+            }
+            None => {
+                skipped.insert(format!("unit {unit}"));
+            }
+        }
+    }
+    for unit in before_units
+        .keys()
+        .filter(|u| !after_units.contains_key(*u))
+    {
+        skipped.insert(format!("unit {unit}"));
+    }
+    SectionDeltas::ByUnit(by_unit)
+}
+
+/// The stats tables of a multi-unit section, by unit.
+fn unit_tables(section: &Value) -> BTreeMap<&String, &Value> {
+    section
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, table)| is_stats(table))
+        .collect()
+}
+
+/// The quantile of a percentile key such as `p99.9`.
+fn percentile_of(key: &str) -> Option<f64> {
+    key.strip_prefix('p')?.parse().ok()
+}
+
+fn compare_stats(before: &Value, after: &Value, skipped: &mut BTreeSet<String>) -> StatsDeltas {
+    let percentiles = |table: &Value| -> Vec<(f64, String)> {
+        let mut keys: Vec<(f64, String)> = table
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, _)| Some((percentile_of(key)?, key.clone())))
+            .collect();
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        keys
+    };
+    let (before_pcts, after_pcts) = (percentiles(before), percentiles(after));
+    for (_, key) in before_pcts.iter().chain(&after_pcts) {
+        if before.get(key).is_none() || after.get(key).is_none() {
+            skipped.insert(key.clone());
+        }
+    }
+    let keys = ["count", "min", "max", "mean", "std"]
+        .map(String::from)
+        .into_iter()
+        .chain(after_pcts.into_iter().map(|(_, key)| key));
+    StatsDeltas(
+        keys.filter_map(|key| {
+            let before = before.get(&key)?.as_f64()?;
+            let after = after.get(&key)?.as_f64()?;
+            Some((key, Delta::new(before, after)))
+        })
+        .collect(),
+    )
+}
+
+/// JSON types in the order ties for [`FieldProfile::r#type`] are broken in.
+const JSON_TYPES: [&str; 6] = ["string", "number", "bool", "object", "array", "null"];
+
+/// One top-level key of the records, in a `fields` array.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldProfile {
+    pub key: String,
+    /// Records that have it, whatever its value.
+    pub count: usize,
+    /// `count` over records.
+    pub coverage: f64,
+    /// The JSON type most of its values have: `string`, `number`, `bool`, `object`,
+    /// `array` or `null`.
+    pub r#type: &'static str,
+    /// Mean length of its string values, in bytes; left out without any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_length: Option<f64>,
+}
+
+/// What [`FieldProfiler`] counted for one key.
+#[derive(Debug, Clone, Default)]
+struct KeyCounts {
+    count: usize,
+    /// Values per type, as in [`JSON_TYPES`].
+    types: [usize; 6],
+    string_bytes: usize,
+}
+
+/// Counts the top-level keys of records as they stream past, for `--profile-fields`.
+#[derive(Debug, Default)]
+pub struct FieldProfiler {
+    records: usize,
+    keys: HashMap<String, KeyCounts>,
+}
+
+impl FieldProfiler {
+    /// Counts a record; one that isn't an object counts, without keys.
+    pub fn push(&mut self, record: &Value) {
+        self.records += 1;
+        let Some(obj) = record.as_object() else {
+            return;
+        };
+        for (key, value) in obj {
+            let type_index = match value {
+                Value::String(_) => 0,
+                Value::Number(_) => 1,
+                Value::Bool(_) => 2,
+                Value::Object(_) => 3,
+                Value::Array(_) => 4,
+                Value::Null => 5,
+            };
+            // The key is only copied the first time it is seen.
+            if !self.keys.contains_key(key) {
+                self.keys.insert(key.clone(), KeyCounts::default());
+            }
+            let counts = self.keys.get_mut(key).expect("inserted above");
+            counts.count += 1;
+            counts.types[type_index] += 1;
+            if let Value::String(s) = value {
+                counts.string_bytes += s.len();
+            }
+        }
+    }
+
+    /// Adds the records counted by `other`.
+    pub fn merge(&mut self, other: FieldProfiler) {
+        self.records += other.records;
+        for (key, theirs) in other.keys {
+            let ours = self.keys.entry(key).or_default();
+            ours.count += theirs.count;
+            for (n, m) in ours.types.iter_mut().zip(theirs.types) {
+                *n += m;
+            }
+            ours.string_bytes += theirs.string_bytes;
+        }
+    }
+
+    /// The `max` keys with the highest coverage, highest first; ties by key.
+    pub fn profiles(&self, max: usize) -> Vec<FieldProfile> {
+        let mut keys: Vec<(&String, &KeyCounts)> = self.keys.iter().collect();
+        keys.sort_unstable_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        keys.into_iter()
+            .take(max)
+            .map(|(key, counts)| {
+                // `max_by_key` keeps the last of equal counts, so go through the types backwards.
+                let (type_index, _) = counts
+                    .types
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .max_by_key(|&(_, &n)| n)
+                    .unwrap_or((0, &0));
+                let strings = counts.types[0];
+                FieldProfile {
+                    key: key.clone(),
+                    count: counts.count,
+                    coverage: counts.count as f64 / self.records as f64,
+                    r#type: JSON_TYPES[type_index],
+                    mean_length: (strings > 0).then(|| counts.string_bytes as f64 / strings as f64),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const QUANTILES: [f64; 8] = [0.01, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99, 0.999];
+
+    /// Log-normal lengths rounded to whole characters, spread over four orders of magnitude.
+    fn lengths(n: usize, seed: u64) -> Vec<TextLen> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                // Box-Muller.
+                let (u1, u2): (f64, f64) = (1.0 - rng.random::<f64>(), rng.random());
+                let normal = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                TextLen((5.0 + 1.5 * normal).exp().round().max(1.0))
+            })
+            .collect()
+    }
+
+    fn assert_close(sketch: &QuantileSketch, vals: &[TextLen]) {
+        let mut sorted = vals.to_vec();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for q in QUANTILES {
+            let exact = percentile(&sorted, q).unwrap();
+            let estimate = sketch.quantile(q).unwrap();
+            let error = (estimate - exact).abs() / exact;
+            assert!(
+                error <= SKETCH_RELATIVE_ACCURACY,
+                "q={q}: estimate {estimate}, exact {exact}, relative error {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn sketch_quantiles_are_within_the_documented_accuracy() {
+        let vals = lengths(200_000, 42);
+        let mut sketch = QuantileSketch::default();
+        vals.iter().for_each(|&v| sketch.push(v));
+        assert_close(&sketch, &vals);
+        // Never outside the values seen.
+        let (min, max) = vals.iter().fold((f64::INFINITY, 0.0_f64), |(lo, hi), v| {
+            (lo.min(v.0), hi.max(v.0))
+        });
+        assert!(sketch.quantile(0.0).unwrap() >= min);
+        assert!(sketch.quantile(1.0).unwrap() <= max);
+    }
+
+    #[test]
+    fn merged_sketches_keep_the_accuracy() {
+        let vals = lengths(200_000, 7);
+        let mut whole = QuantileSketch::default();
+        vals.iter().for_each(|&v| whole.push(v));
+
+        let mut merged = QuantileSketch::default();
+        for chunk in vals.chunks(30_000) {
+            let mut part = QuantileSketch::default();
+            chunk.iter().for_each(|&v| part.push(v));
+            merged.merge(&part);
+        }
+        merged.merge(&QuantileSketch::default());
+        assert_close(&merged, &vals);
+        for q in QUANTILES {
+            assert_eq!(merged.quantile(q), whole.quantile(q), "q={q}");
+        }
+    }
+
+    #[test]
+    fn sketch_handles_signs_and_emptiness() {
+        assert_eq!(QuantileSketch::default().quantile(0.5), None);
+        let mut sketch = QuantileSketch::default();
+        for x in [-200.0, -3.0, 0.0, 0.0, 0.0, 5.0, f64::NAN, 1e6] {
+            sketch.push(TextLen(x));
+        }
+        // Seven values: NaN is ignored, zeros are exact and negatives bucketed by magnitude.
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        for (rank, exact) in [(0, -200.0), (1, -3.0), (5, 5.0), (6, 1e6)] {
+            let estimate = sketch.value_at_rank(rank).clamp(sketch.min, sketch.max);
+            assert!(
+                (estimate - exact).abs() <= exact.abs() * SKETCH_RELATIVE_ACCURACY,
+                "rank {rank}: {estimate}"
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Text clean-up applied by `ethics-pipeline --normalize` and `--truncate-*`.
+
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// NFC-normalizes `s`, drops control characters other than whitespace (plus
+/// zero-width spaces and byte-order marks), and collapses each whitespace run to
+/// one character: `\n` if the run holds a line break, `\t` if it is all tabs, a
+/// space otherwise. Leading and trailing whitespace is removed.
+pub fn normalize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    // Whitespace seen since the last kept character: (holds a line break, all tabs).
+    let mut run: Option<(bool, bool)> = None;
+    let mut push = |c: char| {
+        if is_stripped(c) {
+            return;
+        }
+        if c.is_whitespace() {
+            let (newline, tabs) = run.unwrap_or((false, true));
+            run = Some((newline || is_line_break(c), tabs && c == '\t'));
+            return;
+        }
+        match run.take() {
+            // Nothing kept yet: leading whitespace.
+            _ if out.is_empty() => {}
+            Some((true, _)) => out.push('\n'),
+            Some((false, true)) => out.push('\t'),
+            Some((false, false)) => out.push(' '),
+            None => {}
+        }
+        out.push(c);
+    };
+    // Most rows are already NFC; skip the composition pass for them.
+    if is_nfc_quick(s.chars()) == IsNormalized::Yes {
+        s.chars().for_each(&mut push);
+    } else {
+        s.nfc().for_each(&mut push);
+    }
+    out
+}
+
+/// Normalizes `s` in place; returns whether it changed.
+pub fn normalize_in_place(s: &mut String) -> bool {
+    let normalized = normalize(s);
+    if normalized == *s {
+        return false;
+    }
+    *s = normalized;
+    true
+}
+
+/// C0/C1 controls that aren't whitespace, and invisible characters that only trip up dedup.
+fn is_stripped(c: char) -> bool {
+    (c.is_control() && !c.is_whitespace()) || matches!(c, '\u{200b}' | '\u{2060}' | '\u{feff}')
+}
+
+fn is_line_break(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Cuts `s` so it is at most `max_chars` characters and `max_bytes` bytes, with
+/// `marker` appended when it fits in the limits. The cut falls on a grapheme
+/// boundary and trailing whitespace before it is dropped. `None` if `s` already
+/// fits; an empty string if not even one grapheme does.
+pub fn truncate(
+    s: &str,
+    max_chars: Option<usize>,
+    max_bytes: Option<usize>,
+    marker: &str,
+) -> Option<String> {
+    let fits = |chars: usize, bytes: usize| {
+        max_chars.is_none_or(|m| chars <= m) && max_bytes.is_none_or(|m| bytes <= m)
+    };
+    if fits(s.chars().count(), s.len()) {
+        return None;
+    }
+    let marker = if fits(marker.chars().count(), marker.len()) {
+        marker
+    } else {
+        ""
+    };
+    let (budget_chars, budget_bytes) = (marker.chars().count(), marker.len());
+
+    let (mut chars, mut end) = (0, 0);
+    for (i, g) in s.grapheme_indices(true) {
+        let next_chars = chars + g.chars().count();
+        if !fits(next_chars + budget_chars, i + g.len() + budget_bytes) {
+            break;
+        }
+        (chars, end) = (next_chars, i + g.len());
+    }
+    let cut = s[..end].trim_end();
+    if cut.is_empty() {
+        return Some(String::new());
+    }
+    Some(format!("{cut}{marker}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_composes_strips_and_collapses() {
+        // NFD "é" (e + combining acute) becomes the precomposed character.
+        assert_eq!(normalize("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(normalize("caf\u{e9}"), "caf\u{e9}");
+        // NBSP, zero-width space, BOM, word joiner and C0/C1 controls.
+        assert_eq!(normalize("a\u{a0}\u{a0}b"), "a b");
+        assert_eq!(normalize("\u{feff}zero\u{200b}width\u{2060}"), "zerowidth");
+        assert_eq!(normalize("bell\u{7}\u{1b}[0m\u{85}next"), "bell[0m\nnext");
+        assert_eq!(normalize("del\u{7f}\u{9f}ete"), "delete");
+        // Whitespace runs: a line break wins, all-tab runs stay a tab, else one space.
+        assert_eq!(
+            normalize("  one \r\n\t two\t\tthree \t four  "),
+            "one\ntwo\tthree four"
+        );
+        assert_eq!(normalize("para\u{2029}graph"), "para\ngraph");
+        assert_eq!(normalize(" \u{200b}\t\n"), "");
+    }
+
+    #[test]
+    fn normalize_keeps_joiners_inside_emoji_sequences() {
+        // ZWJ (U+200D) is not stripped: it is part of the family emoji.
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        assert_eq!(normalize(family), family);
+        // Hangul jamo compose under NFC.
+        assert_eq!(normalize("\u{1100}\u{1161}"), "\u{ac00}");
+    }
+
+    #[test]
+    fn normalize_in_place_reports_changes() {
+        let mut s = "already clean".to_string();
+        assert!(!normalize_in_place(&mut s));
+        let mut s = "not\u{a0} clean".to_string();
+        assert!(normalize_in_place(&mut s));
+        assert_eq!(s, "not clean");
+    }
+}
@@ -0,0 +1,319 @@
+//! TFRecord files of `tf.train.Example`, for `export --format tfrecord`.
+//!
+//! Each record is framed as TensorFlow writes it: the data length as a
+//! little-endian u64, the masked CRC32C of those 8 bytes, the data, and the masked
+//! CRC32C of the data. An `Example` becomes these features:
+//!
+//! | feature                 | kind       |
+//! |-------------------------|------------|
+//! | `subset`, `split`       | bytes      |
+//! | `text`, `id`            | bytes      |
+//! | `label`                 | int64      |
+//! | `context`               | bytes, only when set |
+//! | `score`                 | float (f32), only when set |
+//! | `meta/<key>`            | bytes, one per meta entry |
+//! | `labels_by_name/<name>` | int64, one per entry |
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use prost::Message;
+
+use crate::error::{bail, ensure, EthicsError, Result};
+use crate::ethics::Example;
+
+const META_PREFIX: &str = "meta/";
+const LABELS_PREFIX: &str = "labels_by_name/";
+
+/// `tf.train.Example`.
+#[derive(Clone, PartialEq, Message)]
+pub struct TfExample {
+    #[prost(message, optional, tag = "1")]
+    pub features: Option<Features>,
+}
+
+/// `tf.train.Features`; a `BTreeMap` so the same example always encodes the same.
+#[derive(Clone, PartialEq, Message)]
+pub struct Features {
+    #[prost(btree_map = "string, message", tag = "1")]
+    pub feature: BTreeMap<String, Feature>,
+}
+
+/// `tf.train.Feature`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Feature {
+    #[prost(oneof = "Kind", tags = "1, 2, 3")]
+    pub kind: Option<Kind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Kind {
+    #[prost(message, tag = "1")]
+    BytesList(BytesList),
+    #[prost(message, tag = "2")]
+    FloatList(FloatList),
+    #[prost(message, tag = "3")]
+    Int64List(Int64List),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BytesList {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub value: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FloatList {
+    #[prost(float, repeated, packed = "true", tag = "1")]
+    pub value: Vec<f32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Int64List {
+    #[prost(int64, repeated, packed = "true", tag = "1")]
+    pub value: Vec<i64>,
+}
+
+fn bytes(value: &str) -> Feature {
+    Feature {
+        kind: Some(Kind::BytesList(BytesList {
+            value: vec![value.as_bytes().to_vec()],
+        })),
+    }
+}
+
+fn int64(value: i32) -> Feature {
+    Feature {
+        kind: Some(Kind::Int64List(Int64List {
+            value: vec![value.into()],
+        })),
+    }
+}
+
+impl From<&Example> for TfExample {
+    fn from(ex: &Example) -> Self {
+        let mut feature = BTreeMap::from([
+            ("subset".to_string(), bytes(&ex.subset)),
+            ("split".to_string(), bytes(&ex.split)),
+            ("text".to_string(), bytes(&ex.text)),
+            ("label".to_string(), int64(ex.label)),
+            ("id".to_string(), bytes(&ex.id)),
+        ]);
+        if let Some(context) = &ex.context {
+            feature.insert("context".to_string(), bytes(context));
+        }
+        if let Some(score) = ex.score {
+            let value = vec![score as f32];
+            let kind = Some(Kind::FloatList(FloatList { value }));
+            feature.insert("score".to_string(), Feature { kind });
+        }
+        for (k, v) in &ex.meta {
+            feature.insert(format!("{META_PREFIX}{k}"), bytes(v));
+        }
+        for (k, v) in &ex.labels_by_name {
+            feature.insert(format!("{LABELS_PREFIX}{k}"), int64(*v));
+        }
+        TfExample {
+            features: Some(Features { feature }),
+        }
+    }
+}
+
+fn malformed(name: &str) -> EthicsError {
+    EthicsError::SchemaMismatch(format!(
+        "tf.train.Example feature `{name}` is not as exported"
+    ))
+}
+
+/// The single UTF-8 value of a bytes feature.
+fn string_of(name: &str, feature: &Feature) -> Result<String> {
+    match &feature.kind {
+        Some(Kind::BytesList(list)) if list.value.len() == 1 => {
+            String::from_utf8(list.value[0].clone()).map_err(|_| malformed(name))
+        }
+        _ => Err(malformed(name)),
+    }
+}
+
+/// The single value of an int64 feature that fits an `i32`.
+fn int_of(name: &str, feature: &Feature) -> Result<i32> {
+    match &feature.kind {
+        Some(Kind::Int64List(list)) if list.value.len() == 1 => {
+            i32::try_from(list.value[0]).map_err(|_| malformed(name))
+        }
+        _ => Err(malformed(name)),
+    }
+}
+
+impl TryFrom<TfExample> for Example {
+    type Error = EthicsError;
+
+    /// Reverses `From<&Example>`, except that `score` comes back rounded to f32.
+    fn try_from(tf: TfExample) -> Result<Self> {
+        let mut ex = Example::default();
+        let features = tf.features.map(|f| f.feature).unwrap_or_default();
+        for (name, feature) in &features {
+            match name.as_str() {
+                "subset" => ex.subset = string_of(name, feature)?,
+                "split" => ex.split = string_of(name, feature)?,
+                "text" => ex.text = string_of(name, feature)?,
+                "label" => ex.label = int_of(name, feature)?,
+                "id" => ex.id = string_of(name, feature)?,
+                "context" => ex.context = Some(string_of(name, feature)?),
+                "score" => match &feature.kind {
+                    Some(Kind::FloatList(list)) if list.value.len() == 1 => {
+                        ex.score = Some(list.value[0].into())
+                    }
+                    _ => return Err(malformed(name)),
+                },
+                _ => {
+                    if let Some(key) = name.strip_prefix(META_PREFIX) {
+                        ex.meta.insert(key.to_string(), string_of(name, feature)?);
+                    } else if let Some(key) = name.strip_prefix(LABELS_PREFIX) {
+                        ex.labels_by_name
+                            .insert(key.to_string(), int_of(name, feature)?);
+                    } else {
+                        bail!(
+                            SchemaMismatch,
+                            "unexpected tf.train.Example feature `{name}`"
+                        );
+                    }
+                }
+            }
+        }
+        Ok(ex)
+    }
+}
+
+/// CRC32C of `data`, masked as TFRecord stores it.
+pub fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+/// Writes `data` as one framed record.
+pub fn write_record(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = (data.len() as u64).to_le_bytes();
+    out.write_all(&len)?;
+    out.write_all(&masked_crc32c(&len).to_le_bytes())?;
+    out.write_all(data)?;
+    out.write_all(&masked_crc32c(data).to_le_bytes())
+}
+
+/// Reads the next framed record, checking both CRCs; `None` at a clean end of input.
+pub fn read_record(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 8];
+    let mut filled = 0;
+    while filled < len.len() {
+        match input.read(&mut len[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    if filled == 0 {
+        return Ok(None);
+    }
+    ensure!(
+        filled == len.len(),
+        Corrupt,
+        "TFRecord file ends inside a record length"
+    );
+    let mut crc = [0; 4];
+    input.read_exact(&mut crc)?;
+    ensure!(
+        u32::from_le_bytes(crc) == masked_crc32c(&len),
+        Corrupt,
+        "TFRecord length fails its CRC"
+    );
+    let mut data = vec![0; u64::from_le_bytes(len) as usize];
+    input.read_exact(&mut data)?;
+    input.read_exact(&mut crc)?;
+    ensure!(
+        u32::from_le_bytes(crc) == masked_crc32c(&data),
+        Corrupt,
+        "TFRecord data fails its CRC"
+    );
+    Ok(Some(data))
+}
+
+/// Decodes a record written by `export --format tfrecord` back to an `Example`.
+pub fn decode(data: &[u8]) -> Result<Example> {
+    let tf = TfExample::decode(data)
+        .map_err(|e| EthicsError::Corrupt(format!("TFRecord holds a bad tf.train.Example: {e}")))?;
+    Example::try_from(tf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn masked_crc_matches_tensorflow() {
+        // CRC32C check value, then TensorFlow's mask: rotate right by 15, add 0xa282ead8.
+        assert_eq!(crc32c::crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(masked_crc32c(b"123456789"), 0xc78a_b0e5);
+    }
+
+    #[test]
+    fn records_are_framed_like_tensorflow_writes_them() {
+        let mut out = Vec::new();
+        write_record(&mut out, b"hello").unwrap();
+        let expected = [
+            // Length 5 as a little-endian u64, then its masked CRC.
+            &[5, 0, 0, 0, 0, 0, 0, 0][..],
+            &[0xea, 0xb2, 0x04, 0x3e],
+            b"hello",
+            // Masked CRC of the data.
+            &[0xbb, 0x1f, 0x1c, 0x19],
+        ]
+        .concat();
+        assert_eq!(out, expected);
+
+        write_record(&mut out, b"").unwrap();
+        let mut input = Cursor::new(out);
+        assert_eq!(read_record(&mut input).unwrap().unwrap(), b"hello");
+        assert_eq!(read_record(&mut input).unwrap().unwrap(), b"");
+        assert_eq!(read_record(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn damaged_records_fail_their_crc() {
+        let mut out = Vec::new();
+        write_record(&mut out, b"hello").unwrap();
+        let read = |bytes: &[u8]| read_record(&mut Cursor::new(bytes.to_vec()));
+
+        let mut bad_len = out.clone();
+        bad_len[8] ^= 1;
+        assert!(matches!(read(&bad_len), Err(EthicsError::Corrupt(m)) if m.contains("length")));
+        let mut bad_data = out.clone();
+        bad_data[12] ^= 1;
+        assert!(matches!(read(&bad_data), Err(EthicsError::Corrupt(m)) if m.contains("data")));
+        assert!(matches!(read(&out[..5]), Err(EthicsError::Corrupt(_))));
+    }
+
+    #[test]
+    fn examples_round_trip_through_features() {
+        let ex = Example {
+            subset: "utilitarianism".into(),
+            split: "train".into(),
+            text: "I ate a sandwich.".into(),
+            label: 1,
+            id: "u-1".into(),
+            meta: BTreeMap::from([("rationale".into(), "tasty".into())]),
+            context: Some("lunch".into()),
+            score: Some(0.5),
+            labels_by_name: BTreeMap::from([("better".into(), 0)]),
+        };
+        let tf = TfExample::from(&ex);
+        let features = &tf.features.as_ref().unwrap().feature;
+        assert!(features.contains_key("meta/rationale"));
+        assert!(features.contains_key("labels_by_name/better"));
+        assert_eq!(
+            features["label"].kind,
+            Some(Kind::Int64List(Int64List { value: vec![1] }))
+        );
+        assert_eq!(decode(&tf.encode_to_vec()).unwrap(), ex);
+    }
+}
@@ -0,0 +1,66 @@
+//! Token counts under a Hugging Face `tokenizer.json`, for
+//! `calculate_raw_text_length_stats --tokenizer` (the `tokenizer` feature).
+
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tokenizers::Tokenizer;
+use tracing::debug;
+
+use crate::error::{Context, EthicsError, Result};
+
+/// A loaded tokenizer, with the file it came from.
+pub struct TokenCounter {
+    tokenizer: Tokenizer,
+    path: String,
+    sha256: String,
+}
+
+impl TokenCounter {
+    /// Loads `path`, with the file's truncation and padding turned off so every token
+    /// of a text is counted.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let invalid =
+            |e| EthicsError::InvalidArgument(format!("{} is not a tokenizer: {e}", path.display()));
+        let mut tokenizer = Tokenizer::from_bytes(&bytes).map_err(invalid)?;
+        tokenizer.with_truncation(None).map_err(invalid)?;
+        tokenizer.with_padding(None);
+        Ok(Self {
+            tokenizer,
+            path: path.display().to_string(),
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+        })
+    }
+
+    /// The tokenizer file, as given.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// SHA-256 of the tokenizer file, so a report says exactly what counted its tokens.
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Token counts of `texts`, without special tokens; `None` for a text the
+    /// tokenizer fails on. The batch is encoded in parallel on the tokenizer's rayon
+    /// pool (`RAYON_NUM_THREADS` sets its size); only when that fails are the texts
+    /// encoded one by one, to find the ones at fault.
+    pub fn count(&self, texts: &[&str]) -> Vec<Option<usize>> {
+        if let Ok(encodings) = self.tokenizer.encode_batch(texts.to_vec(), false) {
+            return encodings.iter().map(|e| Some(e.len())).collect();
+        }
+        texts
+            .iter()
+            .map(|&text| match self.tokenizer.encode(text, false) {
+                Ok(encoding) => Some(encoding.len()),
+                Err(e) => {
+                    debug!("failed to tokenize a {}-byte text: {e}", text.len());
+                    None
+                }
+            })
+            .collect()
+    }
+}
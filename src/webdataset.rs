@@ -0,0 +1,481 @@
+//! WebDataset-style tar shards, for `export --format webdataset`.
+//!
+//! An export to `dir/name.tar` is written as `dir/name-000000.tar`,
+//! `dir/name-000001.tar`, ... (`.tar.zst` when each tar is zstd-compressed), every
+//! tar but the last holding exactly `--examples-per-tar` examples. Each example is
+//! two consecutive members sharing a key, in this order:
+//!
+//! - `<key>.json`: `id`, `subset`, `split`, `label` and `meta`, plus `context`,
+//!   `score` and `labels_by_name` when the example has them;
+//! - `<key>.txt`: the text, UTF-8.
+//!
+//! The key is the example's 0-based position in the export, zero-padded to nine
+//! digits (`000000042.txt`), or with `--tar-key id` the example's `id`, which then
+//! must not contain `.` or `/`. Members are regular files with mode 0644 and the
+//! mtime of `SOURCE_DATE_EPOCH` when set.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+use crate::error::{bail, ensure, Context, EthicsError, Result};
+use crate::ethics::Example;
+use crate::shard::source_date_epoch;
+use crate::writer::tmp_path;
+
+/// Digits of an index key.
+pub const KEY_DIGITS: usize = 9;
+
+/// What the members of an example are named after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TarKey {
+    /// The example's zero-padded position in the export.
+    #[default]
+    Index,
+    /// The example's `id`.
+    Id,
+}
+
+/// The `<key>.json` member.
+#[derive(Serialize, Deserialize)]
+struct Fields {
+    id: String,
+    subset: String,
+    split: String,
+    label: i32,
+    meta: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels_by_name: BTreeMap<String, i32>,
+}
+
+impl Fields {
+    fn of(ex: &Example) -> Self {
+        Fields {
+            id: ex.id.clone(),
+            subset: ex.subset.clone(),
+            split: ex.split.clone(),
+            label: ex.label,
+            meta: ex.meta.clone(),
+            context: ex.context.clone(),
+            score: ex.score,
+            labels_by_name: ex.labels_by_name.clone(),
+        }
+    }
+
+    fn with_text(self, text: String) -> Example {
+        Example {
+            subset: self.subset,
+            split: self.split,
+            text,
+            label: self.label,
+            meta: self.meta,
+            id: self.id,
+            context: self.context,
+            score: self.score,
+            labels_by_name: self.labels_by_name,
+        }
+    }
+}
+
+/// Tar `n` of an export to `path` (`dir/name.tar` -> `dir/name-000042.tar`).
+pub fn tar_path(path: &Path, n: u64) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, ext) = [".tar.zst", ".tar"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext).map(|stem| (stem, *ext)))
+        .unwrap_or((name.as_str(), ""));
+    path.with_file_name(format!("{stem}-{n:06}{ext}"))
+}
+
+enum Tar {
+    Plain(tar::Builder<BufWriter<File>>),
+    Zstd(tar::Builder<ZstdEncoder<'static, BufWriter<File>>>),
+}
+
+impl Tar {
+    fn append(&mut self, name: &str, data: &[u8], mtime: u64) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        match self {
+            Tar::Plain(b) => b.append_data(&mut header, name, data),
+            Tar::Zstd(b) => b.append_data(&mut header, name, data),
+        }
+    }
+
+    /// Writes the end-of-archive blocks and flushes.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Tar::Plain(b) => b.into_inner()?.flush(),
+            Tar::Zstd(b) => b.into_inner()?.finish()?.flush(),
+        }
+    }
+}
+
+/// The tar being filled, written to `.<name>.tmp` until it is complete.
+struct Current {
+    tar: Tar,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+/// Writes examples into numbered tars of `per_tar` examples each. Dropping it
+/// before `finish` removes the tars it wrote.
+pub struct TarWriter {
+    path: PathBuf,
+    per_tar: u64,
+    key: TarKey,
+    zstd_level: Option<i32>,
+    overwrite: bool,
+    mtime: u64,
+    current: Option<Current>,
+    done: Vec<PathBuf>,
+    examples: u64,
+    bytes: u64,
+    finished: bool,
+}
+
+impl TarWriter {
+    /// Tars are named after `path` by [`tar_path`]; `zstd_level` compresses each one.
+    pub fn create(
+        path: &Path,
+        per_tar: u64,
+        key: TarKey,
+        zstd_level: Option<i32>,
+        overwrite: bool,
+    ) -> Result<Self> {
+        ensure!(
+            per_tar > 0,
+            InvalidArgument,
+            "--examples-per-tar must be at least 1"
+        );
+        let first = tar_path(path, 0);
+        ensure!(
+            overwrite || !first.exists(),
+            InvalidArgument,
+            "{} already exists (pass --overwrite to replace it)",
+            first.display()
+        );
+        let mtime = source_date_epoch().map_or_else(
+            || {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            },
+            |t| t.max(0) as u64,
+        );
+        Ok(TarWriter {
+            path: path.to_path_buf(),
+            per_tar,
+            key,
+            zstd_level,
+            overwrite,
+            mtime,
+            current: None,
+            done: Vec::new(),
+            examples: 0,
+            bytes: 0,
+            finished: false,
+        })
+    }
+
+    fn open(&self) -> Result<Current> {
+        let path = tar_path(&self.path, self.done.len() as u64);
+        ensure!(
+            self.overwrite || !path.exists(),
+            InvalidArgument,
+            "{} already exists (pass --overwrite to replace it)",
+            path.display()
+        );
+        let tmp = tmp_path(&path)?;
+        let file = BufWriter::new(
+            File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?,
+        );
+        let tar = match self.zstd_level {
+            Some(level) => Tar::Zstd(tar::Builder::new(ZstdEncoder::new(file, level)?)),
+            None => Tar::Plain(tar::Builder::new(file)),
+        };
+        Ok(Current { tar, tmp, path })
+    }
+
+    pub fn write(&mut self, ex: &Example) -> Result<()> {
+        let key = match self.key {
+            TarKey::Index => format!("{:0width$}", self.examples, width = KEY_DIGITS),
+            TarKey::Id => {
+                ensure!(
+                    !ex.id.is_empty() && !ex.id.contains(['.', '/']),
+                    InvalidArgument,
+                    "example {} has id {:?}, which can't be a WebDataset key; use --tar-key index",
+                    self.examples,
+                    ex.id
+                );
+                ex.id.clone()
+            }
+        };
+        if self.current.is_none() {
+            self.current = Some(self.open()?);
+        }
+        let current = self.current.as_mut().expect("opened above");
+        let json = serde_json::to_vec(&Fields::of(ex))?;
+        current
+            .tar
+            .append(&format!("{key}.json"), &json, self.mtime)?;
+        current
+            .tar
+            .append(&format!("{key}.txt"), ex.text.as_bytes(), self.mtime)?;
+        self.examples += 1;
+        if self.examples.is_multiple_of(self.per_tar) {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    /// Completes the current tar and moves it into place.
+    fn close(&mut self) -> Result<()> {
+        let Some(Current { tar, tmp, path }) = self.current.take() else {
+            return Ok(());
+        };
+        tar.finish()
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("failed to move {} to {}", tmp.display(), path.display()))?;
+        self.bytes += fs::metadata(&path)?.len();
+        self.done.push(path);
+        Ok(())
+    }
+
+    /// Completes the last tar, returning the total size and number of tars.
+    pub fn finish(mut self) -> Result<(u64, u64)> {
+        self.close()?;
+        self.finished = true;
+        Ok((self.bytes, self.done.len() as u64))
+    }
+}
+
+impl Drop for TarWriter {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Some(current) = self.current.take() {
+            let _ = fs::remove_file(&current.tmp);
+        }
+        for path in &self.done {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads the tars of an export back in order, pairing each key's members into an
+/// `Example`; a key without exactly one `.json` and one `.txt` is an error.
+pub struct TarReader {
+    path: PathBuf,
+    next_tar: u64,
+    pending: VecDeque<Example>,
+}
+
+impl TarReader {
+    /// Reads the tars [`TarWriter`] wrote for `path`, stopping at the first missing number.
+    pub fn open(path: &Path) -> Self {
+        TarReader {
+            path: path.to_path_buf(),
+            next_tar: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let file = BufReader::new(
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?,
+        );
+        let input: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "zst") {
+            Box::new(ZstdDecoder::with_buffer(file)?)
+        } else {
+            Box::new(file)
+        };
+        let corrupt = |what: String| EthicsError::Corrupt(format!("{}: {what}", path.display()));
+
+        let mut archive = tar::Archive::new(input);
+        // Key, then the `.json` and `.txt` members seen for it so far.
+        let mut group: Option<(String, Option<Fields>, Option<String>)> = None;
+        let mut flush = |group: Option<(String, Option<Fields>, Option<String>)>| match group {
+            None => Ok(()),
+            Some((_, Some(fields), Some(text))) => {
+                self.pending.push_back(fields.with_text(text));
+                Ok(())
+            }
+            Some((key, fields, _)) => Err(corrupt(format!(
+                "{key} has no .{} member",
+                if fields.is_none() { "json" } else { "txt" }
+            ))),
+        };
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let Some((key, ext)) = name.split_once('.') else {
+                bail!(
+                    Corrupt,
+                    "{}: member {name} has no extension",
+                    path.display()
+                );
+            };
+            let mut data = String::new();
+            entry
+                .read_to_string(&mut data)
+                .with_context(|| format!("{}: member {name} is not UTF-8", path.display()))?;
+            if group.as_ref().is_none_or(|(k, _, _)| k != key) {
+                flush(group.take())?;
+                group = Some((key.to_string(), None, None));
+            }
+            let (_, fields, text) = group.as_mut().expect("set above");
+            match ext {
+                "json" if fields.is_none() => {
+                    *fields = Some(serde_json::from_str(&data).map_err(|e| {
+                        EthicsError::Corrupt(format!("{}: {name}: {e}", path.display()))
+                    })?)
+                }
+                "txt" if text.is_none() => *text = Some(data),
+                "json" | "txt" => bail!(Corrupt, "{}: {name} appears twice", path.display()),
+                _ => bail!(Corrupt, "{}: unexpected member {name}", path.display()),
+            }
+        }
+        flush(group)
+    }
+}
+
+impl Iterator for TarReader {
+    type Item = Result<Example>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(ex) = self.pending.pop_front() {
+                return Some(Ok(ex));
+            }
+            let path = tar_path(&self.path, self.next_tar);
+            if !path.exists() {
+                return None;
+            }
+            self.next_tar += 1;
+            if let Err(e) = self.load(&path) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn examples(n: usize) -> Vec<Example> {
+        (0..n)
+            .map(|i| Example {
+                subset: "commonsense".into(),
+                split: "train".into(),
+                text: format!("scenario {i}"),
+                label: (i % 2) as i32,
+                id: format!("cm-{i}"),
+                meta: BTreeMap::from([("n".to_string(), i.to_string())]),
+                ..Example::default()
+            })
+            .collect()
+    }
+
+    fn export(path: &Path, key: TarKey, zstd_level: Option<i32>, examples: &[Example]) -> u64 {
+        let mut tars = TarWriter::create(path, 2, key, zstd_level, false).unwrap();
+        for ex in examples {
+            tars.write(ex).unwrap();
+        }
+        tars.finish().unwrap().1
+    }
+
+    /// `(name, contents)` of every member of tar `n`, in order.
+    fn untar(path: &Path, n: u64) -> Vec<(String, Vec<u8>)> {
+        let file = File::open(tar_path(path, n)).unwrap();
+        let input: Box<dyn Read> = if path.to_string_lossy().ends_with(".zst") {
+            Box::new(ZstdDecoder::new(file).unwrap())
+        } else {
+            Box::new(file)
+        };
+        let mut archive = tar::Archive::new(input);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                assert_eq!(entry.header().mode().unwrap(), 0o644);
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (name, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn members_pair_up_by_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = examples(5);
+        for (name, zstd_level) in [("cm.tar", None), ("cm.tar.zst", Some(3))] {
+            let path = dir.path().join(name);
+            assert_eq!(export(&path, TarKey::Index, zstd_level, &written), 3);
+            assert!(!tar_path(&path, 3).exists());
+
+            let members: Vec<_> = (0..3).flat_map(|n| untar(&path, n)).collect();
+            assert_eq!(untar(&path, 2).len(), 2, "the last tar holds the remainder");
+            assert_eq!(members.len(), 2 * written.len());
+            for (pair, ex) in members.chunks(2).zip(&written) {
+                let [(json_name, json), (txt_name, txt)] = pair else {
+                    unreachable!()
+                };
+                let key = json_name.strip_suffix(".json").unwrap();
+                assert_eq!(key.len(), KEY_DIGITS);
+                assert_eq!(*txt_name, format!("{key}.txt"));
+                assert_eq!(txt, ex.text.as_bytes());
+                let fields: serde_json::Value = serde_json::from_slice(json).unwrap();
+                assert_eq!(fields["id"], ex.id.as_str());
+                assert_eq!(fields["label"], ex.label);
+                assert_eq!(fields["meta"]["n"], ex.meta["n"].as_str());
+            }
+            assert_eq!(members[8].0, "000000004.json");
+
+            let read: Vec<Example> = TarReader::open(&path).collect::<Result<_>>().unwrap();
+            assert_eq!(read, written);
+        }
+    }
+
+    #[test]
+    fn id_keys_name_members_after_the_example() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cm.tar");
+        export(&path, TarKey::Id, None, &examples(2));
+        let names: Vec<String> = untar(&path, 0).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["cm-0.json", "cm-0.txt", "cm-1.json", "cm-1.txt"]);
+
+        let bad = Example {
+            id: "a/b".into(),
+            ..Example::default()
+        };
+        let mut tars =
+            TarWriter::create(&dir.path().join("bad.tar"), 2, TarKey::Id, None, false).unwrap();
+        assert!(matches!(
+            tars.write(&bad),
+            Err(EthicsError::InvalidArgument(_))
+        ));
+    }
+}
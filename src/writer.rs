@@ -0,0 +1,707 @@
+//! `ExampleWriter`: the on-disk shard format, for the converter and for other
+//! programs emitting shards. The mirror image of [`crate::reader`].
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+#[cfg(feature = "cloud")]
+use crate::cloud::Upload;
+use crate::dict::Dictionary;
+#[cfg(not(feature = "cloud"))]
+use crate::error::bail;
+use crate::error::{ensure, Context, EthicsError, Result};
+use crate::ethics::ShardHeader;
+use crate::index::{FrameEntry, ShardIndex, INDEX_VERSION};
+use crate::input::{is_object_url, is_stdio};
+use crate::shard;
+
+/// zstd level shards are written at unless told otherwise.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 9;
+
+/// Hashes and counts everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where shard bytes end up: a temp file to be renamed, stdout for `-`, a
+/// caller's writer, or an object storage upload.
+enum Target<'a> {
+    File(File),
+    Stdout(io::StdoutLock<'static>),
+    Writer(&'a mut dyn Write),
+    #[cfg(feature = "cloud")]
+    Upload(Upload),
+}
+
+impl Write for Target<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Target::File(f) => f.write(buf),
+            Target::Stdout(s) => s.write(buf),
+            Target::Writer(w) => w.write(buf),
+            #[cfg(feature = "cloud")]
+            Target::Upload(u) => u.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Target::File(f) => f.flush(),
+            Target::Stdout(s) => s.flush(),
+            Target::Writer(w) => w.flush(),
+            #[cfg(feature = "cloud")]
+            Target::Upload(u) => u.flush(),
+        }
+    }
+}
+
+/// Compressed or raw stream behind an `ExampleWriter`.
+enum Sink<'a> {
+    Zstd(ZstdEncoder<'static, HashingWriter<Target<'a>>>),
+    Raw(BufWriter<HashingWriter<Target<'a>>>),
+}
+
+/// Where an `ExampleWriterBuilder` will write.
+enum Dest<'a> {
+    Path(PathBuf),
+    Writer(&'a mut dyn Write),
+}
+
+/// Settings shared by the builder and the writer it opens.
+struct Settings<'a> {
+    /// `None` writes uncompressed `.pb` output.
+    zstd_level: Option<i32>,
+    zstd_workers: u32,
+    zstd_long: Option<u32>,
+    dict: Option<&'a Dictionary>,
+    frame_every: Option<u64>,
+    checksums: bool,
+    header: Option<ShardHeader>,
+    overwrite: bool,
+    /// Keep the temp file of an unfinished writer, for `--resume`.
+    keep_unfinished: bool,
+}
+
+/// Configures an `ExampleWriter`; start with `ExampleWriter::create` or
+/// `ExampleWriter::to_writer`. Defaults match the converter without flags:
+/// zstd level 9, a header, no checksums, one frame.
+pub struct ExampleWriterBuilder<'a> {
+    dest: Dest<'a>,
+    settings: Settings<'a>,
+}
+
+impl<'a> ExampleWriterBuilder<'a> {
+    fn new(dest: Dest<'a>) -> Self {
+        ExampleWriterBuilder {
+            dest,
+            settings: Settings {
+                zstd_level: Some(DEFAULT_ZSTD_LEVEL),
+                zstd_workers: 0,
+                zstd_long: None,
+                dict: None,
+                frame_every: None,
+                checksums: false,
+                header: Some(shard::header("ethics.v1.Example", "", "")),
+                overwrite: false,
+                keep_unfinished: false,
+            },
+        }
+    }
+
+    /// zstd compression level (0 selects zstd's default).
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.settings.zstd_level = Some(level);
+        self
+    }
+
+    /// Writes raw length-delimited protobuf without the zstd wrapper.
+    pub fn uncompressed(mut self) -> Self {
+        self.settings.zstd_level = None;
+        self
+    }
+
+    /// Compresses on `n` zstd worker threads; 0 compresses on the writing thread.
+    pub fn zstd_workers(mut self, n: u32) -> Self {
+        self.settings.zstd_workers = n;
+        self
+    }
+
+    /// Enables long-distance matching with a 2^`log` byte window.
+    pub fn zstd_long(mut self, log: u32) -> Self {
+        self.settings.zstd_long = Some(log);
+        self
+    }
+
+    /// Compresses with `dict`; its sha256 goes into the header.
+    pub fn dict(mut self, dict: &'a Dictionary) -> Self {
+        self.settings.dict = Some(dict);
+        self
+    }
+
+    /// Starts a new zstd frame every `n` examples and writes the frame table to
+    /// `<shard>.idx` on `finish`.
+    pub fn frame_every(mut self, n: u64) -> Self {
+        self.settings.frame_every = Some(n);
+        self
+    }
+
+    /// Follows every record with a CRC32, announced in the header.
+    pub fn checksums(mut self, on: bool) -> Self {
+        self.settings.checksums = on;
+        self
+    }
+
+    /// Starts the shard with `header` instead of the default one. Its `checksums`
+    /// and `dict_sha256` are set to match the writer.
+    pub fn with_header(mut self, header: ShardHeader) -> Self {
+        self.settings.header = Some(header);
+        self
+    }
+
+    /// Leaves the header out, for consumers that expect nothing but records.
+    pub fn without_header(mut self) -> Self {
+        self.settings.header = None;
+        self
+    }
+
+    /// Replaces an existing file instead of refusing to.
+    pub fn overwrite(mut self, on: bool) -> Self {
+        self.settings.overwrite = on;
+        self
+    }
+
+    /// Keeps the temp file when the writer is dropped unfinished, so it can be
+    /// reopened with `resume`.
+    pub(crate) fn keep_unfinished(mut self, on: bool) -> Self {
+        self.settings.keep_unfinished = on;
+        self
+    }
+
+    /// Opens the shard and writes its header.
+    pub fn open(self) -> Result<ExampleWriter<'a>> {
+        let mut settings = self.settings;
+        ensure!(
+            !settings.checksums || settings.header.is_some(),
+            InvalidArgument,
+            "checksums are announced in the shard header and need one"
+        );
+        ensure!(
+            settings.frame_every.is_none() || settings.zstd_level.is_some(),
+            InvalidArgument,
+            "frame_every needs compression"
+        );
+        if let Some(header) = &mut settings.header {
+            header.checksums = settings.checksums;
+            header.dict_sha256 = settings.dict.map(|d| d.sha256.clone());
+        }
+        let (target, tmp, path) = match self.dest {
+            Dest::Path(path) if is_stdio(&path) => {
+                (Target::Stdout(io::stdout().lock()), None, path)
+            }
+            #[cfg(feature = "cloud")]
+            Dest::Path(path) if is_object_url(&path) => {
+                ensure!(
+                    settings.frame_every.is_none(),
+                    InvalidArgument,
+                    "frame_every writes an index sidecar and needs a local shard file"
+                );
+                let upload = Upload::start(&path, settings.overwrite)?;
+                (Target::Upload(upload), None, path)
+            }
+            #[cfg(not(feature = "cloud"))]
+            Dest::Path(path) if is_object_url(&path) => bail!(
+                InvalidArgument,
+                "{} is an object storage URL; writing there needs the `cloud` feature",
+                path.display()
+            ),
+            Dest::Path(path) => {
+                ensure!(
+                    settings.overwrite || !path.exists(),
+                    InvalidArgument,
+                    "{} already exists (pass --overwrite to replace it)",
+                    path.display()
+                );
+                let tmp = tmp_path(&path)?;
+                let out = File::create(&tmp)
+                    .with_context(|| format!("failed to create {}", tmp.display()))?;
+                (Target::File(out), Some(tmp), path)
+            }
+            Dest::Writer(w) => {
+                ensure!(
+                    settings.frame_every.is_none(),
+                    InvalidArgument,
+                    "frame_every writes an index sidecar and needs a shard file"
+                );
+                (Target::Writer(w), None, PathBuf::new())
+            }
+        };
+        let header = settings.header.clone();
+        let out = HashingWriter {
+            inner: target,
+            hasher: Sha256::new(),
+            bytes: 0,
+        };
+        let mut writer = ExampleWriter::with_output(out, tmp, path, settings, Vec::new(), 0)?;
+        if let Some(header) = &header {
+            // Inside the first zstd frame, so frame 0 of the index still starts at offset 0.
+            writer.write_raw(|w| shard::write_header(w, header))?;
+        }
+        Ok(writer)
+    }
+
+    /// Reopens the temp file of an interrupted run, cutting it back to `bytes`
+    /// (always a frame boundary, after the header) and re-hashing what is kept.
+    pub(crate) fn resume(
+        self,
+        bytes: u64,
+        frames: Vec<FrameEntry>,
+        examples: u64,
+    ) -> Result<ExampleWriter<'a>> {
+        let Dest::Path(path) = self.dest else {
+            unreachable!("only files are resumed")
+        };
+        ensure!(
+            !is_object_url(&path),
+            InvalidArgument,
+            "cannot resume an upload to {}",
+            path.display()
+        );
+        let tmp = tmp_path(&path)?;
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&tmp)
+            .with_context(|| format!("cannot resume: failed to open {}", tmp.display()))?;
+        ensure!(
+            file.metadata()?.len() >= bytes,
+            Corrupt,
+            "cannot resume: {} is shorter than the recorded {bytes} bytes",
+            tmp.display()
+        );
+        file.set_len(bytes)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut (&mut file).take(bytes), &mut hasher)?;
+        file.seek(io::SeekFrom::End(0))?;
+        let out = HashingWriter {
+            inner: Target::File(file),
+            hasher,
+            bytes,
+        };
+        ExampleWriter::with_output(out, Some(tmp), path, self.settings, frames, examples)
+    }
+}
+
+/// Writes one shard: the header, then length-delimited records, optionally
+/// checksummed and split into zstd frames. File data goes to `.<name>.tmp` beside
+/// the final path and is renamed into place only once `finish` succeeds; dropping
+/// an unfinished writer warns and removes the temp file, so a crash never leaves
+/// a truncated shard behind.
+pub struct ExampleWriter<'a> {
+    sink: Option<Sink<'a>>,
+    /// `None` for stdout and caller-supplied writers.
+    tmp: Option<PathBuf>,
+    path: PathBuf,
+    settings: Settings<'a>,
+    frames: Vec<FrameEntry>,
+    examples: u64,
+    /// Reused for encoding in `write`.
+    buf: Vec<u8>,
+}
+
+impl<'a> ExampleWriter<'a> {
+    /// A shard at `path` (`-` for stdout).
+    pub fn create(path: &Path) -> ExampleWriterBuilder<'a> {
+        ExampleWriterBuilder::new(Dest::Path(path.to_path_buf()))
+    }
+
+    /// A shard written to `out`, which gets no temp file, rename or index.
+    pub fn to_writer(out: &'a mut dyn Write) -> ExampleWriterBuilder<'a> {
+        ExampleWriterBuilder::new(Dest::Writer(out))
+    }
+
+    fn with_output(
+        out: HashingWriter<Target<'a>>,
+        tmp: Option<PathBuf>,
+        path: PathBuf,
+        settings: Settings<'a>,
+        frames: Vec<FrameEntry>,
+        examples: u64,
+    ) -> Result<Self> {
+        let sink = Some(Self::sink(out, &settings)?);
+        Ok(ExampleWriter {
+            sink,
+            tmp,
+            path,
+            settings,
+            frames,
+            examples,
+            buf: Vec::new(),
+        })
+    }
+
+    fn sink(out: HashingWriter<Target<'a>>, settings: &Settings) -> Result<Sink<'a>> {
+        Ok(match settings.zstd_level {
+            Some(level) => Sink::Zstd(Self::encoder(out, level, settings)?),
+            None => Sink::Raw(BufWriter::new(out)),
+        })
+    }
+
+    /// A zstd encoder starting a new frame at the current end of `out`.
+    fn encoder(
+        out: HashingWriter<Target<'a>>,
+        level: i32,
+        settings: &Settings,
+    ) -> Result<ZstdEncoder<'static, HashingWriter<Target<'a>>>> {
+        let mut enc = match settings.dict {
+            Some(dict) => ZstdEncoder::with_dictionary(out, level, &dict.bytes)?,
+            None => ZstdEncoder::new(out, level)?,
+        };
+        if settings.zstd_workers > 0 {
+            enc.multithread(settings.zstd_workers)?;
+        }
+        if let Some(log) = settings.zstd_long {
+            enc.long_distance_matching(true)?;
+            enc.window_log(log)?;
+        }
+        Ok(enc)
+    }
+
+    /// Bytes a length-delimited record of `len` bytes takes up in the
+    /// uncompressed stream, checksum included.
+    pub fn record_len(&self, len: usize) -> u64 {
+        len as u64
+            + if self.settings.checksums {
+                shard::CHECKSUM_LEN
+            } else {
+                0
+            }
+    }
+
+    /// Examples written so far.
+    pub fn examples(&self) -> u64 {
+        self.examples
+    }
+
+    /// Encodes and appends one message.
+    pub fn write(&mut self, msg: &impl Message) -> Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        msg.encode_length_delimited(&mut buf)
+            .expect("Vec grows as needed");
+        let result = self.write_encoded(&buf);
+        self.buf = buf;
+        result
+    }
+
+    /// Appends one record as returned by [`ShardReader::next_record`](crate::shard::ShardReader::next_record),
+    /// i.e. without its length prefix, copying it through undecoded.
+    pub fn write_record(&mut self, record: &[u8]) -> Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        prost::encoding::encode_varint(record.len() as u64, &mut buf);
+        buf.extend_from_slice(record);
+        let result = self.write_encoded(&buf);
+        self.buf = buf;
+        result
+    }
+
+    /// Appends one record that is already length-delimited, first closing the
+    /// current frame if it is full.
+    pub fn write_encoded(&mut self, buf: &[u8]) -> Result<()> {
+        if let (Some(every), Some(level)) = (self.settings.frame_every, self.settings.zstd_level) {
+            let full = self.frames.last().is_none_or(|f| f.count >= every);
+            if full {
+                // The first frame is the one `open` started; later ones close the previous frame.
+                let mut offset = 0;
+                if !self.frames.is_empty() {
+                    let Some(Sink::Zstd(enc)) = self.sink.take() else {
+                        unreachable!("frame_every requires compression")
+                    };
+                    let out = enc.finish()?;
+                    offset = out.bytes;
+                    self.sink = Some(Sink::Zstd(Self::encoder(out, level, &self.settings)?));
+                }
+                self.frames.push(FrameEntry {
+                    offset,
+                    first: self.examples,
+                    count: 0,
+                });
+            }
+            self.frames.last_mut().unwrap().count += 1;
+        }
+        self.write_raw(|w| w.write_all(buf))?;
+        if self.settings.checksums {
+            // `buf` starts with the length prefix; the checksum covers the message only.
+            let len = prost::decode_length_delimiter(buf).map_err(|_| {
+                EthicsError::InvalidArgument("record is not length-delimited".into())
+            })?;
+            let checksum = shard::checksum(&buf[buf.len() - len..]);
+            self.write_raw(|w| w.write_all(&checksum))?;
+        }
+        self.examples += 1;
+        Ok(())
+    }
+
+    /// Hands the open stream to `f`.
+    fn write_raw(&mut self, f: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> Result<()> {
+        match self.sink.as_mut().expect("write after finish") {
+            Sink::Zstd(w) => f(w)?,
+            Sink::Raw(w) => f(w)?,
+        }
+        Ok(())
+    }
+
+    /// Ends the current zstd frame and fsyncs the temp file, returning how many of its
+    /// bytes are safe to resume from. Later examples go into a new frame.
+    pub(crate) fn checkpoint(&mut self) -> Result<u64> {
+        let mut out = match self.sink.take().expect("checkpoint after finish") {
+            Sink::Zstd(w) => w.finish()?,
+            Sink::Raw(w) => w.into_inner().map_err(|e| e.into_error())?,
+        };
+        out.flush()?;
+        if let Target::File(f) = &out.inner {
+            f.sync_all()?;
+        }
+        let bytes = out.bytes;
+        // Keep the frame table in step with the frame just started.
+        if self.settings.frame_every.is_some() && self.frames.last().is_some_and(|f| f.count > 0) {
+            self.frames.push(FrameEntry {
+                offset: bytes,
+                first: self.examples,
+                count: 0,
+            });
+        }
+        self.sink = Some(Self::sink(out, &self.settings)?);
+        Ok(bytes)
+    }
+
+    /// The frame table so far, with `frame_every`.
+    pub(crate) fn frames(&self) -> &[FrameEntry] {
+        &self.frames
+    }
+
+    /// Flushes and finalizes the stream and moves it into place, returning the
+    /// on-disk size and hex SHA-256.
+    pub fn finish(mut self) -> Result<(u64, String)> {
+        let mut out = match self.sink.take().expect("finish called twice") {
+            Sink::Zstd(w) => w.finish()?,
+            Sink::Raw(w) => w.into_inner().map_err(|e| e.into_error())?,
+        };
+        out.flush()?;
+        #[cfg(feature = "cloud")]
+        if let Target::Upload(upload) = &mut out.inner {
+            upload.complete()?;
+        }
+        if let (Target::File(f), Some(tmp)) = (&out.inner, &self.tmp) {
+            f.sync_all()?;
+            fs::rename(tmp, &self.path).with_context(|| {
+                format!(
+                    "failed to move {} to {}",
+                    tmp.display(),
+                    self.path.display()
+                )
+            })?;
+        }
+        let sha256 = format!("{:x}", out.hasher.finalize());
+        if self.settings.frame_every.is_some() {
+            let index = ShardIndex {
+                version: INDEX_VERSION,
+                shard_bytes: out.bytes,
+                shard_sha256: sha256.clone(),
+                frames: std::mem::take(&mut self.frames),
+            };
+            index.write(&ShardIndex::path_for(&self.path))?;
+        }
+        Ok((out.bytes, sha256))
+    }
+}
+
+impl Drop for ExampleWriter<'_> {
+    fn drop(&mut self) {
+        if self.sink.take().is_none() {
+            return;
+        }
+        let name = match &self.tmp {
+            Some(_) => self.path.display().to_string(),
+            None if is_stdio(&self.path) => "<stdout>".to_string(),
+            None if is_object_url(&self.path) => self.path.display().to_string(),
+            None => "<writer>".to_string(),
+        };
+        match &self.tmp {
+            // `--resume` keeps the temp file for the next run to continue.
+            Some(tmp) if self.settings.keep_unfinished => warn!(
+                "shard {name} was not finished; keeping {} to resume from",
+                tmp.display()
+            ),
+            Some(tmp) => {
+                let _ = fs::remove_file(tmp);
+                warn!(
+                    "shard {name} was not finished; discarded its {} example(s)",
+                    self.examples
+                );
+            }
+            // The upload was aborted when the sink was dropped above.
+            None if is_object_url(&self.path) => warn!(
+                "shard {name} was not finished; aborted its upload after {} example(s)",
+                self.examples
+            ),
+            None => warn!(
+                "shard {name} was not finished; the stream is truncated after {} example(s)",
+                self.examples
+            ),
+        }
+    }
+}
+
+/// `dir/name` -> `dir/.name.tmp`.
+pub(crate) fn tmp_path(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        EthicsError::InvalidArgument(format!("{} is not a file path", path.display()))
+    })?;
+    Ok(path.with_file_name(format!(".{}.tmp", name.to_string_lossy())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::ethics::Example;
+    use crate::reader::ExampleReader;
+
+    fn examples(n: usize) -> Vec<Example> {
+        (0..n)
+            .map(|i| Example {
+                subset: "virtue".into(),
+                split: "test".into(),
+                text: format!("example {i} [SEP] kind"),
+                label: (i % 2) as i32,
+                id: format!("id-{i}"),
+                meta: BTreeMap::from([("n".to_string(), i.to_string())]),
+                ..Example::default()
+            })
+            .collect()
+    }
+
+    fn read_all(reader: ExampleReader) -> Vec<Example> {
+        reader.collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn file_round_trip_with_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("virtue-test.pb.zst");
+        let written = examples(250);
+        let header = shard::header("ethics.v1.Example", "virtue", "test");
+        let mut writer = ExampleWriter::create(&path)
+            .zstd_level(3)
+            .frame_every(100)
+            .checksums(true)
+            .with_header(header)
+            .open()
+            .unwrap();
+        for ex in &written {
+            writer.write(ex).unwrap();
+        }
+        assert_eq!(writer.examples(), 250);
+        // Nothing at the final path until `finish`.
+        assert!(!path.exists());
+        let (bytes, sha256) = writer.finish().unwrap();
+
+        let on_disk = fs::read(&path).unwrap();
+        assert_eq!(bytes, on_disk.len() as u64);
+        assert_eq!(sha256, format!("{:x}", Sha256::digest(&on_disk)));
+
+        let reader = ExampleReader::open(&path).unwrap();
+        let header = reader.header().unwrap();
+        assert_eq!(
+            (header.subset.as_str(), header.split.as_str()),
+            ("virtue", "test")
+        );
+        assert!(header.checksums);
+        assert_eq!(read_all(reader), written);
+
+        let indexed = ExampleReader::open_indexed(&path, None).unwrap();
+        assert_eq!(indexed.get(217).unwrap().as_ref(), Some(&written[217]));
+        assert_eq!(indexed.get(250).unwrap(), None);
+        let range: Vec<Example> = indexed
+            .range(99..102)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(range, written[99..102]);
+    }
+
+    #[test]
+    fn in_memory_round_trip() {
+        let written = examples(20);
+        for uncompressed in [false, true] {
+            let mut buf = Vec::new();
+            let builder = ExampleWriter::to_writer(&mut buf).without_header();
+            let mut writer = if uncompressed {
+                builder.uncompressed()
+            } else {
+                builder
+            }
+            .open()
+            .unwrap();
+            for ex in &written {
+                writer.write(ex).unwrap();
+            }
+            writer.finish().unwrap();
+            let reader = ExampleReader::from_reader(Cursor::new(buf)).unwrap();
+            assert!(reader.header().is_none());
+            assert_eq!(read_all(reader), written);
+        }
+    }
+
+    #[test]
+    fn unfinished_writers_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dropped.pb.zst");
+        let mut writer = ExampleWriter::create(&path).open().unwrap();
+        writer.write(&examples(1)[0]).unwrap();
+        drop(writer);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn refuses_to_replace_without_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("existing.pb.zst");
+        fs::write(&path, b"keep me").unwrap();
+        let err = ExampleWriter::create(&path).open().err().unwrap();
+        assert!(matches!(err, EthicsError::InvalidArgument(_)), "{err}");
+        assert_eq!(fs::read(&path).unwrap(), b"keep me");
+
+        ExampleWriter::create(&path)
+            .overwrite(true)
+            .open()
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(read_all(ExampleReader::open(&path).unwrap()), []);
+    }
+}
@@ -0,0 +1,112 @@
+//! `convert_stream` end to end: JSONL in from a reader, a shard out to a buffer,
+//! decoded again with `ShardReader`.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use protobuf_ethics::convert::{convert_stream, ConvertOptions, RunState};
+use protobuf_ethics::ethics::{Example, ShardHeader};
+use protobuf_ethics::shard::ShardReader;
+
+const FIXTURE: &str = r#"{"scenario": "I took the last cookie without asking.", "label": 1}
+{"scenario": "I returned the wallet I found.", "label": 0, "rationale": "honest"}
+
+{"scenario": "I read my sister's diary.", "label": "1"}
+not json
+{"scenario": "", "label": 0}
+"#;
+
+fn convert(opts: &ConvertOptions) -> (protobuf_ethics::manifest::ShardInfo, Vec<u8>) {
+    let mut out = Vec::new();
+    let info = convert_stream(
+        Cursor::new(FIXTURE),
+        Path::new("cm-train.jsonl"),
+        ("commonsense", "train"),
+        opts,
+        &RunState::default(),
+        &mut out,
+    )
+    .unwrap();
+    (info, out)
+}
+
+/// The header and examples of `shard`, read back through a temporary file.
+fn decode(shard: &[u8]) -> (Option<ShardHeader>, Vec<Example>) {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), shard).unwrap();
+    let mut reader = ShardReader::open(file.path(), None).unwrap();
+    let examples = std::iter::from_fn(|| reader.next_example().unwrap()).collect();
+    (reader.header, examples)
+}
+
+#[test]
+fn converts_a_reader_into_a_decodable_shard() {
+    let (info, shard) = convert(&ConvertOptions::default());
+    assert_eq!(info.input, Path::new("cm-train.jsonl"));
+    assert_eq!(
+        (info.subset.as_str(), info.split.as_str()),
+        ("commonsense", "train")
+    );
+    assert_eq!(info.bytes, shard.len() as u64);
+    assert_eq!(info.counts.lines_read, 6);
+    assert_eq!(info.counts.lines_skipped, 1);
+    assert_eq!(info.counts.examples, 3);
+    assert_eq!(info.counts.rejected.get("parse_error"), Some(&1));
+    assert_eq!(info.counts.rejected.get("empty_text"), Some(&1));
+
+    let (header, examples) = decode(&shard);
+    let header = header.unwrap();
+    assert_eq!(
+        (header.subset.as_str(), header.split.as_str()),
+        ("commonsense", "train")
+    );
+
+    let texts: Vec<(&str, i32)> = examples
+        .iter()
+        .map(|ex| (ex.text.as_str(), ex.label))
+        .collect();
+    assert_eq!(
+        texts,
+        [
+            ("I took the last cookie without asking.", 1),
+            ("I returned the wallet I found.", 0),
+            ("I read my sister's diary.", 1),
+        ]
+    );
+    assert_eq!(examples[1].meta["rationale"], "honest");
+    assert!(examples
+        .iter()
+        .all(|ex| ex.subset == "commonsense" && ex.split == "train"));
+}
+
+#[test]
+fn uncompressed_output_decodes_the_same() {
+    let compressed = ConvertOptions::default();
+    let raw = ConvertOptions {
+        zstd_level: None,
+        header: compressed.header.clone(),
+        ..ConvertOptions::default()
+    };
+    let (_, zst) = convert(&compressed);
+    let (_, pb) = convert(&raw);
+    assert_eq!(decode(&zst).1, decode(&pb).1);
+}
+
+#[test]
+fn refuses_options_that_need_files() {
+    let opts = ConvertOptions {
+        resume: true,
+        ..ConvertOptions::default()
+    };
+    let mut out = Vec::new();
+    let err = convert_stream(
+        Cursor::new(FIXTURE),
+        Path::new("cm-train.jsonl"),
+        ("commonsense", "train"),
+        &opts,
+        &RunState::default(),
+        &mut out,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("resume"), "{err}");
+}
@@ -0,0 +1,109 @@
+//! Library callers can tell failures apart by matching on `EthicsError`.
+
+use std::error::Error as _;
+use std::io::{Cursor, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+use protobuf_ethics::convert::{
+    convert_stream, parse_label_map, run_job, ConvertOptions, Job, RunState,
+};
+use protobuf_ethics::error::EthicsError;
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::reader::ExampleReader;
+
+/// What a caller might do with each kind of failure.
+fn classify(e: &EthicsError) -> String {
+    match e.root() {
+        EthicsError::Io { source, .. } if source.kind() == ErrorKind::NotFound => "missing".into(),
+        EthicsError::JsonParse { line, .. } => format!("bad json on line {line}"),
+        EthicsError::Decode { offset, .. } => format!("bad record at {offset}"),
+        EthicsError::Corrupt(_) => "corrupt".into(),
+        EthicsError::LabelInvalid(_) => "bad label".into(),
+        other => format!("other: {other}"),
+    }
+}
+
+#[test]
+fn missing_input_is_not_found() {
+    let job = Job {
+        input: PathBuf::from("does/not/exist.jsonl"),
+        subset: "commonsense".into(),
+        split: "train".into(),
+        out: std::env::temp_dir().join("never-written.pb.zst"),
+    };
+    let err = run_job(&job, &ConvertOptions::default(), &RunState::default())
+        .err()
+        .unwrap();
+    assert_eq!(classify(&err), "missing");
+    // The context added on the way up is kept in the message.
+    assert!(err.to_string().contains("does/not/exist.jsonl"), "{err}");
+}
+
+#[test]
+fn malformed_json_carries_its_line() {
+    let opts = ConvertOptions {
+        strict: true,
+        ..ConvertOptions::default()
+    };
+    let jsonl = "{\"scenario\": \"fine\"}\n{\"scenario\": \"unterminated}\n";
+    let err = convert_stream(
+        Cursor::new(jsonl),
+        Path::new("cm-train.jsonl"),
+        ("commonsense", "train"),
+        &opts,
+        &RunState::default(),
+        &mut Vec::new(),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(classify(&err), "bad json on line 2");
+    assert!(err.to_string().contains("cm-train.jsonl:2"), "{err}");
+    assert!(err.root().source().unwrap().is::<serde_json::Error>());
+}
+
+/// An uncompressed, headerless shard holding `records` as length-delimited messages.
+fn raw_shard(records: &[&[u8]]) -> Vec<u8> {
+    let mut shard = Vec::new();
+    for record in records {
+        prost::encoding::encode_varint(record.len() as u64, &mut shard);
+        shard.extend_from_slice(record);
+    }
+    shard
+}
+
+#[test]
+fn undecodable_records_carry_their_offset() {
+    let good = Example {
+        text: "ok".into(),
+        ..Example::default()
+    }
+    .encode_to_vec();
+    // Field 1 with wire type 7, which doesn't exist.
+    let shard = raw_shard(&[&good, &[0x0f, 0x00]]);
+    let mut reader = ExampleReader::from_reader(Cursor::new(shard)).unwrap();
+    assert_eq!(reader.next().unwrap().unwrap().text, "ok");
+    let err = reader.next().unwrap().unwrap_err();
+    assert_eq!(classify(&err), format!("bad record at {}", good.len() + 1));
+}
+
+#[test]
+fn truncated_shards_are_corrupt() {
+    let mut shard = raw_shard(&[b"\x1a\x02ok"]);
+    shard.truncate(shard.len() - 1);
+    let err = ExampleReader::from_reader(Cursor::new(shard))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(classify(&err), "corrupt");
+    assert!(err.to_string().contains("truncated"), "{err}");
+}
+
+#[test]
+fn bad_label_maps_are_label_errors() {
+    for spec in ["acceptable", "acceptable=yes"] {
+        let err = parse_label_map(spec).err().unwrap();
+        assert_eq!(classify(&err), "bad label", "{spec}");
+    }
+}
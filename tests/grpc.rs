@@ -0,0 +1,146 @@
+//! The `server` binary on a free port, driven through the generated client.
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+use protobuf_ethics::ethics::Example;
+use protobuf_ethics::writer::ExampleWriter;
+use tonic::transport::Channel;
+use tonic::Code;
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/grpc/ethics.v1.rs"));
+}
+
+use pb::example_service_client::ExampleServiceClient;
+use pb::{GetShardInfoRequest, ListShardsRequest, StreamExamplesRequest};
+
+/// Kills the server when the test ends, passing or not.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts the server on `root` at port 0 and returns it with the address it logged.
+fn serve(root: &Path) -> (Server, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg(root)
+        .args(["--addr", "127.0.0.1:0", "--log-level", "info"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    let server = Server(child);
+    for line in stderr.lines() {
+        let line = line.unwrap();
+        if let Some((_, addr)) = line
+            .rsplit_once(" on ")
+            .filter(|_| line.contains("serving"))
+        {
+            return (server, format!("http://{}", addr.trim()));
+        }
+    }
+    panic!("the server exited before listening");
+}
+
+fn fixture(root: &Path) -> Vec<Example> {
+    let examples: Vec<Example> = (0..50)
+        .map(|i| Example {
+            subset: "justice".into(),
+            split: "test".into(),
+            text: format!("I deserve {i} cookies because I baked them."),
+            label: i % 2,
+            id: format!("j-{i}"),
+            ..Example::default()
+        })
+        .collect();
+    std::fs::create_dir_all(root.join("justice")).unwrap();
+    let header = protobuf_ethics::shard::header("ethics.v1.Example", "justice", "test");
+    let mut writer = ExampleWriter::create(&root.join("justice/test-00000.pb.zst"))
+        .with_header(header)
+        .open()
+        .unwrap();
+    for ex in &examples {
+        writer.write(ex).unwrap();
+    }
+    writer.finish().unwrap();
+    examples
+}
+
+async fn collect(
+    client: &mut ExampleServiceClient<Channel>,
+    req: StreamExamplesRequest,
+) -> Result<Vec<Example>, tonic::Status> {
+    let mut stream = client.stream_examples(req).await?.into_inner();
+    let mut examples = Vec::new();
+    while let Some(ex) = stream.message().await? {
+        examples.push(ex);
+    }
+    Ok(examples)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_a_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let examples = fixture(dir.path());
+    let (_server, addr) = serve(dir.path());
+    let mut client = ExampleServiceClient::connect(addr).await.unwrap();
+    let shard = "justice/test-00000.pb.zst".to_string();
+
+    let listed = client.list_shards(ListShardsRequest {}).await.unwrap();
+    assert_eq!(listed.into_inner().shards, std::slice::from_ref(&shard));
+
+    let info = client
+        .get_shard_info(GetShardInfoRequest {
+            shard: shard.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.examples, 50);
+    assert!(!info.indexed);
+    assert_eq!(info.header.unwrap().subset, "justice");
+
+    let all = StreamExamplesRequest {
+        shard: shard.clone(),
+        ..StreamExamplesRequest::default()
+    };
+    assert_eq!(collect(&mut client, all).await.unwrap(), examples);
+
+    let some = StreamExamplesRequest {
+        shard: shard.clone(),
+        start_index: 10,
+        limit: 3,
+        label_filter: vec![1],
+    };
+    let ids: Vec<String> = collect(&mut client, some)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|ex| ex.id)
+        .collect();
+    assert_eq!(ids, ["j-11", "j-13", "j-15"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bad_shard_names_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    fixture(dir.path());
+    let (_server, addr) = serve(dir.path());
+    let mut client = ExampleServiceClient::connect(addr).await.unwrap();
+    for (shard, code) in [
+        ("../outside.pb.zst", Code::InvalidArgument),
+        ("justice/missing.pb.zst", Code::NotFound),
+    ] {
+        let req = StreamExamplesRequest {
+            shard: shard.into(),
+            ..StreamExamplesRequest::default()
+        };
+        assert_eq!(collect(&mut client, req).await.unwrap_err().code(), code);
+    }
+}
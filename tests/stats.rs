@@ -0,0 +1,146 @@
+//! `calculate_raw_text_length_stats` run end to end on small fixtures.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Runs the stats tool with `args`, failing the test if it fails.
+fn stats(args: &[&str]) {
+    let out = Command::new(env!("CARGO_BIN_EXE_calculate_raw_text_length_stats"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+fn write_jsonl(dir: &Path, name: &str, texts: &[&str]) {
+    let lines: Vec<String> = texts
+        .iter()
+        .map(|text| serde_json::json!({"text": text, "label": 0}).to_string())
+        .collect();
+    fs::write(dir.join(name), lines.join("\n") + "\n").unwrap();
+}
+
+#[test]
+fn csv_columns_and_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    write_jsonl(
+        dir.path(),
+        "a.jsonl",
+        &["one two", "three", "four five six"],
+    );
+    write_jsonl(dir.path(), "b.jsonl", &["seven", "eight nine"]);
+    let out = dir.path().join("stats");
+    stats(&[
+        "--glob",
+        dir.path().join("*.jsonl").to_str().unwrap(),
+        "--out",
+        out.to_str().unwrap(),
+        "--format",
+        "csv",
+        "--unit",
+        "bytes,words",
+        "--percentiles",
+        "0.5,0.9",
+    ]);
+
+    let csv = fs::read_to_string(out.with_extension("csv")).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("file,unit,count,min,max,mean,std,p50,p90")
+    );
+    let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+    // Exact everywhere: which row is which, counts, min and max.
+    let exact: Vec<String> = rows.iter().map(|row| row[..5].join(",")).collect();
+    assert_eq!(
+        exact,
+        [
+            "a.jsonl,bytes,3,5,13",
+            "a.jsonl,words,3,1,3",
+            "b.jsonl,bytes,2,5,10",
+            "b.jsonl,words,2,1,2",
+            "__overall__,bytes,5,5,13",
+            "__overall__,words,5,1,3",
+        ]
+    );
+    // Means and per-file percentiles are exact up to float rounding; overall
+    // percentiles come from the sketch.
+    let expected: [(f64, f64, f64, f64); 6] = [
+        (25.0 / 3.0, 7.0, 11.8, 0.0),
+        (2.0, 2.0, 2.8, 0.0),
+        (7.5, 7.5, 9.5, 0.0),
+        (1.5, 1.5, 1.9, 0.0),
+        (8.0, 7.0, 11.8, 0.005),
+        (1.8, 2.0, 2.6, 0.005),
+    ];
+    for (row, (mean, p50, p90, rel)) in rows.iter().zip(expected) {
+        let value = |i: usize| row[i].parse::<f64>().unwrap();
+        assert!((value(5) - mean).abs() < 1e-9, "{row:?}");
+        assert!((value(7) - p50).abs() <= 1e-9 + p50 * rel, "{row:?}");
+        assert!((value(8) - p90).abs() <= 1e-9 + p90 * rel, "{row:?}");
+    }
+}
+
+/// Writes `count` files of random texts and labels, named `<subset>-NNN.jsonl`.
+fn write_random_files(dir: &Path, count: usize, seed: u64) {
+    let words = [
+        "the", "a", "kept", "promise", "friend", "lied", "helped", "stranger", "money",
+    ];
+    let mut rng = StdRng::seed_from_u64(seed);
+    for i in 0..count {
+        let subset = ["commonsense", "justice", "virtue"][i % 3];
+        let mut jsonl = String::new();
+        for _ in 0..rng.random_range(0..=2000) {
+            // Skewed towards short texts, with a long tail.
+            let max = rng.random_range(1..=200);
+            let text: Vec<&str> = (0..rng.random_range(1..=max))
+                .map(|_| words[rng.random_range(0..words.len())])
+                .collect();
+            let label = rng.random_range(0..=1);
+            jsonl += &serde_json::json!({"text": text.join(" "), "label": label}).to_string();
+            jsonl.push('\n');
+        }
+        fs::write(dir.join(format!("{subset}-{i:03}.jsonl")), jsonl).unwrap();
+    }
+}
+
+#[test]
+fn report_is_the_same_for_any_jobs() {
+    let dir = tempfile::tempdir().unwrap();
+    write_random_files(dir.path(), 12, 0);
+    let glob = dir.path().join("*.jsonl");
+    let report = |jobs: &str| {
+        let out = dir.path().join(format!("stats-jobs{jobs}.toml"));
+        stats(&[
+            "--glob",
+            glob.to_str().unwrap(),
+            "--out",
+            out.to_str().unwrap(),
+            "--jobs",
+            jobs,
+            "--unit",
+            "bytes,words",
+            "--percentiles",
+            "0.1,0.5,0.9,0.99",
+            "--histogram-auto",
+            "10",
+            "--group-by-regex",
+            "([a-z]+)-",
+        ]);
+        fs::read(out).unwrap()
+    };
+    let sequential = report("1");
+    let parallel = report("4");
+    assert!(
+        sequential == parallel,
+        "--jobs 4 report differs from --jobs 1"
+    );
+}